@@ -1,28 +1,54 @@
 use crossterm::event::KeyEvent;
 use log::warn;
-use matrix_sdk::encryption::verification::SasVerification;
 use matrix_sdk::room::Room;
 use once_cell::sync::OnceCell;
 use ruma::events::receipt::ReceiptEventContent;
-use std::collections::VecDeque;
-use std::sync::mpsc::Sender;
-use std::sync::Mutex;
+use ruma::matrix_uri::MatrixId;
+use ruma::OwnedRoomId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
 
-use crate::event::Event;
+use crate::event::{Event, EventHandler};
+use crate::locale::clock_minute;
 use crate::matrix::matrix::Matrix;
+use crate::matrix::roomcache::Away;
+use crate::settings::take_problem;
+use crate::widgets::access::RoomAccess;
+use crate::widgets::away::{AwayBanner, Mentions};
 use crate::widgets::chat::Chat;
 use crate::widgets::confirm::Confirm;
-use crate::widgets::error::Error;
+use crate::widgets::devices::Devices;
+use crate::widgets::error::{details, Error};
+use crate::widgets::export::Export;
 use crate::widgets::help::Help;
+use crate::widgets::knock::Knock;
+use crate::widgets::knocks::Knocks;
+use crate::widgets::logs::Logs;
+use crate::widgets::pager::Pager;
+use crate::widgets::pickfile::PickFile;
+use crate::widgets::profile::Profile;
 use crate::widgets::progress::Progress;
+use crate::widgets::react::React;
+use crate::widgets::register::{Register, RegisterStep};
+use crate::widgets::rename::Rename;
+use crate::widgets::roominfo::RoomInfo;
 use crate::widgets::rooms::Rooms;
+use crate::widgets::seen::Seen;
 use crate::widgets::signin::Signin;
-use crate::widgets::EventResult;
+use crate::widgets::state::State;
+use crate::widgets::upload::Upload;
+use crate::widgets::verify::Verify;
+use crate::widgets::{take_cursor, EventResult};
 use ratatui::backend::Backend;
 use ratatui::terminal::Frame;
 
-static SENDER: OnceCell<Mutex<Sender<Event>>> = OnceCell::new();
+static SENDER: OnceCell<UnboundedSender<Event>> = OnceCell::new();
+
+/// How long to show what we missed when we come back.
+const AWAY_BANNER: Duration = Duration::from_secs(10);
 
 /// Application.
 pub struct App {
@@ -32,49 +58,73 @@ pub struct App {
     /// How many ticks have passed?
     pub timestamp: usize,
 
-    /// Hold on to all our widgets
-    pub popup: Option<Popup>,
+    /// Does the UI need to be drawn again?
+    pub dirty: bool,
+
+    /// Is the editor running? It has the terminal until it's done.
+    pub editing: bool,
+
+    /// The minute of the clock we last drew in (relative timestamps)
+    last_clock_minute: u64,
+
+    /// Hold on to all our widgets, popups stacked with the top one last
+    pub popups: Vec<Popup>,
     pub chat: Option<Chat>,
 
     /// And our single Matrix client and channel
     pub matrix: Matrix,
-    pub sender: Sender<Event>,
+    pub sender: UnboundedSender<Event>,
 
-    /// We'll hold on to any in-progress verifications here
-    pub sas: Option<SasVerification>,
+    /// Keep old read receipts around, merged by room
+    pub receipts: HashMap<OwnedRoomId, ReceiptEventContent>,
 
-    /// Keep old read receipts around
-    pub receipts: VecDeque<(Room, ReceiptEventContent)>,
+    /// Where to go once we've synced, if we were started with a link
+    pub pending_link: Option<MatrixId>,
+
+    /// Requests to let finish before we exit
+    pub pending: Vec<JoinHandle<()>>,
+
+    /// What we missed the last time we were away, and until when to say so
+    pub away: Option<Away>,
+    away_until: Option<Instant>,
 }
 
 impl App {
-    pub fn new(send: Sender<Event>, runtime: &Runtime) -> Self {
+    pub fn new(send: UnboundedSender<Event>, runtime: &Runtime) -> Self {
         let matrix = Matrix::new(runtime);
 
-        // Save the sender for future threads.
-        SENDER
-            .set(Mutex::new(send.clone()))
-            .expect("could not set sender");
+        App::init_sender(send.clone());
 
         Self {
             running: true,
             timestamp: 0,
-            popup: None,
+            dirty: true,
+            editing: false,
+            last_clock_minute: clock_minute(),
+            popups: vec![],
             chat: None,
             matrix,
             sender: send,
-            sas: None,
-            receipts: VecDeque::new(),
+            receipts: HashMap::new(),
+            pending_link: None,
+            pending: vec![],
+            away: None,
+            away_until: None,
         }
     }
 
-    pub fn get_sender() -> Sender<Event> {
-        SENDER
-            .get()
-            .expect("could not get sender")
-            .lock()
-            .expect("could not lock sender")
-            .clone()
+    /// Save the sender for future threads.
+    pub fn init_sender(send: UnboundedSender<Event>) {
+        SENDER.set(send).expect("could not set sender");
+    }
+
+    pub fn get_sender() -> UnboundedSender<Event> {
+        SENDER.get().expect("could not get sender").clone()
+    }
+
+    /// The sender, if anything has been started to listen yet.
+    pub fn try_get_sender() -> Option<UnboundedSender<Event>> {
+        SENDER.get().cloned()
     }
 
     pub fn select_room(&mut self, room: Room) {
@@ -92,21 +142,59 @@ impl App {
             return;
         }
 
-        // feed all the cached read receipts back in
-        for (room, content) in &self.receipts {
-            chat.as_mut().unwrap().receipt_event(room, content);
+        // don't leave a receipt behind in the old room
+        if let Some(c) = &mut self.chat {
+            c.flush_receipt();
+        }
+
+        // feed the cached read receipts back in
+        if let Some(content) = self.receipts.get(room.room_id()) {
+            chat.as_mut().unwrap().receipt_event(&room, content);
         }
 
         self.chat = chat;
         self.matrix.room_visit_event(room);
     }
 
-    pub fn set_popup(&mut self, popup: Popup) {
-        self.popup = Some(popup);
+    /// Stop the main loop, which shuts Matrix down on the way out.
+    pub fn quit(&mut self) {
+        if let Some(chat) = self.chat.as_mut() {
+            self.pending.extend(chat.flush_receipt());
+            self.pending
+                .push(self.matrix.typing_notification(chat.room(), false));
+        }
+
+        self.running = false;
+    }
+
+    /// Show a popup on top of whatever's already open.
+    pub fn push_popup(&mut self, popup: Popup) {
+        self.popups.push(popup);
+    }
+
+    /// Swap the top popup for another, like going to the next step of a form.
+    pub fn replace_popup(&mut self, popup: Popup) {
+        self.popups.pop();
+        self.popups.push(popup);
     }
 
+    /// Close the top popup, uncovering the one below, if any.
     pub fn close_popup(&mut self) {
-        self.popup = None;
+        self.popups.pop();
+    }
+
+    /// Close every popup that matches, wherever it is in the stack.
+    pub fn close_popups(&mut self, f: impl Fn(&Popup) -> bool) {
+        self.popups.retain(|p| !f(p));
+    }
+
+    pub fn close_all_popups(&mut self) {
+        self.popups.clear();
+    }
+
+    /// The popup that gets key events.
+    pub fn top_popup(&mut self) -> Option<&mut Popup> {
+        self.popups.last_mut()
     }
 
     /// Handles the tick event of the terminal.
@@ -118,23 +206,99 @@ impl App {
             return;
         }
 
+        // a broken config file is worth knowing about
+        if let Some(err) = take_problem() {
+            self.push_popup(Popup::Error(
+                Error::with_heading("Config Error".to_string(), err.to_string())
+                    .details(details(&err)),
+            ));
+        }
+
         // send out the ticks
-        if let Some(w) = self.popup.as_mut() {
-            w.tick_event(self.timestamp)
+        if let Some(c) = self.chat.as_mut() {
+            c.tick_event();
+        }
+
+        for w in self.popups.iter_mut() {
+            if w.tick_event(self.timestamp) {
+                self.dirty = true;
+            }
+        }
+
+        // the banner goes away on its own
+        if self.away_until.is_some_and(|t| Instant::now() >= t) {
+            self.away_until = None;
+            self.mark_dirty();
+        }
+
+        // relative timestamps are only precise to the minute, so redraw
+        // them as soon as it turns over
+        if self.last_clock_minute != clock_minute() {
+            self.mark_dirty();
         }
 
         self.timestamp += 1;
     }
 
+    /// The config file changed, so redo anything that depends on it.
+    pub fn settings_changed(&mut self) {
+        if let Some(c) = self.chat.as_mut() {
+            c.settings_changed();
+        }
+
+        for popup in self.popups.iter_mut() {
+            popup.settings_changed();
+        }
+
+        self.mark_dirty();
+    }
+
+    /// We're back, and missed a few things.
+    pub fn away_event(&mut self, away: Away) {
+        self.away = Some(away);
+        self.away_until = Some(Instant::now() + AWAY_BANNER);
+        self.mark_dirty();
+    }
+
+    /// Everything that mentioned us while we were away, which also means
+    /// we've seen the banner.
+    pub fn show_mentions(&mut self) {
+        let mentions = self
+            .away
+            .as_ref()
+            .map(|a| a.mentions.clone())
+            .unwrap_or_default();
+
+        self.away_until = None;
+        self.push_popup(Popup::Mentions(Mentions::new(mentions)));
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
     /// Renders the user interface widgets.
     pub fn render<B: Backend>(&mut self, frame: &mut Frame<'_, B>) {
+        self.last_clock_minute = clock_minute();
+
         if let Some(c) = &self.chat {
             frame.render_widget(c.widget(), frame.size());
         }
 
-        if let Some(w) = &self.popup {
+        if let (Some(away), Some(_)) = (&self.away, self.away_until) {
+            frame.render_widget(AwayBanner { away }, frame.size());
+        }
+
+        // bottom to top, so each one covers the last, and only the top one
+        // gets a cursor
+        for w in &self.popups {
+            take_cursor();
             w.render(frame);
         }
+
+        if let Some((x, y)) = take_cursor() {
+            frame.set_cursor(x, y);
+        }
     }
 }
 
@@ -144,38 +308,124 @@ impl App {
 pub enum Popup {
     Confirm(Confirm),
     Error(Error),
+    Export(Export),
+    Pager(Pager),
+    PickFile(PickFile),
+    Profile(Profile),
     Progress(Progress),
+    React(React),
+    Register(Register),
+    RegisterStep(RegisterStep),
+    Rename(Rename),
+    RoomAccess(RoomAccess),
+    RoomInfo(RoomInfo),
     Rooms(Rooms),
+    Seen(Seen),
     Signin(Signin),
+    Devices(Devices),
     Help(Help),
+    Knock(Knock),
+    Knocks(Knocks),
+    Logs(Logs),
+    Mentions(Mentions),
+    State(State),
+    Upload(Upload),
+    Verify(Verify),
 }
 
 impl Popup {
-    pub fn key_event(&mut self, event: &KeyEvent) -> EventResult {
+    pub fn key_event(&mut self, event: &KeyEvent, handler: &EventHandler) -> EventResult {
         match self {
             Popup::Confirm(w) => w.key_event(event),
             Popup::Error(w) => w.key_event(event),
+            Popup::Export(w) => w.key_event(event),
+            Popup::Pager(w) => w.key_event(event),
+            Popup::PickFile(w) => w.key_event(event),
+            Popup::Profile(w) => w.key_event(event, handler),
             Popup::Progress(_) => EventResult::Ignored,
+            Popup::React(w) => w.key_event(event),
+            Popup::Register(w) => w.key_event(event),
+            Popup::RegisterStep(w) => w.key_event(event),
+            Popup::Rename(w) => w.key_event(event),
+            Popup::RoomAccess(w) => w.key_event(event),
+            Popup::RoomInfo(w) => w.key_event(event),
             Popup::Rooms(w) => w.key_event(event),
+            Popup::Seen(w) => w.key_event(event),
             Popup::Signin(w) => w.key_event(event),
+            Popup::Devices(w) => w.key_event(event),
             Popup::Help(w) => w.key_event(event),
+            Popup::Knock(w) => w.key_event(event),
+            Popup::Knocks(w) => w.key_event(event),
+            Popup::Logs(w) => w.key_event(event),
+            Popup::Mentions(w) => w.key_event(event),
+            Popup::State(w) => w.key_event(event),
+            Popup::Upload(w) => w.key_event(event),
+            Popup::Verify(w) => w.key_event(event),
         }
     }
 
-    pub fn tick_event(&mut self, timestamp: usize) {
+    /// Only popups with text inputs take a paste.
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        match self {
+            Popup::Export(w) => w.paste(text),
+            Popup::Knock(w) => w.paste(text),
+            Popup::PickFile(w) => w.paste(text),
+            Popup::Register(w) => w.paste(text),
+            Popup::RegisterStep(w) => w.paste(text),
+            Popup::Rename(w) => w.paste(text),
+            Popup::Rooms(w) => w.paste(text),
+            Popup::Signin(w) => w.paste(text),
+            Popup::Upload(w) => w.paste(text),
+            _ => EventResult::Ignored,
+        }
+    }
+
+    /// Rebuild anything that came from the config.
+    pub fn settings_changed(&mut self) {
+        match self {
+            Popup::React(w) => w.settings_changed(),
+            Popup::Rooms(w) => w.settings_changed(),
+            _ => {}
+        }
+    }
+
+    /// Returns true if the popup animates and needs to be drawn again.
+    pub fn tick_event(&mut self, timestamp: usize) -> bool {
         if let Popup::Progress(w) = self {
             w.tick_event(timestamp);
+            return true;
         };
+
+        false
     }
 
     pub fn render<B: Backend>(&self, frame: &mut Frame<'_, B>) {
         match self {
             Popup::Confirm(w) => frame.render_widget(w.widget(), frame.size()),
             Popup::Error(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Export(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Pager(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::PickFile(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Profile(w) => frame.render_widget(w.widget(), frame.size()),
             Popup::Progress(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::React(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Register(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::RegisterStep(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Rename(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::RoomAccess(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::RoomInfo(w) => frame.render_widget(w.widget(), frame.size()),
             Popup::Rooms(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Seen(w) => frame.render_widget(w.widget(), frame.size()),
             Popup::Signin(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Devices(w) => frame.render_widget(w.widget(), frame.size()),
             Popup::Help(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Knock(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Knocks(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Logs(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Mentions(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::State(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Upload(w) => frame.render_widget(w.widget(), frame.size()),
+            Popup::Verify(w) => frame.render_widget(w.widget(), frame.size()),
         }
     }
 }
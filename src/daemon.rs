@@ -0,0 +1,264 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use log::{error, info};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::time::timeout;
+
+use crate::app::App;
+use crate::event::Event;
+use crate::handler::MatuiEvent;
+use crate::matrix::matrix::Matrix;
+
+/// How long the TUI will wait for the daemon to let go of the store.
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often we check on each other.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many times to try for a lock before deciding someone really has it.
+const LOCK_ATTEMPTS: usize = 5;
+
+const TUI_PID: &str = "tui.pid";
+const DAEMON_PID: &str = "daemon.pid";
+
+/// Where the TUI listens for links from later runs.
+const TUI_SOCKET: &str = "tui.sock";
+
+/// Nothing we're handed is longer than this.
+const MAX_LINK: u64 = 4096;
+
+/// A file holding our PID, with an exclusive lock on it for as long as it's
+/// open. The TUI and the daemon use these to make sure only one of them has
+/// the store open at a time. The lock goes with the process, so a crash
+/// can't leave a stale one behind, and the file is never removed, so the
+/// next owner can't end up locking a different one.
+pub struct PidFile {
+    _file: File,
+}
+
+impl PidFile {
+    // None if someone else already has it.
+    fn lock(name: &str) -> anyhow::Result<Option<PidFile>> {
+        let path = pid_path(name);
+        fs::create_dir_all(path.parent().expect("pid file to have a parent"))?;
+
+        // not truncated until we have the lock, or we'd wipe out the owner
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut attempts = 0;
+
+        // someone checking whether we're live holds a lock for a moment too
+        while !try_flock(&file, libc::LOCK_EX)? {
+            attempts += 1;
+
+            if attempts == LOCK_ATTEMPTS {
+                return Ok(None);
+            }
+
+            thread::sleep(POLL_INTERVAL / 20);
+        }
+
+        file.set_len(0)?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+
+        Ok(Some(PidFile { _file: file }))
+    }
+
+    // does anyone hold the lock on this file?
+    fn is_live(name: &str) -> bool {
+        let Ok(file) = File::open(pid_path(name)) else {
+            return false;
+        };
+
+        // if we can get it, nobody else has it; it goes again with the file
+        matches!(try_flock(&file, libc::LOCK_SH), Ok(false))
+    }
+}
+
+fn try_flock(file: &File, operation: libc::c_int) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+
+    if err.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+fn pid_path(name: &str) -> PathBuf {
+    dirs::data_dir()
+        .expect("no data directory found")
+        .join("matui")
+        .join(name)
+}
+
+/// Called by the TUI on startup. Refuses if another TUI is already running,
+/// and otherwise asks any running daemon to step aside, and waits for it to
+/// do so.
+pub fn claim_for_tui() -> anyhow::Result<PidFile> {
+    let Some(lock) = PidFile::lock(TUI_PID)? else {
+        bail!("Matui is already running.");
+    };
+
+    let start = Instant::now();
+
+    while PidFile::is_live(DAEMON_PID) {
+        if start.elapsed() > HANDOFF_TIMEOUT {
+            bail!("The notification daemon won't let go of the session.");
+        }
+
+        thread::sleep(POLL_INTERVAL / 4);
+    }
+
+    Ok(lock)
+}
+
+/// Hand a link to the TUI that's already running, if there is one, so it can
+/// go there instead of us. True if it took it.
+pub fn forward_link(link: &str) -> anyhow::Result<bool> {
+    let Ok(mut stream) = UnixStream::connect(pid_path(TUI_SOCKET)) else {
+        return Ok(false);
+    };
+
+    stream.write_all(format!("{}\n", link).as_bytes())?;
+
+    Ok(true)
+}
+
+/// Listens for links from later runs of matui, for as long as the TUI is
+/// running. The socket is removed when dropped.
+pub struct LinkSocket {
+    path: PathBuf,
+}
+
+impl LinkSocket {
+    pub fn listen() -> anyhow::Result<LinkSocket> {
+        let path = pid_path(TUI_SOCKET);
+
+        // we have the TUI lock, so anything here was left behind by a crash
+        let _ = fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path)?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // don't let anyone hold us up
+                let _ = stream.set_read_timeout(Some(POLL_INTERVAL));
+
+                let mut link = String::new();
+
+                if let Err(e) = BufReader::new(stream.take(MAX_LINK)).read_line(&mut link) {
+                    error!("could not read forwarded link: {}", e);
+                    continue;
+                }
+
+                match Matrix::parse_link(link.trim()) {
+                    Some(id) => Matrix::send(MatuiEvent::OpenLink(id)),
+                    None => error!("could not parse forwarded link: {}", link.trim()),
+                }
+            }
+        });
+
+        Ok(LinkSocket { path })
+    }
+}
+
+impl Drop for LinkSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sync and send notifications, without a UI, for as long as the TUI
+/// isn't running. We pick back up once it exits.
+pub fn run() -> anyhow::Result<()> {
+    let (sender, mut receiver) = unbounded_channel();
+    App::init_sender(sender);
+
+    loop {
+        while PidFile::is_live(TUI_PID) {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let Some(lock) = PidFile::lock(DAEMON_PID)? else {
+            bail!("The notification daemon is already running.");
+        };
+
+        // the TUI may have started while we were getting the lock; it takes
+        // its own before checking on ours, so one of us will see the other
+        if PidFile::is_live(TUI_PID) {
+            drop(lock);
+            continue;
+        }
+
+        info!("daemon taking over the session");
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+
+        let matrix = Matrix::new(&runtime);
+        let result = runtime.block_on(run_until_tui(&matrix, &mut receiver));
+
+        runtime.block_on(matrix.shutdown(vec![]));
+        runtime.shutdown_timeout(Duration::from_secs(2));
+
+        // anything left over is from the runtime we just tore down
+        while receiver.try_recv().is_ok() {}
+
+        // the client has to be gone before the TUI can have the store
+        drop(matrix);
+        drop(lock);
+        result?;
+
+        info!("daemon handing the session to the TUI");
+    }
+}
+
+async fn run_until_tui(
+    matrix: &Matrix,
+    receiver: &mut UnboundedReceiver<Event>,
+) -> anyhow::Result<()> {
+    matrix.init();
+
+    loop {
+        match timeout(POLL_INTERVAL, receiver.recv()).await {
+            Ok(Some(Event::Matui(event))) => match event {
+                MatuiEvent::SyncComplete => matrix.sync(),
+                MatuiEvent::Timeline(event) => matrix.timeline_event(event),
+                MatuiEvent::LoginRequired => {
+                    bail!("Not signed in. Run matui without --daemon to sign in first.")
+                }
+                MatuiEvent::SessionExpired(..) => {
+                    bail!("Signed out. Run matui without --daemon to sign back in.")
+                }
+                MatuiEvent::Error(msg, _) => error!("{}", msg),
+                _ => {}
+            },
+            Ok(Some(_)) => {}
+            Ok(None) => bail!("event channel closed"),
+            Err(_) => {}
+        }
+
+        if PidFile::is_live(TUI_PID) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use crate::settings::setting_names;
+
+/// What every setting does, its default, and what else it can be. Anything
+/// commented out is left at its default.
+const DOCS: &[(&str, &str)] = &[
+    (
+        "reactions",
+        "\
+# All the reactions that will show up in the picker, before the ones you've
+# used most lately. Press / in the picker to search for any other emoji,
+# including custom ones from image packs.
+reactions = [ \"❤️\", \"👍\", \"👎\", \"😂\", \"‼️\", \"❓️\"]",
+    ),
+    (
+        "quick_reactions",
+        "\
+# Keys that send a reaction straight from the picker: r, then the key. Any key
+# but j, k and / will do. None by default.
+# quick_reactions = { \"+\" = \"👍\", \"-\" = \"👎\", \"h\" = \"❤️\" }",
+    ),
+    (
+        "muted",
+        "\
+# Rooms that never send a notification, by ID.
+# muted = [\"!hMPITSQBLFEleSJeVe:matrix.org\"]",
+    ),
+    (
+        "skip_notifications",
+        "\
+# Kinds of message that never send a notification: \"text\", \"image\",
+# \"video\", \"file\" or \"notice\" (from the server). Can be set for one room
+# in its table, too. Edits, reactions and bot notices never notify.
+# skip_notifications = [\"image\", \"video\"]",
+    ),
+    (
+        "notification_previews",
+        "\
+# Notifications say what a message says. Or, with this off, only \"New message
+# from Alice in Family\". Can be set for one room in its table, too.
+# notification_previews = true",
+    ),
+    (
+        "device_warnings",
+        "\
+# In encrypted rooms, warn before sending to devices nobody has verified, so
+# they can be blocked: \"room\" (the first time in each room), \"device\"
+# (every time a new one shows up) or \"off\". Can be set for one room, too.
+# device_warnings = \"off\"",
+    ),
+    (
+        "archived",
+        "\
+# Rooms hidden from the room switcher (Ctrl+A there to add or remove one).
+# archived = [\"!SVkFJHzfwvuaIEawgC:matrix.org\"]",
+    ),
+    (
+        "clean_vim",
+        "\
+# Start Vim without your own config, in case it's interfering with the Enter
+# key bindings.
+# clean_vim = false",
+    ),
+    (
+        "gui_editor",
+        "\
+# Graphical editors (code -w, gedit, etc) open their own window, so the app
+# keeps running while you write. That's guessed from the command, but can be
+# set here if the guess is wrong.
+# gui_editor = true",
+    ),
+    (
+        "external_pager",
+        "\
+# Read messages and rooms (v and V) in your editor, rather than the built-in
+# pager. Press e in the pager to do that once.
+# external_pager = false",
+    ),
+    (
+        "file_picker",
+        "\
+# Pick files to upload with this, rather than the native dialog. Paths are read
+# from its output, or from the file at {} if that's in the command.
+# file_picker = \"fzf -m\"",
+    ),
+    (
+        "room_sort",
+        "\
+# How the room switcher is sorted: \"unread\", \"recent\" or \"alphabetical\".
+# room_sort = \"unread\"",
+    ),
+    (
+        "unread_rooms_only",
+        "\
+# Only show rooms with unread messages in the room switcher (Ctrl+U there).
+# unread_rooms_only = false",
+    ),
+    (
+        "unread_counts",
+        "\
+# What counts as unread in the room switcher: every message (\"all\"), or only
+# mentions (\"mentions\"), for when you're in a few busy rooms. Can be set for
+# one room in its table, too.
+# unread_counts = \"all\"",
+    ),
+    (
+        "receipt_style",
+        "\
+# Read receipts are a row of initials under each message (\"initials\"), or a
+# \"Seen by\" line (\"text\").
+# receipt_style = \"initials\"",
+    ),
+    (
+        "group_messages",
+        "\
+# Messages sent by the same person within a few minutes of each other share
+# one sender and time, rather than repeating it on every message.
+# group_messages = true",
+    ),
+    (
+        "max_message_lines",
+        "\
+# Long messages are cut off after this many lines. Press x to see the rest
+# right in the chat.
+# max_message_lines = 10",
+    ),
+    (
+        "show_position",
+        "\
+# Show where the selected message is, out of everything loaded, in the chat
+# header.
+# show_position = false",
+    ),
+    (
+        "chat_width",
+        "\
+# The chat is centered, up to this many columns wide.
+# chat_width = 80",
+    ),
+    (
+        "full_width",
+        "\
+# Or it can take up the whole terminal (F toggles it).
+# full_width = false",
+    ),
+    (
+        "zen_mode",
+        "\
+# Hide the room header, for tiny terminals (Z toggles it).
+# zen_mode = false",
+    ),
+    (
+        "presentation_mode",
+        "\
+# For streaming or pairing: no notifications, and no message previews in the
+# room switcher (Ctrl+P toggles it).
+# presentation_mode = false",
+    ),
+    (
+        "layout",
+        "\
+# Keep a blank line between messages (\"cozy\"), or leave it out (\"compact\").
+# layout = \"cozy\"",
+    ),
+    (
+        "time_language",
+        "\
+# The language for relative times (\"5 minutes ago\"), by ISO 639-1 code.
+# time_language = \"en\"",
+    ),
+    (
+        "short_times",
+        "\
+# Shorten relative times to \"5m\".
+# short_times = false",
+    ),
+    (
+        "low_bandwidth",
+        "\
+# For metered or slow connections: skip avatars and image thumbnails in
+# notifications, and sync less at a time.
+# low_bandwidth = false",
+    ),
+    (
+        "media_cache_size",
+        "\
+# Downloaded media is kept in ~/.cache/matui/media, up to this many
+# megabytes, with the least recently used thrown out first. Media from
+# encrypted rooms is never kept.
+# media_cache_size = 500",
+    ),
+    (
+        "prefetch_screens",
+        "\
+# How many screens of history to keep loaded ahead of the cursor.
+# prefetch_screens = 3",
+    ),
+    (
+        "log_level",
+        "\
+# How much to write to ~/.local/state/matui/matui.log: \"error\", \"warn\",
+# \"info\", \"debug\" or \"trace\". Can also be set with --log-level.
+# log_level = \"info\"",
+    ),
+    (
+        "keyring",
+        "\
+# Keep the store passphrase in the system keyring (via the Secret Service)
+# instead of the session file.
+# keyring = false",
+    ),
+    (
+        "keyring_tokens",
+        "\
+# And the access tokens too, if the keyring is on.
+# keyring_tokens = false",
+    ),
+    (
+        "proxy",
+        "\
+# Connect through an HTTP or SOCKS proxy, like Tor. If not set, ALL_PROXY,
+# HTTPS_PROXY and HTTP_PROXY are honored. Either way, hosts in NO_PROXY are
+# reached directly. With socks5h://, names are looked up by the proxy too.
+# proxy = \"socks5://127.0.0.1:9050\"",
+    ),
+    (
+        "ca_certificate",
+        "\
+# Trust an extra root certificate (or bundle), in PEM format, for homeservers
+# behind a private CA.
+# ca_certificate = \"~/.config/matui/ca.pem\"",
+    ),
+    (
+        "danger_disable_tls_verification",
+        "\
+# Turn off certificate checks altogether. Anyone between you and your
+# homeserver will be able to read your traffic.
+# danger_disable_tls_verification = false",
+    ),
+    (
+        "device_name",
+        "\
+# What new sessions are called in device lists. Defaults to \"Matui on
+# <hostname>\".
+# device_name = \"Matui on my laptop\"",
+    ),
+    (
+        "encrypt_session",
+        "\
+# Without a keyring, encrypt the session file with a passphrase that's asked
+# for at startup (or read from $MATUI_PASSPHRASE), once you've signed in.
+# encrypt_session = false",
+    ),
+    (
+        "editor",
+        "\
+# The editor, per action: compose, reply, edit or view. Each can have its own
+# command (instead of $EDITOR), extra arguments, and a template to show below
+# the text, filled in with {room}, {sender} and {body}.
+# [editor.view]
+# command = \"nvim\"
+# args = [\"-R\"]
+# template = \"\"",
+    ),
+    (
+        "strings",
+        "\
+# Our own phrases, translated. {} is where the names go. Available strings are
+# is_typing, are_typing, room_is_typing, room_are_typing and seen_by.
+# [strings]
+# seen_by = \"Gesehen von {}.\"",
+    ),
+    (
+        "hooks",
+        "\
+# Commands to run when things happen, given a JSON description of the event
+# on stdin. Available hooks are message, mention, invite and verification.
+# [hooks]
+# mention = \"espeak 'somebody mentioned you'\"",
+    ),
+    (
+        "rooms",
+        "\
+# Settings for just one room, by ID: a name of our own, notifications (\"all\",
+# \"mentions\" or \"none\"), layout, an accent color (a name or hex code),
+# kinds of message to skip notifications for, notification previews, device
+# warnings, and what counts as unread.
+# [rooms.\"!abcdefghijk:example.org\"]
+# name = \"Family\"
+# notifications = \"mentions\"
+# layout = \"compact\"
+# accent = \"cyan\"
+# skip_notifications = [\"file\"]
+# notification_previews = false
+# device_warnings = \"device\"
+# unread_counts = \"mentions\"",
+    ),
+    (
+        "bridges",
+        "\
+# Senders from bridged networks get a tag with the network's name, picked out
+# by their Matrix ID. Discord, IRC, Signal, Slack, Telegram and WhatsApp are
+# already known; add others here, or turn one off with an empty pattern.
+# [bridges]
+# xmpp = \"^@_xmpp_\"
+# irc = \"\"",
+    ),
+];
+
+/// A config.toml with every setting in it, explained, and mostly commented
+/// out.
+pub fn default_config() -> String {
+    let mut config = String::from(
+        "# Matui configuration. Anything commented out is at its default. This file\n\
+         # is reloaded as soon as it's saved.\n",
+    );
+
+    for name in setting_names() {
+        if let Some((_, doc)) = DOCS.iter().find(|(key, _)| key == name) {
+            config.push('\n');
+            config.push_str(doc);
+            config.push('\n');
+        }
+    }
+
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::check_str;
+
+    #[test]
+    fn test_every_setting() {
+        for name in setting_names() {
+            assert!(
+                DOCS.iter().any(|(key, _)| key == name),
+                "{} isn't documented",
+                name
+            );
+        }
+
+        for (key, _) in DOCS {
+            assert!(setting_names().contains(key), "{} isn't a setting", key);
+        }
+    }
+
+    #[test]
+    fn test_default_config() {
+        let config = default_config();
+        check_str(&config).unwrap();
+
+        // and with everything uncommented, too
+        let uncommented: String = config
+            .lines()
+            .map(|l| l.strip_prefix("# ").unwrap_or(l))
+            .filter(|l| l.contains(" = ") || l.starts_with('['))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        check_str(&uncommented).unwrap();
+    }
+}
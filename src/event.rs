@@ -1,9 +1,17 @@
 use crate::handler::MatuiEvent;
-use crossterm::event::{self, Event as CrosstermEvent, KeyEvent};
+use crate::spawn::{EditorTarget, PickTarget};
+use crate::widgets::receipts::Receipts;
+use anyhow::Context;
+use crossterm::event::{Event as CrosstermEvent, EventStream, KeyEvent};
+use futures::{FutureExt, StreamExt};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::pending;
 use std::ops::Sub;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{interval_at, Interval, MissedTickBehavior};
 
 /// Terminal events.
 #[derive(Clone, Debug)]
@@ -12,105 +20,337 @@ pub enum Event {
     Tick,
     /// Force a clear and full re-draw.
     Redraw,
+    /// Drop back to the shell until we're resumed.
+    Suspend,
+    /// Read some text in the external editor.
+    View(String),
+    /// The editor has exited, with whatever was written, and whether it had
+    /// the terminal.
+    EditorClosed(EditorTarget, Result<Option<String>, String>, bool),
+    /// Files have been picked, if there was a way to, and whether the picker
+    /// had the terminal.
+    FilesPicked(PickTarget, Result<Option<Vec<PathBuf>>, String>, bool),
+    /// The terminal has been resized.
+    Resize,
     /// The window has gained focus
     Focus,
     /// The window has lost focus
     Blur,
     /// Key press.
     Key(KeyEvent),
+    /// Pasted text, or whatever an input method composed.
+    Paste(String),
     /// App event
     Matui(MatuiEvent),
 }
 
+/// How soon an event needs handling. Input always goes first, so the UI keeps
+/// up with the keyboard while a backfill streams in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Keys, pastes, and anything else the terminal is waiting on.
+    Input,
+    /// Everything else, in the order it came.
+    Normal,
+    /// Timeline events and friends, that can arrive by the thousand. Only
+    /// so many get handled between frames.
+    Bulk,
+}
+
+/// How many bulk events to handle before drawing again.
+const BULK_PER_FRAME: usize = 25;
+
+impl Event {
+    pub fn priority(&self) -> Priority {
+        match self {
+            Event::Key(_)
+            | Event::Paste(_)
+            | Event::Redraw
+            | Event::Suspend
+            | Event::Resize
+            | Event::Focus
+            | Event::Blur
+            | Event::View(_)
+            | Event::EditorClosed(..)
+            | Event::FilesPicked(..) => Priority::Input,
+            Event::Matui(
+                MatuiEvent::Timeline(_)
+                | MatuiEvent::TimelineBatch(_)
+                | MatuiEvent::TimelineGap(..)
+                | MatuiEvent::Trust(..)
+                | MatuiEvent::RoomCacheUpdated(_)
+                | MatuiEvent::Receipt(..)
+                | MatuiEvent::Typing(..)
+                | MatuiEvent::Presence(..),
+            ) => Priority::Bulk,
+            _ => Priority::Normal,
+        }
+    }
+}
+
 /// Terminal event handler.
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct EventHandler {
     /// Event sender channel.
-    sender: Sender<Event>,
+    sender: UnboundedSender<Event>,
     /// Event receiver channel.
-    receiver: Receiver<Event>,
-    /// Park sender.
-    pk_sender: Sender<bool>,
-    /// Event handler thread.
-    handler: thread::JoinHandle<()>,
+    receiver: UnboundedReceiver<Event>,
+    /// Terminal events, unless something else has the terminal.
+    terminal: RefCell<Option<EventStream>>,
+    /// When we last got the terminal back.
+    last_park: Cell<Instant>,
+    /// Tick rate, in milliseconds.
+    tick_rate: Cell<u64>,
+    /// When to send the next tick.
+    ticks: Interval,
+    /// Bulk events that didn't make it into the last batch.
+    backlog: VecDeque<Event>,
 }
 
 impl EventHandler {
+    /// Stop reading the terminal, so something else can have it.
     pub fn park(&self) {
-        self.pk_sender.send(true).expect("could send park event");
+        self.terminal.borrow_mut().take();
     }
 
     pub fn unpark(&self) {
-        self.handler.thread().unpark();
+        *self.terminal.borrow_mut() = Some(EventStream::new());
+        self.last_park.set(Instant::now());
+    }
+
+    /// Change how often ticks are sent, starting with the next one.
+    pub fn set_tick_rate(&self, tick_rate: u64) {
+        self.tick_rate.set(tick_rate);
     }
 
-    /// Constructs a new instance of [`EventHandler`].
+    /// Constructs a new instance of [`EventHandler`]. This has to be called
+    /// from within the runtime.
     pub fn new(tick_rate: u64) -> Self {
-        let tick_rate = Duration::from_millis(tick_rate);
-        let (sender, receiver) = channel();
-        let (pk_sender, pk_receiver) = channel();
-        let handler = {
-            let sender = sender.clone();
-            thread::spawn(move || {
-                let mut last_tick = Instant::now();
-                let mut last_park = Instant::now().sub(Duration::from_secs(10));
-
-                loop {
-                    let timeout = tick_rate
-                        .checked_sub(last_tick.elapsed())
-                        .unwrap_or(tick_rate);
-
-                    if pk_receiver.try_recv().is_ok() {
-                        thread::park();
-                        last_park = Instant::now()
-                    }
+        let (sender, receiver) = unbounded_channel();
+
+        Self {
+            sender,
+            receiver,
+            terminal: RefCell::new(Some(EventStream::new())),
+            last_park: Cell::new(Instant::now().sub(Duration::from_secs(10))),
+            tick_rate: Cell::new(tick_rate),
+            ticks: ticks(tick_rate),
+            backlog: VecDeque::new(),
+        }
+    }
+
+    /// Wait for the next event: from the app, the terminal, or the clock.
+    pub async fn next(&mut self) -> anyhow::Result<Event> {
+        loop {
+            let tick_rate = Duration::from_millis(self.tick_rate.get());
+
+            if self.ticks.period() != tick_rate {
+                self.ticks = ticks(self.tick_rate.get());
+            }
 
-                    if event::poll(timeout).expect("no events available") {
-                        let event = event::read().expect("unable to read event");
-
-                        if pk_receiver.try_recv().is_ok() {
-                            thread::park();
-                            last_park = Instant::now()
-                        }
-
-                        // right after we unpark, we can get a stream of
-                        // garbage events
-                        if last_park.elapsed() > Duration::from_millis(250) {
-                            match event {
-                                CrosstermEvent::Key(e) => sender.send(Event::Key(e)),
-                                CrosstermEvent::FocusGained => sender.send(Event::Focus),
-                                CrosstermEvent::FocusLost => sender.send(Event::Blur),
-                                _ => Ok(()),
-                            }
-                            .expect("failed to send terminal event")
-                        }
+            let last_park = self.last_park.get();
+
+            tokio::select! {
+                event = self.receiver.recv() => {
+                    return event.context("event channel closed");
+                }
+                event = next_terminal_event(self.terminal.get_mut()) => {
+                    // right after we unpark, we can get a stream of garbage
+                    // events
+                    if last_park.elapsed() <= Duration::from_millis(250) {
+                        continue;
                     }
 
-                    if last_tick.elapsed() >= tick_rate {
-                        sender.send(Event::Tick).expect("failed to send tick event");
-                        last_tick = Instant::now();
+                    if let Some(event) = from_terminal(event?) {
+                        return Ok(event);
                     }
                 }
-            })
-        };
-        Self {
-            sender,
-            receiver,
-            pk_sender,
-            handler,
+                _ = self.ticks.tick() => return Ok(Event::Tick),
+            }
+        }
+    }
+
+    // Whatever the terminal has for us right now, without waiting.
+    fn ready_terminal_events(&mut self, events: &mut Vec<Event>) -> anyhow::Result<()> {
+        let last_park = self.last_park.get();
+
+        while let Some(event) = next_terminal_event(self.terminal.get_mut()).now_or_never() {
+            // the same garbage as in next()
+            if last_park.elapsed() <= Duration::from_millis(250) {
+                continue;
+            }
+
+            if let Some(event) = from_terminal(event?) {
+                events.push(event);
+            }
         }
+
+        Ok(())
     }
 
-    /// Receive the next event from the handler thread.
-    ///
-    /// This function will always block the current thread if
-    /// there is no data available and it's possible for more data to be sent.
-    pub fn next(&self) -> anyhow::Result<Event> {
-        Ok(self.receiver.recv()?)
+    /// Receive the next event, along with anything else that's queued up
+    /// behind it, most urgent first. Bulk events past the first few wait for
+    /// the next batch, so a frame gets drawn in between. Receipts and typing
+    /// notifications for the same room are collapsed so that a busy room
+    /// only costs one update per frame.
+    pub async fn next_batch(&mut self) -> anyhow::Result<Vec<Event>> {
+        // only wait when there's nothing left over from last time
+        let mut events: Vec<Event> = self.backlog.drain(..).collect();
+
+        if events.is_empty() {
+            events.push(self.next().await?);
+        }
+
+        // keys that came in while we were working through the backlog jump
+        // the queue
+        self.ready_terminal_events(&mut events)?;
+
+        while let Ok(event) = self.receiver.try_recv() {
+            events.push(event);
+        }
+
+        let (batch, rest) = prioritize(events, BULK_PER_FRAME);
+        self.backlog = rest;
+
+        Ok(coalesce(batch))
     }
 
-    pub fn sender(&self) -> Sender<Event> {
+    pub fn sender(&self) -> UnboundedSender<Event> {
         self.sender.clone()
     }
 }
+
+fn ticks(tick_rate: u64) -> Interval {
+    let period = Duration::from_millis(tick_rate);
+    let mut ticks = interval_at(tokio::time::Instant::now() + period, period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks
+}
+
+// Never finishes while the terminal is parked.
+async fn next_terminal_event(
+    terminal: &mut Option<EventStream>,
+) -> std::io::Result<CrosstermEvent> {
+    match terminal {
+        Some(stream) => match stream.next().await {
+            Some(event) => event,
+            None => pending().await,
+        },
+        None => pending().await,
+    }
+}
+
+fn from_terminal(event: CrosstermEvent) -> Option<Event> {
+    match event {
+        CrosstermEvent::Key(e) => Some(Event::Key(e)),
+        CrosstermEvent::Paste(s) => Some(Event::Paste(s)),
+        CrosstermEvent::FocusGained => Some(Event::Focus),
+        CrosstermEvent::FocusLost => Some(Event::Blur),
+        CrosstermEvent::Resize(_, _) => Some(Event::Resize),
+        _ => None,
+    }
+}
+
+// Sort what's urgent to the front, keeping the order within each priority,
+// and hold back the bulk events past the budget.
+fn prioritize(events: Vec<Event>, budget: usize) -> (Vec<Event>, VecDeque<Event>) {
+    let mut batch = Vec::with_capacity(events.len());
+    let mut rest = VecDeque::new();
+    let mut bulk = 0;
+
+    for event in events {
+        if event.priority() != Priority::Bulk {
+            batch.push(event);
+        } else if bulk < budget {
+            bulk += 1;
+            batch.push(event);
+        } else {
+            rest.push_back(event);
+        }
+    }
+
+    batch.sort_by_key(Event::priority);
+
+    (batch, rest)
+}
+
+// Merge receipts and typing per room. The merged event takes the place of the
+// latest one, so it still comes after any timeline events it followed.
+fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut ret: Vec<Event> = Vec::with_capacity(events.len());
+
+    for event in events {
+        match event {
+            Event::Matui(MatuiEvent::Receipt(room, content)) => {
+                let earlier = ret.iter().position(|e| {
+                    matches!(e, Event::Matui(MatuiEvent::Receipt(r, _))
+                        if r.room_id() == room.room_id())
+                });
+
+                let content = match earlier.map(|i| ret.remove(i)) {
+                    Some(Event::Matui(MatuiEvent::Receipt(_, mut merged))) => {
+                        Receipts::merge_content(&mut merged, content);
+                        merged
+                    }
+                    _ => content,
+                };
+
+                ret.push(Event::Matui(MatuiEvent::Receipt(room, content)));
+            }
+            Event::Matui(MatuiEvent::Typing(room, ids)) => {
+                // only the latest list of typers matters
+                ret.retain(|e| {
+                    !matches!(e, Event::Matui(MatuiEvent::Typing(r, _))
+                        if r.room_id() == room.room_id())
+                });
+
+                ret.push(Event::Matui(MatuiEvent::Typing(room, ids)));
+            }
+            _ => ret.push(event),
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyCode;
+    use ruma::presence::PresenceState;
+    use ruma::user_id;
+
+    fn presence() -> Event {
+        Event::Matui(MatuiEvent::Presence(
+            user_id!("@alice:example.com").to_owned(),
+            PresenceState::Online,
+        ))
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let events = vec![
+            presence(),
+            Event::Tick,
+            presence(),
+            presence(),
+            Event::Key(KeyEvent::from(KeyCode::Char('j'))),
+        ];
+
+        let (batch, rest) = prioritize(events, 2);
+
+        let priorities: Vec<Priority> = batch.iter().map(Event::priority).collect();
+
+        assert_eq!(
+            priorities,
+            vec![
+                Priority::Input,
+                Priority::Normal,
+                Priority::Bulk,
+                Priority::Bulk
+            ]
+        );
+
+        assert_eq!(rest.len(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+/// A successful fuzzy match: how good it was, and which characters (by char
+/// index, not byte) of the text matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    pub indices: Vec<usize>,
+}
+
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 12;
+const WORD_START: i64 = 8;
+const MAX_GAP_PENALTY: i64 = 8;
+
+/// Past this many characters, a gap costs the same however long it is.
+const MAX_GAP: usize = MAX_GAP_PENALTY as usize;
+
+/// Every character of the pattern has to show up in the text, in order, but
+/// not necessarily together. Runs of characters, and characters that start
+/// words, score higher. Case is ignored.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<Match> {
+    let pattern: Vec<char> = pattern.chars().map(lower).collect();
+    let text: Vec<char> = text.chars().collect();
+    let lowered: Vec<char> = text.iter().map(|c| lower(*c)).collect();
+
+    if pattern.is_empty() {
+        return Some(Match {
+            score: 0,
+            indices: vec![],
+        });
+    }
+
+    if pattern.len() > text.len() {
+        return None;
+    }
+
+    // best[i][j] is the best score with pattern[i] matched at text[j], and
+    // where pattern[i - 1] was matched
+    let mut best: Vec<Vec<Option<(i64, usize)>>> = vec![vec![None; text.len()]; pattern.len()];
+
+    for (i, p) in pattern.iter().enumerate() {
+        // the best previous match far enough back that the gap penalty is
+        // maxed out, so we only look closely at the last few
+        let mut far: Option<(i64, usize)> = None;
+
+        for (j, c) in lowered.iter().enumerate().skip(i) {
+            if i > 0 && j >= MAX_GAP + i {
+                let k = j - MAX_GAP - 1;
+
+                if let Some((prev, _)) = best[i - 1][k] {
+                    if far.map_or(true, |(f, _)| prev > f) {
+                        far = Some((prev, k));
+                    }
+                }
+            }
+
+            if c != p {
+                continue;
+            }
+
+            let base = MATCH + if word_start(&text, j) { WORD_START } else { 0 };
+
+            if i == 0 {
+                best[i][j] = Some((base - (j as i64).min(MAX_GAP_PENALTY), 0));
+                continue;
+            }
+
+            if let Some((prev, k)) = far {
+                best[i][j] = Some((prev + base - MAX_GAP_PENALTY, k));
+            }
+
+            for k in j.saturating_sub(MAX_GAP).max(i - 1)..j {
+                let Some((prev, _)) = best[i - 1][k] else {
+                    continue;
+                };
+
+                let score = if k + 1 == j {
+                    prev + base + CONSECUTIVE
+                } else {
+                    prev + base - ((j - k - 1) as i64).min(MAX_GAP_PENALTY)
+                };
+
+                if best[i][j].map_or(true, |(s, _)| score > s) {
+                    best[i][j] = Some((score, k));
+                }
+            }
+        }
+    }
+
+    let last = pattern.len() - 1;
+
+    let (mut j, (score, _)) = best[last]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, b)| b.map(|b| (j, b)))
+        .max_by_key(|(j, (score, _))| (*score, std::cmp::Reverse(*j)))?;
+
+    let mut indices = vec![j];
+
+    for i in (1..=last).rev() {
+        j = best[i][j].expect("matched characters have scores").1;
+        indices.push(j);
+    }
+
+    indices.reverse();
+
+    Some(Match { score, indices })
+}
+
+// lowercase, but only if it stays a single character, so indices line up
+fn lower(c: char) -> char {
+    let mut lowered = c.to_lowercase();
+
+    match (lowered.next(), lowered.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+fn word_start(text: &[char], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+
+    let (prev, current) = (text[i - 1], text[i]);
+
+    !prev.is_alphanumeric() || (prev.is_lowercase() && current.is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("rst", "Rust").is_some());
+        assert!(fuzzy_match("tsr", "Rust").is_none());
+        assert_eq!(fuzzy_match("", "Rust").unwrap().score, 0);
+
+        // word starts beat the middle of words
+        let m = fuzzy_match("mr", "Matrix Rust").unwrap();
+        assert_eq!(m.indices, vec![0, 7]);
+
+        // and runs beat scattered letters
+        let run = fuzzy_match("rust", "#rust:matrix.org").unwrap();
+        let scattered = fuzzy_match("rust", "Random Users Talk").unwrap();
+        assert!(run.score > scattered.score);
+        assert_eq!(run.indices, vec![1, 2, 3, 4]);
+
+        // however far apart
+        let m = fuzzy_match("ab", &format!("a{}b", "x".repeat(20))).unwrap();
+        assert_eq!(m.indices, vec![0, 21]);
+    }
+}
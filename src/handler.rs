@@ -1,40 +1,98 @@
 use crate::app::{App, Popup};
-use crate::matrix::matrix::format_emojis;
-use crate::widgets::confirm::{Confirm, ConfirmBehavior};
+use crate::matrix::calls::{ended_call, CallWidget, IncomingCall};
+use crate::matrix::matrix::Outgoing;
+use crate::matrix::media::gif_link;
+use crate::matrix::register::RegistrationStep;
+use crate::matrix::roomcache::{Away, DecoratedRoom};
+use crate::settings::{device_name, is_archived, presentation_mode, set_setting};
+use crate::spawn::{EditorTarget, PickTarget};
+use crate::widgets::confirm::Confirm;
+use crate::widgets::devices::{Devices, UnverifiedDevice};
 use crate::widgets::error::Error;
 use crate::widgets::help::Help;
+use crate::widgets::knock::Knock;
+use crate::widgets::knocks::{Knocker, Knocks};
+use crate::widgets::logs::Logs;
+use crate::widgets::message::{Quote, Trust};
+use crate::widgets::pickfile::PickFile;
+use crate::widgets::profile::{confirm_avatar, Profile};
 use crate::widgets::progress::Progress;
+use crate::widgets::receipts::Receipts;
+use crate::widgets::register::RegisterStep;
+use crate::widgets::rename::{Rename, Renaming};
 use crate::widgets::rooms::{sort_rooms, Rooms};
 use crate::widgets::signin::Signin;
+use crate::widgets::state::{State, StateEntry};
+use crate::widgets::upload::Upload;
+use crate::widgets::verify::{Step, Verify};
 use crate::widgets::EventResult;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::error;
 use ruma::events::receipt::ReceiptEventContent;
-use ruma::OwnedUserId;
+use ruma::matrix_uri::MatrixId;
+use ruma::presence::PresenceState;
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomOrAliasId, OwnedUserId};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::PathBuf;
 
-use crate::event::EventHandler;
-use matrix_sdk::encryption::verification::{Emoji, SasVerification};
+use crate::event::{Event, EventHandler};
+use matrix_sdk::encryption::verification::{SasVerification, VerificationRequest};
+use matrix_sdk::reqwest::Url;
 use matrix_sdk::room::{Room, RoomMember};
 use ruma::events::AnyTimelineEvent;
 
 #[derive(Clone, Debug)]
 pub enum MatuiEvent {
+    Away(Away),
+    CachedTimeline(Room, Vec<AnyTimelineEvent>),
+    Calls(Room, Vec<CallWidget>),
     Confirm(String, String),
-    Error(String),
+    ConnectionState(ConnectionState),
+    DeviceWarning(Room, Vec<UnverifiedDevice>, Outgoing),
+    EmotesLoaded(Room),
+    IncomingCall(IncomingCall),
+    JoinPrompt(OwnedRoomOrAliasId),
+    KnockPrompt(OwnedRoomOrAliasId),
+    Knocks(Room, String, Vec<Knocker>),
+    Error(String, Option<String>),
     LoginComplete,
+    LoginFailed(String),
     LoginRequired,
     LoginStarted,
     ProgressStarted(String, u64),
     ProgressComplete,
+    Quote(Room, OwnedEventId, Option<(OwnedUserId, String)>),
     Receipt(Room, ReceiptEventContent),
+    RegistrationStep(RegistrationStep, Option<String>),
+    RoomCacheUpdated(DecoratedRoom),
     RoomMember(Room, RoomMember),
+    RoomMembers(Room, Vec<RoomMember>),
+    RoomState(String, Vec<StateEntry>),
+    Navigate(Room, Option<OwnedEventId>),
+    OpenLink(MatrixId),
+    Notice(String, String),
+    Presence(OwnedUserId, PresenceState),
+    Profile(Profile),
     RoomSelected(Room),
+    SessionExpired(String, bool),
+    SettingsChanged,
     SyncComplete,
     SyncStarted(SyncType),
     Timeline(AnyTimelineEvent),
     TimelineBatch(Batch),
+    TimelineGap(Room, String, MilliSecondsSinceUnixEpoch),
+    Trust(Room, OwnedEventId, Trust),
     Typing(Room, Vec<OwnedUserId>),
-    VerificationStarted(SasVerification, [Emoji; 7]),
-    VerificationCompleted,
+    VerificationRequested(VerificationRequest),
+    VerificationStarted(SasVerification),
+    VerificationStep(Step),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Online,
+    Offline,
 }
 
 #[derive(Clone, Debug)]
@@ -48,29 +106,70 @@ pub struct Batch {
     pub room: Room,
     pub events: Vec<AnyTimelineEvent>,
     pub cursor: Option<String>,
+
+    // the gap token this page was fetched from, if it was filling one
+    pub gap: Option<String>,
+
+    // how far to trust whatever was encrypted
+    pub trust: HashMap<OwnedEventId, Trust>,
 }
 
 pub fn handle_app_event(event: MatuiEvent, app: &mut App) {
     match event {
+        MatuiEvent::Away(away) => app.away_event(away),
         MatuiEvent::Confirm(header, msg) => {
-            app.set_popup(Popup::Error(Error::with_heading(header, msg)));
+            app.push_popup(Popup::Error(Error::with_heading(header, msg)));
+        }
+        MatuiEvent::ConnectionState(state) => {
+            if let Some(c) = &mut app.chat {
+                c.connection_event(state);
+            }
+        }
+        MatuiEvent::Error(msg, details) => {
+            // whatever was going on isn't anymore
+            close_progress(app);
+            app.push_popup(Popup::Error(Error::new(msg).details(details)));
         }
-        MatuiEvent::Error(msg) => {
-            app.set_popup(Popup::Error(Error::new(msg)));
+        MatuiEvent::Notice(heading, msg) => {
+            app.push_popup(Popup::Error(Error::with_heading(heading, msg)));
         }
         MatuiEvent::LoginRequired => {
-            app.set_popup(Popup::Signin(Signin::default()));
+            // a failed login or registration can land us back here
+            app.close_popups(|p| matches!(p, Popup::Signin(_)));
+            app.push_popup(Popup::Signin(Signin::default()));
         }
-        MatuiEvent::LoginStarted => {
-            app.set_popup(Popup::Progress(Progress::new("Logging in", 0)));
+        MatuiEvent::SessionExpired(user_id, soft_logout) => {
+            app.close_popups(|p| matches!(p, Popup::Signin(_)));
+            app.push_popup(Popup::Signin(Signin::expired(&user_id, soft_logout)));
         }
-        MatuiEvent::LoginComplete => {
-            app.popup = None;
+        MatuiEvent::LoginStarted => show_progress(app, Progress::new("Logging in", 0)),
+        MatuiEvent::LoginComplete => app.close_all_popups(),
+        MatuiEvent::LoginFailed(msg) => {
+            close_progress(app);
+
+            // keep the form, and show what went wrong right on it
+            let signin = app.popups.iter_mut().find_map(|p| match p {
+                Popup::Signin(s) => Some(s),
+                _ => None,
+            });
+
+            match signin {
+                Some(signin) => signin.login_failed(msg),
+                None => app.push_popup(Popup::Error(Error::new(msg))),
+            }
         }
-        MatuiEvent::ProgressStarted(msg, delay) => {
-            app.set_popup(Popup::Progress(Progress::new(&msg, delay)))
+        MatuiEvent::ProgressStarted(msg, delay) => show_progress(app, Progress::new(&msg, delay)),
+        MatuiEvent::ProgressComplete => close_progress(app),
+        MatuiEvent::RegistrationStep(step, error) => {
+            // only send them off the first time around
+            if let (RegistrationStep::Browser(url), None) = (&step, &error) {
+                if let Err(e) = open::that(url) {
+                    error!("could not open browser: {}", e);
+                }
+            }
+
+            app.push_popup(Popup::RegisterStep(RegisterStep::new(step, error)));
         }
-        MatuiEvent::ProgressComplete => app.popup = None,
 
         // Let the chat update when we learn about room membership
         MatuiEvent::RoomMember(room, member) => {
@@ -78,18 +177,117 @@ pub fn handle_app_event(event: MatuiEvent, app: &mut App) {
                 c.room_member_event(room, member);
             }
         }
+        MatuiEvent::Navigate(room, event) => {
+            app.select_room(room);
+
+            if let (Some(chat), Some(event)) = (&mut app.chat, event) {
+                chat.jump_to(event);
+            }
+        }
+        MatuiEvent::OpenLink(id) => {
+            // handed to us by another run; if we haven't synced, that will
+            // get to it
+            if app.matrix.is_synced() {
+                app.matrix.open_link(id);
+            } else {
+                app.pending_link = Some(id);
+            }
+        }
+        MatuiEvent::RoomState(name, entries) => {
+            app.push_popup(Popup::State(State::new(name, entries)));
+        }
+        MatuiEvent::JoinPrompt(target) => {
+            let confirm = Confirm::new(
+                "Join Room".to_string(),
+                format!("You aren't in {} yet. Join it?", target),
+                "Join".to_string(),
+                "Cancel".to_string(),
+                move |app| app.matrix.join_room(target),
+            );
+
+            app.push_popup(Popup::Confirm(confirm));
+        }
+        MatuiEvent::DeviceWarning(room, devices, outgoing) => {
+            app.push_popup(Popup::Devices(Devices::new(room, outgoing, devices)));
+        }
+        MatuiEvent::KnockPrompt(target) => {
+            app.push_popup(Popup::Knock(Knock::new(target)));
+        }
+        MatuiEvent::Knocks(room, name, knockers) => {
+            app.push_popup(Popup::Knocks(Knocks::new(room, name, knockers)));
+        }
+        MatuiEvent::Profile(profile) => {
+            app.push_popup(Popup::Profile(profile));
+        }
+        MatuiEvent::CachedTimeline(room, events) => {
+            if let Some(c) = &mut app.chat {
+                c.cached_event(room, events);
+            }
+        }
+        MatuiEvent::Calls(room, calls) => {
+            if let Some(c) = &mut app.chat {
+                c.calls_event(room, calls);
+            }
+        }
+        MatuiEvent::IncomingCall(call) => {
+            let IncomingCall {
+                room,
+                call_id,
+                version,
+                caller,
+            } = call;
+
+            // we can't do calls, but whatever's set to open matrix.to links
+            // might
+            let link = format!("https://matrix.to/#/{}", room.room_id());
+
+            let confirm = Confirm::new(
+                "Incoming Call".to_string(),
+                format!("{} is calling.", caller),
+                "Open in Browser".to_string(),
+                "Reject".to_string(),
+                move |_| {
+                    if let Err(e) = open::that(link) {
+                        error!("could not open browser: {}", e);
+                    }
+                },
+            )
+            .with_key(call_id.to_string());
+
+            let confirm = confirm.on_no(move |app| app.matrix.reject_call(room, call_id, version));
+
+            app.push_popup(Popup::Confirm(confirm));
+        }
+        MatuiEvent::EmotesLoaded(room) => {
+            if let Some(c) = &mut app.chat {
+                c.emotes_loaded_event(room);
+            }
+        }
+        MatuiEvent::RoomMembers(room, members) => {
+            if let Some(c) = &mut app.chat {
+                c.room_members_event(room, members);
+            }
+        }
+        MatuiEvent::RoomCacheUpdated(room) => {
+            for popup in app.popups.iter_mut() {
+                if let Popup::Rooms(rooms) = popup {
+                    rooms.room_cache_event(room.clone());
+                }
+            }
+        }
+        MatuiEvent::Presence(id, presence) => app.matrix.presence_event(&id, presence),
         MatuiEvent::RoomSelected(room) => app.select_room(room),
+        MatuiEvent::SettingsChanged => app.settings_changed(),
         MatuiEvent::SyncStarted(st) => {
             match st {
-                SyncType::Initial => app.set_popup(Popup::Progress(Progress::new(
-                    "Performing initial sync.",
-                    0,
-                ))),
-                SyncType::Latest => app.set_popup(Popup::Progress(Progress::new("Syncing", 0))),
+                SyncType::Initial => {
+                    show_progress(app, Progress::new("Performing initial sync.", 0))
+                }
+                SyncType::Latest => show_progress(app, Progress::new("Syncing", 0)),
             };
         }
         MatuiEvent::SyncComplete => {
-            app.popup = None;
+            close_progress(app);
 
             // now we can sync forever
             app.matrix.sync();
@@ -98,11 +296,29 @@ pub fn handle_app_event(event: MatuiEvent, app: &mut App) {
             let mut rooms = app.matrix.fetch_rooms();
             sort_rooms(&mut rooms);
 
-            if let Some(room) = rooms.first() {
+            // skipping anything archived, if we can
+            let first = rooms
+                .iter()
+                .find(|r| !is_archived(r.room_id()))
+                .or(rooms.first());
+
+            if let Some(room) = first {
                 app.select_room(room.inner.clone())
             }
+
+            // unless we were started with a link to somewhere else
+            if let Some(id) = app.pending_link.take() {
+                app.matrix.open_link(id);
+            }
         }
         MatuiEvent::Timeline(event) => {
+            // stop ringing once the call's over, or answered somewhere else
+            if let Some(call_id) = ended_call(&event) {
+                app.close_popups(
+                    |p| matches!(p, Popup::Confirm(c) if c.key() == Some(call_id.as_str())),
+                );
+            }
+
             if let Some(c) = &mut app.chat {
                 c.timeline_event(event.clone());
             }
@@ -116,7 +332,29 @@ pub fn handle_app_event(event: MatuiEvent, app: &mut App) {
                 c.batch_event(batch);
             }
         }
+        MatuiEvent::TimelineGap(room, token, before) => {
+            if let Some(c) = &mut app.chat {
+                c.gap_event(room, token, before);
+            }
+        }
+        MatuiEvent::Trust(room, id, trust) => {
+            if let Some(c) = &mut app.chat {
+                c.trust_event(room, id, trust);
+            }
+        }
+        MatuiEvent::Quote(room, id, quote) => {
+            let quote = match quote {
+                Some((sender, body)) => Quote::new(sender, &body),
+                None => Quote::unavailable(),
+            };
+
+            if let Some(c) = &mut app.chat {
+                c.quote_event(room, id, quote);
+            }
+        }
         MatuiEvent::Typing(room, ids) => {
+            app.matrix.typing_event(room.clone(), ids.clone());
+
             if let Some(c) = &mut app.chat {
                 c.typing_event(room, ids);
             }
@@ -126,33 +364,47 @@ pub fn handle_app_event(event: MatuiEvent, app: &mut App) {
                 c.receipt_event(&room, &content);
             }
 
-            app.receipts.push_back((room, content));
-
-            if app.receipts.len() > 500 {
-                app.receipts.pop_front();
+            match app.receipts.entry(room.room_id().to_owned()) {
+                Entry::Occupied(mut entry) => Receipts::merge_content(entry.get_mut(), content),
+                Entry::Vacant(entry) => {
+                    entry.insert(content);
+                }
             }
         }
-        MatuiEvent::VerificationStarted(sas, emoji) => {
-            app.sas = Some(sas);
-
-            app.set_popup(Popup::Confirm(Confirm::new(
-                "Verify".to_string(),
-                format!(
-                    "Do these emojis match your other session?\n\n{}",
-                    format_emojis(emoji)
-                ),
-                "Yes".to_string(),
-                "No".to_string(),
-                ConfirmBehavior::Verification,
-            )));
+        MatuiEvent::VerificationRequested(request) => {
+            // one at a time
+            app.close_popups(|p| matches!(p, Popup::Verify(_)));
+            app.push_popup(Popup::Verify(Verify::from_request(request)));
         }
-        MatuiEvent::VerificationCompleted => {
-            app.popup = None;
-            app.sas = None;
+        MatuiEvent::VerificationStarted(sas) => match verify_popup(app) {
+            Some(verify) => verify.set_sas(sas),
+            None => app.push_popup(Popup::Verify(Verify::from_sas(sas))),
+        },
+        MatuiEvent::VerificationStep(step) => {
+            if let Some(verify) = verify_popup(app) {
+                verify.advance(step);
+            }
         }
     }
 }
 
+// Only one spinner at a time, on top of everything else.
+fn show_progress(app: &mut App, progress: Progress) {
+    close_progress(app);
+    app.push_popup(Popup::Progress(progress));
+}
+
+fn close_progress(app: &mut App) {
+    app.close_popups(|p| matches!(p, Popup::Progress(_)));
+}
+
+fn verify_popup(app: &mut App) -> Option<&mut Verify> {
+    app.popups.iter_mut().find_map(|p| match p {
+        Popup::Verify(v) => Some(v),
+        _ => None,
+    })
+}
+
 pub fn handle_key_event(
     key_event: KeyEvent,
     app: &mut App,
@@ -160,16 +412,31 @@ pub fn handle_key_event(
 ) -> anyhow::Result<()> {
     // ctrl-c always quits
     if key_event.modifiers == KeyModifiers::CONTROL && key_event.code == KeyCode::Char('c') {
-        app.running = false;
+        app.quit();
+        return Ok(());
+    }
+
+    // ctrl-z suspends, just like it would outside of raw mode
+    if key_event.modifiers == KeyModifiers::CONTROL && key_event.code == KeyCode::Char('z') {
+        App::get_sender().send(Event::Suspend)?;
+        return Ok(());
+    }
+
+    // ctrl-p hides anything private, from anywhere, before someone sees it
+    if key_event.modifiers == KeyModifiers::CONTROL && key_event.code == KeyCode::Char('p') {
+        if let Err(e) = set_setting("presentation_mode", !presentation_mode()) {
+            app.push_popup(Popup::Error(Error::from_anyhow(&e)));
+        }
+
         return Ok(());
     }
 
     // consider any key event also a sign of "focus"
     handle_focus_event(app);
 
-    // give the popup first crack at the event
-    let result = if let Some(w) = &mut app.popup {
-        w.key_event(&key_event)
+    // give the top popup first crack at the event
+    let result = if let Some(w) = app.top_popup() {
+        w.key_event(&key_event, handler)
     } else {
         EventResult::Ignored
     };
@@ -184,16 +451,36 @@ pub fn handle_key_event(
         KeyCode::Char(' ') => {
             let current = app.chat.as_ref().map(|c| c.room());
 
-            app.set_popup(Popup::Rooms(Rooms::new(app.matrix.clone(), current)));
+            app.push_popup(Popup::Rooms(Rooms::new(app.matrix.clone(), current)));
 
             return Ok(());
         }
         KeyCode::Char('q') => {
-            app.running = false;
+            app.quit();
             return Ok(());
         }
         KeyCode::Char('?') => {
-            app.set_popup(Popup::Help(Help));
+            app.push_popup(Popup::Help(Help::default()));
+            return Ok(());
+        }
+        KeyCode::Char('A') => {
+            app.show_mentions();
+            return Ok(());
+        }
+        KeyCode::Char('L') => {
+            app.push_popup(Popup::Logs(Logs::new()));
+            return Ok(());
+        }
+        KeyCode::Char('D') => {
+            app.push_popup(Popup::Rename(Rename::new(Renaming::Device, &device_name())));
+            return Ok(());
+        }
+        KeyCode::Char('M') => {
+            app.matrix.merge_settings();
+            return Ok(());
+        }
+        KeyCode::Char('P') => {
+            app.matrix.fetch_profile();
             return Ok(());
         }
         _ => {}
@@ -204,7 +491,7 @@ pub fn handle_key_event(
         match w.key_event(&key_event, handler) {
             Ok(r) => r,
             Err(err) => {
-                app.set_popup(Popup::Error(Error::new(err.to_string())));
+                app.push_popup(Popup::Error(Error::from_anyhow(&err)));
                 return Ok(());
             }
         }
@@ -219,6 +506,113 @@ pub fn handle_key_event(
     Ok(())
 }
 
+/// Hand pasted text to whatever has an input open.
+pub fn handle_paste_event(text: String, app: &mut App) {
+    // a paste is text, not a string of commands, so only inputs get it
+    let result = match app.top_popup() {
+        Some(w) => w.paste(&text),
+        None => EventResult::Ignored,
+    };
+
+    if let EventResult::Consumed(f) = result {
+        f(app);
+    }
+}
+
+/// Do whatever the editor was opened for, now that it's closed.
+pub fn handle_editor_closed(
+    target: EditorTarget,
+    result: Result<Option<String>, String>,
+    app: &mut App,
+) {
+    let text = match result {
+        Ok(Some(text)) => text,
+        Ok(None) if matches!(target, EditorTarget::View) => return,
+        Ok(None) => {
+            app.push_popup(Popup::Error(Error::new(
+                "Ignoring blank message.".to_string(),
+            )));
+            return;
+        }
+        Err(e) => {
+            error!("could not read from editor: {}", e);
+            app.push_popup(Popup::Error(
+                Error::new("Couldn't read from editor.".to_string()).details(Some(e)),
+            ));
+            return;
+        }
+    };
+
+    match target {
+        EditorTarget::Compose(room) => match gif_link(&text) {
+            Some(link) => offer_gif(app, room, text, link),
+            None => app.matrix.send_text_message(room, text),
+        },
+        EditorTarget::Reply(room, id) => app.matrix.send_reply(room, text, id),
+        EditorTarget::Edit(room, id, in_reply_to) => {
+            app.matrix.replace_event(room, id, text, in_reply_to)
+        }
+        EditorTarget::View => {}
+    }
+}
+
+/// Offer to do something with the files that were picked, or ask for the
+/// paths if there was no way to pick them.
+pub fn handle_files_picked(
+    target: PickTarget,
+    result: Result<Option<Vec<PathBuf>>, String>,
+    app: &mut App,
+) {
+    let paths = match result {
+        Ok(paths) => paths,
+        Err(e) => {
+            error!("could not pick files: {}", e);
+            app.push_popup(Popup::Error(
+                Error::new("Couldn't pick files.".to_string()).details(Some(e)),
+            ));
+            return;
+        }
+    };
+
+    match target {
+        PickTarget::Upload(room, name) => match paths {
+            Some(paths) if paths.is_empty() => {}
+            Some(paths) => app.push_popup(Popup::Upload(Upload::new(room, name, paths))),
+            None => app.push_popup(Popup::PickFile(PickFile::new(room, name))),
+        },
+        PickTarget::Avatar => {
+            let popup = match paths {
+                Some(paths) => match paths.into_iter().next() {
+                    Some(path) => confirm_avatar(path),
+                    None => return,
+                },
+                None => Popup::PickFile(PickFile::avatar()),
+            };
+
+            // in place of the profile, if it's still open
+            app.close_popups(|p| matches!(p, Popup::Profile(_)));
+            app.push_popup(popup);
+        }
+    }
+}
+
+// A link to a GIF only plays in clients that preview links, and only after
+// asking the site for it, so offer to send the GIF itself.
+fn offer_gif(app: &mut App, room: Room, text: String, link: Url) {
+    let other = room.clone();
+
+    let confirm = Confirm::new(
+        "Send a GIF".to_string(),
+        "Upload the GIF instead of sending the link?".to_string(),
+        "Upload".to_string(),
+        "Send Link".to_string(),
+        move |app| app.matrix.send_gif(room, link),
+    )
+    .on_no(move |app| app.matrix.send_text_message(other, text));
+
+    app.push_popup(Popup::Confirm(confirm));
+}
+
 pub fn handle_focus_event(app: &mut App) {
     app.matrix.focus_event();
 
@@ -230,6 +624,12 @@ pub fn handle_focus_event(app: &mut App) {
     }
 }
 
+pub fn handle_resize_event(app: &mut App) {
+    if let Some(chat) = &mut app.chat {
+        chat.resize_event();
+    }
+}
+
 pub fn handle_blur_event(app: &mut App) {
     app.matrix.blur_event();
 
@@ -0,0 +1,148 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use log::{error, info};
+use matrix_sdk::ruma::exports::serde_json::{self, json, Value};
+use matrix_sdk::Client;
+use ruma::events::AnyTimelineEvent;
+
+use crate::matrix::notify::Notify;
+use crate::settings::hook_command;
+use crate::widgets::message::Message;
+
+/// Things that can happen that a user might want to script around. Each one
+/// maps to a key in the `[hooks]` table of the config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    Message,
+    Mention,
+    Invite,
+    Verification,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::Message => "message",
+            Hook::Mention => "mention",
+            Hook::Invite => "invite",
+            Hook::Verification => "verification",
+        }
+    }
+}
+
+/// Run the configured command for the hook, if there is one, with the
+/// payload as JSON on stdin. We don't wait around for it.
+pub fn run_hook(hook: Hook, payload: Value) {
+    let Some(command) = hook_command(hook.name()) else {
+        return;
+    };
+
+    thread::spawn(move || {
+        if let Err(e) = run_command(&command, &payload) {
+            error!("{} hook failed: {}", hook.name(), e);
+        }
+    });
+}
+
+/// Fire the message and mention hooks for messages from other people.
+pub async fn timeline_hooks(client: &Client, event: &AnyTimelineEvent) {
+    let Some(message) = Message::try_from(event, true) else {
+        return;
+    };
+
+    let Some(me) = client.user_id() else {
+        return;
+    };
+
+    if message.sender.id == me {
+        return;
+    }
+
+    let Some(room) = client.get_room(&message.room_id) else {
+        return;
+    };
+
+    let sender = room
+        .get_member_no_sync(&message.sender.id)
+        .await
+        .ok()
+        .flatten();
+
+    let sender_name = match &sender {
+        Some(member) => member.name().to_string(),
+        None => message.sender.id.to_string(),
+    };
+
+    // a room mention only counts if the sender is allowed to make one
+    let room_mention =
+        message.room_mention && sender.map_or(false, |m| m.can_trigger_room_notification());
+
+    let room_name = room
+        .compute_display_name()
+        .await
+        .map(|n| n.to_string())
+        .unwrap_or_default();
+
+    let payload = json!({
+        "room_id": message.room_id,
+        "room_name": room_name,
+        "event_id": message.id,
+        "sender": message.sender.id,
+        "sender_name": sender_name,
+        "body": message.display(),
+    });
+
+    // the same as for notifications, so the two agree
+    if room_mention || Notify::mentions_me(event, client, &room).await {
+        run_hook(Hook::Mention, payload.clone());
+    }
+
+    run_hook(Hook::Message, payload);
+}
+
+fn run_command(command: &str, payload: &Value) -> anyhow::Result<()> {
+    info!("running hook: {}", command);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(serde_json::to_string(payload)?.as_bytes())?;
+    }
+
+    let status = child.wait()?;
+
+    if !status.success() {
+        error!("hook exited with {}", status);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out.json");
+
+        run_command(
+            &format!("cat > {}", out.display()),
+            &json!({ "body": "hello" }),
+        )
+        .unwrap();
+
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(out).unwrap()).unwrap();
+
+        assert_eq!(written["body"], "hello");
+    }
+}
@@ -0,0 +1,273 @@
+/// Where a key does its thing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Global,
+    Chat,
+    Rooms,
+    Popups,
+}
+
+impl Section {
+    pub const ALL: [Section; 4] = [
+        Section::Global,
+        Section::Chat,
+        Section::Rooms,
+        Section::Popups,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Section::Global => "Global",
+            Section::Chat => "Chat",
+            Section::Rooms => "Room Switcher",
+            Section::Popups => "Popups",
+        }
+    }
+}
+
+pub struct Binding {
+    pub section: Section,
+    pub keys: &'static str,
+    pub description: &'static str,
+}
+
+const fn bind(section: Section, keys: &'static str, description: &'static str) -> Binding {
+    Binding {
+        section,
+        keys,
+        description,
+    }
+}
+
+/// Every key we respond to. The help screen is built from this, so add new
+/// keys here when they're handled.
+pub const BINDINGS: &[Binding] = &[
+    bind(Section::Global, "Space", "Show the room switcher."),
+    bind(Section::Global, "?", "Show this helper."),
+    bind(Section::Global, "L", "Show the most recent log lines."),
+    bind(
+        Section::Global,
+        "A",
+        "Show what mentioned me while I was away.",
+    ),
+    bind(Section::Global, "D", "Rename this device."),
+    bind(
+        Section::Global,
+        "P",
+        "Show who I am, to change my name or avatar.",
+    ),
+    bind(
+        Section::Global,
+        "M",
+        "Merge settings with the ones saved to the account.",
+    ),
+    bind(Section::Global, "q", "Quit."),
+    bind(Section::Global, "Ctrl+C", "Quit, from anywhere."),
+    bind(
+        Section::Global,
+        "Ctrl+P",
+        "Presentation mode: hide notifications and previews.",
+    ),
+    bind(Section::Global, "Ctrl+Z", "Drop back to the shell."),
+    bind(Section::Chat, "j*", "Select one line down."),
+    bind(Section::Chat, "k*", "Select one line up."),
+    bind(
+        Section::Chat,
+        "i",
+        "Create a new message using the external editor.",
+    ),
+    bind(
+        Section::Chat,
+        "Enter",
+        "Open the selected message (again for the full image).",
+    ),
+    bind(
+        Section::Chat,
+        "s",
+        "Save the selected message (images and videos).",
+    ),
+    bind(
+        Section::Chat,
+        "c",
+        "Edit the selected message in the external editor.",
+    ),
+    bind(Section::Chat, "r", "React to the selected message."),
+    bind(Section::Chat, "R", "Reply to the selected message."),
+    bind(Section::Chat, "o", "Jump to the message being replied to."),
+    bind(
+        Section::Chat,
+        "w",
+        "Show who has seen the selected message.",
+    ),
+    bind(
+        Section::Chat,
+        "v",
+        "View the selected message, and its edits.",
+    ),
+    bind(
+        Section::Chat,
+        "x",
+        "Expand (or collapse) a long message in place.",
+    ),
+    bind(Section::Chat, "V", "View the current room."),
+    bind(
+        Section::Chat,
+        "I",
+        "Show the room's addresses and settings, to share it.",
+    ),
+    bind(
+        Section::Chat,
+        "C",
+        "Join the call in this room, in a browser.",
+    ),
+    bind(
+        Section::Chat,
+        "F",
+        "Switch between a centered and a full-width chat.",
+    ),
+    bind(
+        Section::Chat,
+        "Z",
+        "Hide (or show) the room header, for more messages.",
+    ),
+    bind(Section::Chat, "u", "Upload a file."),
+    bind(Section::Chat, "S", "Inspect the state of the current room."),
+    bind(
+        Section::Chat,
+        "K",
+        "See who's knocking to get into this room.",
+    ),
+    bind(
+        Section::Chat,
+        "E",
+        "Export the history of the current room.",
+    ),
+    bind(Section::Chat, "m", "Load messages missed while offline."),
+    bind(Section::Chat, "dd", "Delete the selected message."),
+    bind(Section::Rooms, "Enter", "Switch to the selected room."),
+    bind(Section::Rooms, "Ctrl+S", "Change how rooms are sorted."),
+    bind(
+        Section::Rooms,
+        "Ctrl+U",
+        "Only show rooms with unread messages.",
+    ),
+    bind(Section::Rooms, "Ctrl+A", "Archive (or restore) the room."),
+    bind(Section::Rooms, "Ctrl+X", "Switch to and from the archive."),
+    bind(Section::Popups, "Esc", "Close, or stop searching."),
+    bind(
+        Section::Popups,
+        "j/k*",
+        "Scroll, or pick the next/previous item.",
+    ),
+    bind(
+        Section::Popups,
+        "/",
+        "Search the pager or the reaction picker.",
+    ),
+    bind(Section::Popups, "n/N", "Next/previous match in the pager."),
+    bind(Section::Popups, "gg/G", "Top/bottom of the pager."),
+    bind(
+        Section::Popups,
+        "e",
+        "Open the pager text in the external editor.",
+    ),
+    bind(
+        Section::Popups,
+        "d",
+        "Remove a file before uploading, or show error details.",
+    ),
+    bind(
+        Section::Popups,
+        "c",
+        "Add a caption to a file before uploading.",
+    ),
+    bind(Section::Popups, "y", "Copy an error, for a bug report."),
+    bind(
+        Section::Popups,
+        "a/l",
+        "Copy a room's address, or a link to it.",
+    ),
+    bind(
+        Section::Popups,
+        "e",
+        "Edit who can join a room, from its info.",
+    ),
+    bind(
+        Section::Popups,
+        "h/l",
+        "Change the selected setting, like a join rule.",
+    ),
+    bind(
+        Section::Popups,
+        "a/d",
+        "Let someone knocking in, or turn them away.",
+    ),
+    bind(
+        Section::Popups,
+        "n/a",
+        "Change my display name, or avatar, from my profile.",
+    ),
+    bind(
+        Section::Popups,
+        "y/n",
+        "Say if the emoji match, when verifying a session.",
+    ),
+    bind(
+        Section::Popups,
+        "b",
+        "Block an unverified device before sending.",
+    ),
+    bind(
+        Section::Popups,
+        "Tab",
+        "Complete a path in the file picker.",
+    ),
+    bind(
+        Section::Popups,
+        "Ctrl+R",
+        "Show or hide a password as it's typed.",
+    ),
+];
+
+/// The bindings that mention every word of the filter, by key or by
+/// description.
+pub fn matching(filter: &str) -> Vec<&'static Binding> {
+    let words: Vec<String> = filter.split_whitespace().map(str::to_lowercase).collect();
+
+    BINDINGS
+        .iter()
+        .filter(|b| {
+            let haystack =
+                format!("{} {} {}", b.keys, b.description, b.section.title()).to_lowercase();
+
+            words.iter().all(|w| haystack.contains(w.as_str()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching() {
+        assert_eq!(matching("").len(), BINDINGS.len());
+        assert!(matching("react").iter().any(|b| b.keys == "r"));
+        assert!(matching("room switcher").iter().all(|b| b.keys != "r"));
+        assert!(matching("nothing like this").is_empty());
+    }
+
+    // the README table shouldn't drift from what we actually handle
+    #[test]
+    fn test_readme() {
+        let readme = include_str!("../README.md");
+
+        for binding in BINDINGS
+            .iter()
+            .filter(|b| matches!(b.section, Section::Global | Section::Chat))
+        {
+            let row = format!("| {:<6} | {}", binding.keys, binding.description);
+            assert!(readme.contains(&row), "README is missing: {}", row);
+        }
+    }
+}
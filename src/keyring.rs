@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use zbus::blocking::Connection;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+// Just enough of the Secret Service API to stash a few strings.
+const SERVICE: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_IFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+const COLLECTION_IFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_IFACE: &str = "org.freedesktop.Secret.Item";
+
+/// A secret on the wire: session, parameters, value, content type.
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+/// A connection to the user's keyring, by way of the Secret Service.
+pub struct Keyring {
+    connection: Connection,
+    session: OwnedObjectPath,
+}
+
+impl Keyring {
+    pub fn connect() -> anyhow::Result<Keyring> {
+        let connection = Connection::session()?;
+
+        // "plain" means no transport encryption, which is fine on the
+        // session bus
+        let reply = connection.call_method(
+            Some(SERVICE),
+            SERVICE_PATH,
+            Some(SERVICE_IFACE),
+            "OpenSession",
+            &("plain", Value::from("")),
+        )?;
+
+        let (_, session): (OwnedValue, OwnedObjectPath) = reply.body()?;
+
+        Ok(Keyring {
+            connection,
+            session,
+        })
+    }
+
+    /// Look up a secret by name, for the given account.
+    pub fn get(&self, account: &str, name: &str) -> anyhow::Result<Option<String>> {
+        let Some(item) = self.find(account, name)? else {
+            return Ok(None);
+        };
+
+        let reply = self.connection.call_method(
+            Some(SERVICE),
+            item.as_str(),
+            Some(ITEM_IFACE),
+            "GetSecret",
+            &(&self.session),
+        )?;
+
+        let (_, _, value, _): Secret = reply.body()?;
+
+        Ok(Some(String::from_utf8(value)?))
+    }
+
+    /// Store a secret, replacing whatever was there before.
+    pub fn set(&self, account: &str, name: &str, value: &str) -> anyhow::Result<()> {
+        let mut properties: HashMap<&str, Value> = HashMap::new();
+
+        properties.insert(
+            "org.freedesktop.Secret.Item.Label",
+            Value::from(format!("Matui {} for {}", name, account)),
+        );
+
+        properties.insert(
+            "org.freedesktop.Secret.Item.Attributes",
+            Value::from(attributes(account, name)),
+        );
+
+        let secret: Secret = (
+            self.session.clone(),
+            vec![],
+            value.as_bytes().to_vec(),
+            "text/plain".to_string(),
+        );
+
+        let reply = self.connection.call_method(
+            Some(SERVICE),
+            COLLECTION_PATH,
+            Some(COLLECTION_IFACE),
+            "CreateItem",
+            &(properties, secret, true),
+        )?;
+
+        let (_, prompt): (OwnedObjectPath, OwnedObjectPath) = reply.body()?;
+
+        // we don't do interactive prompts
+        if prompt.as_str() != "/" {
+            bail!("The keyring is locked.");
+        }
+
+        Ok(())
+    }
+
+    fn find(&self, account: &str, name: &str) -> anyhow::Result<Option<OwnedObjectPath>> {
+        let reply = self.connection.call_method(
+            Some(SERVICE),
+            SERVICE_PATH,
+            Some(SERVICE_IFACE),
+            "SearchItems",
+            &(attributes(account, name)),
+        )?;
+
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = reply.body()?;
+
+        if let Some(item) = unlocked.into_iter().next() {
+            return Ok(Some(item));
+        }
+
+        if locked.is_empty() {
+            return Ok(None);
+        }
+
+        // try to unlock it, which works without a prompt in most setups
+        let reply = self.connection.call_method(
+            Some(SERVICE),
+            SERVICE_PATH,
+            Some(SERVICE_IFACE),
+            "Unlock",
+            &(locked),
+        )?;
+
+        let (unlocked, _): (Vec<OwnedObjectPath>, ObjectPath) = reply.body()?;
+
+        match unlocked.into_iter().next() {
+            Some(item) => Ok(Some(item)),
+            None => bail!("The keyring is locked."),
+        }
+    }
+}
+
+fn attributes(account: &str, name: &str) -> HashMap<String, String> {
+    HashMap::from([
+        ("application".to_string(), "matui".to_string()),
+        ("account".to_string(), account.to_string()),
+        ("name".to_string(), name.to_string()),
+    ])
+}
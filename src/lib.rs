@@ -23,6 +23,33 @@ pub mod matrix;
 
 pub mod settings;
 
+/// The documented config file new installs start with.
+pub mod defaults;
+
+/// Every key we handle, for the help screen.
+pub mod keybindings;
+
+/// Fuzzy matching, for finding things with a few letters.
+pub mod fuzzy;
+
+/// Logging to a file, and reading it back.
+pub mod logging;
+
+/// Notifications without the UI.
+pub mod daemon;
+
+/// User scripts, run when things happen.
+pub mod hooks;
+
+/// Secrets in the system keyring.
+pub mod keyring;
+
+/// Times and phrases, in the user's language.
+pub mod locale;
+
+/// Which reactions get used, and how much.
+pub mod usage;
+
 /// Using external apps to do our bidding
 pub mod spawn;
 pub mod video;
@@ -65,9 +92,14 @@ pub fn pretty_list(names: Vec<String>) -> String {
 }
 
 fn truncate(s: String, max_chars: usize) -> String {
-    match s.char_indices().nth(max_chars) {
-        None => s,
-        Some((idx, _)) => format!("{}…", &s[..(idx - 1)]),
+    if s.chars().count() <= max_chars {
+        return s;
+    }
+
+    // count by characters, so a name that isn't ASCII can't split one in half
+    match max_chars {
+        0 => String::new(),
+        n => format!("{}…", s.chars().take(n - 1).collect::<String>()),
     }
 }
 
@@ -120,11 +152,57 @@ impl KeyCombo {
     }
 }
 
+/// Holds on to the latest value it's given, letting at most one through
+/// per delay. Anything held back can be polled for later, or flushed.
+pub struct DelayTimer<T> {
+    last: Option<Instant>,
+    pending: Option<T>,
+    delay: Duration,
+}
+
+impl<T> DelayTimer<T> {
+    pub fn new(delay: Duration) -> Self {
+        DelayTimer {
+            last: None,
+            pending: None,
+            delay,
+        }
+    }
+
+    /// Record a new value, getting it right back if it can go out now.
+    pub fn record(&mut self, value: T) -> Option<T> {
+        self.pending = Some(value);
+        self.poll()
+    }
+
+    /// The pending value, if there is one and the delay has passed.
+    pub fn poll(&mut self) -> Option<T> {
+        if let Some(last) = self.last {
+            if last.elapsed() < self.delay {
+                return None;
+            }
+        }
+
+        self.flush()
+    }
+
+    /// The pending value, no matter how long it's been.
+    pub fn flush(&mut self) -> Option<T> {
+        let value = self.pending.take();
+
+        if value.is_some() {
+            self.last = Some(Instant::now());
+        }
+
+        value
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::{Duration, Instant};
 
-    use crate::KeyCombo;
+    use crate::{truncate, DelayTimer, KeyCombo};
 
     #[test]
     #[allow(clippy::bool_assert_comparison)]
@@ -168,4 +246,38 @@ mod tests {
         assert_eq!(combo.record('x'), false);
         assert_eq!(combo.record('c'), false);
     }
+
+    #[test]
+    fn it_delays_values() {
+        let mut timer = DelayTimer::new(Duration::from_secs(2));
+
+        assert_eq!(timer.record(1), Some(1));
+        assert_eq!(timer.record(2), None);
+        assert_eq!(timer.record(3), None);
+        assert_eq!(timer.poll(), None);
+
+        timer.last = Some(Instant::now() - Duration::from_secs(5));
+
+        assert_eq!(timer.poll(), Some(3));
+        assert_eq!(timer.poll(), None);
+    }
+
+    #[test]
+    fn it_flushes_values() {
+        let mut timer = DelayTimer::new(Duration::from_secs(2));
+
+        assert_eq!(timer.record(1), Some(1));
+        assert_eq!(timer.record(2), None);
+        assert_eq!(timer.flush(), Some(2));
+        assert_eq!(timer.flush(), None);
+    }
+
+    #[test]
+    fn it_truncates() {
+        assert_eq!(truncate("Matui".to_string(), 10), "Matui");
+        assert_eq!(truncate("Matui".to_string(), 5), "Matui");
+        assert_eq!(truncate("Matui".to_string(), 4), "Mat…");
+        assert_eq!(truncate("Ünïcødé".to_string(), 4), "Ünï…");
+        assert_eq!(truncate("Matui".to_string(), 0), "");
+    }
 }
@@ -0,0 +1,95 @@
+use std::time::{Duration, SystemTime};
+
+use timeago::languages::{boxup, english::English, IsolangLanguage};
+use timeago::{BoxedLanguage, Formatter};
+
+use crate::settings::{short_times, time_language, translation};
+
+/// Everything we say in a sentence, in English. `{}` is filled in with
+/// names. Any of these can be replaced in the `[strings]` table of the
+/// config.
+const STRINGS: &[(&str, &str)] = &[
+    ("is_typing", "{} is typing."),
+    ("are_typing", "{} are typing."),
+    ("room_is_typing", "{} is typing…"),
+    ("room_are_typing", "{} are typing…"),
+    ("seen_by", "Seen by {}."),
+];
+
+/// The string for the key, in whatever language the config says.
+pub fn tr(key: &str, arg: &str) -> String {
+    let template = translation(key).unwrap_or_else(|| {
+        STRINGS
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, s)| s.to_string())
+            .unwrap_or_default()
+    });
+
+    fill(&template, arg)
+}
+
+fn fill(template: &str, arg: &str) -> String {
+    template.replacen("{}", arg, 1)
+}
+
+/// How long ago something was, like "5 minutes ago", or just "5m" when
+/// short times are turned on.
+pub fn pretty_elapsed(elapsed: Duration) -> String {
+    if short_times() {
+        return short_duration(elapsed);
+    }
+
+    Formatter::with_language(language(time_language().as_deref())).convert(elapsed)
+}
+
+// From an ISO 639-1 code, like "de", falling back to English.
+fn language(code: Option<&str>) -> BoxedLanguage {
+    code.and_then(IsolangLanguage::from_639_1)
+        .and_then(timeago::from_isolang)
+        .unwrap_or_else(|| boxup(English))
+}
+
+/// Minutes since the epoch, for knowing when relative times go stale.
+pub fn clock_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 60
+}
+
+/// Just enough to tell how long it's been.
+pub fn short_duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+
+    match secs {
+        0..=59 => "now".to_string(),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        86400..=604799 => format!("{}d", secs / 86400),
+        _ => format!("{}w", secs / 604800),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language() {
+        let five = Duration::from_secs(300);
+
+        assert_eq!(
+            Formatter::with_language(language(Some("de"))).convert(five),
+            "vor 5 Minuten"
+        );
+
+        assert_eq!(
+            Formatter::with_language(language(Some("nope"))).convert(five),
+            "5 minutes ago"
+        );
+
+        assert_eq!(fill("Seen by {}.", "Phil"), "Seen by Phil.");
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::settings::log_level;
+
+/// Start a new file once the log gets this big, in bytes. This is only
+/// checked at startup, so a long session can run past it.
+const MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+
+/// And keep this many old ones around.
+const MAX_LOG_FILES: usize = 3;
+
+pub fn get_log_path() -> PathBuf {
+    let mut path = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .expect("no state directory");
+
+    path.push("matui");
+    path.push("matui.log");
+    path
+}
+
+/// Log to a file in the state directory. The level given on the command
+/// line wins over the one in the config.
+pub fn init_logging(level: Option<&str>) -> anyhow::Result<()> {
+    let path = get_log_path();
+    fs::create_dir_all(path.parent().expect("log file to have a parent"))?;
+
+    rotate(&path)?;
+
+    let level = match level {
+        Some(level) => LevelFilter::from_str(level)?,
+        None => LevelFilter::from_str(&log_level()).unwrap_or(LevelFilter::INFO),
+    };
+
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+    // the SDK is chatty, so it only gets to tell us about problems
+    let filter = Targets::new()
+        .with_target("matui", level)
+        .with_default(LevelFilter::WARN.min(level));
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false),
+        )
+        .with(filter)
+        .try_init()?;
+
+    Ok(())
+}
+
+// Shuffle matui.log to matui.log.1, and so on, if it's gotten too big.
+fn rotate(path: &Path) -> anyhow::Result<()> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() >= MAX_LOG_SIZE => {}
+        _ => return Ok(()),
+    }
+
+    for i in (1..MAX_LOG_FILES).rev() {
+        let from = numbered(path, i);
+
+        if from.exists() {
+            fs::rename(from, numbered(path, i + 1))?;
+        }
+    }
+
+    fs::rename(path, numbered(path, 1))?;
+
+    Ok(())
+}
+
+fn numbered(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+}
+
+/// The last `count` lines of the current log.
+pub fn tail(count: usize) -> Vec<String> {
+    let Ok(file) = File::open(get_log_path()) else {
+        return vec![];
+    };
+
+    let lines: Vec<String> = BufReader::new(file).lines().map_while(Result::ok).collect();
+    let skip = lines.len().saturating_sub(count);
+
+    lines.into_iter().skip(skip).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("matui.log");
+
+        fs::write(numbered(&path, 1), "old").unwrap();
+        fs::write(&path, vec![b'a'; MAX_LOG_SIZE as usize]).unwrap();
+
+        rotate(&path).unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(
+            fs::metadata(numbered(&path, 1)).unwrap().len(),
+            MAX_LOG_SIZE
+        );
+        assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_rotate_small() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("matui.log");
+
+        fs::write(&path, "small").unwrap();
+
+        rotate(&path).unwrap();
+
+        assert!(path.exists());
+        assert!(!numbered(&path, 1).exists());
+    }
+}
@@ -1,59 +1,212 @@
-use log::LevelFilter;
+use anyhow::Context;
+use log::error;
 use matui::app::App;
+use matui::daemon;
+use matui::defaults::default_config;
 use matui::event::{Event, EventHandler};
-use matui::handler::{handle_app_event, handle_blur_event, handle_focus_event, handle_key_event};
-use matui::settings::watch_settings_forever;
+use matui::handler::{
+    handle_app_event, handle_blur_event, handle_editor_closed, handle_files_picked,
+    handle_focus_event, handle_key_event, handle_paste_event, handle_resize_event,
+};
+use matui::logging::init_logging;
+use matui::matrix::matrix::Matrix;
+use matui::settings::{check_config, watch_settings_forever};
+use matui::spawn::{spawn_editor, EditorTarget};
 use matui::tui::Tui;
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
+use ruma::matrix_uri::MatrixId;
 use std::io;
 use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// How often to tick while the window has focus, in milliseconds.
+const ACTIVE_TICK_RATE: u64 = 250;
+
+/// And while it doesn't.
+const IDLE_TICK_RATE: u64 = 1000;
+
+// The value of `--log-level`, if given.
+fn log_level_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if let Some(level) = arg.strip_prefix("--log-level=") {
+            return Some(level.to_string());
+        }
+
+        if arg == "--log-level" {
+            return args.next();
+        }
+    }
+
+    None
+}
+
+// A matrix: URI or matrix.to link to open, if given.
+fn link_arg() -> Option<String> {
+    std::env::args()
+        .skip(1)
+        .find(|a| a.starts_with("matrix:") || a.starts_with("https://matrix.to/"))
+}
 
 fn main() -> anyhow::Result<()> {
-    if cfg!(debug_assertions) {
-        simple_logging::log_to_file("test.log", LevelFilter::Info)?;
-        log_panics::init();
+    init_logging(log_level_arg().as_deref())?;
+    log_panics::init();
+
+    if std::env::args().any(|a| a == "--print-default-config") {
+        print!("{}", default_config());
+        return Ok(());
+    }
+
+    if std::env::args().any(|a| a == "--check-config") {
+        println!("{} looks good.", check_config()?.display());
+        return Ok(());
+    }
+
+    let link = link_arg();
+
+    let id = link
+        .as_deref()
+        .map(|l| Matrix::parse_link(l).context("Could not parse Matrix link."))
+        .transpose()?;
+
+    // if we're already running, that can take it from here
+    if let Some(link) = &link {
+        if daemon::forward_link(link)? {
+            return Ok(());
+        }
     }
 
     watch_settings_forever();
 
-    // Initialize the terminal user interface.
-    let backend = CrosstermBackend::new(io::stderr());
-    let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(250);
-    let sender = events.sender();
-    let mut tui = Tui::new(terminal);
-    tui.init()?;
+    Matrix::unlock_session()?;
 
+    if std::env::args().any(|a| a == "--daemon") {
+        return daemon::run();
+    }
+
+    // make sure we have the store to ourselves
+    let _lock = daemon::claim_for_tui()?;
+
+    // everything, from the terminal to Matrix, runs on this one
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(2)
         .enable_all()
         .build()
         .unwrap();
 
+    let result = runtime.block_on(run(&runtime, id));
+
+    // And then the runtime
+    runtime.shutdown_timeout(Duration::from_secs(10));
+
+    // if we just signed in, the new session may still need a passphrase
+    if result.is_ok() {
+        Matrix::unlock_session()?;
+    }
+
+    result
+}
+
+async fn run(runtime: &Runtime, link: Option<MatrixId>) -> anyhow::Result<()> {
+    // Initialize the terminal user interface.
+    let backend = CrosstermBackend::new(io::stderr());
+    let terminal = Terminal::new(backend)?;
+    let mut events = EventHandler::new(ACTIVE_TICK_RATE);
+    let sender = events.sender();
+    let mut tui = Tui::new(terminal);
+    tui.init()?;
+
     // Create an application.
-    let mut app = App::new(sender, &runtime);
+    let mut app = App::new(sender, runtime);
+    app.pending_link = link;
+
+    // for links opened while we're running
+    let _links = daemon::LinkSocket::listen()
+        .map_err(|e| error!("could not listen for links: {}", e))
+        .ok();
 
     // Start the main loop.
     while app.running {
-        tui.draw(&mut app, false)?;
+        // the editor has the terminal while it's open
+        if app.dirty && !app.editing {
+            tui.draw(&mut app, false)?;
+            app.dirty = false;
+        }
 
         // Handle events.
-        match events.next()? {
-            Event::Tick => app.tick(),
-            Event::Redraw => tui.draw(&mut app, true)?,
-            Event::Key(key_event) => handle_key_event(key_event, &mut app, &events)?,
-            Event::Matui(app_event) => handle_app_event(app_event, &mut app),
-            Event::Focus => handle_focus_event(&mut app),
-            Event::Blur => handle_blur_event(&mut app),
+        for event in events.next_batch().await? {
+            match event {
+                Event::Tick => app.tick(),
+                Event::Redraw if app.editing => {}
+                Event::Redraw => tui.draw(&mut app, true)?,
+                Event::Suspend => {
+                    events.park();
+                    tui.suspend()?;
+                    events.unpark();
+                    tui.draw(&mut app, true)?;
+                }
+                Event::View(text) => {
+                    app.editing |=
+                        spawn_editor(&events, EditorTarget::View, Some(text), vec![], None);
+                }
+                Event::EditorClosed(target, result, parked) => {
+                    if parked {
+                        events.unpark();
+                        app.editing = false;
+                        tui.draw(&mut app, true)?;
+                    }
+
+                    handle_editor_closed(target, result, &mut app);
+                    app.mark_dirty();
+                }
+                Event::FilesPicked(target, result, parked) => {
+                    if parked {
+                        events.unpark();
+                        app.editing = false;
+                        tui.draw(&mut app, true)?;
+                    }
+
+                    handle_files_picked(target, result, &mut app);
+                    app.mark_dirty();
+                }
+                Event::Resize if app.editing => handle_resize_event(&mut app),
+                Event::Resize => {
+                    // start from a blank screen, so nothing from the old size
+                    // is left behind
+                    handle_resize_event(&mut app);
+                    tui.draw(&mut app, true)?;
+                }
+                Event::Key(key_event) => {
+                    handle_key_event(key_event, &mut app, &events)?;
+                    app.mark_dirty();
+                }
+                Event::Paste(text) => {
+                    handle_paste_event(text, &mut app);
+                    app.mark_dirty();
+                }
+                Event::Matui(app_event) => {
+                    handle_app_event(app_event, &mut app);
+                    app.mark_dirty();
+                }
+                Event::Focus => {
+                    events.set_tick_rate(ACTIVE_TICK_RATE);
+                    handle_focus_event(&mut app);
+                    app.mark_dirty();
+                }
+                Event::Blur => {
+                    events.set_tick_rate(IDLE_TICK_RATE);
+                    handle_blur_event(&mut app);
+                    app.mark_dirty();
+                }
+            }
         }
     }
 
-    // Exit the user interface.
+    // Let the last few requests land, then exit the user interface.
+    app.matrix.shutdown(std::mem::take(&mut app.pending)).await;
     tui.exit()?;
 
-    // And then the runtime
-    runtime.shutdown_timeout(Duration::from_secs(10));
-
     Ok(())
 }
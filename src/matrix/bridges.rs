@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use log::warn;
+use regex::Regex;
+use ruma::UserId;
+
+use crate::settings::bridge_patterns;
+
+/// The users the usual bridges make for people on the other side, by the
+/// protocol they're tagged with.
+const KNOWN: &[(&str, &str)] = &[
+    ("discord", "^@_?discord_"),
+    ("irc", "^@_?irc_"),
+    ("signal", "^@signal_"),
+    ("slack", "^@slack_"),
+    ("telegram", "^@telegram_"),
+    ("whatsapp", "^@whatsapp_"),
+];
+
+/// Picks out bridged users by their Matrix ID.
+pub struct Bridges {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Bridges {
+    /// The usual bridges, plus any in the config. An empty pattern there
+    /// turns one of ours off.
+    pub fn from_settings() -> Self {
+        let mut patterns: HashMap<String, String> = KNOWN
+            .iter()
+            .map(|(tag, pattern)| (tag.to_string(), pattern.to_string()))
+            .collect();
+
+        patterns.extend(bridge_patterns());
+
+        Self::new(patterns)
+    }
+
+    fn new(patterns: HashMap<String, String>) -> Self {
+        let mut patterns: Vec<(String, Regex)> = patterns
+            .into_iter()
+            .filter(|(_, pattern)| !pattern.is_empty())
+            .filter_map(|(tag, pattern)| match Regex::new(&pattern) {
+                Ok(regex) => Some((tag, regex)),
+                Err(e) => {
+                    warn!("bad pattern for the {} bridge: {}", tag, e);
+                    None
+                }
+            })
+            .collect();
+
+        // so the same one wins every time, if more than one matches
+        patterns.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Self { patterns }
+    }
+
+    /// The protocol this user is bridged from, if they are.
+    pub fn protocol(&self, user_id: &UserId) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|(_, regex)| regex.is_match(user_id.as_str()))
+            .map(|(tag, _)| tag.as_str())
+    }
+}
+
+/// Bridges like to start a message with who sent it, as "alice: hi" or
+/// "<alice> hi", which says nothing once the sender's been shown.
+pub fn strip_name_prefix<'a>(body: &'a str, name: &str) -> &'a str {
+    [format!("{}: ", name), format!("<{}> ", name)]
+        .iter()
+        .find_map(|prefix| body.strip_prefix(prefix.as_str()))
+        .unwrap_or(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ruma::user_id;
+
+    #[test]
+    fn test_protocol() {
+        let bridges = Bridges::new(
+            [
+                ("telegram", "^@telegram_"),
+                ("xmpp", "^@_xmpp_"),
+                ("irc", ""),
+                ("broken", "("),
+            ]
+            .iter()
+            .map(|(tag, pattern)| (tag.to_string(), pattern.to_string()))
+            .collect(),
+        );
+
+        assert_eq!(
+            bridges.protocol(user_id!("@telegram_1234:example.org")),
+            Some("telegram")
+        );
+        assert_eq!(
+            bridges.protocol(user_id!("@_xmpp_alice=40jabber.org:example.org")),
+            Some("xmpp")
+        );
+        assert_eq!(bridges.protocol(user_id!("@irc_alice:example.org")), None);
+        assert_eq!(bridges.protocol(user_id!("@alice:example.org")), None);
+    }
+
+    #[test]
+    fn test_strip_name_prefix() {
+        assert_eq!(strip_name_prefix("alice: hi", "alice"), "hi");
+        assert_eq!(strip_name_prefix("<alice> hi", "alice"), "hi");
+        assert_eq!(strip_name_prefix("bob: hi", "alice"), "bob: hi");
+        assert_eq!(strip_name_prefix("alice:hi", "alice"), "alice:hi");
+    }
+}
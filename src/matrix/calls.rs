@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+
+use log::error;
+use matrix_sdk::deserialized_responses::RawAnySyncOrStrippedState;
+use matrix_sdk::reqwest::Url;
+use matrix_sdk::room::Room;
+use matrix_sdk::ruma::exports::serde_json::Value;
+use matrix_sdk::Client;
+use ruma::events::call::invite::OriginalCallInviteEvent;
+use ruma::events::{AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent, StateEventType};
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedVoipId, UserId, VoipId, VoipVersionId};
+use serde::Deserialize;
+
+/// Where widgets live in room state: Element's name, and the spec's.
+pub const WIDGET_EVENTS: [&str; 2] = ["im.vector.modular.widgets", "m.widget"];
+
+// the kinds of widget that are a meeting someone can join
+const CALL_WIDGETS: [&str; 4] = ["jitsi", "m.jitsi", "io.element.call", "m.call"];
+
+/// A call widget in a room, with everything filled in to open it in a
+/// browser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallWidget {
+    pub name: String,
+    pub url: String,
+}
+
+/// Someone ringing us, one-to-one.
+#[derive(Clone, Debug)]
+pub struct IncomingCall {
+    pub room: Room,
+    pub call_id: OwnedVoipId,
+    pub version: VoipVersionId,
+    pub caller: String,
+}
+
+#[derive(Deserialize)]
+struct WidgetState {
+    state_key: String,
+    content: WidgetContent,
+}
+
+#[derive(Deserialize)]
+struct WidgetContent {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    url: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    data: BTreeMap<String, Value>,
+}
+
+/// Every call widget in the room, ready to open.
+pub async fn call_widgets(room: &Room) -> Vec<CallWidget> {
+    // the widget URL can ask for a bit about us
+    let me = match room.client().user_id() {
+        Some(id) => room.get_member_no_sync(id).await.ok().flatten(),
+        None => None,
+    };
+
+    let mut vars = BTreeMap::new();
+    vars.insert("matrix_room_id", room.room_id().to_string());
+
+    if let Some(me) = &me {
+        vars.insert("matrix_user_id", me.user_id().to_string());
+        vars.insert("matrix_display_name", me.name().to_string());
+
+        if let Some(avatar) = me.avatar_url() {
+            vars.insert("matrix_avatar_url", avatar.to_string());
+        }
+    }
+
+    let mut widgets = vec![];
+
+    for event_type in WIDGET_EVENTS {
+        let events = match room
+            .get_state_events(StateEventType::from(event_type))
+            .await
+        {
+            Ok(events) => events,
+            Err(e) => {
+                error!("could not load room widgets: {}", e);
+                continue;
+            }
+        };
+
+        widgets.extend(
+            events
+                .into_iter()
+                .filter_map(|event| match event {
+                    RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as().ok(),
+                    RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as().ok(),
+                })
+                .filter_map(|state: WidgetState| state.call(&vars)),
+        );
+    }
+
+    widgets
+}
+
+impl WidgetState {
+    // removed widgets are left behind with empty content
+    fn call(self, vars: &BTreeMap<&str, String>) -> Option<CallWidget> {
+        let content = self.content;
+
+        if !CALL_WIDGETS.contains(&content.kind.as_deref()?) {
+            return None;
+        }
+
+        let mut vars = vars.clone();
+        vars.insert("matrix_widget_id", self.state_key);
+
+        // anything in the data can go in the URL, too
+        let data: BTreeMap<String, String> = content
+            .data
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                Value::String(s) => Some((key, s)),
+                Value::Number(n) => Some((key, n.to_string())),
+                Value::Bool(b) => Some((key, b.to_string())),
+                _ => None,
+            })
+            .collect();
+
+        vars.extend(data.iter().map(|(k, v)| (k.as_str(), v.clone())));
+
+        let url = fill_url(&content.url?, &vars);
+
+        // anyone who can set room state picks this, and it goes straight to
+        // the system opener, so nothing but the web
+        if !Url::parse(&url).is_ok_and(|u| matches!(u.scheme(), "https" | "http")) {
+            error!(
+                "ignoring call widget with a URL that isn't on the web: {}",
+                url
+            );
+            return None;
+        }
+
+        Some(CallWidget {
+            name: content.name.unwrap_or_else(|| "Call".to_string()),
+            url,
+        })
+    }
+}
+
+/// A call invite that's still ringing, for us, from someone else.
+pub async fn incoming_call(client: &Client, event: &AnyTimelineEvent) -> Option<IncomingCall> {
+    let me = client.user_id()?;
+    let invite = ringing(event, me, MilliSecondsSinceUnixEpoch::now())?;
+    let room = client.get_room(&invite.room_id)?;
+
+    let caller = match room.get_member_no_sync(&invite.sender).await {
+        Ok(Some(member)) => member.name().to_string(),
+        _ => invite.sender.to_string(),
+    };
+
+    Some(IncomingCall {
+        room,
+        call_id: invite.content.call_id.clone(),
+        version: invite.content.version.clone(),
+        caller,
+    })
+}
+
+fn ringing<'a>(
+    event: &'a AnyTimelineEvent,
+    me: &UserId,
+    now: MilliSecondsSinceUnixEpoch,
+) -> Option<&'a OriginalCallInviteEvent> {
+    let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::CallInvite(MessageLikeEvent::Original(
+        invite,
+    ))) = event
+    else {
+        return None;
+    };
+
+    if invite.sender == me {
+        return None;
+    }
+
+    // ringing someone else in the room
+    if invite.content.invitee.as_ref().is_some_and(|id| id != me) {
+        return None;
+    }
+
+    // and old invites come in with every initial sync
+    let expires = u64::from(invite.origin_server_ts.0) + u64::from(invite.content.lifetime);
+
+    if expires < now.0.into() {
+        return None;
+    }
+
+    Some(invite)
+}
+
+/// The call this event is the end of ringing for: hung up, rejected, or
+/// picked up (maybe on another device).
+pub fn ended_call(event: &AnyTimelineEvent) -> Option<&VoipId> {
+    let AnyTimelineEvent::MessageLike(event) = event else {
+        return None;
+    };
+
+    match event {
+        AnyMessageLikeEvent::CallHangup(MessageLikeEvent::Original(e)) => Some(&e.content.call_id),
+        AnyMessageLikeEvent::CallReject(MessageLikeEvent::Original(e)) => Some(&e.content.call_id),
+        AnyMessageLikeEvent::CallAnswer(MessageLikeEvent::Original(e)) => Some(&e.content.call_id),
+        _ => None,
+    }
+}
+
+// Swap each $variable for its (escaped) value. The longest names go first, so
+// $matrix_room_id isn't mistaken for some $matrix_room.
+fn fill_url(template: &str, vars: &BTreeMap<&str, String>) -> String {
+    let mut names: Vec<&&str> = vars.keys().collect();
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+    let mut url = template.to_string();
+
+    for name in names {
+        url = url.replace(&format!("${}", name), &escape(&vars[*name]));
+    }
+
+    url
+}
+
+fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::exports::serde_json;
+
+    fn invite(sender: &str, invitee: Option<&str>, ts: u64) -> AnyTimelineEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "m.call.invite",
+            "event_id": "$1",
+            "room_id": "!dm:example.org",
+            "sender": sender,
+            "origin_server_ts": ts,
+            "content": {
+                "call_id": "c1",
+                "lifetime": 60_000,
+                "offer": { "type": "offer", "sdp": "v=0" },
+                "version": 0,
+                "invitee": invitee,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ringing() {
+        let me = ruma::user_id!("@me:example.org");
+        let now = MilliSecondsSinceUnixEpoch(100_000u32.into());
+
+        let call = invite("@alice:example.org", None, 90_000);
+        assert!(ringing(&call, me, now).is_some());
+
+        let call = invite("@alice:example.org", Some("@me:example.org"), 90_000);
+        assert!(ringing(&call, me, now).is_some());
+
+        // somebody else's, our own, and rung out
+        let call = invite("@alice:example.org", Some("@bob:example.org"), 90_000);
+        assert!(ringing(&call, me, now).is_none());
+
+        let call = invite("@me:example.org", None, 90_000);
+        assert!(ringing(&call, me, now).is_none());
+
+        let call = invite("@alice:example.org", None, 10_000);
+        assert!(ringing(&call, me, now).is_none());
+    }
+
+    #[test]
+    fn test_call() {
+        let state: WidgetState = serde_json::from_value(serde_json::json!({
+            "state_key": "jitsi_1",
+            "content": {
+                "type": "jitsi",
+                "name": "Standup",
+                "url": "https://example.org/jitsi.html?confId=$conferenceId\
+                        #domain=$domain&userId=$matrix_user_id&name=$matrix_display_name\
+                        &widgetId=$matrix_widget_id",
+                "data": { "conferenceId": "Standup123", "domain": "meet.example.org" },
+            },
+        }))
+        .unwrap();
+
+        let vars = BTreeMap::from([
+            ("matrix_user_id", "@alice:example.org".to_string()),
+            ("matrix_display_name", "Alice B".to_string()),
+        ]);
+
+        assert_eq!(
+            state.call(&vars),
+            Some(CallWidget {
+                name: "Standup".to_string(),
+                url: "https://example.org/jitsi.html?confId=Standup123\
+                      #domain=meet.example.org&userId=%40alice%3Aexample.org&name=Alice%20B\
+                      &widgetId=jitsi_1"
+                    .to_string(),
+            })
+        );
+
+        // removed, or not a call
+        let removed: WidgetState =
+            serde_json::from_value(serde_json::json!({ "state_key": "x", "content": {} })).unwrap();
+        assert_eq!(removed.call(&vars), None);
+
+        let etherpad: WidgetState = serde_json::from_value(serde_json::json!({
+            "state_key": "y",
+            "content": { "type": "m.etherpad", "url": "https://example.org/pad" },
+        }))
+        .unwrap();
+        assert_eq!(etherpad.call(&vars), None);
+    }
+
+    #[test]
+    fn test_call_scheme() {
+        let vars = BTreeMap::new();
+
+        for url in ["file:///etc/passwd", "/usr/bin/xterm", "ssh://example.org"] {
+            let state: WidgetState = serde_json::from_value(serde_json::json!({
+                "state_key": "jitsi_1",
+                "content": { "type": "jitsi", "url": url },
+            }))
+            .unwrap();
+
+            assert_eq!(state.call(&vars), None);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use log::error;
+use matrix_sdk::deserialized_responses::RawAnySyncOrStrippedState;
+use matrix_sdk::room::Room;
+use matrix_sdk::ruma::exports::serde_json;
+use matrix_sdk::Client;
+use ruma::events::{GlobalAccountDataEventType, StateEventType};
+use ruma::{OwnedMxcUri, OwnedRoomId, RoomId};
+use serde::Deserialize;
+
+const USER_EMOTES: &str = "im.ponies.user_emotes";
+const ROOM_EMOTES: &str = "im.ponies.room_emotes";
+const EMOTE_ROOMS: &str = "im.ponies.emote_rooms";
+
+lazy_static::lazy_static! {
+    static ref EMOTES: RwLock<Emotes> = RwLock::new(Emotes::default());
+}
+
+/// A custom emoji from an image pack (MSC2545).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Emote {
+    pub shortcode: String,
+    pub url: OwnedMxcUri,
+    pub pack: String,
+}
+
+#[derive(Default)]
+struct Emotes {
+    // our own, and packs from other rooms we've asked for everywhere
+    user: Vec<Emote>,
+    rooms: HashMap<OwnedRoomId, Vec<Emote>>,
+}
+
+#[derive(Deserialize)]
+struct PackContent {
+    #[serde(default)]
+    images: BTreeMap<String, PackImage>,
+    #[serde(default)]
+    pack: PackInfo,
+}
+
+#[derive(Deserialize)]
+struct PackImage {
+    url: OwnedMxcUri,
+    usage: Option<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+struct PackInfo {
+    display_name: Option<String>,
+    usage: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct PackState {
+    content: PackContent,
+}
+
+#[derive(Deserialize)]
+struct EmoteRooms {
+    #[serde(default)]
+    rooms: BTreeMap<OwnedRoomId, BTreeMap<String, serde_json::Value>>,
+}
+
+impl PackContent {
+    // just the emoticons; stickers are something else
+    fn emotes(self, fallback_name: &str) -> Vec<Emote> {
+        let name = self
+            .pack
+            .display_name
+            .unwrap_or_else(|| fallback_name.to_string());
+
+        let pack_usage = self.pack.usage;
+
+        self.images
+            .into_iter()
+            .filter(
+                |(_, image)| match image.usage.as_ref().or(pack_usage.as_ref()) {
+                    Some(usage) if !usage.is_empty() => usage.iter().any(|u| u == "emoticon"),
+                    _ => true,
+                },
+            )
+            .map(|(shortcode, image)| Emote {
+                shortcode,
+                url: image.url,
+                pack: name.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Our own emotes, then the room's, skipping any shortcode we've already got.
+pub fn for_room(room_id: &RoomId) -> Vec<Emote> {
+    let emotes = EMOTES.read().expect("to read emotes");
+    let mut ret: Vec<Emote> = vec![];
+
+    let room = emotes.rooms.get(room_id).into_iter().flatten();
+
+    for emote in emotes.user.iter().chain(room) {
+        if !ret.iter().any(|e| e.shortcode == emote.shortcode) {
+            ret.push(emote.clone());
+        }
+    }
+
+    ret
+}
+
+/// What an emote is called, from its URL, if we know about it.
+pub fn shortcode(url: &str) -> Option<String> {
+    let emotes = EMOTES.read().expect("to read emotes");
+
+    emotes
+        .user
+        .iter()
+        .chain(emotes.rooms.values().flatten())
+        .find(|e| e.url.as_str() == url)
+        .map(|e| e.shortcode.clone())
+}
+
+/// Load our own image packs, and the room's, from the store.
+pub async fn load(client: &Client, room: &Room) {
+    let mut user = vec![];
+
+    match client
+        .account()
+        .account_data_raw(GlobalAccountDataEventType::from(USER_EMOTES))
+        .await
+    {
+        Ok(Some(raw)) => match raw.deserialize_as::<PackContent>() {
+            Ok(content) => user.extend(content.emotes("Personal")),
+            Err(e) => error!("could not parse user emotes: {}", e),
+        },
+        Ok(None) => {}
+        Err(e) => error!("could not load user emotes: {}", e),
+    }
+
+    // packs in other rooms that we've asked to have everywhere
+    if let Ok(Some(raw)) = client
+        .account()
+        .account_data_raw(GlobalAccountDataEventType::from(EMOTE_ROOMS))
+        .await
+    {
+        let rooms = raw
+            .deserialize_as::<EmoteRooms>()
+            .map(|r| r.rooms)
+            .unwrap_or_default();
+
+        for (room_id, keys) in rooms {
+            let Some(other) = client.get_room(&room_id) else {
+                continue;
+            };
+
+            let keys: Vec<&str> = keys.keys().map(String::as_str).collect();
+
+            user.extend(room_packs(&other, Some(&keys)).await);
+        }
+    }
+
+    let packs = room_packs(room, None).await;
+
+    let mut emotes = EMOTES.write().expect("to write emotes");
+    emotes.user = user;
+    emotes.rooms.insert(room.room_id().to_owned(), packs);
+}
+
+async fn room_packs(room: &Room, keys: Option<&[&str]>) -> Vec<Emote> {
+    let event_type = StateEventType::from(ROOM_EMOTES);
+
+    let events = match keys {
+        Some(keys) => room.get_state_events_for_keys(event_type, keys).await,
+        None => room.get_state_events(event_type).await,
+    };
+
+    let events = match events {
+        Ok(events) => events,
+        Err(e) => {
+            error!("could not load room emotes: {}", e);
+            return vec![];
+        }
+    };
+
+    let fallback = room.name().unwrap_or_else(|| room.room_id().to_string());
+
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as::<PackState>().ok(),
+            RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as::<PackState>().ok(),
+        })
+        .flat_map(|state| state.content.emotes(&fallback))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_emotes() {
+        let content: PackContent = serde_json::from_value(serde_json::json!({
+            "images": {
+                "blobcat": { "url": "mxc://example.org/blobcat" },
+                "wave": { "url": "mxc://example.org/wave", "usage": ["sticker"] },
+                "both": { "url": "mxc://example.org/both", "usage": ["sticker", "emoticon"] }
+            },
+            "pack": { "display_name": "Blobs" }
+        }))
+        .unwrap();
+
+        let emotes = content.emotes("Fallback");
+
+        let names: Vec<&str> = emotes.iter().map(|e| e.shortcode.as_str()).collect();
+        assert_eq!(names, vec!["blobcat", "both"]);
+        assert_eq!(emotes[0].pack, "Blobs");
+        assert_eq!(emotes[0].url.as_str(), "mxc://example.org/blobcat");
+    }
+}
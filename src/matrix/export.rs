@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::bail;
+use chrono::{Local, NaiveDate, TimeZone};
+use log::error;
+use matrix_sdk::media::{MediaFormat, MediaRequestParameters};
+use matrix_sdk::room::{MessagesOptions, Room};
+use matrix_sdk::ruma::api::Direction;
+use matrix_sdk::Client;
+use ruma::events::room::message::MessageType;
+use ruma::events::room::MediaSource;
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, UInt};
+
+use crate::handler::MatuiEvent;
+use crate::matrix::matrix::Matrix;
+use crate::widgets::message::Message;
+
+/// How many events to ask for at a time.
+const PAGE_SIZE: u16 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Text,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Json => "jsonl",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "text" | "txt" => Ok(ExportFormat::Text),
+            "json" | "jsonl" => Ok(ExportFormat::Json),
+            "html" => Ok(ExportFormat::Html),
+            _ => bail!("Unknown export format: {}", s),
+        }
+    }
+}
+
+/// What to export, and where to.
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub path: PathBuf,
+}
+
+impl ExportOptions {
+    // is the timestamp past the start of the range?
+    fn after_start(&self, ts: MilliSecondsSinceUnixEpoch) -> bool {
+        match self.from {
+            Some(from) => ts_to_date(ts) >= from,
+            None => true,
+        }
+    }
+
+    fn in_range(&self, ts: MilliSecondsSinceUnixEpoch) -> bool {
+        let before_end = match self.to {
+            Some(to) => ts_to_date(ts) <= to,
+            None => true,
+        };
+
+        self.after_start(ts) && before_end
+    }
+}
+
+/// The parts of a message we hold on to while we page through history.
+struct Entry {
+    id: OwnedEventId,
+    sender: OwnedUserId,
+    sent: MilliSecondsSinceUnixEpoch,
+    body: MessageType,
+    text: String,
+}
+
+impl From<Message> for Entry {
+    fn from(message: Message) -> Self {
+        Entry {
+            text: message.display(),
+            id: message.id,
+            sender: message.sender.id,
+            sent: message.sent,
+            body: message.body,
+        }
+    }
+}
+
+/// A single message, flattened for writing out.
+struct Line {
+    sent: MilliSecondsSinceUnixEpoch,
+    sender: String,
+    body: String,
+    // a path to downloaded media, relative to the export
+    media: Option<String>,
+    image: bool,
+}
+
+/// Walk backwards through the room until we run out of history or pass
+/// the start of the range, then write it all out. Returns the number of
+/// messages written.
+pub async fn export_room(
+    client: &Client,
+    room: &Room,
+    options: &ExportOptions,
+) -> anyhow::Result<usize> {
+    let mut raw = vec![];
+    let mut messages = vec![];
+    let mut cursor = None;
+
+    loop {
+        let mut request = MessagesOptions::new(Direction::Backward);
+        request.limit = UInt::from(PAGE_SIZE);
+        request.from = cursor;
+
+        let page = room.messages(request).await?;
+        let mut done = page.end.is_none();
+
+        for te in &page.chunk {
+            let Ok(event) = Matrix::deserialize_event(te, room.room_id().to_owned()) else {
+                continue;
+            };
+
+            if !options.after_start(event.origin_server_ts()) {
+                done = true;
+                continue;
+            }
+
+            if !options.in_range(event.origin_server_ts()) {
+                continue;
+            }
+
+            raw.push(te.raw().json().get().to_string());
+
+            if let Some(message) = Message::try_from(&event, true) {
+                messages.push(Entry::from(message));
+            }
+        }
+
+        Matrix::send(MatuiEvent::ProgressStarted(
+            format!("Exporting... ({} messages)", messages.len()),
+            0,
+        ));
+
+        if done {
+            break;
+        }
+
+        cursor = page.end;
+    }
+
+    // we walked backwards, but people read forwards
+    raw.reverse();
+    messages.reverse();
+
+    let mut file = fs::File::create(&options.path)?;
+
+    if options.format == ExportFormat::Json {
+        for line in &raw {
+            writeln!(file, "{}", line)?;
+        }
+
+        return Ok(raw.len());
+    }
+
+    let names = sender_names(room, &messages).await;
+    let media_dir = media_dir(&options.path);
+    let mut lines = vec![];
+
+    for message in &messages {
+        let sender = names
+            .get(&message.sender)
+            .cloned()
+            .unwrap_or(message.sender.to_string());
+
+        let mut line = Line {
+            sent: message.sent,
+            sender,
+            body: message.text.clone(),
+            media: None,
+            image: matches!(message.body, MessageType::Image(_)),
+        };
+
+        if options.format == ExportFormat::Html {
+            line.media = download_media(client, message, &media_dir).await;
+        }
+
+        lines.push(line);
+    }
+
+    let output = match options.format {
+        ExportFormat::Html => render_html(&room_name(room).await, &lines),
+        _ => render_text(&lines),
+    };
+
+    file.write_all(output.as_bytes())?;
+
+    Ok(lines.len())
+}
+
+async fn room_name(room: &Room) -> String {
+    room.compute_display_name()
+        .await
+        .map(|n| n.to_string())
+        .unwrap_or(room.room_id().to_string())
+}
+
+async fn sender_names(room: &Room, messages: &[Entry]) -> HashMap<OwnedUserId, String> {
+    let mut names = HashMap::new();
+
+    for message in messages {
+        if names.contains_key(&message.sender) {
+            continue;
+        }
+
+        if let Ok(Some(member)) = room.get_member_no_sync(&message.sender).await {
+            names.insert(message.sender.clone(), member.name().to_string());
+        }
+    }
+
+    names
+}
+
+// Media for an export at foo.html goes in foo_files/.
+fn media_dir(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or("export".to_string());
+
+    path.with_file_name(format!("{}_files", stem))
+}
+
+async fn download_media(client: &Client, message: &Entry, dir: &Path) -> Option<String> {
+    let (source, name): (MediaSource, &str) = match &message.body {
+        MessageType::Image(c) => (c.source.clone(), &c.body),
+        MessageType::Video(c) => (c.source.clone(), &c.body),
+        MessageType::File(c) => (c.source.clone(), &c.body),
+        _ => return None,
+    };
+
+    let request = MediaRequestParameters {
+        source,
+        format: MediaFormat::File,
+    };
+
+    let data = match client.media().get_media_content(&request, true).await {
+        Ok(data) => data,
+        Err(e) => {
+            error!("could not download media for export: {}", e);
+            return None;
+        }
+    };
+
+    // event IDs are unique, file names aren't
+    let file_name = format!(
+        "{}-{}",
+        message.id.localpart().trim_start_matches('$'),
+        Path::new(name).file_name()?.to_string_lossy()
+    );
+
+    fs::create_dir_all(dir).ok()?;
+    fs::write(dir.join(&file_name), data).ok()?;
+
+    Some(format!(
+        "{}/{}",
+        dir.file_name()?.to_string_lossy(),
+        file_name
+    ))
+}
+
+fn ts_to_date(ts: MilliSecondsSinceUnixEpoch) -> NaiveDate {
+    Local
+        .timestamp_opt(ts.as_secs().into(), 0)
+        .unwrap()
+        .date_naive()
+}
+
+fn format_ts(ts: MilliSecondsSinceUnixEpoch) -> String {
+    Local
+        .timestamp_opt(ts.as_secs().into(), 0)
+        .unwrap()
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+fn render_text(lines: &[Line]) -> String {
+    let mut output = String::new();
+
+    for line in lines {
+        output.push_str(&format!(
+            "[{}] {}: {}\n",
+            format_ts(line.sent),
+            line.sender,
+            line.body
+        ));
+    }
+
+    output
+}
+
+fn render_html(title: &str, lines: &[Line]) -> String {
+    let mut output = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>body {{ font-family: sans-serif; max-width: 50em; margin: auto; }} \
+         .ts {{ color: gray; }} img {{ max-width: 100%; }}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(title),
+        escape(title)
+    );
+
+    for line in lines {
+        output.push_str(&format!(
+            "<p><span class=\"ts\">{}</span> <b>{}</b>: ",
+            format_ts(line.sent),
+            escape(&line.sender)
+        ));
+
+        match &line.media {
+            Some(path) if line.image => output.push_str(&format!(
+                "<br><img src=\"{}\" alt=\"{}\">",
+                escape(path),
+                escape(&line.body)
+            )),
+            Some(path) => output.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape(path),
+                escape(&line.body)
+            )),
+            None => output.push_str(&escape(&line.body).replace('\n', "<br>")),
+        }
+
+        output.push_str("</p>\n");
+    }
+
+    output.push_str("</body>\n</html>\n");
+    output
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(body: &str) -> Line {
+        Line {
+            sent: MilliSecondsSinceUnixEpoch(UInt::from(0_u32)),
+            sender: "Alice".to_string(),
+            body: body.to_string(),
+            media: None,
+            image: false,
+        }
+    }
+
+    #[test]
+    fn test_render_text() {
+        let output = render_text(&[line("hello"), line("world")]);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("] Alice: hello"));
+    }
+
+    #[test]
+    fn test_render_html_escapes() {
+        let output = render_html("<room>", &[line("<script>")]);
+
+        assert!(output.contains("<title>&lt;room&gt;</title>"));
+        assert!(output.contains("&lt;script&gt;"));
+        assert!(!output.contains("<script>"));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(ExportFormat::from_str("HTML").unwrap(), ExportFormat::Html);
+        assert!(ExportFormat::from_str("pdf").is_err());
+    }
+}
@@ -1,65 +1,119 @@
 use crate::matrix::matrix::MessageType::File;
 use crate::video::get_video_thumbnail;
-use std::{fs, thread};
+use std::fs;
 
+use std::collections::HashSet;
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Sender, TryRecvError};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::{bail, Context};
+use futures::future::BoxFuture;
 use futures::stream::StreamExt;
-use log::{error, info};
+use log::{error, info, warn};
 use matrix_sdk::attachment::{AttachmentConfig, Thumbnail};
 use matrix_sdk::config::SyncSettings;
-use matrix_sdk::deserialized_responses::{TimelineEvent, TimelineEventKind};
-use matrix_sdk::encryption::verification::{Emoji, SasState, SasVerification, Verification};
-use matrix_sdk::matrix_auth::MatrixSession;
-use matrix_sdk::media::{MediaFormat, MediaRequestParameters};
+use matrix_sdk::deserialized_responses::{EncryptionInfo, TimelineEvent, TimelineEventKind};
+use matrix_sdk::encryption::verification::{
+    Emoji, SasState, SasVerification, Verification, VerificationRequest, VerificationRequestState,
+};
+use matrix_sdk::encryption::LocalTrust;
+use matrix_sdk::media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings};
+use matrix_sdk::reqwest::{self, Certificate, NoProxy, Proxy, Url};
 use matrix_sdk::room::{MessagesOptions, Receipts, Room};
+use matrix_sdk::ruma::api::client::error::ErrorKind;
 use matrix_sdk::ruma::api::client::filter::{
-    FilterDefinition, LazyLoadOptions, RoomEventFilter, RoomFilter,
+    Filter, FilterDefinition, LazyLoadOptions, RoomEventFilter, RoomFilter,
 };
+use matrix_sdk::ruma::api::client::state::get_state_events;
 use matrix_sdk::ruma::api::Direction;
 use matrix_sdk::ruma::events::key::verification::request::ToDeviceKeyVerificationRequestEvent;
 use matrix_sdk::ruma::events::key::verification::start::{
     OriginalSyncKeyVerificationStartEvent, ToDeviceKeyVerificationStartEvent,
 };
 use matrix_sdk::ruma::events::room::message::{MessageType, OriginalSyncRoomMessageEvent};
-use matrix_sdk::ruma::exports::serde_json;
+use matrix_sdk::ruma::exports::serde_json::json;
 use matrix_sdk::ruma::UserId;
-use matrix_sdk::RoomState;
-use matrix_sdk::{Client, LoopCtrl, ServerName};
+use matrix_sdk::sync::SyncResponse;
+use matrix_sdk::{Client, ClientBuilder, LoopCtrl, ServerName, SessionChange};
+use matrix_sdk::{RoomMemberships, RoomState};
 use mime::IMAGE_JPEG;
 use once_cell::sync::OnceCell;
 use rand::rngs::OsRng;
 use rand::{distributions::Alphanumeric, Rng};
+use ruma::events::call::hangup::CallHangupEventContent;
+use ruma::events::call::reject::CallRejectEventContent;
 use ruma::events::key::verification::VerificationMethod;
+use ruma::events::presence::PresenceEvent;
 use ruma::events::reaction::ReactionEventContent;
 
 use ruma::events::relation::Annotation;
+use ruma::events::room::guest_access::{GuestAccess, RoomGuestAccessEventContent};
+use ruma::events::room::history_visibility::{
+    HistoryVisibility, RoomHistoryVisibilityEventContent,
+};
+use ruma::events::room::join_rules::{AllowRule, JoinRule, Restricted, RoomJoinRulesEventContent};
+use ruma::events::room::member::{MembershipState, StrippedRoomMemberEvent};
 use ruma::events::room::message::MessageType::Image;
 use ruma::events::room::message::MessageType::Video;
-use ruma::events::room::message::{AddMentions, ForwardThread, RoomMessageEventContent};
+use ruma::events::room::message::{
+    AddMentions, ForwardThread, ImageMessageEventContent, RoomMessageEventContent,
+};
+use ruma::events::room::MediaSource;
+use ruma::events::Mentions;
 use ruma::events::{
     AnyMessageLikeEvent, AnySyncEphemeralRoomEvent, AnySyncTimelineEvent, AnyTimelineEvent,
     MessageLikeEvent, SyncEphemeralRoomEvent,
 };
-use ruma::{OwnedEventId, OwnedRoomId, OwnedUserId, UInt};
-use serde::{Deserialize, Serialize};
+use ruma::matrix_uri::MatrixId;
+use ruma::presence::PresenceState;
+use ruma::{
+    MatrixToUri, MatrixUri, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedEventId, OwnedRoomId,
+    OwnedRoomOrAliasId, OwnedUserId, OwnedVoipId, UInt, VoipVersionId,
+};
 use tokio::runtime::{Handle, Runtime};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
 
 use crate::app::App;
 use crate::event::Event;
 use crate::event::Event::Matui;
 use crate::handler::MatuiEvent::{
-    Error, ProgressComplete, ProgressStarted, VerificationCompleted, VerificationStarted,
+    Error, ProgressComplete, ProgressStarted, VerificationRequested, VerificationStarted,
+    VerificationStep,
 };
-use crate::handler::{Batch, MatuiEvent, SyncType};
+use crate::handler::{Batch, ConnectionState, MatuiEvent, SyncType};
+use crate::hooks::{run_hook, timeline_hooks, Hook};
+use crate::matrix::calls;
+use crate::matrix::emotes;
+use crate::matrix::export::{export_room, ExportOptions};
+use crate::matrix::media::{animation, discard_gif, fetch_gif, send_animation};
+use crate::matrix::mediacache::MediaCache;
+use crate::matrix::register::{Outcome, Registration};
+use crate::matrix::roaming;
 use crate::matrix::roomcache::{DecoratedRoom, RoomCache};
+use crate::matrix::session::{
+    load_session, persist_sync_token, persist_user_session, save_session, unlock_session,
+    ClientSession, FullSession,
+};
+use crate::matrix::timelinecache::TimelineCache;
+use crate::settings::{
+    ca_certificate, danger_disable_tls_verification, device_name, device_warnings, low_bandwidth,
+    proxy, DeviceWarnings,
+};
 use crate::spawn::{save_file, view_file};
-
-use super::mime::mime_from_path;
+use crate::widgets::access::AccessChanges;
+use crate::widgets::devices::UnverifiedDevice;
+use crate::widgets::error::details;
+use crate::widgets::knocks::Knocker;
+use crate::widgets::message::{mentions_room, Message, Trust};
+use crate::widgets::profile::Profile;
+use crate::widgets::state::StateEntry;
+use crate::widgets::verify::Step;
+
+use super::mime::mime_from_file;
 use super::notify::Notify;
 
 /// A Matrix client that maintains it's own Tokio runtime
@@ -68,25 +122,77 @@ pub struct Matrix {
     rt: Handle,
     client: Arc<OnceCell<Client>>,
     room_cache: Arc<RoomCache>,
+    timeline_cache: Arc<TimelineCache>,
     notify: Arc<Notify>,
+    fetching: Arc<Mutex<HashSet<PageKey>>>,
+    online: Arc<AtomicBool>,
+    logged_out: Arc<AtomicBool>,
+    synced: Arc<AtomicBool>,
+    sync_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    registration: Arc<Mutex<Option<Registration>>>,
+    queue: Arc<Mutex<Vec<BoxFuture<'static, ()>>>>,
+    // rooms we've already warned about unverified devices in
+    warned_rooms: Arc<Mutex<HashSet<OwnedRoomId>>>,
+    seen_devices: Arc<Mutex<HashSet<(OwnedUserId, OwnedDeviceId)>>>,
 }
 
+/// A page of a room's history, by the cursor that fetches it.
+type PageKey = (OwnedRoomId, Option<String>);
+
 /// What should we do with the file after we download it?
 pub enum AfterDownload {
     View,
     Save,
+    /// View a smaller, quicker version, if there is one.
+    Preview,
 }
 
+/// Something on its way to a room, held back while the user looks over who
+/// will be able to read it.
+#[derive(Clone, Debug)]
+pub enum Outgoing {
+    Text(String),
+    Reply(String, OwnedEventId),
+    // and a caption for the last of them
+    Attachments(Vec<PathBuf>, Option<String>),
+}
+
+/// A device first seen this recently gets called out as new.
+const NEW_DEVICE_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How big a preview we ask the homeserver for.
+const PREVIEW_SIZE: u32 = 1280;
+
+/// The same as the SDK's, for requests that don't ask for their own.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
 impl Matrix {
     pub fn new(runtime: &Runtime) -> Self {
         Matrix {
             rt: runtime.handle().clone(),
             client: Arc::new(OnceCell::default()),
             room_cache: Arc::new(RoomCache::default()),
+            timeline_cache: Arc::new(TimelineCache::default()),
             notify: Arc::new(Notify::default()),
+            fetching: Arc::new(Mutex::new(HashSet::new())),
+            online: Arc::new(AtomicBool::new(true)),
+            logged_out: Arc::new(AtomicBool::new(false)),
+            synced: Arc::new(AtomicBool::new(false)),
+            sync_task: Arc::new(Mutex::new(None)),
+            registration: Arc::new(Mutex::new(None)),
+            queue: Arc::new(Mutex::new(vec![])),
+            warned_rooms: Arc::new(Mutex::new(HashSet::new())),
+            seen_devices: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
+    /// Ask for the session passphrase, if the session file needs one. This
+    /// has to happen before the terminal is taken over.
+    pub fn unlock_session() -> anyhow::Result<()> {
+        let (_, session_file) = Matrix::dirs();
+        unlock_session(&session_file)
+    }
+
     fn dirs() -> (PathBuf, PathBuf) {
         let data_dir = dirs::data_dir()
             .expect("no data directory found")
@@ -113,6 +219,12 @@ impl Matrix {
             .expect("could not send Matrix event");
     }
 
+    /// Report a failure, keeping what caused it for the details.
+    pub fn send_error(err: impl Into<anyhow::Error>) {
+        let err = err.into();
+        Matrix::send(Error(err.to_string(), details(&err)));
+    }
+
     pub fn init(&self) {
         info!("initializing matrix");
 
@@ -131,28 +243,25 @@ impl Matrix {
             let (client, token) = match restore_session(session_file.as_path()).await {
                 Ok(tuple) => tuple,
                 Err(err) => {
-                    Matrix::send(Error(err.to_string()));
+                    Matrix::send_error(err);
                     return;
                 }
             };
 
             info!("session restored");
 
+            matrix.unlock_caches(&session_file).await;
+
             matrix
                 .client
                 .set(client.clone())
                 .expect("could not set client");
 
-            info!("syncing with token {:?}", token);
-
-            if let Err(err) = sync_once(client.clone(), token, &session_file).await {
-                Matrix::send(Error(err.to_string()));
-                return;
-            };
+            matrix.watch_session(client.clone());
 
-            matrix.room_cache.populate(client).await;
+            info!("syncing with token {:?}", token);
 
-            Matrix::send(MatuiEvent::SyncComplete);
+            matrix.first_sync(client, token, &session_file).await;
         });
     }
 
@@ -162,73 +271,286 @@ impl Matrix {
         let pass = password.to_string();
         let matrix = self.clone();
 
+        // our token went bad, so sign back in to the same device
+        if let Some(client) = self.client.get().cloned() {
+            self.rt.spawn(async move {
+                Matrix::send(MatuiEvent::LoginStarted);
+
+                if let Err(err) = relogin(&client, &session_file, &user, &pass).await {
+                    Matrix::send(MatuiEvent::LoginFailed(err.to_string()));
+                    return;
+                }
+
+                matrix.logged_out.store(false, Ordering::Relaxed);
+                Matrix::send(MatuiEvent::LoginComplete);
+
+                // the token may have gone bad before we ever got going
+                if !matrix.synced.load(Ordering::Relaxed) {
+                    let token = load_session(&session_file)
+                        .await
+                        .ok()
+                        .and_then(|s| s.sync_token);
+                    matrix.first_sync(client, token, &session_file).await;
+                }
+            });
+
+            return;
+        }
+
         self.rt.spawn(async move {
             Matrix::send(MatuiEvent::LoginStarted);
 
             let client = match login(&data_dir, &session_file, &user, &pass).await {
                 Ok(client) => client,
                 Err(err) => {
-                    Matrix::send(Error(err.to_string()));
+                    Matrix::send(MatuiEvent::LoginFailed(err.to_string()));
                     return;
                 }
             };
 
-            matrix
-                .client
-                .set(client.clone())
-                .expect("could not set client");
+            matrix.start(client, &session_file).await;
+        });
+    }
+
+    /// Sign up for a new account. The homeserver will usually want more
+    /// from the user, so this can end in a `RegistrationStep`, and then it's
+    /// back here by way of `continue_registration`.
+    pub fn register(
+        &self,
+        username: &str,
+        password: &str,
+        email: Option<String>,
+        token: Option<String>,
+    ) {
+        let (data_dir, _) = Matrix::dirs();
+        let user = username.to_string();
+        let pass = password.to_string();
+        let matrix = self.clone();
 
-            Matrix::send(MatuiEvent::LoginComplete);
-            Matrix::send(MatuiEvent::SyncStarted(SyncType::Initial));
+        self.rt.spawn(async move {
+            Matrix::send(ProgressStarted("Registering".to_string(), 0));
 
-            if let Err(err) = sync_once(client.clone(), None, &session_file).await {
-                Matrix::send(Error(err.to_string()));
-                return;
-            };
+            let result = async {
+                let id = <&UserId>::try_from(user.as_str())?;
+                let (client, client_session) = build_client(&data_dir, id).await?;
+
+                let mut registration =
+                    Registration::new(client, client_session, id.localpart(), &pass, email, token);
+
+                let outcome = registration.advance().await?;
+
+                anyhow::Ok((registration, outcome))
+            }
+            .await;
+
+            match result {
+                Ok((registration, outcome)) => matrix.registered(registration, outcome).await,
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
 
-            matrix.room_cache.populate(client.clone()).await;
+    pub fn continue_registration(&self, code: &str) {
+        let Some(mut registration) = self.registration.lock().unwrap().take() else {
+            Matrix::send(Error(
+                "There's no registration in progress.".to_string(),
+                None,
+            ));
+            return;
+        };
+
+        let code = code.to_string();
+        let matrix = self.clone();
+
+        self.rt.spawn(async move {
+            Matrix::send(ProgressStarted("Registering".to_string(), 0));
+
+            match registration.resume(&code).await {
+                Ok(outcome) => matrix.registered(registration, outcome).await,
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    pub fn cancel_registration(&self) {
+        if let Some(registration) = self.registration.lock().unwrap().take() {
+            // nobody's ever going to use this store
+            let _ = std::fs::remove_dir_all(registration.client_session.db_path);
+        }
+
+        Matrix::send(MatuiEvent::LoginRequired);
+    }
+
+    async fn registered(&self, registration: Registration, outcome: Outcome) {
+        let Outcome::Done = outcome else {
+            if let Outcome::Waiting(step, error) = outcome {
+                *self.registration.lock().unwrap() = Some(registration);
+                Matrix::send(MatuiEvent::RegistrationStep(step, error));
+            }
+
+            return;
+        };
+
+        let (_, session_file) = Matrix::dirs();
+        let client = registration.client;
+
+        let Some(user_session) = client.matrix_auth().session() else {
+            Matrix::send(Error(
+                "Registered, but the homeserver didn't sign us in.".to_string(),
+                None,
+            ));
+            return;
+        };
+
+        if let Err(err) = save_session(
+            &session_file,
+            &FullSession::new(registration.client_session, user_session),
+        )
+        .await
+        {
+            Matrix::send_error(err);
+            return;
+        }
+
+        self.start(client, &session_file).await;
+    }
 
-            Matrix::send(MatuiEvent::SyncComplete);
+    // Everything after we have a freshly signed-in client.
+    async fn start(&self, client: Client, session_file: &Path) {
+        self.unlock_caches(session_file).await;
 
-            if let Some(user_id) = client.user_id() {
-                match client.encryption().get_user_identity(user_id).await {
-                    Ok(Some(identity)) => {
-                        if let Err(err) = identity
-                            .request_verification_with_methods(vec![VerificationMethod::SasV1])
-                            .await
-                        {
-                            error!("could not request verification: {}", err);
-                        } else {
+        self.client
+            .set(client.clone())
+            .expect("could not set client");
+
+        self.watch_session(client.clone());
+
+        Matrix::send(MatuiEvent::LoginComplete);
+        Matrix::send(MatuiEvent::SyncStarted(SyncType::Initial));
+
+        if !self.first_sync(client.clone(), None, session_file).await {
+            return;
+        }
+
+        if let Some(user_id) = client.user_id() {
+            match client.encryption().get_user_identity(user_id).await {
+                Ok(Some(identity)) => {
+                    match identity
+                        .request_verification_with_methods(vec![VerificationMethod::SasV1])
+                        .await
+                    {
+                        Ok(request) => {
                             info!("verification requested");
+                            tokio::spawn(request_verification_handler(request));
                         }
+                        Err(err) => error!("could not request verification: {}", err),
                     }
-                    Ok(None) => error!("no user identity"),
-                    Err(err) => error!("could not get user identity: {}", err),
                 }
+                Ok(None) => error!("no user identity"),
+                Err(err) => error!("could not get user identity: {}", err),
             }
-        });
+        }
+    }
+
+    // The sync that fills the room cache, before the long-running one can
+    // start. Returns false if it didn't work out.
+    async fn first_sync(&self, client: Client, token: Option<String>, session_file: &Path) -> bool {
+        if let Err(err) = sync_once(client.clone(), token, session_file).await {
+            // the session watcher has already asked them to sign back in
+            if err
+                .downcast_ref::<matrix_sdk::Error>()
+                .is_some_and(is_unknown_token)
+            {
+                info!("initial sync needs a new login");
+            } else {
+                Matrix::send_error(err);
+            }
+
+            return false;
+        };
+
+        self.synced.store(true, Ordering::Relaxed);
+        self.room_cache.populate(client.clone()).await;
+        roaming::load(&client).await;
+
+        Matrix::send(MatuiEvent::SyncComplete);
+
+        let room_cache = self.room_cache.clone();
+        tokio::spawn(async move { room_cache.hydrate(client).await });
+
+        true
     }
 
     pub fn sync(&self) {
         add_default_handlers(self.client());
         add_verification_handlers(self.client());
 
+        let matrix = self.clone();
+
+        let task = self.rt.spawn(async move {
+            let mut attempt = 0;
+
+            loop {
+                // there's no point in syncing until we sign back in
+                if matrix.logged_out.load(Ordering::Relaxed) {
+                    attempt = 0;
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+
+                let result = matrix.sync_forever().await;
+
+                // we only get here when the sync has failed
+                if let Err(err) = result {
+                    error!("sync failed: {}", err);
+                }
+
+                // start over if we had made it back online
+                if matrix.is_online() {
+                    attempt = 0;
+                }
+
+                matrix.set_online(false);
+
+                let delay = backoff(attempt);
+                attempt += 1;
+
+                info!("retrying sync in {:?}", delay);
+                tokio::time::sleep(delay).await;
+            }
+        });
+
+        *self.sync_task.lock().expect("to lock sync task") = Some(task);
+    }
+
+    // Sync until there's an error, which is handed back to be retried.
+    async fn sync_forever(&self) -> anyhow::Result<()> {
         let client = self.client();
 
         // apparently we only need the token for sync_once
         let sync_settings = build_sync_settings(None);
 
-        self.rt.spawn(async move {
-            client
-                .sync_with_result_callback(sync_settings, |sync_result| async move {
+        client
+            .sync_with_result_callback(sync_settings, |sync_result| {
+                let matrix = self.clone();
+
+                async move {
                     let response = match sync_result {
                         Ok(resp) => resp,
                         Err(err) => {
                             error!("no sync result: {}", err.to_string());
-                            return Ok(LoopCtrl::Continue);
+                            return Err(err);
                         }
                     };
 
+                    matrix.set_online(true);
+
+                    if roaming::changed(&response.account_data) {
+                        roaming::load(&matrix.client()).await;
+                    }
+
+                    matrix.report_gaps(&response);
+
                     let (_, session_file) = Matrix::dirs();
 
                     // We persist the token each time to keep the disk up-to-date
@@ -237,17 +559,137 @@ impl Matrix {
                     }
 
                     Ok(LoopCtrl::Continue)
-                })
-                .await
-                .expect("could not sync");
+                }
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    // A limited timeline means the server skipped over some events (we were
+    // offline for a while, or it was a busy room), so let the chat know where
+    // the hole is.
+    fn report_gaps(&self, response: &SyncResponse) {
+        for (room_id, update) in response.rooms.join.iter() {
+            let timeline = &update.timeline;
+
+            if !timeline.limited {
+                continue;
+            }
+
+            let (Some(token), Some(first)) = (&timeline.prev_batch, timeline.events.first()) else {
+                continue;
+            };
+
+            let Ok(Some(before)) = first.raw().get_field("origin_server_ts") else {
+                continue;
+            };
+
+            if let Some(room) = self.client().get_room(room_id) {
+                Matrix::send(MatuiEvent::TimelineGap(room, token.clone(), before));
+            }
+        }
+    }
+
+    // Keep the session file in step with refreshed tokens, and ask for a
+    // new login when the server forgets about us.
+    fn watch_session(&self, client: Client) {
+        let matrix = self.clone();
+        let mut changes = client.subscribe_to_session_changes();
+
+        self.rt.spawn(async move {
+            while let Ok(change) = changes.recv().await {
+                match change {
+                    SessionChange::TokensRefreshed => {
+                        info!("access token refreshed");
+
+                        let Some(session) = client.matrix_auth().session() else {
+                            continue;
+                        };
+
+                        let (_, session_file) = Matrix::dirs();
+
+                        if let Err(err) = persist_user_session(&session_file, session).await {
+                            error!("could not persist session {}", err.to_string())
+                        }
+                    }
+                    SessionChange::UnknownToken { soft_logout } => {
+                        info!("unknown token, soft logout: {}", soft_logout);
+
+                        if !matrix.logged_out.swap(true, Ordering::Relaxed) {
+                            let user_id = client
+                                .user_id()
+                                .map(|id| id.to_string())
+                                .unwrap_or_default();
+
+                            Matrix::send(MatuiEvent::SessionExpired(user_id, soft_logout));
+                        }
+                    }
+                }
+            }
         });
     }
 
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Relaxed)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Relaxed)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.lock().expect("to lock queue").len()
+    }
+
+    fn set_online(&self, online: bool) {
+        if self.online.swap(online, Ordering::Relaxed) == online {
+            return;
+        }
+
+        if online {
+            info!("back online");
+            Matrix::send(MatuiEvent::ConnectionState(ConnectionState::Online));
+
+            // send everything that piled up while we were away
+            let queue: Vec<_> = self
+                .queue
+                .lock()
+                .expect("to lock queue")
+                .drain(..)
+                .collect();
+
+            for action in queue {
+                self.rt.spawn(action);
+            }
+        } else {
+            info!("offline");
+            Matrix::send(MatuiEvent::ConnectionState(ConnectionState::Offline));
+        }
+    }
+
+    // Anything that goes out to the server waits until we're online.
+    fn spawn_outgoing<F>(&self, action: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if self.is_online() {
+            self.rt.spawn(action);
+        } else {
+            info!("offline; queuing outgoing action");
+            self.queue
+                .lock()
+                .expect("to lock queue")
+                .push(Box::pin(action));
+            Matrix::send(MatuiEvent::ConnectionState(ConnectionState::Offline));
+        }
+    }
+
     pub fn confirm_verification(&self, sas: SasVerification) {
         self.rt.spawn(async move {
             if let Err(err) = sas.confirm().await {
                 error!("could not verify: {}", err.to_string());
-                Matrix::send(Error(format!("Could not verify: {}", err)));
+                Matrix::send_error(anyhow::Error::new(err).context("Could not verify."));
             }
         });
     }
@@ -262,52 +704,515 @@ impl Matrix {
         });
     }
 
+    // Back out, whichever part of the way through we are.
+    pub fn cancel_verification(
+        &self,
+        request: Option<VerificationRequest>,
+        sas: Option<SasVerification>,
+    ) {
+        self.rt.spawn(async move {
+            let result = match (sas, request) {
+                (Some(sas), _) => sas.cancel().await,
+                (None, Some(request)) => request.cancel().await,
+                (None, None) => return,
+            };
+
+            if let Err(err) = result {
+                error!("could not cancel verification: {}", err.to_string())
+            } else {
+                info!("verification has been cancelled")
+            }
+        });
+    }
+
     pub fn fetch_rooms(&self) -> Vec<DecoratedRoom> {
         self.room_cache.get_rooms()
     }
 
+    /// What we had of the room last time, read off the disk in the
+    /// background.
+    pub fn load_cached_messages(&self, room: Room) {
+        let cache = self.timeline_cache.clone();
+
+        self.rt.spawn_blocking(move || {
+            let events = cache.load(room.room_id());
+
+            if !events.is_empty() {
+                Matrix::send(MatuiEvent::CachedTimeline(room, events));
+            }
+        });
+    }
+
+    // The caches on disk are sealed under the store passphrase.
+    async fn unlock_caches(&self, session_file: &Path) {
+        let passphrase = match load_session(session_file).await {
+            Ok(session) => session.client_session.passphrase,
+            Err(err) => {
+                error!("could not unlock the timeline cache: {}", err);
+                return;
+            }
+        };
+
+        let cache = self.timeline_cache.clone();
+        let result = tokio::task::spawn_blocking(move || cache.unlock(&passphrase)).await;
+
+        if let Err(err) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+            error!("could not unlock the timeline cache: {}", err);
+        }
+    }
+
     pub fn fetch_messages(&self, room: Room, cursor: Option<String>) {
+        self.fetch_page(room, cursor, false);
+    }
+
+    /// Fill in a stretch of history that sync skipped over.
+    pub fn fetch_gap(&self, room: Room, token: String) {
+        self.fetch_page(room, Some(token), true);
+    }
+
+    fn fetch_page(&self, room: Room, cursor: Option<String>, gap: bool) {
+        let matrix = self.clone();
+        let key = (room.room_id().to_owned(), cursor.clone());
+
+        // don't fetch the same page twice at once
+        if !self
+            .fetching
+            .lock()
+            .expect("to lock fetches")
+            .insert(key.clone())
+        {
+            info!("already fetching {:?}", key);
+            return;
+        }
+
         self.rt.spawn(async move {
-            Matrix::send(ProgressStarted("Fetching more messages.".to_string(), 1000));
+            matrix.fetch_messages_internal(room, cursor, gap).await;
+            matrix
+                .fetching
+                .lock()
+                .expect("to lock fetches")
+                .remove(&key);
+        });
+    }
+
+    async fn fetch_messages_internal(&self, room: Room, cursor: Option<String>, gap: bool) {
+        // only the first page blocks anything; the rest is prefetching
+        let latest = cursor.is_none();
+        let requested = if gap { cursor.clone() } else { None };
+
+        if latest {
+            Matrix::send(ProgressStarted("Fetching messages.".to_string(), 1000));
+        }
+
+        // fetch the actual messages
+        let mut options = MessagesOptions::new(Direction::Backward);
+        options.limit = UInt::from(25_u16);
+        options.from = cursor;
+
+        let messages = match room.messages(options).await {
+            Ok(msg) => msg,
+            Err(err) => {
+                Matrix::send_error(err);
+                return;
+            }
+        };
 
-            // fetch the actual messages
-            let mut options = MessagesOptions::new(Direction::Backward);
-            options.limit = UInt::from(25_u16);
-            options.from = cursor;
+        let trust = messages
+            .chunk
+            .iter()
+            .filter_map(|te| {
+                let id = te.kind.event_id()?;
+                let info = te.encryption_info()?;
+                Some((id, Trust::from_state(&info.verification_state)))
+            })
+            .collect();
+
+        let unpacked: Vec<AnyTimelineEvent> = messages
+            .chunk
+            .iter()
+            .map(|te| {
+                Matrix::deserialize_event(te, room.room_id().into()).expect("could not deserialize")
+            })
+            .collect();
+
+        // hold on to the latest page so we can show it right away next time
+        if latest {
+            let raw = messages.chunk.iter().map(|te| te.raw().clone()).collect();
+            self.timeline_cache.store(room.room_id(), raw);
+            Matrix::send(MatuiEvent::ProgressComplete);
+        }
 
-            let messages = match room.messages(options).await {
-                Ok(msg) => msg,
+        let batch = Batch {
+            room: room.clone(),
+            events: unpacked,
+            cursor: messages.end,
+            gap: requested,
+            trust,
+        };
+
+        Matrix::send(MatuiEvent::TimelineBatch(batch));
+    }
+
+    pub fn fetch_room_member(&self, room: Room, id: OwnedUserId) {
+        self.rt.spawn(async move {
+            match room.get_member(&id).await {
+                Ok(Some(member)) => Matrix::send(MatuiEvent::RoomMember(room, member)),
+                Ok(None) => info!("{} is not a member of {}", id, room.room_id()),
+                Err(err) => error!("could not fetch member {}: {}", id, err),
+            }
+        });
+    }
+
+    pub fn fetch_room_members(&self, room: Room) {
+        self.rt.spawn(async move {
+            // this is lazy-loaded (and then cached) by the SDK
+            match room.members(RoomMemberships::JOIN).await {
+                Ok(members) => Matrix::send(MatuiEvent::RoomMembers(room, members)),
                 Err(err) => {
-                    Matrix::send(Error(err.to_string()));
+                    error!("could not fetch members: {}", err);
+                    Matrix::send(MatuiEvent::RoomMembers(room, vec![]));
+                }
+            }
+        });
+    }
+
+    /// Load the image packs available in a room, for custom emoji.
+    pub fn load_emotes(&self, room: Room) {
+        let client = self.client();
+
+        self.rt.spawn(async move {
+            emotes::load(&client, &room).await;
+            Matrix::send(MatuiEvent::EmotesLoaded(room));
+        });
+    }
+
+    /// Look for calls (Jitsi, Element Call) pinned to the room as widgets.
+    pub fn load_calls(&self, room: Room) {
+        self.rt.spawn(async move {
+            let calls = calls::call_widgets(&room).await;
+            Matrix::send(MatuiEvent::Calls(room, calls));
+        });
+    }
+
+    /// Parse a `matrix:` URI, or a matrix.to link.
+    pub fn parse_link(link: &str) -> Option<MatrixId> {
+        if let Ok(uri) = MatrixUri::parse(link) {
+            return Some(uri.id().clone());
+        }
+
+        MatrixToUri::parse(link).ok().map(|uri| uri.id().clone())
+    }
+
+    /// Find the room, and maybe the event, that a link points to.
+    pub fn open_link(&self, id: MatrixId) {
+        let client = self.client();
+
+        self.rt.spawn(async move {
+            match resolve_link(&client, id).await {
+                Ok(Link::Joined(room, event)) => Matrix::send(MatuiEvent::Navigate(room, event)),
+                Ok(Link::Elsewhere(target)) => Matrix::send(MatuiEvent::JoinPrompt(target)),
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    /// Join a room, or offer to knock if it needs an invite.
+    pub fn join_room(&self, target: OwnedRoomOrAliasId) {
+        let client = self.client();
+
+        self.spawn_outgoing(async move {
+            Matrix::send(ProgressStarted("Joining.".to_string(), 250));
+
+            let result = client.join_room_by_id_or_alias(&target, &[]).await;
+
+            Matrix::send(ProgressComplete);
+
+            match result {
+                Ok(room) => Matrix::send(MatuiEvent::Navigate(room, None)),
+                Err(err) if is_forbidden(&err) => Matrix::send(MatuiEvent::KnockPrompt(target)),
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    pub fn knock(&self, target: OwnedRoomOrAliasId, reason: Option<String>) {
+        let client = self.client();
+
+        self.spawn_outgoing(async move {
+            match client.knock(target.clone(), reason, vec![]).await {
+                Ok(_) => Matrix::send(MatuiEvent::Notice(
+                    "Knocked".to_string(),
+                    format!(
+                        "You'll be able to join {} once someone lets you in.",
+                        target
+                    ),
+                )),
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    /// Everyone knocking on a room, if I'm allowed to let them in.
+    pub fn fetch_knocks(&self, room: Room, name: String) {
+        let me = self.me();
+
+        self.rt.spawn(async move {
+            if !room.can_user_invite(&me).await.unwrap_or_default() {
+                Matrix::send(Error(
+                    "You can't let anyone into this room.".to_string(),
+                    None,
+                ));
+                return;
+            }
+
+            match room.members(RoomMemberships::KNOCK).await {
+                Ok(members) => {
+                    let knockers = members.iter().map(Knocker::from_member).collect();
+                    Matrix::send(MatuiEvent::Knocks(room, name, knockers));
+                }
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    /// Let someone in with an invite, or turn them away.
+    pub fn answer_knock(&self, room: Room, user_id: OwnedUserId, accept: bool) {
+        self.spawn_outgoing(async move {
+            let result = if accept {
+                room.invite_user_by_id(&user_id).await
+            } else {
+                room.kick_user(&user_id, None).await
+            };
+
+            if let Err(err) = result {
+                Matrix::send_error(err);
+            }
+        });
+    }
+
+    pub fn export_room(&self, room: Room, options: ExportOptions) {
+        let client = self.client();
+
+        self.rt.spawn(async move {
+            Matrix::send(ProgressStarted("Exporting...".to_string(), 0));
+
+            match export_room(&client, &room, &options).await {
+                Ok(count) => {
+                    Matrix::send(ProgressComplete);
+                    Matrix::send(MatuiEvent::Notice(
+                        "Export Complete".to_string(),
+                        format!("Wrote {} messages to {}.", count, options.path.display()),
+                    ));
+                }
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    pub fn rename_device(&self, name: &str) {
+        let client = self.client();
+        let name = name.to_string();
+
+        self.rt.spawn(async move {
+            let Some(device_id) = client.device_id() else {
+                Matrix::send(Error("This session has no device.".to_string(), None));
+                return;
+            };
+
+            match client.rename_device(device_id, &name).await {
+                Ok(_) => Matrix::send(MatuiEvent::Notice(
+                    "Device Renamed".to_string(),
+                    format!("This session is now called \"{}\".", name),
+                )),
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    /// Look up who I am, for the profile popup.
+    pub fn fetch_profile(&self) {
+        let client = self.client();
+
+        self.rt.spawn(async move {
+            let profile = match client.account().fetch_user_profile().await {
+                Ok(p) => p,
+                Err(err) => {
+                    Matrix::send_error(err);
                     return;
                 }
             };
 
-            let unpacked: Vec<AnyTimelineEvent> = messages
-                .chunk
-                .iter()
-                .map(|te| {
-                    Matrix::deserialize_event(te, room.room_id().into())
-                        .expect("could not deserialize")
-                })
-                .collect();
+            Matrix::send(MatuiEvent::Profile(Profile::new(
+                profile.displayname,
+                client
+                    .user_id()
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                client.homeserver().to_string(),
+                client.device_id().map(|id| id.to_string()),
+                profile.avatar_url.map(|uri| uri.to_string()),
+            )));
+        });
+    }
+
+    pub fn set_display_name(&self, name: &str) {
+        let client = self.client();
+        let name = name.to_string();
 
-            let batch = Batch {
-                room: room.clone(),
-                events: unpacked,
-                cursor: messages.end,
+        self.rt.spawn(async move {
+            match client.account().set_display_name(Some(&name)).await {
+                Ok(_) => Matrix::send(MatuiEvent::Notice(
+                    "Display Name Changed".to_string(),
+                    format!("You're now \"{}\" everywhere.", name),
+                )),
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    pub fn set_avatar(&self, path: PathBuf) {
+        let client = self.client();
+
+        self.spawn_outgoing(async move {
+            let data = match fs::read(&path) {
+                Ok(d) => d,
+                Err(err) => {
+                    Matrix::send_error(err);
+                    return;
+                }
             };
 
-            Matrix::send(MatuiEvent::ProgressComplete);
-            Matrix::send(MatuiEvent::TimelineBatch(batch));
+            let content_type = mime_from_file(&path, &data);
+
+            if content_type.type_() != "image" {
+                Matrix::send(Error("An avatar has to be an image.".to_string(), None));
+                return;
+            }
+
+            Matrix::send(ProgressStarted("Uploading avatar.".to_string(), 0));
+
+            let result = client.account().upload_avatar(&content_type, data).await;
+
+            Matrix::send(ProgressComplete);
+
+            match result {
+                Ok(_) => Matrix::send(MatuiEvent::Notice(
+                    "Avatar Changed".to_string(),
+                    "Everyone will see your new avatar.".to_string(),
+                )),
+                Err(err) => Matrix::send_error(err),
+            }
         });
     }
 
-    pub fn fetch_room_member(&self, room: Room, id: OwnedUserId) {
+    /// Save the settings that roam to the account, merged with what's
+    /// already there.
+    pub fn merge_settings(&self) {
+        let client = self.client();
+
         self.rt.spawn(async move {
-            match room.get_member(&id).await {
-                Ok(Some(member)) => Matrix::send(MatuiEvent::RoomMember(room, member)),
-                _ => todo!(),
+            match roaming::merge(&client).await {
+                Ok(_) => Matrix::send(MatuiEvent::Notice(
+                    "Settings Synced".to_string(),
+                    "Reactions, muted and archived rooms, and room settings are saved to your \
+                     account, for your other sessions."
+                        .to_string(),
+                )),
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    pub fn fetch_room_state(&self, room: Room, name: String) {
+        let client = self.client();
+
+        self.rt.spawn(async move {
+            Matrix::send(ProgressStarted("Fetching room state.".to_string(), 250));
+
+            let request = get_state_events::v3::Request::new(room.room_id().to_owned());
+
+            match client.send(request, None).await {
+                Ok(response) => {
+                    let entries = response
+                        .room_state
+                        .iter()
+                        .filter_map(StateEntry::from_raw)
+                        .collect();
+
+                    Matrix::send(MatuiEvent::RoomState(name, entries));
+                }
+                Err(err) => Matrix::send_error(err),
+            }
+        });
+    }
+
+    /// Every space I've joined, by ID and name.
+    pub fn spaces(&self) -> Vec<(OwnedRoomId, String)> {
+        let mut spaces: Vec<(OwnedRoomId, String)> = self
+            .client()
+            .joined_rooms()
+            .into_iter()
+            .filter(|r| r.is_space())
+            .map(|r| {
+                let name = r
+                    .cached_display_name()
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| r.room_id().to_string());
+
+                (r.room_id().to_owned(), name)
+            })
+            .collect();
+
+        spaces.sort_by(|a, b| a.1.cmp(&b.1));
+        spaces
+    }
+
+    pub fn set_room_access(&self, room: Room, changes: AccessChanges) {
+        self.spawn_outgoing(async move {
+            Matrix::send(ProgressStarted("Changing room access.".to_string(), 250));
+
+            let result = async {
+                if let Some((rule, spaces)) = changes.join_rule {
+                    let allow = spaces.into_iter().map(AllowRule::room_membership).collect();
+
+                    let rule = match rule.as_str() {
+                        "public" => JoinRule::Public,
+                        "knock" => JoinRule::Knock,
+                        "restricted" => JoinRule::Restricted(Restricted::new(allow)),
+                        "knock_restricted" => JoinRule::KnockRestricted(Restricted::new(allow)),
+                        _ => JoinRule::Invite,
+                    };
+
+                    room.send_state_event(RoomJoinRulesEventContent::new(rule))
+                        .await?;
+                }
+
+                if let Some(visibility) = changes.history_visibility {
+                    let visibility = HistoryVisibility::from(visibility.as_str());
+
+                    room.send_state_event(RoomHistoryVisibilityEventContent::new(visibility))
+                        .await?;
+                }
+
+                if let Some(access) = changes.guest_access {
+                    let access = GuestAccess::from(access.as_str());
+
+                    room.send_state_event(RoomGuestAccessEventContent::new(access))
+                        .await?;
+                }
+
+                anyhow::Ok(())
+            }
+            .await;
+
+            Matrix::send(ProgressComplete);
+
+            match result {
+                Ok(_) => Matrix::send(MatuiEvent::Notice(
+                    "Room Access Changed".to_string(),
+                    "The room's access settings have been saved.".to_string(),
+                )),
+                Err(err) => Matrix::send_error(err),
             }
         });
     }
@@ -319,6 +1224,10 @@ impl Matrix {
             Matrix::send(ProgressStarted("Downloading file.".to_string(), 250));
 
             let (content_type, request, file_name) = match message {
+                Image(content) if matches!(after, AfterDownload::Preview) => {
+                    let (content_type, request) = preview_request(&content);
+                    (content_type, request, content.body)
+                }
                 Image(content) => (
                     content.info.unwrap().mimetype.unwrap(),
                     MediaRequestParameters {
@@ -344,64 +1253,221 @@ impl Matrix {
                     content.body,
                 ),
                 _ => {
-                    Matrix::send(Error("Unknown file type.".to_string()));
+                    Matrix::send(Error("Unknown file type.".to_string(), None));
+                    return;
+                }
+            };
+
+            let media = match MediaCache::fetch(&matrix.client(), &request, &content_type).await {
+                Err(err) => {
+                    Matrix::send_error(err);
                     return;
                 }
-            };
+                Ok(media) => media,
+            };
+
+            Matrix::send(ProgressComplete);
+
+            match after {
+                AfterDownload::View | AfterDownload::Preview => {
+                    tokio::task::spawn_blocking(move || view_file(media.path()));
+                }
+                AfterDownload::Save => match save_file(media.path(), &file_name) {
+                    Err(err) => Matrix::send_error(err),
+                    Ok(path) => Matrix::send(MatuiEvent::Confirm(
+                        "Download Complete".to_string(),
+                        format!("Saved to {}", path.to_str().unwrap()),
+                    )),
+                },
+            };
+        });
+    }
+
+    pub fn send_text_message(&self, room: Room, message: String) {
+        self.send_checked(room, Outgoing::Text(message));
+    }
+
+    pub fn send_reply(&self, room: Room, message: String, in_reply_to: OwnedEventId) {
+        self.send_checked(room, Outgoing::Reply(message, in_reply_to));
+    }
+
+    pub fn send_attachements(&self, room: Room, paths: Vec<PathBuf>, caption: Option<String>) {
+        self.send_checked(room, Outgoing::Attachments(paths, caption));
+    }
+
+    // Look for devices to warn about first, if we've been asked to.
+    fn send_checked(&self, room: Room, outgoing: Outgoing) {
+        let mode = device_warnings(room.room_id());
+
+        if mode == DeviceWarnings::Off {
+            self.send_now(room, outgoing);
+            return;
+        }
+
+        let matrix = self.clone();
+
+        self.rt.spawn(async move {
+            match matrix.devices_to_warn(&room, mode).await {
+                Ok(devices) if !devices.is_empty() => {
+                    Matrix::send(MatuiEvent::DeviceWarning(room, devices, outgoing));
+                }
+                Ok(_) => matrix.send_now(room, outgoing),
+                Err(err) => {
+                    warn!("could not check devices: {}", err);
+                    matrix.send_now(room, outgoing);
+                }
+            }
+        });
+    }
+
+    async fn devices_to_warn(
+        &self,
+        room: &Room,
+        mode: DeviceWarnings,
+    ) -> anyhow::Result<Vec<UnverifiedDevice>> {
+        if !room.is_encrypted().await? {
+            return Ok(vec![]);
+        }
+
+        if mode == DeviceWarnings::Room
+            && self.warned_rooms.lock().unwrap().contains(room.room_id())
+        {
+            return Ok(vec![]);
+        }
+
+        let client = self.client();
+        let own_device = client.device_id().map(|id| id.to_owned());
+        let new_since = MilliSecondsSinceUnixEpoch::now()
+            .as_secs()
+            .saturating_sub(UInt::try_from(NEW_DEVICE_AGE.as_secs()).unwrap_or_default());
+        let mut warn_about = vec![];
+
+        for member in room.members(RoomMemberships::ACTIVE).await? {
+            let devices = client
+                .encryption()
+                .get_user_devices(member.user_id())
+                .await?;
+
+            for device in devices.devices() {
+                if device.is_verified() || device.is_blacklisted() || device.is_deleted() {
+                    continue;
+                }
+
+                if own_device.as_deref() == Some(device.device_id()) {
+                    continue;
+                }
+
+                let key = (device.user_id().to_owned(), device.device_id().to_owned());
+
+                if mode == DeviceWarnings::Device
+                    && self.seen_devices.lock().unwrap().contains(&key)
+                {
+                    continue;
+                }
+
+                let new = device.first_time_seen_ts().as_secs() >= new_since;
+                warn_about.push(UnverifiedDevice::from_device(&device, new));
+            }
+        }
+
+        Ok(warn_about)
+    }
+
+    /// The user has seen the warning: block whatever they picked, and send.
+    pub fn send_past_warning(
+        &self,
+        room: Room,
+        outgoing: Outgoing,
+        devices: Vec<UnverifiedDevice>,
+        blocked: Vec<UnverifiedDevice>,
+    ) {
+        let matrix = self.clone();
+        let client = self.client();
+
+        self.rt.spawn(async move {
+            for device in blocked {
+                let result = async {
+                    let found = client
+                        .encryption()
+                        .get_device(&device.user_id, &device.device_id)
+                        .await?;
+
+                    if let Some(found) = found {
+                        found.set_local_trust(LocalTrust::BlackListed).await?;
+                    }
+
+                    anyhow::Ok(())
+                }
+                .await;
 
-            let handle = match matrix
-                .client()
-                .media()
-                .get_media_file(&request, None, &content_type.parse().unwrap(), true, None)
-                .await
-            {
-                Err(err) => {
-                    Matrix::send(Error(err.to_string()));
+                // better to not send at all than to send to somebody we meant to block
+                if let Err(err) = result {
+                    Matrix::send_error(err.context(format!(
+                        "Could not block {} of {}, so nothing was sent.",
+                        device.device_id, device.user_id
+                    )));
                     return;
                 }
-                Ok(mfh) => mfh,
-            };
+            }
 
-            Matrix::send(ProgressComplete);
+            matrix
+                .warned_rooms
+                .lock()
+                .unwrap()
+                .insert(room.room_id().to_owned());
+            matrix
+                .seen_devices
+                .lock()
+                .unwrap()
+                .extend(devices.into_iter().map(|d| (d.user_id, d.device_id)));
 
-            match after {
-                AfterDownload::View => {
-                    tokio::task::spawn_blocking(move || view_file(handle));
-                }
-                AfterDownload::Save => match save_file(handle, &file_name) {
-                    Err(err) => Matrix::send(Error(err.to_string())),
-                    Ok(path) => Matrix::send(MatuiEvent::Confirm(
-                        "Download Complete".to_string(),
-                        format!("Saved to {}", path.to_str().unwrap()),
-                    )),
-                },
-            };
+            matrix.send_now(room, outgoing);
         });
     }
 
-    pub fn send_text_message(&self, room: Room, message: String) {
-        self.rt.spawn(async move {
+    fn send_now(&self, room: Room, outgoing: Outgoing) {
+        match outgoing {
+            Outgoing::Text(message) => self.send_text_now(room, message),
+            Outgoing::Reply(message, in_reply_to) => {
+                self.send_reply_now(room, message, in_reply_to)
+            }
+            Outgoing::Attachments(paths, caption) => {
+                self.send_attachments_now(room, paths, caption)
+            }
+        }
+    }
+
+    fn send_text_now(&self, room: Room, message: String) {
+        self.spawn_outgoing(async move {
             Matrix::send(ProgressStarted("Sending message.".to_string(), 500));
 
-            if let Err(err) = room
-                .send(RoomMessageEventContent::text_markdown(message))
-                .await
-            {
-                Matrix::send(Error(err.to_string()));
+            let (content, denied) = Matrix::with_room_mention(
+                &room,
+                &message,
+                RoomMessageEventContent::text_markdown(&message),
+            )
+            .await;
+
+            if let Err(err) = room.send(content).await {
+                Matrix::send_error(err);
             }
 
             Matrix::send(ProgressComplete);
+
+            if denied {
+                Matrix::room_mention_denied();
+            }
         });
     }
 
-    pub fn send_reply(&self, room: Room, message: String, in_reply_to: OwnedEventId) {
-        self.rt.spawn(async move {
+    fn send_reply_now(&self, room: Room, message: String, in_reply_to: OwnedEventId) {
+        self.spawn_outgoing(async move {
             Matrix::send(ProgressStarted("Sending message.".to_string(), 500));
 
             let in_reply_to = match Matrix::get_room_event(&room, &in_reply_to).await {
                 Some(e) => e,
                 None => {
-                    Matrix::send(Error("Could not find reply event.".to_string()));
+                    Matrix::send(Error("Could not find reply event.".to_string(), None));
                     return;
                 }
             };
@@ -410,32 +1476,68 @@ impl Matrix {
                 return;
             };
 
-            let reply = RoomMessageEventContent::text_markdown(message).make_reply_to(
-                og_in_reply_to,
-                ForwardThread::Yes,
-                AddMentions::No,
-            );
+            let (content, denied) = Matrix::with_room_mention(
+                &room,
+                &message,
+                RoomMessageEventContent::text_markdown(&message),
+            )
+            .await;
+
+            let reply = content.make_reply_to(og_in_reply_to, ForwardThread::Yes, AddMentions::No);
 
             if let Err(err) = room.send(reply).await {
-                Matrix::send(Error(err.to_string()));
+                Matrix::send_error(err);
             }
 
             Matrix::send(ProgressComplete);
+
+            if denied {
+                Matrix::room_mention_denied();
+            }
         });
     }
 
-    pub fn send_attachements(&self, room: Room, paths: Vec<PathBuf>) {
+    // Flag an @room for the homeserver, but only if we're allowed to make
+    // one. Also returns whether we weren't.
+    async fn with_room_mention(
+        room: &Room,
+        message: &str,
+        content: RoomMessageEventContent,
+    ) -> (RoomMessageEventContent, bool) {
+        if !mentions_room(message) {
+            return (content, false);
+        }
+
+        let allowed = match room.get_member(room.own_user_id()).await {
+            Ok(Some(me)) => me.can_trigger_room_notification(),
+            _ => false,
+        };
+
+        if !allowed {
+            return (content, true);
+        }
+
+        (content.add_mentions(Mentions::with_room_mention()), false)
+    }
+
+    fn room_mention_denied() {
+        Matrix::send(MatuiEvent::Notice(
+            "@room".to_string(),
+            "You don't have permission to notify the whole room, so nobody was notified."
+                .to_string(),
+        ));
+    }
+
+    fn send_attachments_now(&self, room: Room, paths: Vec<PathBuf>, caption: Option<String>) {
         let total = paths.len();
 
-        self.rt.spawn(async move {
+        self.spawn_outgoing(async move {
             for (i, path) in paths.into_iter().enumerate() {
                 Matrix::send(ProgressStarted(
                     format!("Uploading {} of {}.", i + 1, total),
                     0,
                 ));
 
-                let content_type = mime_from_path(&path);
-
                 let name = path
                     .file_name()
                     .unwrap_or_default()
@@ -446,11 +1548,35 @@ impl Matrix {
                 let data = match fs::read(path.to_str().unwrap()) {
                     Ok(d) => d,
                     Err(err) => {
-                        Matrix::send(Error(err.to_string()));
+                        Matrix::send_error(err);
                         return;
                     }
                 };
 
+                discard_gif(&path);
+
+                let content_type = mime_from_file(&path, &data);
+
+                // the caption goes under the last one, so it reads under them all
+                let caption = if i + 1 == total {
+                    caption.clone()
+                } else {
+                    None
+                };
+
+                // the SDK can't say an image moves, so those go their own way
+                if let Some(animation) = animation(&data, &content_type) {
+                    let result =
+                        send_animation(&room, &name, &content_type, data, animation, caption).await;
+
+                    if let Err(err) = result {
+                        Matrix::send_error(err);
+                    }
+
+                    Matrix::send(ProgressComplete);
+                    continue;
+                }
+
                 // try to grab a thumbnail if it's a video
                 let config = if content_type.type_() == "video" {
                     match get_video_thumbnail(&path) {
@@ -470,10 +1596,10 @@ impl Matrix {
                 };
 
                 if let Err(err) = room
-                    .send_attachment(&name, &content_type, data, config)
+                    .send_attachment(&name, &content_type, data, config.caption(caption))
                     .await
                 {
-                    Matrix::send(Error(err.to_string()));
+                    Matrix::send_error(err);
                 }
 
                 Matrix::send(ProgressComplete);
@@ -481,15 +1607,54 @@ impl Matrix {
         });
     }
 
+    /// Upload the GIF a Tenor or Giphy link points to, instead of the link.
+    pub fn send_gif(&self, room: Room, link: Url) {
+        let matrix = self.clone();
+
+        self.spawn_outgoing(async move {
+            Matrix::send(ProgressStarted("Fetching the GIF.".to_string(), 500));
+
+            let result = fetch_gif(&link).await;
+
+            Matrix::send(ProgressComplete);
+
+            match result {
+                Ok(path) => matrix.send_attachements(room, vec![path], None),
+                Err(err) => Matrix::send_error(err.context("Could not fetch the GIF.")),
+            }
+        });
+    }
+
     pub fn send_reaction(&self, room: Room, event_id: OwnedEventId, key: String) {
-        self.rt.spawn(async move {
+        self.spawn_outgoing(async move {
             Matrix::send(ProgressStarted("Sending reaction.".to_string(), 500));
 
-            if let Err(err) = room
-                .send(ReactionEventContent::new(Annotation::new(event_id, key)))
+            // custom emoji carry their shortcode along (MSC4027), which ruma
+            // doesn't know about
+            let result = if key.starts_with("mxc://") {
+                let shortcode = emotes::shortcode(&key).map(|s| format!(":{}:", s));
+
+                room.send_raw(
+                    "m.reaction",
+                    json!({
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": event_id,
+                            "key": key,
+                        },
+                        "com.beeper.reaction.shortcode": shortcode,
+                    }),
+                )
                 .await
-            {
-                Matrix::send(Error(err.to_string()));
+                .map(|_| ())
+            } else {
+                room.send(ReactionEventContent::new(Annotation::new(event_id, key)))
+                    .await
+                    .map(|_| ())
+            };
+
+            if let Err(err) = result {
+                Matrix::send_error(err);
             }
 
             Matrix::send(ProgressComplete);
@@ -497,17 +1662,42 @@ impl Matrix {
     }
 
     pub fn redact_event(&self, room: Room, event_id: OwnedEventId) {
-        self.rt.spawn(async move {
+        self.spawn_outgoing(async move {
             Matrix::send(ProgressStarted("Removing.".to_string(), 500));
 
             if let Err(err) = room.redact(&event_id, None, None).await {
-                Matrix::send(Error(err.to_string()));
+                Matrix::send_error(err);
             }
 
             Matrix::send(ProgressComplete);
         });
     }
 
+    /// Turn down a call that's ringing. The first version of VoIP only knew
+    /// how to hang up.
+    pub fn reject_call(&self, room: Room, call_id: OwnedVoipId, version: VoipVersionId) {
+        let party_id = self
+            .client()
+            .device_id()
+            .map(|id| OwnedVoipId::from(id.as_str()));
+
+        self.spawn_outgoing(async move {
+            let result = match (version, party_id) {
+                (VoipVersionId::V0, _) | (_, None) => {
+                    room.send(CallHangupEventContent::version_0(call_id)).await
+                }
+                (version, Some(party_id)) => {
+                    room.send(CallRejectEventContent::new(call_id, party_id, version))
+                        .await
+                }
+            };
+
+            if let Err(err) = result {
+                Matrix::send_error(err);
+            }
+        });
+    }
+
     async fn get_room_event(
         room: &Room,
         id: &OwnedEventId,
@@ -521,6 +1711,24 @@ impl Matrix {
         }
     }
 
+    /// Look up the message a reply points at, for when it isn't loaded.
+    pub fn fetch_quote(&self, room: Room, id: OwnedEventId) {
+        self.rt.spawn(async move {
+            let quote = match room.event(&id, None).await {
+                Ok(event) => Matrix::deserialize_event(&event, room.room_id().to_owned())
+                    .ok()
+                    .and_then(|e| Message::try_from(&e, true))
+                    .map(|m| (m.sender.id.clone(), m.display())),
+                Err(err) => {
+                    error!("could not fetch replied-to event: {}", err);
+                    None
+                }
+            };
+
+            Matrix::send(MatuiEvent::Quote(room, id, quote));
+        });
+    }
+
     pub fn deserialize_event(
         event: &TimelineEvent,
         room_id: OwnedRoomId,
@@ -558,7 +1766,7 @@ impl Matrix {
         message: String,
         in_reply_to: Option<OwnedEventId>,
     ) {
-        self.rt.spawn(async move {
+        self.spawn_outgoing(async move {
             Matrix::send(ProgressStarted("Editing message.".to_string(), 500));
 
             let Some(event) = Matrix::get_room_event(&room, &id).await else {
@@ -588,7 +1796,7 @@ impl Matrix {
                 )
                 .await
             {
-                Matrix::send(Error(err.to_string()));
+                Matrix::send_error(err);
             }
 
             Matrix::send(ProgressComplete);
@@ -608,18 +1816,48 @@ impl Matrix {
                 .timeline_event(matrix.client(), &event)
                 .await;
 
+            timeline_hooks(&matrix.client(), &event).await;
+
+            if let Some(call) = calls::incoming_call(&matrix.client(), &event).await {
+                if let Err(e) = matrix.notify.call_event(&call) {
+                    error!("could not send call notification: {}", e.to_string());
+                }
+
+                Matrix::send(MatuiEvent::IncomingCall(call));
+            }
+
             if let Err(e) = matrix.notify.timeline_event(matrix.client(), event).await {
                 error!("could not send notification: {}", e.to_string());
             }
         });
     }
 
+    pub fn typing_event(&self, room: Room, ids: Vec<OwnedUserId>) {
+        let matrix = self.clone();
+
+        self.rt.spawn(async move {
+            matrix
+                .room_cache
+                .typing_event(matrix.client(), room, ids)
+                .await;
+        });
+    }
+
+    pub fn presence_event(&self, id: &UserId, presence: PresenceState) {
+        self.room_cache.presence_event(id, presence);
+    }
+
     pub fn focus_event(&self) {
         self.notify.focus_event();
+
+        if let Some(away) = self.room_cache.focus_event() {
+            Matrix::send(MatuiEvent::Away(away));
+        }
     }
 
     pub fn blur_event(&self) {
         self.notify.blur_event();
+        self.room_cache.blur_event();
     }
 
     pub fn room_visit_event(&self, room: Room) {
@@ -627,7 +1865,7 @@ impl Matrix {
         self.room_cache.room_visit_event(room);
     }
 
-    pub fn read_to(&self, room: Room, to: OwnedEventId) {
+    pub fn read_to(&self, room: Room, to: OwnedEventId) -> JoinHandle<()> {
         let receipts = Receipts::new()
             .fully_read_marker(Some(to.clone()))
             .public_read_receipt(Some(to));
@@ -636,66 +1874,147 @@ impl Matrix {
             if let Err(e) = room.send_multiple_receipts(receipts).await {
                 error!("could not send read receipt: {}", e.to_string());
             }
-        });
+        })
+    }
+
+    /// Stop syncing, close our notifications, and give the last few
+    /// requests a moment to land before we go.
+    pub async fn shutdown(&self, pending: Vec<JoinHandle<()>>) {
+        info!("shutting down");
+
+        // the sync token is written after every response, so there's
+        // nothing to lose by cutting the long poll short
+        if let Some(task) = self.sync_task.lock().expect("to lock sync task").take() {
+            task.abort();
+        }
+
+        self.notify.close_all();
+
+        let result =
+            tokio::time::timeout(Duration::from_secs(2), futures::future::join_all(pending)).await;
+
+        if result.is_err() {
+            error!("timed out waiting for pending requests");
+        }
     }
 
-    pub fn typing_notification(&self, room: Room, typing: bool) {
+    pub fn typing_notification(&self, room: Room, typing: bool) -> JoinHandle<()> {
         self.rt.spawn(async move {
             if let Err(e) = room.typing_notice(typing).await {
                 error!("could not send typing notice: {}", e);
             }
-        });
+        })
     }
 
-    pub fn begin_typing(&self, room: Room) -> Sender<()> {
-        let (send, recv) = channel();
-        let matrix = self.clone();
+    /// Tell the room we're typing, and keep telling it until the guard is
+    /// dropped. However that happens, the room hears that we've stopped.
+    pub fn begin_typing(&self, room: Room) -> TypingGuard {
+        let typing_room = room.clone();
+
+        let task = self.rt.spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
 
-        thread::spawn(move || {
-            while let Err(TryRecvError::Empty) = recv.try_recv() {
-                matrix.typing_notification(room.clone(), true);
-                thread::sleep(Duration::from_millis(1000));
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = typing_room.typing_notice(true).await {
+                    error!("could not send typing notice: {}", e);
+                }
             }
         });
 
-        send
+        TypingGuard {
+            matrix: self.clone(),
+            room,
+            task,
+        }
     }
+}
 
-    pub fn end_typing(&self, room: Room, send: Sender<()>) {
-        send.send(()).expect("could not stop thread");
-        self.typing_notification(room, false);
+/// Keeps a typing notice alive. See `Matrix::begin_typing`.
+pub struct TypingGuard {
+    matrix: Matrix,
+    room: Room,
+    task: JoinHandle<()>,
+}
+
+impl Drop for TypingGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.matrix.typing_notification(self.room.clone(), false);
     }
 }
 
-/// The data needed to re-build a client.
-#[derive(Debug, Serialize, Deserialize)]
-struct ClientSession {
-    homeserver: String,
-    db_path: PathBuf,
-    passphrase: String,
+// What every client gets, whether it's new or restored.
+fn client_builder() -> anyhow::Result<ClientBuilder> {
+    if danger_disable_tls_verification() {
+        Matrix::send(MatuiEvent::Notice(
+            "TLS Verification Disabled".to_string(),
+            "Anyone between you and your homeserver can read and change your traffic. \
+             Use ca_certificate instead, if you can."
+                .to_string(),
+        ));
+    }
+
+    Ok(Client::builder()
+        .handle_refresh_tokens()
+        .http_client(http_client()?))
 }
 
-/// The full session to persist.
-#[derive(Debug, Serialize, Deserialize)]
-struct FullSession {
-    client_session: ClientSession,
-    user_session: MatrixSession,
-    sync_token: Option<String>,
+/// An HTTP client that trusts what we've been told to trust, and goes through
+/// the proxy, for the homeserver and anything else we fetch. The SDK would
+/// build much the same thing, but can't be told about NO_PROXY.
+pub fn http_client() -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!("matui/", env!("CARGO_PKG_VERSION")))
+        .timeout(HTTP_TIMEOUT);
+
+    if let Some(path) = ca_certificate() {
+        let pem = std::fs::read(&path)
+            .with_context(|| format!("Could not read CA certificate {}.", path.display()))?;
+
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Could not parse CA certificate {}.", path.display()))?;
+
+        info!("trusting {} extra root certificate(s)", certificates.len());
+
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if danger_disable_tls_verification() {
+        warn!("TLS VERIFICATION IS DISABLED");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Some(proxy) = proxy() {
+        info!("connecting through {}", proxy);
+
+        let proxy = Proxy::all(&proxy)
+            .with_context(|| format!("Could not use the proxy {}.", proxy))?
+            .no_proxy(NoProxy::from_env());
+
+        builder = builder.proxy(proxy);
+    }
+
+    Ok(builder.build()?)
 }
 
 async fn restore_session(session_file: &Path) -> anyhow::Result<(Client, Option<String>)> {
-    let serialized_session = fs::read_to_string(session_file)?;
-
     let FullSession {
         client_session,
         user_session,
         sync_token,
-    } = serde_json::from_str(&serialized_session)?;
+        ..
+    } = load_session(session_file).await?;
+
+    let user_session = user_session.context("The session file has no user session.")?;
 
     let homeserver = <&ServerName>::try_from(client_session.homeserver.as_str())?;
 
     // Build the client with the previous settings from the session.
-    let client = Client::builder()
+    let client = client_builder()?
         .server_name(homeserver)
         .sqlite_store(client_session.db_path, Some(&client_session.passphrase))
         .build()
@@ -722,7 +2041,8 @@ async fn login(
 
     matrix_auth
         .login_username(username, password)
-        .initial_device_display_name("Matui")
+        .initial_device_display_name(&device_name())
+        .request_refresh_token()
         .send()
         .await?;
 
@@ -730,17 +2050,86 @@ async fn login(
         .session()
         .context("Your logged-in user has no session.")?;
 
-    let serialized_session = serde_json::to_string(&FullSession {
-        client_session,
-        user_session,
-        sync_token: None,
-    })?;
-
-    fs::write(session_file, serialized_session)?;
+    save_session(
+        session_file,
+        &FullSession::new(client_session, user_session),
+    )
+    .await?;
 
     Ok(client)
 }
 
+/// Where a link goes: a room I'm in, or one I'd have to join first.
+enum Link {
+    Joined(Room, Option<OwnedEventId>),
+    Elsewhere(OwnedRoomOrAliasId),
+}
+
+async fn resolve_link(client: &Client, id: MatrixId) -> anyhow::Result<Link> {
+    let (room_or_alias, event): (OwnedRoomOrAliasId, _) = match id {
+        MatrixId::Room(id) => (id.into(), None),
+        MatrixId::RoomAlias(alias) => (alias.into(), None),
+        MatrixId::Event(room, event) => (room, Some(event)),
+        MatrixId::User(user) => {
+            let room = client
+                .joined_rooms()
+                .into_iter()
+                .find(|r| r.direct_targets().contains(&user))
+                .context("You don't have a direct chat with that user.")?;
+
+            return Ok(Link::Joined(room, None));
+        }
+        _ => bail!("That kind of Matrix link isn't supported."),
+    };
+
+    let room_id = match OwnedRoomId::try_from(room_or_alias.clone()) {
+        Ok(id) => id,
+        Err(alias) => client.resolve_room_alias(&alias).await?.room_id,
+    };
+
+    match client
+        .get_room(&room_id)
+        .filter(|r| r.state() == RoomState::Joined)
+    {
+        Some(room) => Ok(Link::Joined(room, event)),
+        None => Ok(Link::Elsewhere(room_or_alias)),
+    }
+}
+
+// Sign in again on a client we already have, keeping its device and store.
+async fn relogin(
+    client: &Client,
+    session_file: &Path,
+    id: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    let id = <&UserId>::try_from(id)?;
+    let matrix_auth = client.matrix_auth();
+
+    // the store belongs to whoever was signed in
+    if let Some(user_id) = client.user_id().filter(|user_id| *user_id != id) {
+        bail!("This session belongs to {}, so sign in as them.", user_id);
+    }
+
+    let device_id = client
+        .device_id()
+        .context("Your logged-in user has no device.")?
+        .to_string();
+
+    matrix_auth
+        .login_username(id.localpart(), password)
+        .device_id(&device_id)
+        .request_refresh_token()
+        .send()
+        .await?;
+
+    let user_session = matrix_auth
+        .session()
+        .context("Your logged-in user has no session.")?;
+
+    persist_user_session(session_file, user_session).await
+}
+
 async fn build_client(data_dir: &Path, id: &UserId) -> anyhow::Result<(Client, ClientSession)> {
     let mut rng = OsRng;
 
@@ -759,7 +2148,7 @@ async fn build_client(data_dir: &Path, id: &UserId) -> anyhow::Result<(Client, C
         .map(char::from)
         .collect();
 
-    let client = Client::builder()
+    let client = client_builder()?
         .server_name(id.server_name())
         .sqlite_store(&db_path, Some(passphrase.as_str()))
         .build()
@@ -775,6 +2164,61 @@ async fn build_client(data_dir: &Path, id: &UserId) -> anyhow::Result<(Client, C
     ))
 }
 
+/// How many events per room to sync in low-bandwidth mode.
+const LOW_BANDWIDTH_TIMELINE_LIMIT: u32 = 10;
+
+// The homeserver can shrink anything it can see. Encrypted images may come
+// with a thumbnail of their own; otherwise, we need the whole thing.
+fn preview_request(content: &ImageMessageEventContent) -> (String, MediaRequestParameters) {
+    let mimetype = content
+        .info
+        .as_ref()
+        .and_then(|i| i.mimetype.clone())
+        .unwrap_or_else(|| "image/jpeg".to_string());
+
+    if let MediaSource::Plain(_) = content.source {
+        // thumbnails come back as PNG or JPEG, depending on the original
+        let content_type = if mimetype == "image/png" {
+            mimetype
+        } else {
+            "image/jpeg".to_string()
+        };
+
+        let size = UInt::from(PREVIEW_SIZE);
+
+        return (
+            content_type,
+            MediaRequestParameters {
+                source: content.source.clone(),
+                format: MediaFormat::Thumbnail(MediaThumbnailSettings::new(size, size)),
+            },
+        );
+    }
+
+    let thumbnail = content.info.as_ref().and_then(|i| {
+        let source = i.thumbnail_source.clone()?;
+        let mimetype = i.thumbnail_info.as_ref()?.mimetype.clone()?;
+        Some((mimetype, source))
+    });
+
+    match thumbnail {
+        Some((mimetype, source)) => (
+            mimetype,
+            MediaRequestParameters {
+                source,
+                format: MediaFormat::File,
+            },
+        ),
+        None => (
+            mimetype,
+            MediaRequestParameters {
+                source: content.source.clone(),
+                format: MediaFormat::File,
+            },
+        ),
+    }
+}
+
 fn build_sync_settings(sync_token: Option<String>) -> SyncSettings {
     let mut state_filter = RoomEventFilter::empty();
     state_filter.lazy_load_options = LazyLoadOptions::Enabled {
@@ -785,6 +2229,13 @@ fn build_sync_settings(sync_token: Option<String>) -> SyncSettings {
     room_filter.state = state_filter;
 
     let mut filter = FilterDefinition::empty();
+
+    // we can backfill whatever we actually look at
+    if low_bandwidth() {
+        room_filter.timeline.limit = Some(UInt::from(LOW_BANDWIDTH_TIMELINE_LIMIT));
+        filter.presence = Filter::ignore_all();
+    }
+
     filter.room = room_filter;
 
     let mut sync_settings = SyncSettings::default().filter(filter.into());
@@ -810,6 +2261,11 @@ async fn sync_once(
                 return Ok(response.next_batch);
             }
             Err(error) => {
+                // no amount of retrying is going to fix that
+                if is_unknown_token(&error) {
+                    return Err(error.into());
+                }
+
                 info!("An error occurred during initial sync: {error}");
                 info!("Trying again…");
             }
@@ -819,24 +2275,52 @@ async fn sync_once(
     bail!("Sync timeout.")
 }
 
-fn persist_sync_token(session_file: &Path, sync_token: String) -> anyhow::Result<()> {
-    let serialized_session = fs::read_to_string(session_file)?;
-    let mut full_session: FullSession = serde_json::from_str(&serialized_session)?;
+// The homeserver doesn't know who we are anymore.
+fn is_unknown_token(err: &matrix_sdk::Error) -> bool {
+    matches!(
+        err.client_api_error_kind(),
+        Some(ErrorKind::UnknownToken { .. })
+    )
+}
 
-    full_session.sync_token = Some(sync_token);
-    let serialized_session = serde_json::to_string(&full_session)?;
-    fs::write(session_file, serialized_session)?;
+// We need to be let in.
+fn is_forbidden(err: &matrix_sdk::Error) -> bool {
+    matches!(
+        err.client_api_error_kind(),
+        Some(ErrorKind::Forbidden { .. })
+    )
+}
 
-    Ok(())
+// Exponential, starting at a second and topping out at a minute.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(2_u64.saturating_pow(attempt).min(60))
 }
 
 fn add_default_handlers(client: Client) {
-    client.add_event_handler(|event: AnySyncTimelineEvent, room: Room| async move {
+    client.add_event_handler(
+        |event: AnySyncTimelineEvent, room: Room, encryption: Option<EncryptionInfo>| async move {
+            // before the event itself, so the shield is there when it shows up
+            if let Some(info) = encryption {
+                let trust = Trust::from_state(&info.verification_state);
+                let id = event.event_id().to_owned();
+                Matrix::send(MatuiEvent::Trust(room.clone(), id, trust));
+            }
+
+            App::get_sender()
+                .send(Matui(MatuiEvent::Timeline(
+                    event.into_full_event(room.room_id().into()),
+                )))
+                .expect("could not send timeline event");
+        },
+    );
+
+    client.add_event_handler(|event: PresenceEvent| async move {
         App::get_sender()
-            .send(Matui(MatuiEvent::Timeline(
-                event.into_full_event(room.room_id().into()),
+            .send(Matui(MatuiEvent::Presence(
+                event.sender,
+                event.content.presence,
             )))
-            .expect("could not send timeline event");
+            .expect("could not send presence event");
     });
 
     client.add_event_handler(|event: AnySyncEphemeralRoomEvent, room: Room| async move {
@@ -858,11 +2342,44 @@ fn add_default_handlers(client: Client) {
             _ => {}
         };
     });
+
+    client.add_event_handler(
+        |event: StrippedRoomMemberEvent, client: Client, room: Room| async move {
+            if client.user_id() != Some(&*event.state_key)
+                || event.content.membership != MembershipState::Invite
+            {
+                return;
+            }
+
+            let room_name = room
+                .compute_display_name()
+                .await
+                .map(|n| n.to_string())
+                .unwrap_or_default();
+
+            run_hook(
+                Hook::Invite,
+                json!({
+                    "room_id": room.room_id(),
+                    "room_name": room_name,
+                    "sender": event.sender,
+                }),
+            );
+        },
+    );
 }
 
 fn add_verification_handlers(client: Client) {
     client.add_event_handler(
         |ev: ToDeviceKeyVerificationRequestEvent, client: Client| async move {
+            run_hook(
+                Hook::Verification,
+                json!({
+                    "sender": ev.sender,
+                    "transaction_id": ev.content.transaction_id,
+                }),
+            );
+
             let request = match client
                 .encryption()
                 .get_verification_request(&ev.sender, &ev.content.transaction_id)
@@ -875,6 +2392,8 @@ fn add_verification_handlers(client: Client) {
                 }
             };
 
+            tokio::spawn(request_verification_handler(request.clone()));
+
             request
                 .accept()
                 .await
@@ -909,6 +2428,8 @@ fn add_verification_handlers(client: Client) {
                     }
                 };
 
+                tokio::spawn(request_verification_handler(request.clone()));
+
                 request
                     .accept()
                     .await
@@ -930,13 +2451,50 @@ fn add_verification_handlers(client: Client) {
     );
 }
 
-async fn sas_verification_handler(sas: SasVerification, sender: Sender<Event>) {
-    sas.accept().await.unwrap();
+// Follow a request until it turns into emoji verification, or doesn't.
+async fn request_verification_handler(request: VerificationRequest) {
+    let mut stream = request.changes();
 
-    let mut stream = sas.changes();
+    Matrix::send(VerificationRequested(request));
 
     while let Some(state) = stream.next().await {
         match state {
+            VerificationRequestState::Ready { .. } => {
+                info!("verification request ready");
+                Matrix::send(VerificationStep(Step::Accepted));
+            }
+            VerificationRequestState::Done => {
+                info!("verification request done");
+                Matrix::send(VerificationStep(Step::Done));
+                break;
+            }
+            VerificationRequestState::Cancelled(info) => {
+                info!("verification request cancelled: {}", info.reason());
+                Matrix::send(VerificationStep(Step::Cancelled(info.reason().to_string())));
+                break;
+            }
+            VerificationRequestState::Transitioned { .. } => info!("verification transitioned"),
+            VerificationRequestState::Created { .. } => info!("verification request created"),
+            VerificationRequestState::Requested { .. } => info!("verification requested"),
+        }
+    }
+}
+
+async fn sas_verification_handler(sas: SasVerification, sender: UnboundedSender<Event>) {
+    // watch from the start, so we don't miss anything accepting kicks off
+    let mut stream = sas.changes();
+
+    sender
+        .send(Matui(VerificationStarted(sas.clone())))
+        .expect("could not send sas started event");
+
+    if let Err(err) = sas.accept().await {
+        error!("could not accept verification: {}", err);
+        return;
+    }
+
+    while let Some(state) = stream.next().await {
+        let step = match state {
             SasState::KeysExchanged {
                 emojis,
                 decimals: _,
@@ -944,23 +2502,42 @@ async fn sas_verification_handler(sas: SasVerification, sender: Sender<Event>) {
                 info!("verification keys exchanged");
 
                 let emoji_slice = emojis.expect("only emoji verification is supported").emojis;
-
-                sender
-                    .send(Matui(VerificationStarted(sas.clone(), emoji_slice)))
-                    .expect("could not send sas started event");
+                Step::Compare(emoji_slice)
             }
             SasState::Done { .. } => {
                 info!("verification done");
-
-                sender
-                    .send(Matui(VerificationCompleted))
-                    .expect("could not send sas completed event");
+                Step::Done
+            }
+            SasState::Cancelled(info) => {
+                info!("verification cancelled: {}", info.reason());
+                Step::Cancelled(info.reason().to_string())
+            }
+            SasState::Started { .. } => {
+                info!("verification started");
+                Step::Exchanging
+            }
+            SasState::Accepted { .. } => {
+                info!("verification accepted");
+                Step::Exchanging
             }
-            SasState::Started { .. } => info!("verification started"),
-            SasState::Accepted { .. } => info!("verification accepted"),
-            SasState::Confirmed => info!("verification confirmed"),
-            SasState::Cancelled(_) => info!("verification cancelled"),
-            SasState::Created { .. } => info!("verification created"),
+            SasState::Confirmed => {
+                info!("verification confirmed");
+                Step::Confirmed
+            }
+            SasState::Created { .. } => {
+                info!("verification created");
+                continue;
+            }
+        };
+
+        let finished = matches!(step, Step::Done | Step::Cancelled(_));
+
+        sender
+            .send(Matui(VerificationStep(step)))
+            .expect("could not send sas step event");
+
+        if finished {
+            break;
         }
     }
 }
@@ -998,3 +2575,19 @@ pub fn format_emojis(emojis: [Emoji; 7]) -> String {
         .collect::<Vec<_>>()
         .join("")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link() {
+        let id = Matrix::parse_link("matrix:r/somewhere:example.org").unwrap();
+        assert!(matches!(id, MatrixId::RoomAlias(a) if a == "#somewhere:example.org"));
+
+        let id = Matrix::parse_link("https://matrix.to/#/@alice:example.org").unwrap();
+        assert!(matches!(id, MatrixId::User(u) if u == "@alice:example.org"));
+
+        assert!(Matrix::parse_link("https://example.org").is_none());
+    }
+}
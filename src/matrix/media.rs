@@ -0,0 +1,383 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::io::Reader as ImageReader;
+use image::{AnimationDecoder, ImageOutputFormat};
+use log::warn;
+use matrix_sdk::reqwest::Url;
+use matrix_sdk::room::Room;
+use matrix_sdk::ruma::exports::serde_json::{json, Value};
+use mime::{Mime, IMAGE_PNG};
+
+use crate::matrix::matrix::http_client;
+
+/// The still we send along with an animation is no bigger than this.
+const THUMBNAIL_SIZE: (u32, u32) = (800, 600);
+
+/// Nobody needs a GIF bigger than this.
+const MAX_GIF_SIZE: u64 = 50 * 1024 * 1024;
+
+/// A fetched GIF still waiting to be sent after this long never will be.
+const STALE_GIF: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What other clients need to know to play an animated image: how big it is,
+/// and a still of the first frame for the ones that don't autoplay.
+pub struct Animation {
+    pub width: u32,
+    pub height: u32,
+    pub thumbnail: Option<Still>,
+}
+
+pub struct Still {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The animation in a GIF, WebP or PNG, if it's got more than one frame.
+pub fn animation(data: &[u8], mime: &Mime) -> Option<Animation> {
+    if !is_animated(data, mime) {
+        return None;
+    }
+
+    let (width, height) = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+
+    Some(Animation {
+        width,
+        height,
+        thumbnail: still(data),
+    })
+}
+
+fn is_animated(data: &[u8], mime: &Mime) -> bool {
+    match mime.essence_str() {
+        "image/gif" => GifDecoder::new(Cursor::new(data))
+            .map(|d| d.into_frames().take(2).count() > 1)
+            .unwrap_or(false),
+        "image/webp" => WebPDecoder::new(Cursor::new(data))
+            .map(|d| d.has_animation())
+            .unwrap_or(false),
+        "image/png" => PngDecoder::new(Cursor::new(data))
+            .map(|d| d.is_apng())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+// the first frame, shrunk down and saved as a PNG
+fn still(data: &[u8]) -> Option<Still> {
+    let mut frame = image::load_from_memory(data).ok()?;
+
+    // thumbnail() would blow a small one up, too
+    if frame.width() > THUMBNAIL_SIZE.0 || frame.height() > THUMBNAIL_SIZE.1 {
+        frame = frame.thumbnail(THUMBNAIL_SIZE.0, THUMBNAIL_SIZE.1);
+    }
+
+    let mut png = vec![];
+    frame
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .ok()?;
+
+    Some(Still {
+        data: png,
+        width: frame.width(),
+        height: frame.height(),
+    })
+}
+
+/// Send an animated image ourselves, since the SDK has nowhere to say that
+/// it moves (MSC4230), and clients won't autoplay it unless we do.
+pub async fn send_animation(
+    room: &Room,
+    name: &str,
+    mime: &Mime,
+    data: Vec<u8>,
+    animation: Animation,
+    caption: Option<String>,
+) -> anyhow::Result<()> {
+    let size = data.len();
+    let encrypted = room.is_encrypted().await?;
+
+    let mut info = json!({
+        "mimetype": mime.essence_str(),
+        "size": size,
+        "w": animation.width,
+        "h": animation.height,
+        "is_animated": true,
+        "org.matrix.msc4230.is_animated": true,
+    });
+
+    if let Some(still) = animation.thumbnail {
+        info["thumbnail_info"] = json!({
+            "mimetype": IMAGE_PNG.essence_str(),
+            "size": still.data.len(),
+            "w": still.width,
+            "h": still.height,
+        });
+
+        let (key, source) = upload(room, &IMAGE_PNG, still.data, encrypted).await?;
+        info[format!("thumbnail_{}", key)] = source;
+    }
+
+    // with a caption, the body is the caption, and the name moves (MSC2530)
+    let mut content = match caption {
+        Some(caption) => json!({ "msgtype": "m.image", "body": caption, "filename": name }),
+        None => json!({ "msgtype": "m.image", "body": name }),
+    };
+
+    let (key, source) = upload(room, mime, data, encrypted).await?;
+    content[key] = source;
+    content["info"] = info;
+
+    room.send_raw("m.room.message", content).await?;
+
+    Ok(())
+}
+
+// Upload the data, encrypted if the room is, and say where it went: either
+// a "url", or a "file" with the keys to it.
+async fn upload(
+    room: &Room,
+    mime: &Mime,
+    data: Vec<u8>,
+    encrypted: bool,
+) -> anyhow::Result<(&'static str, Value)> {
+    let client = room.client();
+
+    if encrypted {
+        let file = client
+            .upload_encrypted_file(mime, &mut Cursor::new(data))
+            .await?;
+
+        Ok(("file", json!(file)))
+    } else {
+        let response = client.media().upload(mime, data, None).await?;
+        Ok(("url", json!(response.content_uri)))
+    }
+}
+
+/// A message that's nothing but a link to a GIF on Tenor or Giphy.
+pub fn gif_link(message: &str) -> Option<Url> {
+    let message = message.trim();
+
+    if message.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let url = Url::parse(message).ok()?;
+
+    let host = url.host_str()?;
+    let known = ["tenor.com", "giphy.com"]
+        .iter()
+        .any(|site| host == *site || host.ends_with(&format!(".{}", site)));
+
+    if known && matches!(url.scheme(), "http" | "https") {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Download the GIF behind a link, whether it goes straight to the file or to
+/// a page that shows it.
+pub async fn fetch_gif(link: &Url) -> anyhow::Result<PathBuf> {
+    let client = http_client()?;
+
+    let media = if is_media(link) {
+        link.clone()
+    } else {
+        let page = client.get(link.clone()).send().await?.error_for_status()?;
+        let html = page.text().await?;
+        let found = og_image(&html).context("There's no GIF on that page.")?;
+        Url::parse(&found)?
+    };
+
+    let mut response = client.get(media.clone()).send().await?.error_for_status()?;
+
+    if response.content_length().unwrap_or_default() > MAX_GIF_SIZE {
+        bail!("That GIF is too big to send.");
+    }
+
+    let mut data = vec![];
+
+    while let Some(chunk) = response.chunk().await? {
+        data.extend_from_slice(&chunk);
+
+        if data.len() as u64 > MAX_GIF_SIZE {
+            bail!("That GIF is too big to send.");
+        }
+    }
+
+    let name = media
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("animation.gif")
+        .to_string();
+
+    // it has to outlive the upload, which might wait on a device warning, so
+    // it's cleaned up after that instead (or later, if it's never sent)
+    let gifs = gif_dir();
+    fs::create_dir_all(&gifs)?;
+    sweep(&gifs);
+
+    let dir = tempfile::Builder::new().tempdir_in(&gifs)?.into_path();
+    let path = dir.join(name);
+    fs::write(&path, &data)?;
+
+    Ok(path)
+}
+
+/// Done with a file, so if it's a GIF we fetched, it can go.
+pub fn discard_gif(path: &Path) {
+    let Some(dir) = path
+        .parent()
+        .filter(|d| d.parent() == Some(gif_dir().as_path()))
+    else {
+        return;
+    };
+
+    if let Err(e) = fs::remove_dir_all(dir) {
+        warn!("could not remove {}: {}", dir.display(), e);
+    }
+}
+
+fn gif_dir() -> PathBuf {
+    std::env::temp_dir().join("matui-gifs")
+}
+
+// anything left over from a GIF that was never sent
+fn sweep(gifs: &Path) {
+    let Ok(entries) = fs::read_dir(gifs) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .map_or(false, |age| age > STALE_GIF);
+
+        if stale {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+fn is_media(url: &Url) -> bool {
+    let path = url.path().to_lowercase();
+    path.ends_with(".gif") || path.ends_with(".webp")
+}
+
+// The preview image a page gives for itself, which is the GIF on both sites.
+fn og_image(html: &str) -> Option<String> {
+    html.split("<meta").skip(1).find_map(|tag| {
+        let tag = &tag[..tag.find('>')?];
+
+        if !tag.contains("\"og:image\"") {
+            return None;
+        }
+
+        let start = tag.find("content=\"")? + "content=\"".len();
+        let end = start + tag[start..].find('"')?;
+
+        Some(tag[start..end].replace("&amp;", "&"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gif_link() {
+        assert!(gif_link("https://tenor.com/view/cat-typing-gif-12345").is_some());
+        assert!(gif_link(" https://media.giphy.com/media/abc/giphy.gif\n").is_some());
+        assert!(gif_link("https://giphy.com/gifs/cat-abc").is_some());
+
+        assert!(gif_link("look https://tenor.com/view/cat-gif-1").is_none());
+        assert!(gif_link("https://nottenor.com/view/cat-gif-1").is_none());
+        assert!(gif_link("https://example.org/cat.gif").is_none());
+        assert!(gif_link("ftp://giphy.com/cat.gif").is_none());
+    }
+
+    #[test]
+    fn test_og_image() {
+        let html = r#"<head>
+            <meta property="og:image:width" content="498">
+            <meta content="https://media.tenor.com/x/cat.gif?a=1&amp;b=2" property="og:image">
+        </head>"#;
+
+        assert_eq!(
+            og_image(html),
+            Some("https://media.tenor.com/x/cat.gif?a=1&b=2".to_string())
+        );
+
+        assert_eq!(
+            og_image("<meta name=\"description\" content=\"cat\">"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_discard_gif() {
+        fs::create_dir_all(gif_dir()).unwrap();
+
+        let dir = tempfile::Builder::new().tempdir_in(gif_dir()).unwrap();
+        let path = dir.path().join("cat.gif");
+        fs::write(&path, b"GIF89a").unwrap();
+
+        discard_gif(&path);
+        assert!(!dir.path().exists());
+
+        // anything else is left alone
+        let elsewhere = tempfile::tempdir().unwrap();
+        let path = elsewhere.path().join("cat.gif");
+        fs::write(&path, b"GIF89a").unwrap();
+
+        discard_gif(&path);
+        assert!(path.exists());
+    }
+
+    fn gif(frames: u8) -> Vec<u8> {
+        use image::codecs::gif::GifEncoder;
+        use image::{Frame, Rgba, RgbaImage};
+
+        let mut data = vec![];
+
+        GifEncoder::new(&mut data)
+            .encode_frames(
+                (0..frames).map(|i| Frame::new(RgbaImage::from_pixel(4, 3, Rgba([i, 0, 0, 255])))),
+            )
+            .unwrap();
+
+        data
+    }
+
+    #[test]
+    fn test_animation() {
+        let animation = animation(&gif(2), &mime::IMAGE_GIF).unwrap();
+
+        assert_eq!((animation.width, animation.height), (4, 3));
+
+        let still = animation.thumbnail.unwrap();
+        assert_eq!((still.width, still.height), (4, 3));
+        assert!(still.data.starts_with(b"\x89PNG"));
+
+        // one frame doesn't move, and a PNG has to say it's an APNG
+        assert!(super::animation(&gif(1), &mime::IMAGE_GIF).is_none());
+        assert!(super::animation(&gif(2), &IMAGE_PNG).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use log::{error, info};
+use matrix_sdk::media::{MediaRequestParameters, UniqueKey};
+use matrix_sdk::Client;
+use ruma::events::room::MediaSource;
+use tempfile::TempPath;
+
+use crate::matrix::mime::{extension_for, mime_from_bytes};
+use crate::settings::media_cache_size;
+
+/// Downloaded media, kept on disk between runs, so that opening something
+/// twice doesn't mean downloading it twice. Files are keyed on the request
+/// (the MXC URI, and the size, for thumbnails), and the least recently used
+/// are thrown out once the cache grows past the configured size.
+///
+/// Only media that was sent in the clear is kept. Anything from an encrypted
+/// room would be sitting around decrypted, so that goes in a temporary file
+/// instead, gone once it's been looked at.
+pub struct MediaCache;
+
+/// Where some media ended up on disk.
+pub struct Media {
+    path: PathBuf,
+    // deletes the file when dropped, if it isn't in the cache
+    _temp: Option<TempPath>,
+}
+
+impl Media {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl MediaCache {
+    /// The media on disk, downloading it first if we have to.
+    pub async fn fetch(
+        client: &Client,
+        request: &MediaRequestParameters,
+        content_type: &str,
+    ) -> anyhow::Result<Media> {
+        if let MediaSource::Encrypted(_) = request.source {
+            let data = client.media().get_media_content(request, false).await?;
+            let mut temp = tempfile::Builder::new();
+            temp.prefix("matui");
+
+            let ext = extension(&data, content_type).map(|ext| format!(".{}", ext));
+
+            if let Some(ext) = &ext {
+                temp.suffix(ext);
+            }
+
+            let file = temp.tempfile()?;
+            fs::write(file.path(), data)?;
+
+            let temp = file.into_temp_path();
+
+            return Ok(Media {
+                path: temp.to_path_buf(),
+                _temp: Some(temp),
+            });
+        }
+
+        let path = Self::fetch_plain(client, request, content_type).await?;
+
+        Ok(Media { path, _temp: None })
+    }
+
+    /// Just the bytes, for when nobody else needs to open it.
+    pub async fn content(
+        client: &Client,
+        request: &MediaRequestParameters,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let MediaSource::Encrypted(_) = request.source {
+            return Ok(client.media().get_media_content(request, false).await?);
+        }
+
+        let path = Self::fetch_plain(client, request, "application/octet-stream").await?;
+
+        Ok(fs::read(path)?)
+    }
+
+    async fn fetch_plain(
+        client: &Client,
+        request: &MediaRequestParameters,
+        content_type: &str,
+    ) -> anyhow::Result<PathBuf> {
+        let dir = cache_dir()?;
+        let stem = key(request);
+
+        if let Some(path) = find(&dir, &stem) {
+            touch(&path);
+            return Ok(path);
+        }
+
+        // the SDK would keep a copy in the store as well; once is enough
+        let data = client.media().get_media_content(request, false).await?;
+
+        let mut path = dir.join(&stem);
+
+        if let Some(ext) = extension(&data, content_type) {
+            path.set_extension(ext);
+        }
+
+        // write it somewhere else first, so nobody sees half a file
+        let partial = dir.join(format!("{}.partial", stem));
+        fs::write(&partial, data)?;
+        fs::rename(&partial, &path)?;
+
+        evict(&dir, media_cache_size() * 1024 * 1024, &path);
+
+        Ok(path)
+    }
+}
+
+fn cache_dir() -> anyhow::Result<PathBuf> {
+    let mut path = dirs::cache_dir().context("no cache directory")?;
+    path.push("matui");
+    path.push("media");
+    fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+// What it really is, if we can tell, or what it says it is.
+fn extension(data: &[u8], content_type: &str) -> Option<&'static str> {
+    let mime = mime_from_bytes(data)
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| content_type.to_string());
+
+    extension_for(&mime)
+}
+
+// A hash that won't change with the toolchain, or the cache would be lost.
+fn key(request: &MediaRequestParameters) -> String {
+    blake3::hash(request.unique_key().as_bytes()).to_hex()[..16].to_string()
+}
+
+// the extension depends on what we downloaded, so look for any
+fn find(dir: &Path, stem: &str) -> Option<PathBuf> {
+    fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let rest = name.strip_prefix(stem)?;
+
+        if rest.is_empty() || (rest.starts_with('.') && !rest.ends_with(".partial")) {
+            Some(entry.path())
+        } else {
+            None
+        }
+    })
+}
+
+// bump the modified time, which is what we evict by
+fn touch(path: &Path) {
+    let result = File::options()
+        .write(true)
+        .open(path)
+        .and_then(|f| f.set_modified(SystemTime::now()));
+
+    if let Err(e) = result {
+        error!("could not touch cached media: {}", e);
+    }
+}
+
+fn evict(dir: &Path, cap: u64, keep: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let files = entries
+        .flatten()
+        .filter(|e| e.path() != keep)
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        })
+        .collect();
+
+    let keep_size = fs::metadata(keep).map(|m| m.len()).unwrap_or_default();
+
+    for path in to_evict(files, cap.saturating_sub(keep_size)) {
+        info!("evicting cached media: {}", path.display());
+
+        if let Err(e) = fs::remove_file(&path) {
+            error!("could not evict cached media: {}", e);
+        }
+    }
+}
+
+// The oldest files, until what's left fits.
+fn to_evict(mut files: Vec<(SystemTime, u64, PathBuf)>, cap: u64) -> Vec<PathBuf> {
+    let mut total: u64 = files.iter().map(|f| f.1).sum();
+
+    files.sort_by_key(|f| f.0);
+
+    let mut evicted = vec![];
+
+    for (_, size, path) in files {
+        if total <= cap {
+            break;
+        }
+
+        total -= size;
+        evicted.push(path);
+    }
+
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let request = MediaRequestParameters {
+            source: MediaSource::Plain(ruma::owned_mxc_uri!("mxc://example.org/abc")),
+            format: matrix_sdk::media::MediaFormat::File,
+        };
+
+        // it's on disk, so it can't ever change
+        assert_eq!(key(&request), "1d84152a8dd5c171");
+    }
+
+    #[test]
+    fn test_to_evict() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let files = vec![
+            (at(3), 10, PathBuf::from("newest")),
+            (at(1), 10, PathBuf::from("oldest")),
+            (at(2), 10, PathBuf::from("middle")),
+        ];
+
+        assert!(to_evict(files.clone(), 30).is_empty());
+        assert_eq!(to_evict(files.clone(), 25), vec![PathBuf::from("oldest")]);
+        assert_eq!(
+            to_evict(files, 10),
+            vec![PathBuf::from("oldest"), PathBuf::from("middle")]
+        );
+    }
+}
@@ -6,6 +6,7 @@ use mime::{Mime, APPLICATION_OCTET_STREAM};
 /// everything else can be an octet stream
 pub static MIME_TYPES: &[(&str, &str)] = &[
     ("avif", "image/avif"),
+    ("flac", "audio/flac"),
     ("gif", "image/gif"),
     ("jpeg", "image/jpeg"),
     ("jpg", "image/jpeg"),
@@ -20,14 +21,55 @@ pub static MIME_TYPES: &[(&str, &str)] = &[
     ("mp3", "audio/mpeg"),
     ("mp4", "video/mp4"),
     ("mp4a", "audio/mp4"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("opus", "audio/opus"),
     ("pdf", "application/pdf"),
     ("png", "image/png"),
+    ("svg", "image/svg+xml"),
     ("tar", "application/x-tar"),
     ("tar.gz", "application/gzip"),
+    ("txt", "text/plain"),
     ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
     ("zip", "application/zip"),
 ];
 
+/// Magic bytes for the formats we care about: (offset, signature, mime).
+/// Order matters; more specific signatures need to come first.
+static MAGIC_BYTES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (4, b"ftypavif", "image/avif"),
+    (4, b"ftypheic", "image/heic"),
+    (4, b"ftypheix", "image/heic"),
+    (4, b"ftypmif1", "image/heif"),
+    (4, b"ftypqt", "video/quicktime"),
+    (4, b"ftypM4A", "audio/m4a"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\xff\x0a", "image/jxl"),
+    (0, b"\x00\x00\x00\x0cJXL ", "image/jxl"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (257, b"ustar", "application/x-tar"),
+];
+
+/// RIFF containers say what they hold at offset 8, after "RIFF" and a length.
+static RIFF_TYPES: &[(&[u8], &str)] = &[
+    (b"WEBP", "image/webp"),
+    (b"WAVE", "audio/wav"),
+    (b"AVI ", "video/x-msvideo"),
+];
+
 pub fn mime_from_path(path: &Path) -> Mime {
     let ext = path
         .extension()
@@ -47,11 +89,49 @@ pub fn mime_from_path(path: &Path) -> Mime {
     APPLICATION_OCTET_STREAM
 }
 
+/// The usual extension for a mime type, if it's one we know.
+pub fn extension_for(mime: &str) -> Option<&'static str> {
+    MIME_TYPES.iter().find(|m| m.1 == mime).map(|m| m.0)
+}
+
+/// Sniff the mime type from the first few bytes of the file, if we can.
+pub fn mime_from_bytes(data: &[u8]) -> Option<Mime> {
+    for (offset, magic, mime) in MAGIC_BYTES {
+        if data.len() >= offset + magic.len() && &data[*offset..offset + magic.len()] == *magic {
+            return mime.parse().ok();
+        }
+    }
+
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        for (kind, mime) in RIFF_TYPES {
+            if &data[8..12] == *kind {
+                return mime.parse().ok();
+            }
+        }
+    }
+
+    // SVGs are just text, so it has to start out like one; plenty of other
+    // files mention an <svg> somewhere
+    let head = String::from_utf8_lossy(&data[..data.len().min(512)]).to_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+
+    if head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg")) {
+        return "image/svg+xml".parse().ok();
+    }
+
+    None
+}
+
+/// Prefer the actual content of the file, falling back to the extension.
+pub fn mime_from_file(path: &Path, data: &[u8]) -> Mime {
+    mime_from_bytes(data).unwrap_or_else(|| mime_from_path(path))
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Context;
 
-    use crate::matrix::mime::mime_from_path;
+    use crate::matrix::mime::{mime_from_bytes, mime_from_file, mime_from_path};
 
     #[test]
     fn it_finds_mime_types() -> anyhow::Result<()> {
@@ -75,4 +155,39 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn it_sniffs_mime_types() {
+        let webp = b"RIFF\x24\x00\x00\x00WEBPVP8 ";
+        assert_eq!(mime_from_bytes(webp).unwrap().to_string(), "image/webp");
+
+        let ogg = b"OggS\x00\x02\x00\x00";
+        assert_eq!(mime_from_bytes(ogg).unwrap().to_string(), "audio/ogg");
+
+        let svg = b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\">";
+        assert_eq!(mime_from_bytes(svg).unwrap().to_string(), "image/svg+xml");
+
+        assert!(mime_from_bytes(b"just some text").is_none());
+
+        // the tag alone isn't enough, and neither is a WEBP outside of RIFF
+        let html = b"<html><body><svg width=\"10\"></svg></body></html>";
+        assert!(mime_from_bytes(html).is_none());
+        assert!(mime_from_bytes(b"Draw it with <svg> tags.").is_none());
+        assert!(mime_from_bytes(b"nope\x24\x00\x00\x00WEBPVP8 ").is_none());
+
+        let wav = b"RIFF\x24\x00\x00\x00WAVEfmt ";
+        assert_eq!(mime_from_bytes(wav).unwrap().to_string(), "audio/wav");
+    }
+
+    #[test]
+    fn it_prefers_content_over_extension() -> anyhow::Result<()> {
+        let mut path = dirs::home_dir().context("")?;
+        path.push("not_really.jpg");
+
+        let flac = b"fLaC\x00\x00\x00\x22";
+        assert_eq!(mime_from_file(&path, flac).to_string(), "audio/flac");
+        assert_eq!(mime_from_file(&path, b"").to_string(), "image/jpeg");
+
+        Ok(())
+    }
 }
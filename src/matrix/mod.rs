@@ -1,7 +1,17 @@
 #[allow(clippy::module_inception)]
 pub mod matrix;
 
+pub mod bridges;
+pub mod calls;
+pub mod emotes;
+pub mod export;
+pub mod media;
+pub mod mediacache;
 pub mod mime;
 pub mod notify;
+pub mod register;
+pub mod roaming;
 pub mod roomcache;
+pub mod session;
+pub mod timelinecache;
 pub mod username;
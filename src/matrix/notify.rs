@@ -1,6 +1,9 @@
 use log::error;
-use ruma::UserId;
-use ruma::{events::AnyTimelineEvent, OwnedRoomId};
+use ruma::events::room::message::MessageType::{self, Image};
+use ruma::events::room::MediaSource;
+use ruma::events::{AnyMessageLikeEvent::RoomMessage, MessageLikeEvent};
+use ruma::{events::AnyTimelineEvent, OwnedEventId, OwnedRoomId};
+use ruma::{MxcUri, UserId};
 use std::fs::OpenOptions;
 use std::{
     collections::HashMap,
@@ -16,15 +19,22 @@ use std::{
 use image::imageops::FilterType;
 
 use matrix_sdk::{
-    media::MediaFormat,
+    media::{MediaEventContent, MediaFormat, MediaRequestParameters},
     room::{Room, RoomMember},
     Client,
 };
-use notify_rust::{CloseReason, Hint};
+use notify_rust::{CloseReason, Hint, Urgency};
 
-use crate::{handler::MatuiEvent, settings::is_muted, widgets::message::Message};
+use crate::handler::MatuiEvent;
+use crate::matrix::calls::IncomingCall;
+use crate::settings::{
+    is_muted, low_bandwidth, notification_previews, presentation_mode, room_name,
+    room_notifications, skip_notification, Notifications,
+};
+use crate::widgets::message::Message;
 
 use super::matrix::Matrix;
+use super::mediacache::MediaCache;
 
 pub struct Notify {
     focus: AtomicBool,
@@ -42,6 +52,17 @@ impl Default for Notify {
     }
 }
 
+// What the message is, in the terms of `skip_notifications`.
+fn kind(body: &MessageType) -> &'static str {
+    match body {
+        Image(_) => "image",
+        MessageType::Video(_) => "video",
+        MessageType::File(_) => "file",
+        MessageType::ServerNotice(_) => "notice",
+        _ => "text",
+    }
+}
+
 impl Notify {
     pub async fn timeline_event(
         &self,
@@ -54,8 +75,13 @@ impl Notify {
                 return Ok(());
             }
 
-            // or when the room is muted
-            if is_muted(message.room_id.as_ref()) {
+            // or when the room is muted, or everything is
+            if is_muted(message.room_id.as_ref()) || presentation_mode() {
+                return Ok(());
+            }
+
+            // or it's something they'd rather not hear about
+            if skip_notification(&message.room_id, kind(&message.body)) {
                 return Ok(());
             }
 
@@ -77,15 +103,70 @@ impl Notify {
                 .await?
                 .unwrap();
 
-            let avatar = Notify::get_image(room.clone(), user.clone()).await;
+            // a room mention from someone allowed to make one is urgent
+            let urgent = message.room_mention && user.can_trigger_room_notification();
+
+            // some rooms only get to say something when it's for us
+            if room_notifications(room.room_id()) == Notifications::Mentions
+                && !urgent
+                && !Notify::mentions_me(&event, &client, &room).await
+            {
+                return Ok(());
+            }
+
             let body = message.display();
+            let avatar = Notify::get_image(&client, message, room.clone(), user.clone()).await;
 
-            self.send_notification(user.name(), &body, room, avatar)?;
+            self.send_notification(user.name(), &body, room, avatar, urgent, true)?;
         }
 
         Ok(())
     }
 
+    /// Ring, unless they're looking right at us.
+    pub fn call_event(&self, call: &IncomingCall) -> anyhow::Result<()> {
+        if is_muted(call.room.room_id())
+            || presentation_mode()
+            || self.focus.load(Ordering::Relaxed)
+        {
+            return Ok(());
+        }
+
+        self.send_notification(
+            &format!("{} is calling", call.caller),
+            "Open Matui to pick up in a browser, or to reject the call.",
+            call.room.clone(),
+            None,
+            true,
+            false,
+        )
+    }
+
+    // named in the mentions, or anywhere in the body, by ID or name
+    pub async fn mentions_me(event: &AnyTimelineEvent, client: &Client, room: &Room) -> bool {
+        let (Some(me), AnyTimelineEvent::MessageLike(RoomMessage(MessageLikeEvent::Original(c)))) =
+            (client.user_id(), event)
+        else {
+            return false;
+        };
+
+        if let Some(mentions) = &c.content.mentions {
+            if mentions.user_ids.contains(me) {
+                return true;
+            }
+        }
+
+        let body = c.content.body().to_lowercase();
+
+        let mut names = vec![me.to_string(), me.localpart().to_string()];
+
+        if let Ok(Some(member)) = room.get_member_no_sync(me).await {
+            names.push(member.name().to_string());
+        }
+
+        names.iter().any(|n| body.contains(&n.to_lowercase()))
+    }
+
     pub fn focus_event(&self) {
         self.focus.store(true, Ordering::Relaxed);
     }
@@ -98,24 +179,48 @@ impl Notify {
         let mut map = self.rooms.lock().expect("could not lock rooms");
 
         if let Some(handle_id) = map.remove(room.room_id().as_str()) {
-            if let Ok(handle) = notify_rust::Notification::new().id(handle_id).show() {
-                handle.close();
-            }
+            close_notifications([handle_id]);
         }
 
         *self.room_id.lock().unwrap() = Some(room.room_id().to_owned());
     }
 
+    /// Close every notification we still have up.
+    pub fn close_all(&self) {
+        let mut map = self.rooms.lock().expect("could not lock rooms");
+
+        let ids: Vec<u32> = map.drain().map(|(_, handle_id)| handle_id).collect();
+
+        if !ids.is_empty() {
+            close_notifications(ids);
+        }
+    }
+
     fn send_notification(
         &self,
         summary: &str,
         body: &str,
         room: Room,
         image: Option<PathBuf>,
+        urgent: bool,
+        message: bool,
     ) -> anyhow::Result<()> {
         let mut notification = notify_rust::Notification::new();
 
-        notification.summary(summary).body(body);
+        // only who and where, for rooms that would rather keep it private
+        if message && !notification_previews(room.room_id()) {
+            let name = room_name(room.room_id())
+                .or_else(|| room.cached_display_name().map(|n| n.to_string()))
+                .unwrap_or_else(|| room.room_id().to_string());
+
+            notification.summary(&format!("New message from {} in {}", summary, name));
+        } else {
+            notification.summary(summary).body(body);
+        }
+
+        if urgent {
+            notification.urgency(Urgency::Critical);
+        }
 
         if let Some(path) = image {
             notification.hint(Hint::ImagePath(path.to_str().unwrap().to_string()));
@@ -141,7 +246,12 @@ impl Notify {
         // spawn a thread to sit around and wait for the notification to close
         std::thread::spawn(move || {
             handle.on_close({
-                move |_: CloseReason| {
+                move |reason: CloseReason| {
+                    // we closed it ourselves, so nobody clicked on it
+                    if matches!(reason, CloseReason::CloseAction) {
+                        return;
+                    }
+
                     Matrix::send(MatuiEvent::RoomSelected(room.clone()));
                 }
             });
@@ -158,6 +268,39 @@ impl Notify {
         path
     }
 
+    // Key the cache on the avatar URI as well as the owner, so that a new
+    // avatar gets a new file.
+    fn get_avatar_path(id: &str, uri: &MxcUri) -> PathBuf {
+        let hash = blake3::hash(uri.as_str().as_bytes()).to_hex();
+
+        Notify::get_cache_path(&format!("{}-{}", id, &hash[..16]))
+    }
+
+    // Remove any avatars for this owner that aren't the current one.
+    fn invalidate_avatars(id: &str, current: &PathBuf) {
+        let Some(dir) = current.parent() else {
+            return;
+        };
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        let prefix = format!("{}-", id);
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            // the old, un-hashed key is stale too
+            if (name.starts_with(&prefix) || name == id) && &path != current {
+                if let Err(e) = fs::remove_file(&path) {
+                    error!("could not remove stale avatar: {}", e);
+                }
+            }
+        }
+    }
+
     fn write_image_to_file(img: Vec<u8>, path: &PathBuf) -> anyhow::Result<()> {
         let data = Cursor::new(img);
         let reader = image::io::Reader::new(data).with_guessed_format()?;
@@ -173,17 +316,34 @@ impl Notify {
         Ok(())
     }
 
-    async fn get_room_image(room: &Room) -> Option<PathBuf> {
-        let path = Notify::get_cache_path(room.room_id().as_str());
+    // Fetch through the media cache, which decrypts encrypted sources before
+    // we try to decode them.
+    async fn fetch_media(client: &Client, source: MediaSource) -> Option<Vec<u8>> {
+        let request = MediaRequestParameters {
+            source,
+            format: MediaFormat::File,
+        };
+
+        match MediaCache::content(client, &request).await {
+            Ok(data) => Some(data),
+            Err(e) => {
+                error!("could not fetch media: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn get_avatar(client: &Client, id: &str, uri: Option<&MxcUri>) -> Option<PathBuf> {
+        let uri = uri?;
+        let path = Notify::get_avatar_path(id, uri);
 
         if path.exists() {
             return Some(path);
         }
 
-        let avatar = match room.avatar(MediaFormat::File).await {
-            Ok(Some(a)) => a,
-            _ => return None,
-        };
+        Notify::invalidate_avatars(id, &path);
+
+        let avatar = Notify::fetch_media(client, MediaSource::Plain(uri.to_owned())).await?;
 
         if let Err(e) = Notify::write_image_to_file(avatar, &path) {
             error!("could not write image: {}", e);
@@ -193,19 +353,36 @@ impl Notify {
         Some(path)
     }
 
-    async fn get_user_image(user: &RoomMember) -> Option<PathBuf> {
-        let path = Notify::get_cache_path(user.user_id().as_str());
+    async fn get_room_image(client: &Client, room: &Room) -> Option<PathBuf> {
+        let uri = room.avatar_url();
+        Notify::get_avatar(client, room.room_id().as_str(), uri.as_deref()).await
+    }
+
+    async fn get_user_image(client: &Client, user: &RoomMember) -> Option<PathBuf> {
+        Notify::get_avatar(client, user.user_id().as_str(), user.avatar_url()).await
+    }
+
+    // The thumbnail (or full image) attached to an image message. These are
+    // one-offs, so they're keyed on the event.
+    async fn get_message_image(
+        client: &Client,
+        id: OwnedEventId,
+        body: MessageType,
+    ) -> Option<PathBuf> {
+        let Image(content) = body else {
+            return None;
+        };
+
+        let source = content.thumbnail_source().or_else(|| content.source())?;
+        let path = Notify::get_cache_path(id.as_str());
 
         if path.exists() {
             return Some(path);
         }
 
-        let avatar = match user.avatar(MediaFormat::File).await {
-            Ok(Some(a)) => a,
-            _ => return None,
-        };
+        let data = Notify::fetch_media(client, source).await?;
 
-        if let Err(e) = Notify::write_image_to_file(avatar, &path) {
+        if let Err(e) = Notify::write_image_to_file(data, &path) {
             error!("could not write image: {}", e);
             return None;
         }
@@ -213,11 +390,51 @@ impl Notify {
         Some(path)
     }
 
-    async fn get_image(room: Room, user: RoomMember) -> Option<PathBuf> {
-        if let Some(path) = Notify::get_user_image(&user).await {
+    async fn get_image(
+        client: &Client,
+        message: Message,
+        room: Room,
+        user: RoomMember,
+    ) -> Option<PathBuf> {
+        if low_bandwidth() {
+            return None;
+        }
+
+        // the image would give away as much as the body would
+        if notification_previews(room.room_id()) {
+            if let Some(path) = Notify::get_message_image(client, message.id, message.body).await {
+                return Some(path);
+            }
+        }
+
+        if let Some(path) = Notify::get_user_image(client, &user).await {
             return Some(path);
         }
 
-        Notify::get_room_image(&room).await
+        Notify::get_room_image(client, &room).await
+    }
+}
+
+// Straight over D-Bus, by ID. Getting a handle to close means showing a blank
+// notification with the same ID first, and that flashes up on the screen.
+fn close_notifications(ids: impl IntoIterator<Item = u32>) {
+    let connection = match zbus::blocking::Connection::session() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not close notifications: {}", e);
+            return;
+        }
+    };
+
+    for id in ids {
+        if let Err(e) = connection.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "CloseNotification",
+            &(id,),
+        ) {
+            error!("could not close notification {}: {}", id, e);
+        }
     }
 }
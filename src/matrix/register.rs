@@ -0,0 +1,320 @@
+use anyhow::{bail, Context};
+use matrix_sdk::reqwest;
+use matrix_sdk::ruma::api::client::account::register::v3::Request as RegisterRequest;
+use matrix_sdk::ruma::api::client::account::request_registration_token_via_email::v3::Request as EmailRequest;
+use matrix_sdk::ruma::api::client::uiaa::{AuthData, AuthFlow, AuthType};
+use matrix_sdk::ruma::exports::serde_json::{self, json, Value};
+use matrix_sdk::Client;
+use rand::rngs::OsRng;
+use rand::{distributions::Alphanumeric, Rng};
+use ruma::{OwnedClientSecret, OwnedSessionId, UInt};
+
+use crate::matrix::session::ClientSession;
+use crate::settings::device_name;
+
+/// What we need the user to go do before we can carry on.
+#[derive(Clone, Debug)]
+pub enum RegistrationStep {
+    /// Click the link, or copy the code, sent to the address.
+    Email(String),
+
+    /// Finish a stage we can't do in a terminal (a captcha, or terms of
+    /// service) at the URL.
+    Browser(String),
+}
+
+/// Where a registration ended up after a trip to the homeserver.
+pub enum Outcome {
+    Done,
+    Waiting(RegistrationStep, Option<String>),
+}
+
+/// A registration in progress, held on to between trips to the user.
+pub struct Registration {
+    pub client: Client,
+    pub client_session: ClientSession,
+    username: String,
+    password: String,
+    email: Option<String>,
+    token: Option<String>,
+    // the UIA session, once the homeserver gives us one
+    session: Option<String>,
+    // once we've asked for a validation email
+    email_session: Option<EmailSession>,
+    // what to send along with the next request
+    auth: Option<AuthData>,
+    // the stage we're waiting on the user for
+    waiting: Option<(AuthType, RegistrationStep)>,
+}
+
+struct EmailSession {
+    sid: OwnedSessionId,
+    client_secret: OwnedClientSecret,
+    submit_url: Option<String>,
+}
+
+impl Registration {
+    pub fn new(
+        client: Client,
+        client_session: ClientSession,
+        username: &str,
+        password: &str,
+        email: Option<String>,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            client_session,
+            username: username.to_string(),
+            password: password.to_string(),
+            email,
+            token,
+            session: None,
+            email_session: None,
+            auth: None,
+            waiting: None,
+        }
+    }
+
+    /// The user is back, having done what we asked. The code is only used
+    /// for email validation, and only by homeservers that hand them out.
+    pub async fn resume(&mut self, code: &str) -> anyhow::Result<Outcome> {
+        if let Some((stage, step)) = self.waiting.clone() {
+            self.auth = Some(match stage {
+                AuthType::EmailIdentity => match self.email_auth(code).await {
+                    Ok(auth) => auth,
+                    Err(e) => return Ok(Outcome::Waiting(step, Some(e.to_string()))),
+                },
+                _ => AuthData::fallback_acknowledgement(
+                    self.session.clone().context("No registration session.")?,
+                ),
+            });
+        }
+
+        self.advance().await
+    }
+
+    /// Keep going until we're registered, or need the user for something.
+    pub async fn advance(&mut self) -> anyhow::Result<Outcome> {
+        loop {
+            let mut request = RegisterRequest::new();
+            request.username = Some(self.username.clone());
+            request.password = Some(self.password.clone());
+            request.initial_device_display_name = Some(device_name());
+            request.refresh_token = true;
+            request.auth = self.auth.take();
+
+            let err = match self.client.matrix_auth().register(request).await {
+                Ok(_) => return Ok(Outcome::Done),
+                Err(err) => err,
+            };
+
+            let Some(info) = err.as_uiaa_response() else {
+                return Err(err.into());
+            };
+
+            if info.session.is_some() {
+                self.session = info.session.clone();
+            }
+
+            let auth_error = info.auth_error.as_ref().map(|e| e.message.clone());
+
+            // the user didn't quite finish what we sent them off to do
+            if let Some((stage, step)) = &self.waiting {
+                if !info.completed.contains(stage) {
+                    return Ok(Outcome::Waiting(step.clone(), auth_error));
+                }
+            }
+
+            if let Some(message) = auth_error {
+                bail!(message);
+            }
+
+            let flow = pick_flow(&info.flows, self.email.is_some(), self.token.is_some())?;
+
+            let Some(stage) = flow
+                .stages
+                .iter()
+                .find(|s| !info.completed.contains(s))
+                .cloned()
+            else {
+                bail!("The homeserver wants more, but every stage is complete.");
+            };
+
+            self.waiting = None;
+
+            match stage {
+                AuthType::Dummy => self.auth = Some(self.auth_data("m.login.dummy", json!({}))?),
+                AuthType::RegistrationToken => {
+                    let token = self.token.clone().unwrap_or_default();
+
+                    self.auth = Some(
+                        self.auth_data("m.login.registration_token", json!({ "token": token }))?,
+                    );
+                }
+                AuthType::EmailIdentity => {
+                    let address = self.email.clone().unwrap_or_default();
+                    self.request_email(&address).await?;
+
+                    let step = RegistrationStep::Email(address);
+                    self.waiting = Some((stage, step.clone()));
+
+                    return Ok(Outcome::Waiting(step, None));
+                }
+                _ => {
+                    let step = RegistrationStep::Browser(self.fallback_url(&stage)?);
+                    self.waiting = Some((stage, step.clone()));
+
+                    return Ok(Outcome::Waiting(step, None));
+                }
+            }
+        }
+    }
+
+    fn auth_data(&self, auth_type: &str, data: Value) -> anyhow::Result<AuthData> {
+        let Value::Object(data) = data else {
+            bail!("Auth data must be an object.");
+        };
+
+        Ok(AuthData::new(auth_type, self.session.clone(), data)?)
+    }
+
+    async fn request_email(&mut self, address: &str) -> anyhow::Result<()> {
+        if self.email_session.is_some() {
+            return Ok(());
+        }
+
+        let secret: String = OsRng
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        let client_secret = OwnedClientSecret::try_from(secret)?;
+
+        let response = self
+            .client
+            .send(
+                EmailRequest::new(
+                    client_secret.clone(),
+                    address.to_string(),
+                    UInt::from(1_u32),
+                ),
+                None,
+            )
+            .await?;
+
+        self.email_session = Some(EmailSession {
+            sid: response.sid,
+            client_secret,
+            submit_url: response.submit_url,
+        });
+
+        Ok(())
+    }
+
+    // Some homeservers send a code to type in, rather than a link to click.
+    async fn email_auth(&self, code: &str) -> anyhow::Result<AuthData> {
+        let email = self
+            .email_session
+            .as_ref()
+            .context("No email validation in progress.")?;
+
+        if let (false, Some(url)) = (code.trim().is_empty(), &email.submit_url) {
+            let body = json!({
+                "sid": email.sid,
+                "client_secret": email.client_secret,
+                "token": code.trim(),
+            });
+
+            let response = reqwest::Client::new()
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(&body)?)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                bail!("The homeserver didn't accept that code.");
+            }
+        }
+
+        self.auth_data(
+            "m.login.email.identity",
+            json!({
+                "threepid_creds": {
+                    "sid": email.sid,
+                    "client_secret": email.client_secret,
+                }
+            }),
+        )
+    }
+
+    fn fallback_url(&self, stage: &AuthType) -> anyhow::Result<String> {
+        let session = self.session.as_ref().context("No registration session.")?;
+
+        let mut url = self.client.homeserver().join(&format!(
+            "_matrix/client/v3/auth/{}/fallback/web",
+            stage.as_str()
+        ))?;
+
+        url.query_pairs_mut().append_pair("session", session);
+
+        Ok(url.to_string())
+    }
+}
+
+// The first flow we can get all the way through with what we've been given.
+fn pick_flow(flows: &[AuthFlow], email: bool, token: bool) -> anyhow::Result<&AuthFlow> {
+    let possible = |flow: &AuthFlow, email: bool, token: bool| {
+        flow.stages.iter().all(|s| match s {
+            AuthType::Dummy | AuthType::Terms | AuthType::ReCaptcha => true,
+            AuthType::EmailIdentity => email,
+            AuthType::RegistrationToken => token,
+            _ => false,
+        })
+    };
+
+    if let Some(flow) = flows.iter().find(|f| possible(f, email, token)) {
+        return Ok(flow);
+    }
+
+    if flows.iter().any(|f| possible(f, true, token)) {
+        bail!("This homeserver needs an email address to register.");
+    }
+
+    if flows.iter().any(|f| possible(f, email, true)) {
+        bail!("This homeserver needs a registration token to register.");
+    }
+
+    bail!("This homeserver doesn't allow registration from matui.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(stages: &[&str]) -> AuthFlow {
+        AuthFlow::new(stages.iter().map(|s| AuthType::from(*s)).collect())
+    }
+
+    #[test]
+    fn test_pick_flow() {
+        let flows = vec![
+            flow(&["m.login.msisdn"]),
+            flow(&["m.login.recaptcha", "m.login.email.identity"]),
+            flow(&["m.login.registration_token", "m.login.dummy"]),
+        ];
+
+        let picked = pick_flow(&flows, true, false).unwrap();
+        assert_eq!(picked.stages[1], AuthType::EmailIdentity);
+
+        let picked = pick_flow(&flows, false, true).unwrap();
+        assert_eq!(picked.stages[0], AuthType::RegistrationToken);
+
+        let err = pick_flow(&flows[..2], false, false).unwrap_err();
+        assert!(err.to_string().contains("email"));
+
+        assert!(pick_flow(&flows[..1], true, true).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use log::error;
+use matrix_sdk::ruma::exports::serde_json::Value;
+use matrix_sdk::ruma::serde::Raw;
+use matrix_sdk::Client;
+use ruma::events::{AnyGlobalAccountDataEvent, GlobalAccountDataEventType};
+
+use crate::settings::{roaming_settings, set_roaming};
+
+/// Where our settings live in account data.
+const ROAMING_SETTINGS: &str = "com.github.pkulak.matui.settings";
+
+fn event_type() -> GlobalAccountDataEventType {
+    GlobalAccountDataEventType::from(ROAMING_SETTINGS)
+}
+
+/// Take on the settings saved to the account, from the store.
+pub async fn load(client: &Client) {
+    match client.account().account_data_raw(event_type()).await {
+        Ok(Some(raw)) => match raw.deserialize_as::<Value>() {
+            Ok(content) => set_roaming(content),
+            Err(e) => error!("could not parse roaming settings: {}", e),
+        },
+        Ok(None) => {}
+        Err(e) => error!("could not load roaming settings: {}", e),
+    }
+}
+
+/// Did another session save its settings?
+pub fn changed(account_data: &[Raw<AnyGlobalAccountDataEvent>]) -> bool {
+    account_data.iter().any(|raw| {
+        raw.get_field::<String>("type")
+            .ok()
+            .flatten()
+            .is_some_and(|t| t == ROAMING_SETTINGS)
+    })
+}
+
+/// Pick up whatever's in the account now, lay config.toml over it, and save
+/// the lot back for everyone else.
+pub async fn merge(client: &Client) -> anyhow::Result<()> {
+    if let Some(raw) = client.account().fetch_account_data(event_type()).await? {
+        set_roaming(raw.deserialize_as::<Value>()?);
+    }
+
+    let content = Value::Object(roaming_settings());
+
+    client
+        .account()
+        .set_account_data_raw(event_type(), Raw::new(&content)?.cast())
+        .await?;
+
+    set_roaming(content);
+
+    Ok(())
+}
@@ -1,37 +1,97 @@
 use anyhow::Context;
 use futures::future::join_all;
+use futures::StreamExt;
 use log::info;
 use matrix_sdk::room::{MessagesOptions, Room};
 
 use matrix_sdk::{Client, RoomDisplayName, RoomState};
 use ruma::api::Direction;
-use ruma::events::room::message::MessageType;
-use ruma::events::AnyTimelineEvent;
-use ruma::{MilliSecondsSinceUnixEpoch, RoomId};
+use ruma::events::presence::PresenceEvent;
+use ruma::events::room::message::{MessageType, Relation};
+use ruma::events::tag::TagName;
+use ruma::events::{AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent};
+use ruma::presence::PresenceState;
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId};
+use std::collections::HashSet;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use crate::handler::MatuiEvent;
 use crate::matrix::matrix::Matrix;
+use crate::matrix::notify::Notify;
+use crate::settings::{is_muted, mentions_only_unread, room_name};
+use crate::widgets::message::Message;
+
+/// How many rooms to fetch last messages for at once.
+const HYDRATE_CONCURRENCY: usize = 8;
+
+/// Any shorter, and there's nothing to catch up on.
+const AWAY_AFTER: Duration = Duration::from_secs(10 * 60);
 
 pub struct RoomCache {
     rooms: Mutex<Vec<DecoratedRoom>>,
+    // when the window lost focus, and what's happened since
+    away: Mutex<Option<(Instant, Away)>>,
 }
 
 impl Default for RoomCache {
     fn default() -> Self {
         RoomCache {
             rooms: Mutex::new(vec![]),
+            away: Mutex::new(None),
         }
     }
 }
 
+/// What happened while the window was in the background.
+#[derive(Clone, Debug, Default)]
+pub struct Away {
+    pub messages: usize,
+    pub rooms: HashSet<OwnedRoomId>,
+    pub mentions: Vec<Mention>,
+}
+
+/// A message that mentioned us while we were away.
+#[derive(Clone, Debug)]
+pub struct Mention {
+    pub room: Room,
+    pub event_id: OwnedEventId,
+    pub sender: String,
+    pub body: String,
+}
+
+impl Away {
+    /// A line to catch up with, if there's anything to catch up on.
+    pub fn summary(&self) -> Option<String> {
+        let (count, kind, rooms) = if !self.mentions.is_empty() {
+            let rooms: HashSet<&RoomId> = self.mentions.iter().map(|m| m.room.room_id()).collect();
+            (self.mentions.len(), "mention", rooms.len())
+        } else if self.messages > 0 {
+            (self.messages, "message", self.rooms.len())
+        } else {
+            return None;
+        };
+
+        Some(format!(
+            "While you were away: {} {}{} in {} room{}",
+            count,
+            kind,
+            if count == 1 { "" } else { "s" },
+            rooms,
+            if rooms == 1 { "" } else { "s" },
+        ))
+    }
+}
+
 impl RoomCache {
     pub async fn populate(&self, client: Client) {
         info!("populating room cache");
 
+        // names and unread counts are local to the store, so this is quick
         let rooms = client
             .joined_rooms()
             .into_iter()
-            .map(|r| async move { DecoratedRoom::from_room(r.clone()).await });
+            .map(|r| async move { DecoratedRoom::from_room_lazy(r.clone()).await });
 
         let rooms = join_all(rooms).await;
 
@@ -41,6 +101,49 @@ impl RoomCache {
         info!("room cache populated")
     }
 
+    /// Fill in the last message of every room, in the background. Each
+    /// room is announced as it comes in.
+    pub async fn hydrate(&self, client: Client) {
+        info!("hydrating room cache");
+
+        futures::stream::iter(client.joined_rooms())
+            .for_each_concurrent(HYDRATE_CONCURRENCY, |room| async move {
+                let decorated = DecoratedRoom::from_room(room).await;
+
+                if let Some(updated) = self.replace(decorated) {
+                    Matrix::send(MatuiEvent::RoomCacheUpdated(updated));
+                }
+            })
+            .await;
+
+        info!("room cache hydrated")
+    }
+
+    // swap in a freshly built room, keeping our local state
+    fn replace(&self, mut decorated: DecoratedRoom) -> Option<DecoratedRoom> {
+        let mut rooms = self.rooms.lock().expect("to unlock rooms");
+
+        for dec in rooms.iter_mut() {
+            if dec.inner.room_id() == decorated.inner.room_id() {
+                decorated.visited = dec.visited;
+                decorated.typing = std::mem::take(&mut dec.typing);
+                decorated.activity = std::mem::take(&mut dec.activity);
+
+                // a message may have come in live since this was fetched
+                if dec.last_ts > decorated.last_ts {
+                    decorated.last_message = dec.last_message.take();
+                    decorated.last_sender = dec.last_sender.take();
+                    decorated.last_ts = dec.last_ts;
+                }
+
+                *dec = decorated;
+                return Some(dec.clone());
+            }
+        }
+
+        None
+    }
+
     pub fn get_rooms(&self) -> Vec<DecoratedRoom> {
         self.rooms.lock().expect("to unlock rooms").clone()
     }
@@ -57,6 +160,74 @@ impl RoomCache {
         None
     }
 
+    /// Start keeping track of what we miss.
+    pub fn blur_event(&self) {
+        *self.away.lock().expect("to unlock away") = Some((Instant::now(), Away::default()));
+    }
+
+    /// Everything we missed, if we were gone long enough to miss anything.
+    pub fn focus_event(&self) -> Option<Away> {
+        let (since, away) = self.away.lock().expect("to unlock away").take()?;
+
+        if since.elapsed() < AWAY_AFTER || away.summary().is_none() {
+            return None;
+        }
+
+        Some(away)
+    }
+
+    // count a new message toward what we've missed, if we're away
+    async fn away_event(&self, client: &Client, room: &Room, event: &AnyTimelineEvent) {
+        if self.away.lock().expect("to unlock away").is_none() {
+            return;
+        }
+
+        let Some(message) = Message::try_from(event, false) else {
+            return;
+        };
+
+        if Some(message.sender.id.as_ref()) == client.user_id() || is_muted(room.room_id()) {
+            return;
+        }
+
+        let sender = room
+            .get_member_no_sync(&message.sender.id)
+            .await
+            .ok()
+            .flatten();
+
+        // a room mention only counts if the sender is allowed to make one
+        let room_mention = message.room_mention
+            && sender
+                .as_ref()
+                .map_or(false, |m| m.can_trigger_room_notification());
+
+        let mention = if room_mention || Notify::mentions_me(event, client, room).await {
+            Some(Mention {
+                room: room.clone(),
+                event_id: message.id.clone(),
+                sender: match &sender {
+                    Some(member) => member.name().to_string(),
+                    None => message.sender.id.to_string(),
+                },
+                body: message.display(),
+            })
+        } else {
+            None
+        };
+
+        let mut away = self.away.lock().expect("to unlock away");
+
+        // we could have come back while looking the sender up
+        let Some((_, away)) = away.as_mut() else {
+            return;
+        };
+
+        away.messages += 1;
+        away.rooms.insert(room.room_id().to_owned());
+        away.mentions.extend(mention);
+    }
+
     pub fn room_visit_event(&self, room: Room) {
         let mut rooms = self.rooms.lock().expect("to unlock rooms");
 
@@ -68,6 +239,60 @@ impl RoomCache {
         }
     }
 
+    /// Keep track of who else is typing, and let the room list know.
+    pub async fn typing_event(&self, client: Client, room: Room, ids: Vec<OwnedUserId>) {
+        let me = client.user_id();
+        let mut names = vec![];
+
+        for id in ids.iter().filter(|id| Some(id.as_ref()) != me) {
+            names.push(match room.get_member_no_sync(id).await {
+                Ok(Some(member)) => member.name().to_string(),
+                _ => id.localpart().to_string(),
+            });
+        }
+
+        let updated = {
+            let mut rooms = self.rooms.lock().expect("to unlock rooms");
+
+            rooms
+                .iter_mut()
+                .find(|dec| dec.inner.room_id() == room.room_id() && dec.typing != names)
+                .map(|dec| {
+                    dec.typing = names;
+                    dec.clone()
+                })
+        };
+
+        if let Some(updated) = updated {
+            Matrix::send(MatuiEvent::RoomCacheUpdated(updated));
+        }
+    }
+
+    /// Keep the presence of the people we have direct chats with up to date.
+    pub fn presence_event(&self, id: &UserId, presence: PresenceState) {
+        let updated: Vec<DecoratedRoom> = {
+            let mut rooms = self.rooms.lock().expect("to unlock rooms");
+
+            rooms
+                .iter_mut()
+                .filter_map(|dec| {
+                    let peer = dec.peer.as_mut().filter(|p| p.id == id)?;
+
+                    if peer.presence.as_ref() == Some(&presence) {
+                        return None;
+                    }
+
+                    peer.presence = Some(presence.clone());
+                    Some(dec.clone())
+                })
+                .collect()
+        };
+
+        for dec in updated {
+            Matrix::send(MatuiEvent::RoomCacheUpdated(dec));
+        }
+    }
+
     pub async fn timeline_event(&self, client: Client, event: &AnyTimelineEvent) {
         let room = match client.get_room(event.room_id()) {
             Some(room) => room,
@@ -78,12 +303,54 @@ impl RoomCache {
             return;
         }
 
-        let decorated = DecoratedRoom::from_room(room).await;
+        self.away_event(&client, &room, event).await;
+
+        let known = self.wrap(&room);
+
+        // edits and redactions may change the last message, which we can't
+        // tell from here, as do rooms we've never seen
+        let decorated = match known.filter(|_| !DecoratedRoom::needs_refetch(event)) {
+            Some(mut decorated) => {
+                decorated.apply_event(&room, event).await;
+                decorated
+            }
+            None => DecoratedRoom::from_room(room).await,
+        };
+
+        self.upsert(decorated);
+        self.activity_event(event);
+    }
+
+    // count a new message toward the room's activity
+    fn activity_event(&self, event: &AnyTimelineEvent) {
+        if !Activity::counts_toward(event) {
+            return;
+        }
+
+        let updated = {
+            let mut rooms = self.rooms.lock().expect("to unlock rooms");
+
+            rooms
+                .iter_mut()
+                .find(|dec| dec.inner.room_id() == event.room_id())
+                .map(|dec| {
+                    dec.activity.record(event.origin_server_ts());
+                    dec.clone()
+                })
+        };
+
+        if let Some(updated) = updated {
+            Matrix::send(MatuiEvent::RoomCacheUpdated(updated));
+        }
+    }
 
+    fn upsert(&self, mut decorated: DecoratedRoom) {
         let mut rooms = self.rooms.lock().expect("to unlock rooms");
 
         for dec in rooms.iter_mut() {
-            if dec.inner.room_id() == event.room_id() {
+            if dec.inner.room_id() == decorated.inner.room_id() {
+                decorated.typing = std::mem::take(&mut dec.typing);
+                decorated.activity = std::mem::take(&mut dec.activity);
                 *dec = decorated;
                 return;
             }
@@ -94,7 +361,7 @@ impl RoomCache {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct DecoratedRoom {
     pub inner: Room,
     pub name: RoomDisplayName,
@@ -102,6 +369,80 @@ pub struct DecoratedRoom {
     pub last_message: Option<String>,
     pub last_sender: Option<String>,
     pub last_ts: Option<MilliSecondsSinceUnixEpoch>,
+    // everyone but us who's typing right now
+    pub typing: Vec<String>,
+    // where the homeserver admins tell us things, like that we're over quota
+    pub server_notices: bool,
+    // who's on the other end, if it's a direct chat with one person
+    pub peer: Option<Peer>,
+    // messages per hour, since we started
+    pub activity: Activity,
+}
+
+/// How many hours of activity we keep for each room.
+pub const ACTIVITY_HOURS: usize = 8;
+
+/// How many messages a room has seen in each of the last few hours.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Activity {
+    // hours since the epoch, as of the last bucket
+    hour: u64,
+    counts: [u32; ACTIVITY_HOURS],
+}
+
+impl Activity {
+    /// New messages count, but not edits or anything else in the timeline.
+    pub fn counts_toward(event: &AnyTimelineEvent) -> bool {
+        match event {
+            AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(og),
+            )) => !matches!(og.content.relates_to, Some(Relation::Replacement(_))),
+            _ => false,
+        }
+    }
+
+    pub fn record(&mut self, ts: MilliSecondsSinceUnixEpoch) {
+        let hour = hour_of(ts);
+        self.advance(hour);
+
+        let age = (self.hour - hour) as usize;
+
+        if age < ACTIVITY_HOURS {
+            self.counts[ACTIVITY_HOURS - 1 - age] += 1;
+        }
+    }
+
+    /// Messages per hour, oldest first, with the current hour last.
+    pub fn counts(&self, now: MilliSecondsSinceUnixEpoch) -> [u32; ACTIVITY_HOURS] {
+        let mut activity = self.clone();
+        activity.advance(hour_of(now));
+        activity.counts
+    }
+
+    // move the window up to the given hour, dropping anything that falls off
+    fn advance(&mut self, hour: u64) {
+        if hour <= self.hour {
+            return;
+        }
+
+        let by = (hour - self.hour).min(ACTIVITY_HOURS as u64) as usize;
+
+        self.counts.rotate_left(by);
+        self.counts[ACTIVITY_HOURS - by..].fill(0);
+        self.hour = hour;
+    }
+}
+
+fn hour_of(ts: MilliSecondsSinceUnixEpoch) -> u64 {
+    u64::from(ts.as_secs()) / 3600
+}
+
+/// The other person in a direct chat.
+#[derive(Clone, Debug)]
+pub struct Peer {
+    pub id: OwnedUserId,
+    pub name: String,
+    pub presence: Option<PresenceState>,
 }
 
 impl DecoratedRoom {
@@ -109,12 +450,26 @@ impl DecoratedRoom {
         self.inner.room_id()
     }
 
+    /// What we call the room, which might be our own name for it, or the
+    /// name of the person it's with.
+    pub fn display_name(&self) -> String {
+        room_name(self.room_id())
+            .or_else(|| self.peer.as_ref().map(|p| p.name.clone()))
+            .unwrap_or_else(|| self.name.to_string())
+    }
+
+    /// Listed with the people, rather than the rooms.
+    pub fn is_direct(&self) -> bool {
+        self.inner.direct_targets_length() > 0
+    }
+
     pub fn inner(&self) -> Room {
         self.inner.clone()
     }
 
+    /// Everything that's waiting, unless only mentions count in this room.
     pub fn unread_count(&self) -> u64 {
-        if self.visited {
+        if self.visited || mentions_only_unread(self.room_id()) {
             return 0;
         }
 
@@ -129,6 +484,76 @@ impl DecoratedRoom {
         self.inner.unread_notification_counts().highlight_count
     }
 
+    fn needs_refetch(event: &AnyTimelineEvent) -> bool {
+        match event {
+            AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomRedaction(_)) => true,
+            AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(og),
+            )) => matches!(og.content.relates_to, Some(Relation::Replacement(_))),
+            _ => false,
+        }
+    }
+
+    // update ourselves with an event we already have in hand
+    async fn apply_event(&mut self, room: &Room, event: &AnyTimelineEvent) {
+        let ts = event.origin_server_ts();
+
+        if self.last_ts.map_or(true, |last| ts >= last) {
+            self.last_ts = Some(ts);
+        }
+
+        match event {
+            AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(og),
+            )) => {
+                self.last_message = match &og.content.msgtype {
+                    MessageType::Text(content) => Some(content.body.clone()),
+                    _ => Some("".to_string()),
+                };
+
+                // the store has anyone who's sent a message recently
+                self.last_sender = match room.get_member_no_sync(&og.sender).await {
+                    Ok(Some(member)) => Some(member.name().to_string()),
+                    _ => Some(og.sender.to_string()),
+                };
+
+                self.visited = false;
+            }
+            AnyTimelineEvent::State(_) => {
+                // names can change with membership and room state
+                self.name = room
+                    .compute_display_name()
+                    .await
+                    .unwrap_or(RoomDisplayName::Empty);
+            }
+            _ => {}
+        }
+    }
+
+    // everything but the last message, which needs a network call
+    async fn from_room_lazy(room: Room) -> DecoratedRoom {
+        let name = room
+            .compute_display_name()
+            .await
+            .unwrap_or(RoomDisplayName::Empty);
+
+        let server_notices = is_server_notices(&room).await;
+        let peer = peer(&room).await;
+
+        DecoratedRoom {
+            inner: room,
+            name,
+            visited: false,
+            last_message: None,
+            last_sender: None,
+            last_ts: None,
+            typing: vec![],
+            server_notices,
+            peer,
+            activity: Activity::default(),
+        }
+    }
+
     async fn from_room(room: Room) -> DecoratedRoom {
         let name = room
             .compute_display_name()
@@ -173,6 +598,10 @@ impl DecoratedRoom {
                     last_message: Some(body),
                     last_sender: Some(member.name().to_string()),
                     last_ts: latest_ts,
+                    typing: vec![],
+                    server_notices: false,
+                    peer: None,
+                    activity: Activity::default(),
                 });
             }
 
@@ -183,10 +612,17 @@ impl DecoratedRoom {
                 last_message: None,
                 last_sender: None,
                 last_ts: latest_ts,
+                typing: vec![],
+                server_notices: false,
+                peer: None,
+                activity: Activity::default(),
             })
         }
 
-        match inner(room.clone(), name.clone()).await {
+        let server_notices = is_server_notices(&room).await;
+        let peer = peer(&room).await;
+
+        let mut decorated = match inner(room.clone(), name.clone()).await {
             Ok(r) => r,
             Err(e) => {
                 info!("could not fetch room details: {}", e.to_string());
@@ -197,8 +633,120 @@ impl DecoratedRoom {
                     last_message: None,
                     last_sender: None,
                     last_ts: None,
+                    typing: vec![],
+                    server_notices: false,
+                    peer: None,
+                    activity: Activity::default(),
                 }
             }
-        }
+        };
+
+        decorated.server_notices = server_notices;
+        decorated.peer = peer;
+        decorated
+    }
+}
+
+// Tagged by the homeserver, and the spec says there's only ever one.
+async fn is_server_notices(room: &Room) -> bool {
+    match room.tags().await {
+        Ok(Some(tags)) => tags.contains_key(&TagName::ServerNotice),
+        _ => false,
+    }
+}
+
+// Only for a chat with exactly one other person; bigger groups keep the name
+// of the room.
+async fn peer(room: &Room) -> Option<Peer> {
+    let targets = room.direct_targets();
+
+    if targets.len() != 1 {
+        return None;
+    }
+
+    let id = targets.into_iter().next()?;
+
+    let name = match room.get_member_no_sync(&id).await {
+        Ok(Some(member)) => member.name().to_string(),
+        _ => id.localpart().to_string(),
+    };
+
+    let presence = room
+        .client()
+        .store()
+        .get_presence_event(&id)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|raw| raw.deserialize().ok())
+        .map(|event: PresenceEvent| event.content.presence);
+
+    Some(Peer { id, name, presence })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u32) -> MilliSecondsSinceUnixEpoch {
+        MilliSecondsSinceUnixEpoch::from_system_time(
+            std::time::UNIX_EPOCH + Duration::from_secs(secs.into()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_activity() {
+        let hour = 3600;
+        let now = at(100 * hour + 10);
+
+        let mut activity = Activity::default();
+        assert_eq!(activity.counts(now), [0; ACTIVITY_HOURS]);
+
+        activity.record(at(100 * hour));
+        activity.record(at(100 * hour + 5));
+        activity.record(at(98 * hour));
+        // too old to show up at all
+        activity.record(at(90 * hour));
+
+        assert_eq!(activity.counts(now), [0, 0, 0, 0, 0, 1, 0, 2]);
+
+        // backfill lands in the right hour, too
+        activity.record(at(99 * hour));
+        assert_eq!(activity.counts(now), [0, 0, 0, 0, 0, 1, 1, 2]);
+
+        // and everything slides left as time goes by
+        assert_eq!(activity.counts(at(102 * hour)), [0, 0, 0, 1, 1, 2, 0, 0]);
+        assert_eq!(activity.counts(at(200 * hour)), [0; ACTIVITY_HOURS]);
+    }
+
+    #[test]
+    fn test_away_summary() {
+        assert_eq!(Away::default().summary(), None);
+
+        let away = Away {
+            messages: 1,
+            rooms: HashSet::from([ruma::owned_room_id!("!a:example.org")]),
+            mentions: vec![],
+        };
+
+        assert_eq!(
+            away.summary().as_deref(),
+            Some("While you were away: 1 message in 1 room")
+        );
+
+        let away = Away {
+            messages: 12,
+            rooms: HashSet::from([
+                ruma::owned_room_id!("!a:example.org"),
+                ruma::owned_room_id!("!b:example.org"),
+            ]),
+            mentions: vec![],
+        };
+
+        assert_eq!(
+            away.summary().as_deref(),
+            Some("While you were away: 12 messages in 2 rooms")
+        );
     }
 }
@@ -0,0 +1,392 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use log::{error, info};
+use matrix_sdk::matrix_auth::MatrixSession;
+use matrix_sdk::ruma::exports::serde_json;
+use matrix_sdk_store_encryption::StoreCipher;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use crate::keyring::Keyring;
+use crate::settings::{encrypt_session, keyring_tokens, use_keyring};
+
+/// Set this to skip the passphrase prompt, for running unattended.
+const PASSPHRASE_VAR: &str = "MATUI_PASSPHRASE";
+
+/// The cipher for an encrypted session file, once unlocked, along with the
+/// passphrase-protected export of it that gets written next to the data.
+static CIPHER: OnceCell<(StoreCipher, Vec<u8>)> = OnceCell::new();
+
+/// The data needed to re-build a client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClientSession {
+    pub homeserver: String,
+    pub db_path: PathBuf,
+    // empty on disk when it lives in the keyring
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub passphrase: String,
+}
+
+/// The full session to persist.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FullSession {
+    pub client_session: ClientSession,
+    // missing on disk when it lives in the keyring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_session: Option<MatrixSession>,
+    pub sync_token: Option<String>,
+    // are any of our secrets in the keyring?
+    #[serde(default)]
+    pub keyring: bool,
+}
+
+/// What an encrypted session file looks like on disk.
+#[derive(Serialize, Deserialize)]
+struct EncryptedSession {
+    cipher: Vec<u8>,
+    session: Vec<u8>,
+}
+
+impl FullSession {
+    pub fn new(client_session: ClientSession, user_session: MatrixSession) -> Self {
+        FullSession {
+            client_session,
+            user_session: Some(user_session),
+            sync_token: None,
+            keyring: false,
+        }
+    }
+
+    // secrets are filed under the store, which is unique to the login
+    fn account(&self) -> String {
+        self.client_session.db_path.to_string_lossy().to_string()
+    }
+}
+
+/// Read the session, pulling anything we need out of the keyring. If the
+/// keyring setting has changed since we last saved, move the secrets over.
+pub async fn load_session(session_file: &Path) -> anyhow::Result<FullSession> {
+    let session_file = session_file.to_owned();
+    spawn_blocking(move || load(&session_file)).await?
+}
+
+/// Write the session, keeping secrets in the keyring if we're configured
+/// to. If the keyring isn't working, we fall back to the file.
+pub async fn save_session(session_file: &Path, session: &FullSession) -> anyhow::Result<()> {
+    let session_file = session_file.to_owned();
+    let session = session.clone();
+    spawn_blocking(move || save(&session_file, &session)).await?
+}
+
+pub async fn persist_user_session(
+    session_file: &Path,
+    user_session: MatrixSession,
+) -> anyhow::Result<()> {
+    let session_file = session_file.to_owned();
+
+    spawn_blocking(move || {
+        let mut session = load(&session_file)?;
+        session.user_session = Some(user_session);
+
+        save(&session_file, &session)
+    })
+    .await?
+}
+
+// The keyring is a blocking D-Bus call, so these run off the async workers.
+fn load(session_file: &Path) -> anyhow::Result<FullSession> {
+    let mut session = read_raw(session_file)?;
+    let tokens_in_keyring = session.keyring && session.user_session.is_none();
+
+    if session.keyring {
+        let keyring = Keyring::connect().context("Could not connect to the keyring.")?;
+        let account = session.account();
+
+        if session.client_session.passphrase.is_empty() {
+            session.client_session.passphrase = keyring
+                .get(&account, "passphrase")?
+                .context("The store passphrase is missing from the keyring.")?;
+        }
+
+        if session.user_session.is_none() {
+            let serialized = keyring
+                .get(&account, "session")?
+                .context("The session is missing from the keyring.")?;
+
+            session.user_session = Some(serde_json::from_str(&serialized)?);
+        }
+    }
+
+    if session.keyring != use_keyring() || tokens_in_keyring != keyring_tokens() {
+        info!("migrating session secrets");
+        save(session_file, &session)?;
+    }
+
+    Ok(session)
+}
+
+fn save(session_file: &Path, session: &FullSession) -> anyhow::Result<()> {
+    let mut to_write = session.clone();
+    to_write.keyring = false;
+
+    if use_keyring() {
+        match store_secrets(session) {
+            Ok(tokens) => {
+                to_write.keyring = true;
+                to_write.client_session.passphrase = String::new();
+
+                if tokens {
+                    to_write.user_session = None;
+                }
+            }
+            Err(e) => error!("could not use the keyring: {}", e),
+        }
+    }
+
+    write_raw(session_file, &to_write)
+}
+
+// Returns true if the tokens went in, too.
+fn store_secrets(session: &FullSession) -> anyhow::Result<bool> {
+    let keyring = Keyring::connect()?;
+    let account = session.account();
+
+    keyring.set(&account, "passphrase", &session.client_session.passphrase)?;
+
+    if !keyring_tokens() {
+        return Ok(false);
+    }
+
+    let Some(user_session) = &session.user_session else {
+        return Ok(false);
+    };
+
+    keyring.set(&account, "session", &serde_json::to_string(user_session)?)?;
+
+    Ok(true)
+}
+
+// The sync token isn't secret, and changes often, so skip the keyring.
+pub fn persist_sync_token(session_file: &Path, sync_token: String) -> anyhow::Result<()> {
+    let mut session = read_raw(session_file)?;
+    session.sync_token = Some(sync_token);
+
+    write_raw(session_file, &session)
+}
+
+/// Get the session file ready before anything touches it. If it's
+/// encrypted, ask for the passphrase. If it should be, but isn't yet, ask for
+/// a new one and encrypt it. Until we've signed in, there's nothing to
+/// protect, so no passphrase to choose.
+pub fn unlock_session(session_file: &Path) -> anyhow::Result<()> {
+    let encrypted = fs::read_to_string(session_file)
+        .ok()
+        .and_then(|s| serde_json::from_str::<EncryptedSession>(&s).ok());
+
+    if CIPHER.get().is_some() {
+        return Ok(());
+    }
+
+    if let Some(encrypted) = &encrypted {
+        let cipher = unlock_cipher(&encrypted.cipher)?;
+        let _ = CIPHER.set((cipher, encrypted.cipher.clone()));
+    } else if encrypt_session() && session_file.exists() {
+        let passphrase = new_passphrase()?;
+        let cipher = StoreCipher::new()?;
+        let exported = cipher.export(&passphrase)?;
+        let _ = CIPHER.set((cipher, exported));
+    } else {
+        return Ok(());
+    }
+
+    // encrypt or decrypt what's already there, if the setting has changed
+    if session_file.exists() && encrypted.is_some() != encrypt_session() {
+        info!("migrating session file encryption");
+        let session = read_raw(session_file)?;
+        write_raw(session_file, &session)?;
+    }
+
+    Ok(())
+}
+
+fn unlock_cipher(exported: &[u8]) -> anyhow::Result<StoreCipher> {
+    for _ in 0..3 {
+        let passphrase = prompt("Session passphrase: ")?;
+
+        match StoreCipher::import(&passphrase, exported) {
+            Ok(cipher) => return Ok(cipher),
+            Err(_) => eprintln!("Wrong passphrase."),
+        }
+    }
+
+    bail!("Could not unlock the session file.")
+}
+
+fn new_passphrase() -> anyhow::Result<String> {
+    loop {
+        let passphrase = prompt("Choose a session passphrase: ")?;
+
+        if passphrase.is_empty() {
+            eprintln!("The passphrase can't be empty.");
+            continue;
+        }
+
+        if std::env::var(PASSPHRASE_VAR).is_ok() || prompt("Once more: ")? == passphrase {
+            return Ok(passphrase);
+        }
+
+        eprintln!("Those didn't match.");
+    }
+}
+
+// Read a line from the terminal without echoing it.
+fn prompt(message: &str) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+
+    eprint!("{}", message);
+    io::stderr().flush()?;
+
+    enable_raw_mode()?;
+    let result = read_hidden();
+    disable_raw_mode()?;
+    eprintln!();
+
+    result
+}
+
+fn read_hidden() -> anyhow::Result<String> {
+    let mut value = String::new();
+
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+
+        match key.code {
+            KeyCode::Enter => return Ok(value),
+            KeyCode::Backspace => {
+                value.pop();
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                bail!("Cancelled.")
+            }
+            KeyCode::Char(c) => value.push(c),
+            _ => {}
+        }
+    }
+}
+
+fn read_raw(session_file: &Path) -> anyhow::Result<FullSession> {
+    let serialized_session = fs::read_to_string(session_file)?;
+    decode(&serialized_session, CIPHER.get().map(|(c, _)| c))
+}
+
+// Encrypt if we've been asked to, and have a cipher to do it with. If the
+// setting was turned on while running, that waits until the next start.
+fn write_raw(session_file: &Path, session: &FullSession) -> anyhow::Result<()> {
+    let cipher = CIPHER.get().filter(|_| encrypt_session());
+    fs::write(session_file, encode(session, cipher)?)?;
+
+    Ok(())
+}
+
+fn decode(serialized: &str, cipher: Option<&StoreCipher>) -> anyhow::Result<FullSession> {
+    let Ok(encrypted) = serde_json::from_str::<EncryptedSession>(serialized) else {
+        return Ok(serde_json::from_str(serialized)?);
+    };
+
+    let cipher = cipher.context("The session file is encrypted, but hasn't been unlocked.")?;
+
+    Ok(cipher.decrypt_value(&encrypted.session)?)
+}
+
+fn encode(
+    session: &FullSession,
+    cipher: Option<&(StoreCipher, Vec<u8>)>,
+) -> anyhow::Result<String> {
+    let Some((cipher, exported)) = cipher else {
+        return Ok(serde_json::to_string(session)?);
+    };
+
+    let encrypted = EncryptedSession {
+        cipher: exported.clone(),
+        session: cipher.encrypt_value(session)?,
+    };
+
+    Ok(serde_json::to_string(&encrypted)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persist_sync_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session");
+
+        // a session from before the keyring was a thing
+        fs::write(
+            &path,
+            r#"{
+                "client_session": {
+                    "homeserver": "example.org",
+                    "db_path": "/tmp/abc",
+                    "passphrase": "secret"
+                },
+                "user_session": {
+                    "user_id": "@a:example.org",
+                    "device_id": "ABC",
+                    "access_token": "token"
+                },
+                "sync_token": null
+            }"#,
+        )
+        .unwrap();
+
+        persist_sync_token(&path, "s123".to_string()).unwrap();
+
+        let session = read_raw(&path).unwrap();
+
+        assert_eq!(session.sync_token.as_deref(), Some("s123"));
+        assert_eq!(session.client_session.passphrase, "secret");
+        assert!(session.user_session.is_some());
+        assert!(!session.keyring);
+    }
+
+    #[test]
+    fn test_encryption() {
+        let session = FullSession {
+            client_session: ClientSession {
+                homeserver: "example.org".to_string(),
+                db_path: PathBuf::from("/tmp/abc"),
+                passphrase: "secret".to_string(),
+            },
+            user_session: None,
+            sync_token: Some("s123".to_string()),
+            keyring: false,
+        };
+
+        let cipher = StoreCipher::new().unwrap();
+        let exported = cipher._insecure_export_fast_for_testing("hunter2").unwrap();
+        let encoded = encode(&session, Some(&(cipher, exported.clone()))).unwrap();
+
+        assert!(!encoded.contains("secret"));
+        assert!(decode(&encoded, None).is_err());
+
+        let cipher = StoreCipher::import("hunter2", &exported).unwrap();
+        let decoded = decode(&encoded, Some(&cipher)).unwrap();
+
+        assert_eq!(decoded.client_session.passphrase, "secret");
+        assert_eq!(decoded.sync_token.as_deref(), Some("s123"));
+        assert!(StoreCipher::import("hunter3", &exported).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::PathBuf;
+
+use log::{error, info};
+use matrix_sdk_store_encryption::StoreCipher;
+use once_cell::sync::OnceCell;
+use ruma::events::{AnySyncTimelineEvent, AnyTimelineEvent};
+use ruma::serde::Raw;
+use ruma::RoomId;
+
+/// How many events to keep around for each room.
+const MAX_EVENTS: usize = 100;
+
+/// A sidecar cache of the most recent events in each room, so that we have
+/// something to show while the real timeline is fetched. It has decrypted
+/// messages in it, so it's sealed with a key kept under the store passphrase,
+/// and is no easier to read than the store itself.
+pub struct TimelineCache {
+    dir: PathBuf,
+    // nothing is read or written until we have this
+    cipher: OnceCell<StoreCipher>,
+}
+
+impl Default for TimelineCache {
+    fn default() -> Self {
+        let dir = dirs::data_dir()
+            .expect("no data directory found")
+            .join("matui")
+            .join("timeline");
+
+        TimelineCache::new(dir)
+    }
+}
+
+impl TimelineCache {
+    pub fn new(dir: PathBuf) -> Self {
+        TimelineCache {
+            dir,
+            cipher: OnceCell::new(),
+        }
+    }
+
+    /// Open the cache with the store passphrase. If the key doesn't fit,
+    /// the store has been replaced (or this is the first time), and nothing
+    /// in here could be read anyway, so start over.
+    pub fn unlock(&self, passphrase: &str) -> anyhow::Result<()> {
+        let key = blake3::derive_key("matui timeline cache", passphrase.as_bytes());
+        let key_path = self.dir.join("key");
+
+        let existing = fs::read(&key_path)
+            .ok()
+            .and_then(|exported| StoreCipher::import_with_key(&key, &exported).ok());
+
+        let cipher = match existing {
+            Some(cipher) => cipher,
+            None => {
+                info!("starting a new timeline cache");
+
+                if self.dir.exists() {
+                    fs::remove_dir_all(&self.dir)?;
+                }
+
+                fs::create_dir_all(&self.dir)?;
+
+                let cipher = StoreCipher::new()?;
+                fs::write(&key_path, cipher.export_with_key(&key)?)?;
+                cipher
+            }
+        };
+
+        let _ = self.cipher.set(cipher);
+
+        Ok(())
+    }
+
+    fn path(&self, room_id: &RoomId) -> PathBuf {
+        // room IDs are safe as file names, aside from the sigil
+        self.dir
+            .join(format!("{}.json", room_id.as_str().trim_start_matches('!')))
+    }
+
+    pub fn load(&self, room_id: &RoomId) -> Vec<AnyTimelineEvent> {
+        let path = self.path(room_id);
+
+        let Some(cipher) = self.cipher.get().filter(|_| path.exists()) else {
+            return vec![];
+        };
+
+        let raw: Vec<Raw<AnySyncTimelineEvent>> = match fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(cipher.decrypt_value(&data)?))
+        {
+            Ok(raw) => raw,
+            Err(e) => {
+                error!("could not read timeline cache: {}", e);
+                return vec![];
+            }
+        };
+
+        let events: Vec<AnyTimelineEvent> = raw
+            .iter()
+            .filter_map(|r| r.deserialize().ok())
+            .map(|e| e.into_full_event(room_id.to_owned()))
+            .collect();
+
+        info!("loaded {} cached events for {}", events.len(), room_id);
+
+        events
+    }
+
+    pub fn store(&self, room_id: &RoomId, mut events: Vec<Raw<AnySyncTimelineEvent>>) {
+        let Some(cipher) = self.cipher.get() else {
+            return;
+        };
+
+        events.truncate(MAX_EVENTS);
+
+        let result = cipher
+            .encrypt_value(&events)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(fs::write(self.path(room_id), data)?));
+
+        if let Err(e) = result {
+            error!("could not write timeline cache: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use matrix_sdk::ruma::exports::serde_json;
+    use ruma::events::AnySyncTimelineEvent;
+    use ruma::room_id;
+    use ruma::serde::Raw;
+
+    use crate::matrix::timelinecache::TimelineCache;
+
+    #[test]
+    fn it_round_trips_events() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = TimelineCache::new(dir.path().to_path_buf());
+        let room_id = room_id!("!abc:example.com");
+
+        assert!(cache.load(room_id).is_empty());
+        cache.unlock("secret")?;
+        assert!(cache.load(room_id).is_empty());
+
+        let raw: Raw<AnySyncTimelineEvent> = serde_json::from_str(
+            r#"{
+                "type": "m.room.message",
+                "event_id": "$1:example.com",
+                "sender": "@phil:example.com",
+                "origin_server_ts": 1,
+                "content": { "msgtype": "m.text", "body": "hello" }
+            }"#,
+        )?;
+
+        cache.store(room_id, vec![raw]);
+
+        let events = cache.load(room_id);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].room_id(), room_id);
+        assert_eq!(events[0].event_id().as_str(), "$1:example.com");
+
+        // nothing readable on disk
+        let path = cache.path(room_id);
+        assert!(!fs::read_to_string(&path)?.contains("hello"));
+
+        // the same passphrase opens it again, and a new one starts over
+        let again = TimelineCache::new(dir.path().to_path_buf());
+        again.unlock("secret")?;
+        assert_eq!(again.load(room_id).len(), 1);
+
+        let other = TimelineCache::new(dir.path().to_path_buf());
+        other.unlock("another")?;
+        assert!(other.load(room_id).is_empty());
+        assert!(!path.exists());
+
+        Ok(())
+    }
+}
@@ -1,8 +1,25 @@
 use core::fmt;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 use matrix_sdk::room::RoomMember;
+use ratatui::style::Color;
 use ruma::OwnedUserId;
 
+// Colors that show up well on a black background.
+const COLORS: [Color; 10] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightBlue,
+    Color::LightMagenta,
+];
+
 /// A way to store a user ID, with a display name that can be updated later.
 #[derive(Clone)]
 pub struct Username {
@@ -37,6 +54,23 @@ impl Username {
             self.id.as_str()
         }
     }
+
+    /// The first letter of the name, for when there's no room for the rest.
+    pub fn initial(&self) -> char {
+        self.as_str()
+            .chars()
+            .find(|c| c.is_alphanumeric())
+            .and_then(|c| c.to_uppercase().next())
+            .unwrap_or('?')
+    }
+
+    /// A color that sticks with the user, no matter their display name.
+    pub fn color(&self) -> Color {
+        let mut hasher = DefaultHasher::new();
+        self.id.hash(&mut hasher);
+
+        COLORS[(hasher.finish() % COLORS.len() as u64) as usize]
+    }
 }
 
 impl fmt::Display for Username {
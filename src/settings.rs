@@ -1,17 +1,140 @@
-use config::Config;
+use anyhow::{anyhow, Context};
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, FileFormat};
 use log::{info, warn};
+use matrix_sdk::ruma::exports::serde_json::{Map, Value};
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use ratatui::style::Color;
+use regex::Regex;
 use ruma::RoomId;
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
-use std::sync::{RwLock, RwLockReadGuard};
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
 use std::time::Duration;
 use std::{fs, thread};
+use toml_edit::DocumentMut;
 
-const DEFAULT_CONFIG: &str = "reactions = [ \"❤️\", \"👍\", \"👎\", \"😂\", \"‼️\", \"❓️\"]\n";
+use crate::app::App;
+use crate::defaults::default_config;
+use crate::handler::MatuiEvent;
 
 lazy_static::lazy_static! {
     static ref SETTINGS: RwLock<Config> = RwLock::new(build_settings());
+
+    // what was wrong with the config file, until somebody shows it
+    static ref PROBLEM: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+
+    // the settings that follow the account around, as JSON
+    static ref ROAMING: RwLock<String> = RwLock::new(String::new());
+}
+
+/// The settings kept in account data, so they're the same on every machine.
+/// Whatever's in config.toml still wins.
+pub const ROAMING_KEYS: &[&str] = &["reactions", "quick_reactions", "muted", "archived", "rooms"];
+
+/// Everything that can go in config.toml, for catching typos and values of
+/// the wrong type. The accessors below still read from the config itself.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct Settings {
+    reactions: Option<Vec<String>>,
+    quick_reactions: Option<HashMap<String, String>>,
+    muted: Option<Vec<String>>,
+    skip_notifications: Option<Vec<String>>,
+    notification_previews: Option<bool>,
+    device_warnings: Option<String>,
+    archived: Option<Vec<String>>,
+    clean_vim: Option<bool>,
+    gui_editor: Option<bool>,
+    external_pager: Option<bool>,
+    file_picker: Option<String>,
+    room_sort: Option<String>,
+    unread_rooms_only: Option<bool>,
+    unread_counts: Option<String>,
+    receipt_style: Option<String>,
+    group_messages: Option<bool>,
+    max_message_lines: Option<usize>,
+    show_position: Option<bool>,
+    chat_width: Option<u16>,
+    full_width: Option<bool>,
+    zen_mode: Option<bool>,
+    presentation_mode: Option<bool>,
+    layout: Option<String>,
+    time_language: Option<String>,
+    short_times: Option<bool>,
+    low_bandwidth: Option<bool>,
+    media_cache_size: Option<u64>,
+    prefetch_screens: Option<usize>,
+    log_level: Option<String>,
+    keyring: Option<bool>,
+    keyring_tokens: Option<bool>,
+    proxy: Option<String>,
+    ca_certificate: Option<String>,
+    danger_disable_tls_verification: Option<bool>,
+    device_name: Option<String>,
+    encrypt_session: Option<bool>,
+    editor: Option<EditorActions>,
+    strings: Option<HashMap<String, String>>,
+    hooks: Option<HashMap<String, String>>,
+    rooms: Option<HashMap<String, RoomSettings>>,
+    bridges: Option<HashMap<String, String>>,
+}
+
+/// The name of every setting, in the order they're declared above.
+pub fn setting_names() -> &'static [&'static str] {
+    let mut names: &'static [&'static str] = &[];
+    let _ = Settings::deserialize(FieldNames(&mut names));
+    names
+}
+
+// Doesn't deserialize anything; just notes the fields it was asked for.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(serde::de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(serde::de::Error::custom("just looking"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct EditorActions {
+    compose: Option<EditorSettings>,
+    reply: Option<EditorSettings>,
+    edit: Option<EditorSettings>,
+    view: Option<EditorSettings>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct EditorSettings {
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    template: Option<String>,
 }
 
 fn get_path() -> PathBuf {
@@ -22,26 +145,588 @@ fn get_path() -> PathBuf {
     path
 }
 
+// Anything that isn't quite right gets reported, but we make do with what we
+// can read. There's nothing better to fall back on yet.
 fn build_settings() -> Config {
-    Config::builder()
-        .add_source(config::File::from(get_path().as_path()))
+    let path = get_path();
+
+    let config = builder()
+        .add_source(config::File::from(path.as_path()).required(false))
+        .build();
+
+    match config {
+        Ok(config) => {
+            if let Err(e) = check(&config) {
+                report(e);
+            }
+
+            config
+        }
+        Err(e) => {
+            report(anyhow::Error::new(e).context(format!("Could not read {}.", path.display())));
+            Config::default()
+        }
+    }
+}
+
+// The config file, but only if there's nothing wrong with it.
+fn load_settings() -> anyhow::Result<Config> {
+    let path = get_path();
+
+    let config = builder()
+        .add_source(config::File::from(path.as_path()))
         .build()
-        .expect("could not build settings")
+        .with_context(|| format!("Could not read {}.", path.display()))?;
+
+    check(&config)?;
+
+    Ok(config)
+}
+
+// What roams goes in first, so the config file is laid over the top.
+fn builder() -> ConfigBuilder<DefaultState> {
+    let roaming = ROAMING.read().unwrap();
+
+    if roaming.is_empty() {
+        return Config::builder();
+    }
+
+    Config::builder().add_source(config::File::from_str(&roaming, FileFormat::Json))
+}
+
+fn check(config: &Config) -> anyhow::Result<()> {
+    let settings = match config.clone().try_deserialize::<Settings>() {
+        Ok(settings) => settings,
+        Err(e) => return Err(friendly(e.to_string())),
+    };
+
+    let skipped = settings
+        .skip_notifications
+        .iter()
+        .chain(
+            settings
+                .rooms
+                .iter()
+                .flat_map(|r| r.values())
+                .filter_map(|r| r.skip_notifications.as_ref()),
+        )
+        .flatten();
+
+    for kind in skipped {
+        if !NOTIFICATION_KINDS.contains(&kind.as_str()) {
+            return Err(anyhow!(
+                "There's a problem with config.toml: \"{}\" isn't something that notifies. \
+                 Try one of {}.",
+                kind,
+                NOTIFICATION_KINDS.join(", ")
+            ));
+        }
+    }
+
+    let warnings = settings.device_warnings.iter().chain(
+        settings
+            .rooms
+            .iter()
+            .flat_map(|r| r.values())
+            .filter_map(|r| r.device_warnings.as_ref()),
+    );
+
+    for warning in warnings {
+        if !["off", "room", "device"].contains(&warning.as_str()) {
+            return Err(anyhow!(
+                "There's a problem with config.toml: device_warnings can be \"off\", \"room\" \
+                 or \"device\", not \"{}\".",
+                warning
+            ));
+        }
+    }
+
+    for (tag, pattern) in settings.bridges.unwrap_or_default() {
+        if let Err(e) = Regex::new(&pattern) {
+            return Err(anyhow::Error::new(e).context(format!(
+                "There's a problem with config.toml: the pattern for bridges.{} won't work.",
+                tag
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// Serde lists every field it was expecting, which is a lot. That goes in the
+// details.
+fn friendly(message: String) -> anyhow::Error {
+    match message.split_once(", expected one of") {
+        Some((short, _)) => anyhow::Error::msg(message.clone())
+            .context(format!("There's a problem with config.toml: {}.", short)),
+        None => anyhow!("There's a problem with config.toml: {}.", message),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn check_str(toml: &str) -> anyhow::Result<()> {
+    check(
+        &Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()?,
+    )
+}
+
+fn report(err: anyhow::Error) {
+    warn!("{:#}", err);
+    *PROBLEM.lock().unwrap() = Some(err);
+}
+
+// Swap in the config file, or keep what we had if it's broken.
+fn reload() {
+    match load_settings() {
+        Ok(config) => {
+            *SETTINGS.write().unwrap() = config;
+
+            // nobody to tell yet, if we're still starting up
+            if let Some(sender) = App::try_get_sender() {
+                let _ = sender.send(crate::event::Event::Matui(MatuiEvent::SettingsChanged));
+            }
+        }
+        Err(e) => report(e.context("Keeping the settings from before.")),
+    }
+}
+
+/// Take on the settings from account data, leaving out anything that doesn't
+/// roam, and all of it if it doesn't check out.
+pub fn set_roaming(content: Value) {
+    let json = Value::Object(roaming_only(content)).to_string();
+
+    let checked = Config::builder()
+        .add_source(config::File::from_str(&json, FileFormat::Json))
+        .build()
+        .map_err(anyhow::Error::new)
+        .and_then(|config| check(&config));
+
+    if let Err(e) = checked {
+        warn!("ignoring roaming settings: {:#}", e);
+        return;
+    }
+
+    if *ROAMING.read().unwrap() == json {
+        return;
+    }
+
+    info!("roaming settings changed; refreshing configuration");
+    *ROAMING.write().unwrap() = json;
+    reload();
+}
+
+/// The roaming settings as they are now, config file and all, for saving
+/// back to the account.
+pub fn roaming_settings() -> Map<String, Value> {
+    let settings = get_settings();
+
+    ROAMING_KEYS
+        .iter()
+        .filter_map(|key| Some((key.to_string(), settings.get::<Value>(key).ok()?)))
+        .collect()
+}
+
+fn roaming_only(content: Value) -> Map<String, Value> {
+    let Value::Object(map) = content else {
+        return Map::new();
+    };
+
+    map.into_iter()
+        .filter(|(key, _)| ROAMING_KEYS.contains(&key.as_str()))
+        .collect()
+}
+
+/// Whatever was wrong with the config file the last time it was read, once.
+pub fn take_problem() -> Option<anyhow::Error> {
+    PROBLEM.lock().unwrap().take()
+}
+
+/// Check the config file, for `--check-config`.
+pub fn check_config() -> anyhow::Result<PathBuf> {
+    load_settings()?;
+    Ok(get_path())
 }
 
 pub fn get_settings() -> RwLockReadGuard<'static, Config> {
     SETTINGS.read().unwrap()
 }
 
+/// Single keys in the reaction picker that send a reaction straight away.
+pub fn quick_reactions() -> HashMap<String, String> {
+    get_settings().get("quick_reactions").unwrap_or_default()
+}
+
 pub fn is_muted(room: &RoomId) -> bool {
     let muted: Vec<String> = get_settings().get("muted").unwrap_or_default();
-    muted.contains(&room.to_string())
+    muted.contains(&room.to_string()) || room_notifications(room) == Notifications::None
+}
+
+/// Overrides for a single room, from `[rooms."!id:server"]`.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoomSettings {
+    name: Option<String>,
+    notifications: Option<String>,
+    layout: Option<String>,
+    accent: Option<String>,
+    skip_notifications: Option<Vec<String>>,
+    notification_previews: Option<bool>,
+    device_warnings: Option<String>,
+    unread_counts: Option<String>,
+}
+
+fn room_settings(room: &RoomId) -> RoomSettings {
+    get_settings()
+        .get::<HashMap<String, RoomSettings>>("rooms")
+        .ok()
+        .and_then(|mut rooms| rooms.remove(room.as_str()))
+        .unwrap_or_default()
+}
+
+/// Our own name for a room, rather than the one it has.
+pub fn room_name(room: &RoomId) -> Option<String> {
+    room_settings(room).name
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notifications {
+    All,
+    Mentions,
+    None,
+}
+
+/// Which messages in a room get a notification: "all" (the default),
+/// "mentions" or "none".
+pub fn room_notifications(room: &RoomId) -> Notifications {
+    match room_settings(room).notifications.as_deref() {
+        Some("mentions") => Notifications::Mentions,
+        Some("none") => Notifications::None,
+        _ => Notifications::All,
+    }
+}
+
+/// The kinds of message that can be left out of notifications.
+pub const NOTIFICATION_KINDS: &[&str] = &["text", "image", "video", "file", "notice"];
+
+/// Don't notify for this kind of message (one of `NOTIFICATION_KINDS`). Set
+/// for every room with `skip_notifications`, or just one in its table.
+pub fn skip_notification(room: &RoomId, kind: &str) -> bool {
+    room_settings(room)
+        .skip_notifications
+        .or_else(|| get_settings().get("skip_notifications").ok())
+        .map_or(false, |kinds: Vec<String>| kinds.iter().any(|k| k == kind))
+}
+
+/// Show what a message says in its notification, or just who sent it, and
+/// where. Set for every room with `notification_previews`, or just one in
+/// its table.
+pub fn notification_previews(room: &RoomId) -> bool {
+    room_settings(room)
+        .notification_previews
+        .or_else(|| get_settings().get("notification_previews").ok())
+        .unwrap_or(true)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceWarnings {
+    Off,
+    Room,
+    Device,
+}
+
+/// Warn before sending to devices nobody has verified: "room" (the first time
+/// in each room), "device" (whenever there's one we haven't warned about) or
+/// "off" (the default). Set for every room with `device_warnings`, or just one
+/// in its table.
+pub fn device_warnings(room: &RoomId) -> DeviceWarnings {
+    let warnings = room_settings(room)
+        .device_warnings
+        .or_else(|| get_settings().get("device_warnings").ok());
+
+    match warnings.as_deref() {
+        Some("room") => DeviceWarnings::Room,
+        Some("device") => DeviceWarnings::Device,
+        _ => DeviceWarnings::Off,
+    }
+}
+
+/// Leave out the blank line between messages: "compact", or "cozy" (the
+/// default). Set for every room with `layout`, or just one in its table.
+pub fn compact_layout(room: &RoomId) -> bool {
+    room_settings(room)
+        .layout
+        .or_else(|| get_settings().get("layout").ok())
+        .map_or(false, |l| l == "compact")
+}
+
+/// Count only mentions in the room switcher, rather than every message:
+/// "mentions", or "all" (the default). Set for every room with
+/// `unread_counts`, or just one in its table.
+pub fn mentions_only_unread(room: &RoomId) -> bool {
+    room_settings(room)
+        .unread_counts
+        .or_else(|| get_settings().get("unread_counts").ok())
+        .map_or(false, |c| c == "mentions")
+}
+
+/// A color to pick a room out with, like "cyan" or "#ff8800".
+pub fn room_accent(room: &RoomId) -> Option<Color> {
+    room_settings(room).accent.and_then(|c| c.parse().ok())
+}
+
+/// Patterns for the Matrix IDs of bridged users, by the protocol to tag them
+/// with, from the `[bridges]` table.
+pub fn bridge_patterns() -> HashMap<String, String> {
+    get_settings().get("bridges").unwrap_or_default()
+}
+
+/// Rooms hidden from the room switcher, unless asked for.
+pub fn archived_rooms() -> Vec<String> {
+    get_settings().get("archived").unwrap_or_default()
+}
+
+pub fn is_archived(room: &RoomId) -> bool {
+    archived_rooms().contains(&room.to_string())
 }
 
 pub fn clean_vim() -> bool {
     get_settings().get("clean_vim").unwrap_or_default()
 }
 
+/// A setting from the editor table for an action, like `editor.reply.command`.
+pub fn editor_setting(action: &str, key: &str) -> Option<String> {
+    get_settings()
+        .get(&format!("editor.{}.{}", action, key))
+        .ok()
+}
+
+/// Whether the editor opens its own window, and so doesn't need the
+/// terminal. Guessed from the command if not set.
+pub fn gui_editor() -> Option<bool> {
+    get_settings().get("gui_editor").ok()
+}
+
+/// Extra arguments to give the editor for an action, before the file name.
+pub fn editor_args(action: &str) -> Vec<String> {
+    get_settings()
+        .get(&format!("editor.{}.args", action))
+        .unwrap_or_default()
+}
+
+/// Read messages and rooms in the editor, rather than the built-in pager.
+pub fn external_pager() -> bool {
+    get_settings().get("external_pager").unwrap_or_default()
+}
+
+/// A command to pick files with, instead of the native dialog, like
+/// `fzf -m`. Picked paths are read from its output, or from the file at `{}`
+/// if that's in the command.
+pub fn file_picker() -> Option<String> {
+    get_settings().get("file_picker").ok()
+}
+
+/// How the room switcher is sorted: "unread", "recent" or "alphabetical".
+pub fn room_sort() -> Option<String> {
+    get_settings().get("room_sort").ok()
+}
+
+/// Only show rooms with something unread in the room switcher.
+pub fn unread_rooms_only() -> bool {
+    get_settings().get("unread_rooms_only").unwrap_or_default()
+}
+
+/// Change a single setting in the config file, leaving everything else in
+/// there (comments included) alone.
+pub fn set_setting(key: &str, value: impl Into<toml_edit::Value>) -> anyhow::Result<()> {
+    let path = get_path();
+
+    let mut doc: DocumentMut = fs::read_to_string(&path).unwrap_or_default().parse()?;
+    doc[key] = toml_edit::value(value);
+    fs::write(&path, doc.to_string())?;
+
+    // don't wait on the watcher to notice
+    reload();
+
+    Ok(())
+}
+
+/// Show read receipts as "Seen by" text, rather than a row of initials.
+pub fn text_receipts() -> bool {
+    get_settings()
+        .get::<String>("receipt_style")
+        .map_or(false, |s| s == "text")
+}
+
+/// Leave the sender off of messages that follow right on from the last one
+/// they sent.
+pub fn group_messages() -> bool {
+    get_settings().get("group_messages").unwrap_or(true)
+}
+
+/// Long messages are cut off after this many lines in the chat.
+pub fn max_message_lines() -> Option<usize> {
+    get_settings().get("max_message_lines").ok()
+}
+
+/// Show which message is selected, out of how many are loaded, in the
+/// chat header.
+pub fn show_position() -> bool {
+    get_settings().get("show_position").unwrap_or_default()
+}
+
+/// How wide the chat can get before it's centered, in columns.
+pub fn chat_width() -> u16 {
+    get_settings()
+        .get::<u16>("chat_width")
+        .unwrap_or(80)
+        .max(20)
+}
+
+/// Use the whole width of the terminal for the chat.
+pub fn full_width() -> bool {
+    get_settings().get("full_width").unwrap_or_default()
+}
+
+/// Leave off the header, so every row is a message.
+pub fn zen_mode() -> bool {
+    get_settings().get("zen_mode").unwrap_or_default()
+}
+
+/// Sharing the screen, so keep private chats private: no notifications, and
+/// no message previews in the room switcher.
+pub fn presentation_mode() -> bool {
+    get_settings().get("presentation_mode").unwrap_or_default()
+}
+
+/// The language for relative times, as an ISO 639-1 code, like "de".
+pub fn time_language() -> Option<String> {
+    get_settings().get("time_language").ok()
+}
+
+/// "5m" rather than "5 minutes ago".
+pub fn short_times() -> bool {
+    get_settings().get("short_times").unwrap_or_default()
+}
+
+/// A replacement for one of our phrases, from the `[strings]` table.
+pub fn translation(key: &str) -> Option<String> {
+    get_settings().get(&format!("strings.{}", key)).ok()
+}
+
+/// Go easy on the network: no avatars or thumbnails, and smaller syncs.
+pub fn low_bandwidth() -> bool {
+    get_settings().get("low_bandwidth").unwrap_or_default()
+}
+
+/// How big the media cache can get, in megabytes.
+pub fn media_cache_size() -> u64 {
+    get_settings().get("media_cache_size").unwrap_or(500)
+}
+
+/// How many screens of history to keep loaded ahead of the cursor.
+pub fn prefetch_screens() -> usize {
+    get_settings().get("prefetch_screens").unwrap_or(3)
+}
+
+/// One of "error", "warn", "info", "debug" or "trace".
+pub fn log_level() -> String {
+    get_settings()
+        .get("log_level")
+        .unwrap_or("info".to_string())
+}
+
+/// The command to run for a hook, from the `[hooks]` table.
+pub fn hook_command(name: &str) -> Option<String> {
+    get_settings().get(&format!("hooks.{}", name)).ok()
+}
+
+/// Keep the store passphrase in the system keyring, not the session file.
+pub fn use_keyring() -> bool {
+    get_settings().get("keyring").unwrap_or_default()
+}
+
+/// And the access tokens, too.
+pub fn keyring_tokens() -> bool {
+    use_keyring() && get_settings().get("keyring_tokens").unwrap_or_default()
+}
+
+/// The proxy to connect through, if any. Falls back to the usual environment
+/// variables.
+pub fn proxy() -> Option<String> {
+    let configured = get_settings().get("proxy").ok();
+    pick_proxy(configured, |name| std::env::var(name).ok())
+}
+
+fn pick_proxy(configured: Option<String>, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    configured
+        .into_iter()
+        .chain(
+            [
+                "ALL_PROXY",
+                "all_proxy",
+                "HTTPS_PROXY",
+                "https_proxy",
+                "HTTP_PROXY",
+                "http_proxy",
+            ]
+            .iter()
+            .filter_map(|name| env(name)),
+        )
+        .find(|p| !p.trim().is_empty())
+}
+
+/// An extra root certificate (or bundle of them) in PEM format, for
+/// homeservers behind a private CA.
+pub fn ca_certificate() -> Option<PathBuf> {
+    let path: String = get_settings().get("ca_certificate").ok()?;
+
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().map(|home| home.join(rest)),
+        None => Some(PathBuf::from(path)),
+    }
+}
+
+/// Skip TLS certificate verification entirely. Never a good idea.
+pub fn danger_disable_tls_verification() -> bool {
+    get_settings()
+        .get("danger_disable_tls_verification")
+        .unwrap_or_default()
+}
+
+/// What to call new sessions in device lists.
+pub fn device_name() -> String {
+    get_settings()
+        .get("device_name")
+        .unwrap_or_else(|_| match hostname() {
+            Some(host) => format!("Matui on {}", host),
+            None => "Matui".to_string(),
+        })
+}
+
+fn hostname() -> Option<String> {
+    let mut buf = [0_u8; 256];
+
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    let host = String::from_utf8_lossy(&buf[..end]).to_string();
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+/// Encrypt the session file with a passphrase asked for at startup.
+pub fn encrypt_session() -> bool {
+    get_settings().get("encrypt_session").unwrap_or_default()
+}
+
 fn watch_internal() {
     let (tx, rx) = channel();
 
@@ -62,7 +747,7 @@ fn watch_internal() {
                 ..
             })) => {
                 info!("config.toml written; refreshing configuration");
-                *SETTINGS.write().unwrap() = build_settings();
+                reload();
             }
             Err(e) => warn!("watch error: {:?}", e),
             _ => {}
@@ -77,7 +762,7 @@ pub fn watch_settings_forever() {
     if !path.exists() {
         let dir = path.parent().unwrap();
         fs::create_dir_all(dir).unwrap();
-        fs::write(path, DEFAULT_CONFIG).unwrap();
+        fs::write(path, default_config()).unwrap();
     }
 
     // Spawn a thread to keep an eye on it
@@ -85,3 +770,86 @@ pub fn watch_settings_forever() {
         watch_internal();
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::exports::serde_json;
+
+    #[test]
+    fn test_check() {
+        assert!(check_str("zen_mode = true\n[rooms.\"!a:b.c\"]\nlayout = \"compact\"").is_ok());
+
+        let err = check_str("zen_mod = true").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "There's a problem with config.toml: unknown field `zen_mod`."
+        );
+
+        assert!(check_str("chat_width = \"wide\"").is_err());
+        assert!(check_str("[editor.reply]\ncmd = \"vim\"").is_err());
+        assert!(check_str("[bridges]\nxmpp = \"^@_xmpp_(\"").is_err());
+        assert!(check_str("skip_notifications = [\"image\", \"notice\"]").is_ok());
+        assert!(check_str("skip_notifications = [\"reaction\"]").is_err());
+        assert!(check_str("[rooms.\"!a:b.c\"]\nskip_notifications = [\"gif\"]").is_err());
+        assert!(check_str("[rooms.\"!a:b.c\"]\nnotification_previews = false").is_ok());
+        assert!(check_str("notification_previews = [true]").is_err());
+        assert!(check_str("device_warnings = \"room\"").is_ok());
+        assert!(check_str("[rooms.\"!a:b.c\"]\ndevice_warnings = \"always\"").is_err());
+    }
+
+    // anything we suggest had better be valid
+    #[test]
+    fn test_readme_config() {
+        let readme = include_str!("../README.md");
+        let start = readme.find("# Configuration Example\n\n```\n").unwrap();
+        let example = readme[start..].split("```").nth(1).unwrap();
+
+        check_str(example).unwrap();
+    }
+
+    #[test]
+    fn test_roaming_only() {
+        let content = serde_json::json!({
+            "muted": ["!a:b.c"],
+            "keyring": true,
+            "rooms": { "!a:b.c": { "layout": "compact" } },
+        });
+
+        let roaming = roaming_only(content);
+
+        assert_eq!(roaming.len(), 2);
+        assert!(roaming.contains_key("muted"));
+        assert!(!roaming.contains_key("keyring"));
+        assert!(roaming_only(serde_json::json!([1, 2])).is_empty());
+    }
+
+    #[test]
+    fn test_pick_proxy() {
+        let env = |name: &str| match name {
+            "https_proxy" => Some("http://proxy:3128".to_string()),
+            "ALL_PROXY" => Some("socks5://127.0.0.1:9050".to_string()),
+            "HTTPS_PROXY" => Some("".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            pick_proxy(Some("socks5://127.0.0.1:9050".to_string()), env).as_deref(),
+            Some("socks5://127.0.0.1:9050")
+        );
+        assert_eq!(
+            pick_proxy(None, env).as_deref(),
+            Some("socks5://127.0.0.1:9050")
+        );
+
+        // a blank one doesn't count
+        let env = |name: &str| match name {
+            "HTTPS_PROXY" => Some(" ".to_string()),
+            "http_proxy" => Some("http://proxy:3128".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(pick_proxy(None, env).as_deref(), Some("http://proxy:3128"));
+        assert_eq!(pick_proxy(None, |_| None), None);
+    }
+}
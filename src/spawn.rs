@@ -3,37 +3,273 @@ use image::imageops::FilterType;
 use lazy_static::lazy_static;
 use linkify::LinkFinder;
 use log::error;
-use matrix_sdk::media::MediaFileHandle;
+use matrix_sdk::room::Room;
 use native_dialog::FileDialog;
 use notify_rust::Hint;
 use regex::Regex;
+use ruma::serde::{base64::Standard, Base64};
+use ruma::OwnedEventId;
 use std::env::var;
 use std::fs;
-use std::io::{Cursor, Read};
-use std::path::PathBuf;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tempfile::Builder;
 
-use crate::settings::clean_vim;
+use crate::app::App;
+use crate::event::{Event, EventHandler};
+use crate::matrix::matrix::TypingGuard;
+use crate::settings::{clean_vim, editor_args, editor_setting, file_picker, gui_editor};
 
 lazy_static! {
     static ref FILE_RE: Regex = Regex::new(r"-([0-9]+)(\.|$)").unwrap();
 }
 
-pub fn get_file_paths() -> anyhow::Result<Vec<PathBuf>> {
+/// What files are being picked for, and so what to do with them once they
+/// have been.
+#[derive(Clone, Debug)]
+pub enum PickTarget {
+    Upload(Room, String),
+    Avatar,
+}
+
+/// Pick files on a blocking task, like the editor, and send them back in an
+/// `Event::FilesPicked`. A picker command runs in the terminal, so the
+/// terminal event handler is parked, and this returns true: nothing should
+/// be drawn until the event arrives.
+pub fn spawn_file_picker(handler: &EventHandler, target: PickTarget) -> bool {
+    let parked = file_picker().is_some();
+
+    if parked {
+        handler.park();
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let result = get_file_paths().map_err(|e| e.to_string());
+
+        if let Err(e) = App::get_sender().send(Event::FilesPicked(target, result, parked)) {
+            error!("could not send picked files: {}", e);
+        }
+    });
+
+    parked
+}
+
+// Pick files with the configured picker command, or the native dialog. If
+// neither is available (over SSH, say), there's nothing, and the caller will
+// have to ask some other way.
+fn get_file_paths() -> anyhow::Result<Option<Vec<PathBuf>>> {
+    if let Some(picker) = file_picker() {
+        return run_file_picker(&picker).map(Some);
+    }
+
+    if var("DISPLAY").is_err() && var("WAYLAND_DISPLAY").is_err() {
+        return Ok(None);
+    }
+
     let home = dirs::home_dir().context("no home directory")?;
 
-    let path = FileDialog::new()
+    match FileDialog::new()
         .set_location(home.as_path())
-        .show_open_multiple_file()?;
+        .show_open_multiple_file()
+    {
+        Ok(paths) => Ok(Some(paths)),
+        Err(e) => {
+            error!("could not show a file dialog: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+// Pickers either print what was picked (fzf), or write it to a file we give
+// them (ranger --choosefiles={}).
+fn run_file_picker(picker: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let choices = Builder::new().suffix(".txt").tempfile()?;
+    let to_file = picker.contains("{}");
+
+    let picker = picker.replace("{}", &choices.path().to_string_lossy());
+
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&picker);
 
-    Ok(path)
+    // same as the editor: stay out of the alternate screen
+    command.env("TERM", "xterm1");
+
+    // backing out of a picker isn't an error, it's just nothing picked
+    let picked = if to_file {
+        command.status()?;
+        fs::read_to_string(choices.path())?
+    } else {
+        let output = command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    Ok(picked
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(PathBuf::from)
+        .collect())
 }
 
-pub fn get_text(existing: Option<&str>, suffix: Option<&str>) -> anyhow::Result<Option<String>> {
-    let editor = &var("EDITOR").unwrap_or("/usr/bin/vi".to_string());
+/// Why we're opening the editor, which decides the template below the text,
+/// and lets each have its own editor and arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Editing {
+    Compose,
+    Reply,
+    Edit,
+    View,
+}
+
+impl Editing {
+    fn name(&self) -> &'static str {
+        match self {
+            Editing::Compose => "compose",
+            Editing::Reply => "reply",
+            Editing::Edit => "edit",
+            Editing::View => "view",
+        }
+    }
+
+    fn default_template(&self) -> Option<&'static str> {
+        match self {
+            Editing::Compose => Some("<!-- Type a new message above to send to {room}. -->"),
+            Editing::Reply => Some("<!--\n  Replying to {sender}:\n\n{body}\n-->"),
+            Editing::Edit => Some("<!-- Edit your message above to change it in {room}. -->"),
+            Editing::View => None,
+        }
+    }
+
+    /// Does the editor for this open its own window? Then it doesn't need
+    /// the terminal, and we can keep drawing.
+    pub fn is_gui(&self) -> bool {
+        gui_editor().unwrap_or_else(|| looks_graphical(&editor_command(*self)))
+    }
+
+    // an empty template in the config turns it off
+    fn template(&self) -> Option<String> {
+        match editor_setting(self.name(), "template") {
+            Some(t) if t.is_empty() => None,
+            Some(t) => Some(t),
+            None => self.default_template().map(str::to_string),
+        }
+    }
+}
+
+/// What the editor was opened for, and so what to do with the text once it's
+/// closed.
+#[derive(Clone, Debug)]
+pub enum EditorTarget {
+    Compose(Room),
+    Reply(Room, OwnedEventId),
+    Edit(Room, OwnedEventId, Option<OwnedEventId>),
+    View,
+}
+
+impl EditorTarget {
+    pub fn editing(&self) -> Editing {
+        match self {
+            EditorTarget::Compose(_) => Editing::Compose,
+            EditorTarget::Reply(_, _) => Editing::Reply,
+            EditorTarget::Edit(_, _, _) => Editing::Edit,
+            EditorTarget::View => Editing::View,
+        }
+    }
+}
+
+/// Run the editor on a blocking task, so that syncing (and everything else)
+/// carries on while it's open, and send the text back in an
+/// `Event::EditorClosed`. Unless it's a GUI editor, the terminal event
+/// handler is parked, and this returns true: nothing should be drawn until
+/// the event arrives. Any typing notice is kept up until the editor exits.
+pub fn spawn_editor(
+    handler: &EventHandler,
+    target: EditorTarget,
+    existing: Option<String>,
+    vars: Vec<(&'static str, String)>,
+    typing: Option<TypingGuard>,
+) -> bool {
+    let parked = !target.editing().is_gui();
+
+    if parked {
+        handler.park();
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let vars: Vec<(&str, &str)> = vars.iter().map(|(n, v)| (*n, v.as_str())).collect();
+
+        let result =
+            get_text(target.editing(), existing.as_deref(), &vars).map_err(|e| e.to_string());
+
+        drop(typing);
+
+        if let Err(e) = App::get_sender().send(Event::EditorClosed(target, result, parked)) {
+            error!("could not send editor result: {}", e);
+        }
+    });
+
+    parked
+}
+
+// Editors that open their own window, as long as they're asked to wait for it
+// to close (code -w, subl -w, gvim -f).
+const GUI_EDITORS: [&str; 14] = [
+    "code",
+    "code-oss",
+    "codium",
+    "subl",
+    "gedit",
+    "gnome-text-editor",
+    "kate",
+    "kwrite",
+    "mousepad",
+    "xed",
+    "pluma",
+    "zeditor",
+    "gvim",
+    "neovide",
+];
+
+fn editor_command(editing: Editing) -> String {
+    editor_setting(editing.name(), "command")
+        .unwrap_or_else(|| var("EDITOR").unwrap_or("/usr/bin/vi".to_string()))
+}
+
+fn looks_graphical(editor: &str) -> bool {
+    let program = editor.split_whitespace().next().unwrap_or_default();
+    let name = Path::new(program)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    GUI_EDITORS.contains(&name.as_ref())
+}
+
+/// Fill in `{name}` variables.
+fn fill_template(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(template.to_string(), |t, (name, value)| {
+        t.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+pub fn get_text(
+    editing: Editing,
+    existing: Option<&str>,
+    vars: &[(&str, &str)],
+) -> anyhow::Result<Option<String>> {
+    let editor = editor_command(editing);
+    let gui = editing.is_gui();
     let mut tmpfile = Builder::new().suffix(".md").tempfile()?;
 
+    let suffix = editing.template().map(|t| fill_template(&t, vars));
+    let suffix = suffix.as_deref();
+
     let mut to_write = "".to_string();
 
     if let Some(str) = existing {
@@ -48,15 +284,22 @@ pub fn get_text(existing: Option<&str>, suffix: Option<&str>) -> anyhow::Result<
         std::fs::write(&tmpfile, to_write)?;
     }
 
-    let mut command = Command::new(editor);
+    // the command can come with arguments, like "code -w"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("/usr/bin/vi");
+
+    let mut command = Command::new(program);
+    command.args(parts);
 
     // xterm1 is a terminfo that explicitly ignores the alternate screen,
     // which is great for us, because an editor forcing us back to the
     // main screen is not at all ideal
-    command.env("TERM", "xterm1");
+    if !gui {
+        command.env("TERM", "xterm1");
+    }
 
     // set up vim just right, if that's what we're using
-    if editor.ends_with("vim") || editor.ends_with("vi") {
+    if !gui && (program.ends_with("vim") || program.ends_with("vi")) {
         if clean_vim() {
             command.arg("--clean");
         }
@@ -79,6 +322,8 @@ pub fn get_text(existing: Option<&str>, suffix: Option<&str>) -> anyhow::Result<
         command.arg("set wrap linebreak nolist spell");
     }
 
+    command.args(editor_args(editing.name()));
+
     let status = command.arg(tmpfile.path()).status()?;
 
     if !status.success() {
@@ -99,11 +344,8 @@ pub fn get_text(existing: Option<&str>, suffix: Option<&str>) -> anyhow::Result<
     Ok(Some(contents.trim().to_string()))
 }
 
-pub fn view_file(handle: MediaFileHandle) -> anyhow::Result<()> {
-    let status = open::commands(handle.path())[0].status()?;
-
-    // keep the file handle open until the viewer exits
-    drop(handle);
+pub fn view_file(path: &Path) -> anyhow::Result<()> {
+    let status = open::commands(path)[0].status()?;
 
     if !status.success() {
         bail!("Invalid status code.")
@@ -112,11 +354,11 @@ pub fn view_file(handle: MediaFileHandle) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn save_file(handle: MediaFileHandle, file_name: &str) -> anyhow::Result<PathBuf> {
+pub fn save_file(path: &Path, file_name: &str) -> anyhow::Result<PathBuf> {
     let mut destination = dirs::download_dir().context("no download directory")?;
     destination.push(file_name);
     let destination = make_unique(destination);
-    fs::copy(handle.path(), &destination)?;
+    fs::copy(path, &destination)?;
     Ok(destination)
 }
 
@@ -166,6 +408,51 @@ pub fn view_text(text: &str) {
     }
 }
 
+/// Put some text on the system clipboard. Without a clipboard tool, ask the
+/// terminal to do it (OSC 52), which also works over SSH.
+pub fn copy_to_clipboard(text: &str) -> anyhow::Result<()> {
+    let tools: &[&[&str]] = if var("WAYLAND_DISPLAY").is_ok() {
+        &[&["wl-copy"]]
+    } else if var("DISPLAY").is_ok() {
+        &[&["xclip", "-selection", "clipboard"], &["xsel", "-ib"]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else {
+        &[]
+    };
+
+    for tool in tools {
+        let child = Command::new(tool[0])
+            .args(&tool[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let Ok(mut child) = child else {
+            continue;
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+
+    // Matrix doesn't pad its base64, but terminals expect it
+    let mut encoded = Base64::<Standard, &[u8]>::new(text.as_bytes()).encode();
+    encoded.push_str(&"=".repeat((4 - encoded.len() % 4) % 4));
+
+    let mut stderr = std::io::stderr();
+    write!(stderr, "\x1b]52;c;{}\x07", encoded)?;
+    stderr.flush()?;
+
+    Ok(())
+}
+
 pub fn send_notification(summary: &str, body: &str, image: Option<Vec<u8>>) -> anyhow::Result<()> {
     if let Some(img) = image {
         let data = Cursor::new(img);
@@ -191,6 +478,25 @@ pub fn send_notification(summary: &str, body: &str, image: Option<Vec<u8>>) -> a
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_looks_graphical() {
+        assert!(looks_graphical("code -w"));
+        assert!(looks_graphical("/usr/bin/gedit"));
+        assert!(!looks_graphical("nvim"));
+        assert!(!looks_graphical(""));
+    }
+
+    #[test]
+    fn test_fill_template() {
+        assert_eq!(
+            fill_template(
+                "{sender} in {room}: {body} {unknown}",
+                &[("room", "Rust"), ("sender", "Phil"), ("body", "hi")]
+            ),
+            "Phil in Rust: hi {unknown}"
+        );
+    }
+
     #[test]
     fn test_next_file_first() {
         assert_eq!(next_file_name("image.jpg"), "image-1.jpg");
@@ -1,5 +1,8 @@
 use crate::app::App;
-use crossterm::event::{DisableMouseCapture, EnableFocusChange, DisableFocusChange};
+use crossterm::event::{
+    DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+    EnableFocusChange,
+};
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::backend::Backend;
 use ratatui::Terminal;
@@ -26,7 +29,12 @@ impl<B: Backend> Tui<B> {
     /// It enables the raw mode and sets terminal properties.
     pub fn init(&mut self) -> anyhow::Result<()> {
         terminal::enable_raw_mode()?;
-        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableFocusChange)?;
+        crossterm::execute!(
+            io::stderr(),
+            EnterAlternateScreen,
+            EnableFocusChange,
+            EnableBracketedPaste
+        )?;
         self.terminal.hide_cursor()?;
         self.terminal.clear()?;
         Ok(())
@@ -46,12 +54,26 @@ impl<B: Backend> Tui<B> {
         Ok(())
     }
 
+    /// Hands the terminal back to the shell and stops the process, then
+    /// picks up where we left off once we're continued.
+    pub fn suspend(&mut self) -> anyhow::Result<()> {
+        self.exit()?;
+
+        // this doesn't return until someone sends SIGCONT
+        #[cfg(unix)]
+        unsafe {
+            libc::raise(libc::SIGTSTP);
+        }
+
+        self.init()
+    }
+
     /// Exits the terminal interface.
     ///
     /// It disables the raw mode and reverts back the terminal properties.
     pub fn exit(&mut self) -> anyhow::Result<()> {
         terminal::disable_raw_mode()?;
-        crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture, DisableFocusChange)?;
+        crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture, DisableFocusChange, DisableBracketedPaste)?;
         self.terminal.show_cursor()?;
         Ok(())
     }
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+use matrix_sdk::ruma::exports::serde_json;
+use serde::{Deserialize, Serialize};
+
+/// How many recently used reactions make it into the picker.
+pub const MAX_RECENT: usize = 8;
+
+/// How long it takes a use to count for half as much, in seconds.
+const HALF_LIFE: f64 = 14.0 * 24.0 * 60.0 * 60.0;
+
+/// How often each reaction has been sent, and when last, so the picker can
+/// put the usual ones up top.
+#[derive(Default, Deserialize, Serialize)]
+pub struct ReactionUsage {
+    reactions: HashMap<String, Usage>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+struct Usage {
+    count: u32,
+    last_used: u64,
+}
+
+fn get_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("matui").join("reactions.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ReactionUsage {
+    pub fn load() -> Self {
+        let Some(path) = get_path().filter(|p| p.exists()) else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str(&s)?))
+        {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!("could not read reaction usage: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Count a reaction we've just sent.
+    pub fn record(reaction: &str) {
+        let mut usage = Self::load();
+        usage.add(reaction, now());
+
+        if let Err(e) = usage.save() {
+            error!("could not save reaction usage: {}", e);
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let path = get_path().ok_or_else(|| anyhow::anyhow!("no data directory"))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    fn add(&mut self, reaction: &str, at: u64) {
+        let usage = self.reactions.entry(reaction.to_string()).or_insert(Usage {
+            count: 0,
+            last_used: at,
+        });
+
+        usage.count += 1;
+        usage.last_used = at;
+    }
+
+    /// The reactions we use the most, best first. Every use counts, but old
+    /// ones count for less.
+    pub fn ranked(&self) -> Vec<String> {
+        self.ranked_at(now())
+    }
+
+    fn ranked_at(&self, now: u64) -> Vec<String> {
+        let mut scored: Vec<(f64, &String)> = self
+            .reactions
+            .iter()
+            .map(|(reaction, usage)| {
+                let age = now.saturating_sub(usage.last_used) as f64;
+                (usage.count as f64 * 0.5_f64.powf(age / HALF_LIFE), reaction)
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+        scored.into_iter().map(|(_, r)| r.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranked() {
+        let day = 24 * 60 * 60;
+        let now = 100 * day;
+        let mut usage = ReactionUsage::default();
+
+        // used a lot, but a long time ago
+        for _ in 0..6 {
+            usage.add("🎉", now - 60 * day);
+        }
+
+        // a few times lately
+        for _ in 0..3 {
+            usage.add("👍", now - day);
+        }
+
+        usage.add("😂", now);
+
+        assert_eq!(usage.ranked_at(now), vec!["👍", "😂", "🎉"]);
+    }
+}
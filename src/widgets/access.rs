@@ -0,0 +1,423 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::room::Room;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+use ruma::events::room::join_rules::{AllowRule, JoinRule};
+use ruma::OwnedRoomId;
+
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+type Options = &'static [(&'static str, &'static str, &'static str)];
+
+// (value, what we call it, what it means)
+const JOIN_RULES: Options = &[
+    (
+        "invite",
+        "invite only",
+        "Only people who are invited can join.",
+    ),
+    ("public", "public", "Anyone who finds the room can join."),
+    (
+        "knock",
+        "knock",
+        "Anyone can ask to join, and members can let them in.",
+    ),
+    (
+        "restricted",
+        "spaces",
+        "Members of the chosen spaces can join. Everyone else needs an invite.",
+    ),
+    (
+        "knock_restricted",
+        "spaces, or knock",
+        "Members of the chosen spaces can join. Everyone else can ask to.",
+    ),
+];
+
+const HISTORY: Options = &[
+    (
+        "shared",
+        "members",
+        "Members can read everything from when this was chosen, even before they joined.",
+    ),
+    (
+        "invited",
+        "since invited",
+        "Members can read from when they were invited.",
+    ),
+    (
+        "joined",
+        "since joined",
+        "Members can only read from when they joined.",
+    ),
+    (
+        "world_readable",
+        "anyone",
+        "Anyone can read the room, without joining.",
+    ),
+];
+
+const GUESTS: Options = &[
+    (
+        "forbidden",
+        "forbidden",
+        "Guest accounts can't join, even if the room is public.",
+    ),
+    (
+        "can_join",
+        "can join",
+        "Guest accounts can join, if the join rule lets them.",
+    ),
+];
+
+/// One setting, and which of its options is picked.
+struct Setting {
+    label: &'static str,
+    options: Options,
+    chosen: usize,
+    original: Option<usize>,
+}
+
+impl Setting {
+    fn new(label: &'static str, options: Options, current: &str) -> Self {
+        let original = options.iter().position(|(value, _, _)| *value == current);
+
+        Self {
+            label,
+            options,
+            chosen: original.unwrap_or_default(),
+            original,
+        }
+    }
+
+    fn value(&self) -> &'static str {
+        self.options[self.chosen].0
+    }
+
+    fn changed(&self) -> bool {
+        self.original != Some(self.chosen)
+    }
+
+    fn cycle(&mut self, forward: bool) {
+        let total = self.options.len();
+
+        self.chosen = if forward {
+            (self.chosen + 1) % total
+        } else {
+            (self.chosen + total - 1) % total
+        };
+    }
+}
+
+/// What to send, leaving out whatever didn't change.
+#[derive(Clone, Debug, Default)]
+pub struct AccessChanges {
+    pub join_rule: Option<(String, Vec<OwnedRoomId>)>,
+    pub history_visibility: Option<String>,
+    pub guest_access: Option<String>,
+}
+
+/// Pick who can join a room, who can read its history, and if guests are
+/// welcome.
+pub struct RoomAccess {
+    room: Room,
+    name: String,
+
+    // join rule, history, then guests
+    settings: [Setting; 3],
+
+    // every space I'm in, and if its members can join
+    spaces: Vec<(OwnedRoomId, String, bool)>,
+    original_spaces: Vec<OwnedRoomId>,
+
+    selected: usize,
+    hint: Option<&'static str>,
+}
+
+impl RoomAccess {
+    pub fn new(room: Room, name: String, spaces: Vec<(OwnedRoomId, String)>) -> Self {
+        let join_rule = room.join_rule();
+
+        let allowed: Vec<OwnedRoomId> = match &join_rule {
+            JoinRule::Restricted(r) | JoinRule::KnockRestricted(r) => r
+                .allow
+                .iter()
+                .filter_map(|rule| match rule {
+                    AllowRule::RoomMembership(m) => Some(m.room_id.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        let mut spaces: Vec<(OwnedRoomId, String, bool)> = spaces
+            .into_iter()
+            .map(|(id, name)| {
+                let chosen = allowed.contains(&id);
+                (id, name, chosen)
+            })
+            .collect();
+
+        // spaces I've left can still let people in
+        for id in allowed.iter() {
+            if !spaces.iter().any(|(s, _, _)| s == id) {
+                spaces.push((id.clone(), id.to_string(), true));
+            }
+        }
+
+        let settings = [
+            Setting::new("Who can join", JOIN_RULES, join_rule.as_str()),
+            Setting::new(
+                "Who can read history",
+                HISTORY,
+                room.history_visibility().as_str(),
+            ),
+            Setting::new("Guest access", GUESTS, room.guest_access().as_str()),
+        ];
+
+        Self {
+            room,
+            name,
+            settings,
+            spaces,
+            original_spaces: allowed,
+            selected: 0,
+            hint: None,
+        }
+    }
+
+    pub fn widget(&self) -> RoomAccessWidget {
+        RoomAccessWidget { access: self }
+    }
+
+    fn restricted(&self) -> bool {
+        self.settings[0].value().ends_with("restricted")
+    }
+
+    // settings, then spaces, if they matter
+    fn rows(&self) -> usize {
+        if self.restricted() {
+            self.settings.len() + self.spaces.len()
+        } else {
+            self.settings.len()
+        }
+    }
+
+    fn chosen_spaces(&self) -> Vec<OwnedRoomId> {
+        self.spaces
+            .iter()
+            .filter(|(_, _, chosen)| *chosen)
+            .map(|(id, _, _)| id.clone())
+            .collect()
+    }
+
+    fn changes(&self) -> AccessChanges {
+        let [join_rule, history, guests] = &self.settings;
+
+        let spaces = if self.restricted() {
+            self.chosen_spaces()
+        } else {
+            vec![]
+        };
+
+        let join_rule =
+            if join_rule.changed() || (self.restricted() && spaces != self.original_spaces) {
+                Some((join_rule.value().to_string(), spaces))
+            } else {
+                None
+            };
+
+        AccessChanges {
+            join_rule,
+            history_visibility: history.changed().then(|| history.value().to_string()),
+            guest_access: guests.changed().then(|| guests.value().to_string()),
+        }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        self.hint = None;
+
+        match input.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.rows() - 1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            KeyCode::Char('h')
+            | KeyCode::Left
+            | KeyCode::Char('l')
+            | KeyCode::Right
+            | KeyCode::Char(' ') => {
+                let forward = !matches!(input.code, KeyCode::Char('h') | KeyCode::Left);
+
+                match self.selected.checked_sub(self.settings.len()) {
+                    None => self.settings[self.selected].cycle(forward),
+                    Some(i) => self.spaces[i].2 = !self.spaces[i].2,
+                }
+
+                // don't leave the selection on a space that's gone
+                self.selected = self.selected.min(self.rows() - 1);
+            }
+            KeyCode::Enter => {
+                if self.restricted() && self.chosen_spaces().is_empty() {
+                    self.hint = Some("Choose at least one space.");
+                    return consumed!();
+                }
+
+                let changes = self.changes();
+                let room = self.room.clone();
+
+                return EventResult::Consumed(Box::new(move |app| {
+                    app.close_popup();
+                    app.matrix.set_room_access(room, changes);
+                }));
+            }
+            KeyCode::Esc | KeyCode::Char('q') => return close!(),
+            _ => {}
+        }
+
+        consumed!()
+    }
+}
+
+pub struct RoomAccessWidget<'a> {
+    access: &'a RoomAccess,
+}
+
+impl RoomAccessWidget<'_> {
+    fn lines(&self) -> Vec<Line> {
+        let access = self.access;
+        let mut lines = vec![];
+
+        for (i, setting) in access.settings.iter().enumerate() {
+            let (_, short, long) = setting.options[setting.chosen];
+
+            let style = if access.selected == i {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("{}: ", setting.label),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                Span::styled(format!("‹ {} ›", short), style),
+            ]));
+
+            lines.push(Line::from(Span::styled(
+                long,
+                Style::default().fg(Color::DarkGray),
+            )));
+
+            lines.push(Line::default());
+        }
+
+        if access.restricted() {
+            lines.push(Line::from(Span::styled(
+                "Spaces:",
+                Style::default().fg(Color::DarkGray),
+            )));
+
+            if access.spaces.is_empty() {
+                lines.push(Line::from("  You're not in any spaces."));
+            }
+
+            for (i, (_, name, chosen)) in access.spaces.iter().enumerate() {
+                let style = if access.selected == access.settings.len() + i {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else {
+                    Style::default()
+                };
+
+                let check = if *chosen { "[x]" } else { "[ ]" };
+
+                lines.push(Line::from(vec![
+                    Span::raw("  "),
+                    Span::styled(format!("{} {}", check, name), style),
+                ]));
+            }
+        }
+
+        lines
+    }
+}
+
+impl Widget for RoomAccessWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines = self.lines();
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 80))
+            .vertical_margin(get_margin(area.height, lines.len() as u16 + 6))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title(format!("Access to {}", self.access.name))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .vertical_margin(1)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .split(inner);
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .render(splits[0], buf);
+
+        let hint = self
+            .access
+            .hint
+            .unwrap_or("h/l change · Space pick a space · Enter save · q cancel");
+
+        Paragraph::new(Span::styled(hint, Style::default().fg(Color::DarkGray)))
+            .alignment(Alignment::Center)
+            .render(splits[1], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setting() {
+        let mut setting = Setting::new("Guest access", GUESTS, "forbidden");
+        assert!(!setting.changed());
+
+        setting.cycle(true);
+        assert_eq!(setting.value(), "can_join");
+        assert!(setting.changed());
+
+        setting.cycle(true);
+        assert_eq!(setting.value(), "forbidden");
+        assert!(!setting.changed());
+
+        setting.cycle(false);
+        assert_eq!(setting.value(), "can_join");
+
+        // something we don't offer is always a change
+        let setting = Setting::new("Who can join", JOIN_RULES, "private");
+        assert_eq!(setting.value(), "invite");
+        assert!(setting.changed());
+    }
+}
@@ -0,0 +1,197 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+
+use crate::handler::MatuiEvent;
+use crate::matrix::matrix::Matrix;
+use crate::matrix::roomcache::{Away, Mention};
+use crate::widgets::get_margin;
+use crate::{close, consumed, truncate};
+
+use super::EventResult;
+
+/// A line along the bottom to say what we missed, for a few seconds after we
+/// get back.
+pub struct AwayBanner<'a> {
+    pub away: &'a Away,
+}
+
+impl Widget for AwayBanner<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let Some(mut text) = self.away.summary() else {
+            return;
+        };
+
+        if !self.away.mentions.is_empty() {
+            text.push_str(" · A to see them");
+        }
+
+        let text = truncate(text, area.width.saturating_sub(4).into());
+        let width = text.chars().count() as u16 + 4;
+
+        if area.height < 3 {
+            return;
+        }
+
+        let area = Rect {
+            x: area.x + get_margin(area.width, width),
+            y: area.bottom() - 3,
+            width: width.min(area.width),
+            height: 3,
+        };
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(Color::Cyan))
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        Paragraph::new(text)
+            .alignment(Alignment::Center)
+            .render(inner, buf);
+    }
+}
+
+/// Everything that mentioned us while we were away, to jump to.
+pub struct Mentions {
+    mentions: Vec<Mention>,
+    selected: usize,
+}
+
+impl Mentions {
+    pub fn new(mentions: Vec<Mention>) -> Self {
+        Self {
+            mentions,
+            selected: 0,
+        }
+    }
+
+    pub fn widget(&self) -> MentionsWidget {
+        MentionsWidget { mentions: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        match input.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.mentions.len().saturating_sub(1));
+                consumed!()
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                consumed!()
+            }
+            KeyCode::Enter => {
+                let Some(mention) = self.mentions.get(self.selected).cloned() else {
+                    return consumed!();
+                };
+
+                EventResult::Consumed(Box::new(move |app| {
+                    app.close_popup();
+                    Matrix::send(MatuiEvent::Navigate(mention.room, Some(mention.event_id)));
+                }))
+            }
+            KeyCode::Esc | KeyCode::Char('q') => close!(),
+            _ => consumed!(),
+        }
+    }
+}
+
+pub struct MentionsWidget<'a> {
+    mentions: &'a Mentions,
+}
+
+impl MentionsWidget<'_> {
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = vec![];
+
+        for (i, mention) in self.mentions.mentions.iter().enumerate() {
+            let room = mention
+                .room
+                .cached_display_name()
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| mention.room.room_id().to_string());
+
+            let mut style = Style::default().fg(Color::Magenta);
+
+            if i == self.mentions.selected {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            lines.push(Line::from(vec![
+                Span::styled(mention.sender.clone(), style),
+                Span::styled(
+                    format!(" in {}", room),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+
+            let body = mention.body.lines().next().unwrap_or_default();
+            lines.push(Line::from(format!("  {}", body)));
+        }
+
+        if lines.is_empty() {
+            lines.push(Line::from(Span::styled(
+                "Nothing mentioned you while you were away.",
+                Style::default().fg(Color::DarkGray),
+            )));
+        }
+
+        lines
+    }
+}
+
+impl Widget for MentionsWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines = self.lines();
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 70))
+            .vertical_margin(get_margin(area.height, lines.len() as u16 + 5))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title("While You Were Away")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints(
+                [
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .render(splits[0], buf);
+
+        Paragraph::new(Span::styled(
+            "Enter jump to it · q close",
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[2], buf);
+    }
+}
@@ -1,40 +1,60 @@
 use crate::app::{App, Popup};
 use crate::event::{Event, EventHandler};
-use crate::handler::Batch;
+use crate::handler::{Batch, ConnectionState};
+use crate::locale::tr;
+use crate::matrix::bridges::Bridges;
+use crate::matrix::calls::{CallWidget, WIDGET_EVENTS};
+use crate::matrix::emotes;
 use crate::matrix::matrix::Matrix;
 use crate::matrix::roomcache::DecoratedRoom;
-use crate::settings::is_muted;
-use crate::spawn::{get_file_paths, get_text};
-use crate::widgets::message::{Message, Reaction, ReactionEvent};
+use crate::settings::{
+    chat_width, compact_layout, external_pager, full_width, group_messages, is_muted,
+    low_bandwidth, max_message_lines, prefetch_screens, presentation_mode, room_accent,
+    set_setting, show_position, zen_mode,
+};
+use crate::spawn::{spawn_editor, spawn_file_picker, EditorTarget, PickTarget};
+use crate::widgets::message::{Message, Quote, Reaction, Trust};
+use crate::widgets::pager::Pager;
 use crate::widgets::react::React;
-use crate::widgets::react::ReactResult;
+use crate::widgets::roominfo::RoomInfo;
+use crate::widgets::seen::Seen;
 use crate::widgets::EventResult::Consumed;
-use crate::widgets::{get_margin, EventResult};
-use crate::{consumed, limit_list, pretty_list, truncate, KeyCombo};
-use anyhow::bail;
+use crate::widgets::{get_margin, is_narrow, EventResult};
+use crate::{consumed, limit_list, pretty_list, truncate, DelayTimer, KeyCombo};
 use crossterm::event::{KeyCode, KeyEvent};
-use log::info;
+use log::{error, info};
 use matrix_sdk::room::{Room, RoomMember};
 use once_cell::sync::OnceCell;
 use ruma::events::receipt::ReceiptEventContent;
+use ruma::events::room::member::MembershipState;
 use ruma::events::room::message::MessageType::Text;
-use ruma::events::AnyTimelineEvent;
-use ruma::{OwnedEventId, OwnedUserId};
+use ruma::events::{AnyStateEvent, AnyTimelineEvent};
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId};
 use std::cell::Cell;
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ops::Deref;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Corner, Direction, Layout, Rect};
-use ratatui::style::{Color, Style};
+use ratatui::style::{Color, Modifier, Style};
 use ratatui::widgets::{
     Block, BorderType, Borders, List, ListItem, ListState, Paragraph, StatefulWidget, Widget,
 };
 
-use super::confirm::{Confirm, ConfirmBehavior};
-use super::message::MergeResult;
+use super::confirm::Confirm;
+use super::export::Export;
 use super::receipts::Receipts;
+use super::scrollbar::Scrollbar;
+
+// A stretch of history that sync skipped over, just before a point in time.
+struct Gap {
+    token: String,
+    before: MilliSecondsSinceUnixEpoch,
+    loading: bool,
+}
 
 pub struct Chat {
     matrix: Matrix,
@@ -43,19 +63,56 @@ pub struct Chat {
     receipts: Receipts,
     messages: Vec<Message>,
     read_to: Option<OwnedEventId>,
-    react: Option<React>,
+    receipt_timer: DelayTimer<OwnedEventId>,
     typing: Option<String>,
     list_state: Cell<ListState>,
     next_cursor: Option<String>,
     fetching: Cell<bool>,
     width: Cell<usize>,
     total_list_items: Cell<usize>,
+    list_height: Cell<usize>,
+    // the message that was selected when the terminal was resized, and how
+    // far into it, to find again once everything's wrapped to the new width
+    anchor: Cell<Option<(OwnedEventId, usize)>>,
     focus: bool,
+    connection: ConnectionState,
     delete_combo: KeyCombo,
 
     members: Vec<RoomMember>,
     pretty_members: OnceCell<String>,
     in_flight: Vec<OwnedUserId>,
+
+    // senders we've seen before the full member list has loaded
+    members_loaded: bool,
+    stragglers: Vec<OwnedUserId>,
+
+    // events loaded from the timeline cache that the server hasn't confirmed
+    cached: Vec<OwnedEventId>,
+
+    // holes in the timeline, newest first, that can be filled on request
+    gaps: Vec<Gap>,
+
+    // how far to trust each encrypted event, from when it was decrypted
+    trust: HashMap<OwnedEventId, Trust>,
+
+    // an event we've been asked to show, but haven't loaded yet
+    jump_to: Option<OwnedEventId>,
+
+    // replied-to messages we had to go fetch, because they aren't loaded
+    quotes: HashMap<OwnedEventId, Quote>,
+    quotes_requested: HashSet<OwnedEventId>,
+
+    // images we've shown a preview of, so the next open gets the original
+    previewed: HashSet<OwnedEventId>,
+
+    // long messages that are shown in full, right in the chat
+    expanded: HashSet<OwnedEventId>,
+
+    // for tagging senders from other networks
+    bridges: Bridges,
+
+    // meetings pinned to the room, that can be joined in a browser
+    calls: Vec<CallWidget>,
 }
 
 impl Chat {
@@ -65,6 +122,10 @@ impl Chat {
             None => return None,
         };
 
+        matrix.load_cached_messages(room.clone());
+        matrix.fetch_room_members(room.clone());
+        matrix.load_emotes(room.clone());
+        matrix.load_calls(room.clone());
         matrix.fetch_messages(room, None);
 
         Some(Self {
@@ -74,21 +135,184 @@ impl Chat {
             receipts: Receipts::new(matrix.me()),
             messages: vec![],
             read_to: None,
-            react: None,
+            receipt_timer: DelayTimer::new(Duration::from_secs(2)),
             typing: None,
             list_state: Cell::new(ListState::default()),
             next_cursor: None,
             fetching: Cell::new(true),
             width: Cell::new(80),
             total_list_items: Cell::new(0),
+            list_height: Cell::new(0),
+            anchor: Cell::new(None),
             focus: true,
+            connection: if matrix.is_online() {
+                ConnectionState::Online
+            } else {
+                ConnectionState::Offline
+            },
             delete_combo: KeyCombo::new(vec!['d', 'd']),
             members: vec![],
             pretty_members: OnceCell::new(),
             in_flight: vec![],
+            members_loaded: false,
+            stragglers: vec![],
+            cached: vec![],
+            gaps: vec![],
+            trust: HashMap::new(),
+            jump_to: None,
+            quotes: HashMap::new(),
+            quotes_requested: HashSet::new(),
+            previewed: HashSet::new(),
+            expanded: HashSet::new(),
+            bridges: Bridges::from_settings(),
+            calls: vec![],
         })
     }
 
+    /// Show whatever we had on disk, if the real timeline hasn't beaten it
+    /// here.
+    pub fn cached_event(&mut self, room: Room, events: Vec<AnyTimelineEvent>) {
+        if room.room_id() != self.room.room_id() || !self.fetching.get() {
+            return;
+        }
+
+        for event in events {
+            self.check_event_sender(&event);
+            self.cached.push(event.event_id().to_owned());
+            self.events.insert(OrderedEvent::new(event));
+        }
+
+        self.rebuild_messages();
+
+        let mut state = self.list_state.take();
+        state.select(Some(0));
+        self.list_state.set(state);
+    }
+
+    fn rebuild_messages(&mut self) {
+        let old = std::mem::replace(
+            &mut self.messages,
+            make_message_list(&self.events, &self.members, &self.receipts),
+        );
+
+        for message in self.messages.iter_mut() {
+            message.set_bridge(self.bridges.protocol(&message.sender.id));
+        }
+
+        // fill in what replies are replying to, from what we have loaded, or
+        // from what we've fetched
+        let loaded: HashMap<OwnedEventId, Quote> = self
+            .messages
+            .iter()
+            .map(|m| (m.id.clone(), Quote::new(m.sender.id.clone(), &m.display())))
+            .collect();
+
+        let mut missing = vec![];
+        let max_lines = max_message_lines();
+        let compact = compact_layout(self.room.room_id());
+
+        for message in self.messages.iter_mut() {
+            if let Some(max) = max_lines {
+                message.set_max_lines(max);
+            }
+
+            message.set_expanded(self.expanded.contains(&message.id));
+            message.set_trust(self.trust.get(&message.id).copied());
+            message.set_compact(compact);
+
+            // only people with the power to notify the room get to shout
+            if message.room_mention {
+                let allowed = self
+                    .members
+                    .iter()
+                    .find(|m| m.user_id() == message.sender.id)
+                    .map_or(false, |m| m.can_trigger_room_notification());
+
+                message.set_highlight(allowed);
+            }
+
+            let Some(id) = &message.in_reply_to else {
+                continue;
+            };
+
+            match loaded.get(id).or_else(|| self.quotes.get(id)) {
+                Some(quote) => message.set_quote(quote.clone()),
+                None => missing.push(id.clone()),
+            }
+
+            message.update_senders(&self.members);
+        }
+
+        // the marker goes on the oldest message after each gap
+        for gap in self.gaps.iter() {
+            if let Some(message) = self
+                .messages
+                .iter_mut()
+                .rev()
+                .find(|m| m.sent >= gap.before)
+            {
+                message.set_gap(Some(gap.loading));
+            }
+        }
+
+        if group_messages() {
+            Message::group(&mut self.messages);
+        }
+
+        // most of what's there is just as it was
+        let old: HashMap<&OwnedEventId, &Message> = old.iter().map(|m| (&m.id, m)).collect();
+
+        for message in self.messages.iter() {
+            if let Some(old) = old.get(&message.id) {
+                message.keep_layout(old);
+            }
+        }
+
+        for id in missing {
+            if self.quotes_requested.insert(id.clone()) {
+                self.matrix.fetch_quote(self.room(), id);
+            }
+        }
+    }
+
+    pub fn quote_event(&mut self, room: Room, id: OwnedEventId, quote: Quote) {
+        if room.room_id() != self.room.room_id() {
+            return;
+        }
+
+        if let Some(sender) = &quote.sender {
+            self.check_sender(&sender.id);
+        }
+
+        self.quotes.insert(id, quote);
+        self.rebuild_messages();
+    }
+
+    // The first real batch covers the same window as the cache did, so
+    // anything cached in that window that didn't come back is stale
+    // (redacted, or otherwise gone).
+    fn reconcile_cached(&mut self, batch: &Batch) {
+        if self.cached.is_empty() {
+            return;
+        }
+
+        let oldest = batch.events.iter().map(|e| e.origin_server_ts()).min();
+        let cached = std::mem::take(&mut self.cached);
+
+        self.events.retain(|e| {
+            if !cached.iter().any(|id| id == e.event_id()) {
+                return true;
+            }
+
+            if batch.events.iter().any(|b| b.event_id() == e.event_id()) {
+                return true;
+            }
+
+            // older than anything the server sent; we can't tell yet
+            matches!(oldest, Some(ts) if e.origin_server_ts() < ts)
+        });
+    }
+
     pub fn render(&self, area: Rect, buf: &mut Buffer) {
         self.widget().render(area, buf);
     }
@@ -102,38 +326,7 @@ impl Chat {
         input: &KeyEvent,
         handler: &EventHandler,
     ) -> anyhow::Result<EventResult> {
-        // give our reaction window first dibs
-        if let Some(react) = &mut self.react {
-            match react.key_event(input) {
-                ReactResult::Exit => {
-                    self.react = None;
-                    return Ok(consumed!());
-                }
-                ReactResult::SelectReaction(reaction) => {
-                    self.react = None;
-
-                    if let Some(message) = self.selected_reply() {
-                        self.matrix
-                            .send_reaction(self.room(), message.id.clone(), reaction)
-                    }
-
-                    return Ok(consumed!());
-                }
-                ReactResult::RemoveReaction(reaction) => {
-                    self.react = None;
-
-                    if let Some(event) = self.my_selected_reaction_event(reaction) {
-                        self.matrix.redact_event(self.room(), event.id)
-                    }
-
-                    return Ok(consumed!());
-                }
-                ReactResult::Consumed => return Ok(consumed!()),
-                ReactResult::Ignored => {}
-            }
-        }
-
-        // then look for key combos
+        // look for key combos
         if let KeyCode::Char(c) = input.code {
             if self.delete_combo.record(c) {
                 let message = match self.selected_reply() {
@@ -144,16 +337,19 @@ impl Chat {
                 let preview = truncate(message.display().to_string(), 16);
                 let warning = format!("Are you sure you want to delete \"{}\"", preview);
 
+                let room = self.room();
+                let id = message.id.clone();
+
                 let confirm = Confirm::new(
                     "Delete Message".to_string(),
                     warning,
                     "Yes".to_string(),
                     "No".to_string(),
-                    ConfirmBehavior::DeleteMessage(self.room(), message.id.clone()),
+                    move |app| app.matrix.redact_event(room, id),
                 );
 
                 return Ok(Consumed(Box::new(|app| {
-                    app.set_popup(Popup::Confirm(confirm))
+                    app.push_popup(Popup::Confirm(confirm))
                 })));
             }
         }
@@ -169,8 +365,22 @@ impl Chat {
                 Ok(consumed!())
             }
             KeyCode::Enter => {
-                if let Some(message) = &self.selected_reply() {
-                    message.open(self.matrix.clone())
+                let Some(message) = self.selected_reply() else {
+                    return Ok(consumed!());
+                };
+
+                // a quick preview the first time, then the original
+                let preview = !self.previewed.contains(&message.id);
+                message.open(self.matrix.clone(), preview);
+
+                let id = message.id.clone();
+                self.previewed.insert(id);
+
+                Ok(consumed!())
+            }
+            KeyCode::Char('o') => {
+                if let Some(id) = self.selected_reply().and_then(|m| m.in_reply_to.clone()) {
+                    self.jump_to(id);
                 }
                 Ok(consumed!())
             }
@@ -186,68 +396,36 @@ impl Chat {
                     None => return Ok(EventResult::Ignored),
                 };
 
-                if matches!(message.body, Text(_)) {
-                    handler.park();
-
-                    let result = get_text(
-                        Some(&message.display()),
-                        Some(&format!(
-                            "<!-- Edit your message above to change it in {}. -->",
-                            self.room.name
-                        )),
-                    );
-
-                    handler.unpark();
-
-                    // make sure we redraw the whole app when we come back
-                    App::get_sender().send(Event::Redraw)?;
-
-                    if let Ok(edit) = result {
-                        if let Some(edit) = edit {
-                            self.matrix.replace_event(
-                                self.room(),
-                                message.id.clone(),
-                                edit,
-                                message.in_reply_to.clone(),
-                            );
-
-                            return Ok(consumed!());
-                        } else {
-                            bail!("Ignoring blank message.")
-                        }
-                    } else {
-                        bail!("Couldn't read from editor.")
-                    }
+                if !matches!(message.body, Text(_)) {
+                    return Ok(consumed!());
                 }
 
-                Ok(consumed!())
+                let parked = spawn_editor(
+                    handler,
+                    EditorTarget::Edit(
+                        self.room(),
+                        message.id.clone(),
+                        message.in_reply_to.clone(),
+                    ),
+                    Some(message.display()),
+                    vec![("room", self.room.display_name())],
+                    None,
+                );
+
+                Ok(Consumed(Box::new(move |app| app.editing |= parked)))
             }
             KeyCode::Char('i') => {
-                let send = self.matrix.begin_typing(self.room());
+                let typing = self.matrix.begin_typing(self.room());
 
-                handler.park();
-                let result = get_text(
+                let parked = spawn_editor(
+                    handler,
+                    EditorTarget::Compose(self.room()),
                     None,
-                    Some(&format!(
-                        "<!-- Type a new message above to send to {}. -->",
-                        self.room.name
-                    )),
+                    vec![("room", self.room.display_name())],
+                    Some(typing),
                 );
-                handler.unpark();
-
-                self.matrix.end_typing(self.room(), send);
-                App::get_sender().send(Event::Redraw)?;
-
-                if let Ok(input) = result {
-                    if let Some(input) = input {
-                        self.matrix.send_text_message(self.room(), input);
-                        Ok(consumed!())
-                    } else {
-                        bail!("Ignoring blank message.")
-                    }
-                } else {
-                    bail!("Couldn't read from editor.")
-                }
+
+                Ok(Consumed(Box::new(move |app| app.editing |= parked)))
             }
             KeyCode::Char('R') => {
                 let message = match self.selected_reply() {
@@ -261,26 +439,21 @@ impl Chat {
 
                 let body = textwrap::wrap(&message.display(), &wrap_options).join("\n");
 
-                let send = self.matrix.begin_typing(self.room());
-
-                handler.park();
-                let result = get_text(None, Some(&REPLY_TEMPLATE.replace("{}", &body)));
-                handler.unpark();
+                let typing = self.matrix.begin_typing(self.room());
 
-                self.matrix.end_typing(self.room(), send);
-                App::get_sender().send(Event::Redraw)?;
+                let parked = spawn_editor(
+                    handler,
+                    EditorTarget::Reply(self.room(), message.id.clone()),
+                    None,
+                    vec![
+                        ("room", self.room.display_name()),
+                        ("sender", message.sender.to_string()),
+                        ("body", body),
+                    ],
+                    Some(typing),
+                );
 
-                if let Ok(input) = result {
-                    if let Some(input) = input {
-                        self.matrix
-                            .send_reply(self.room(), input, message.id.clone());
-                        Ok(consumed!())
-                    } else {
-                        bail!("Ignoring blank message.")
-                    }
-                } else {
-                    bail!("Couldn't read from editor.")
-                }
+                Ok(Consumed(Box::new(move |app| app.editing |= parked)))
             }
             KeyCode::Char('v') => {
                 let message = match self.selected_reply() {
@@ -288,46 +461,114 @@ impl Chat {
                     None => return Ok(EventResult::Ignored),
                 };
 
-                handler.park();
-                get_text(Some(&message.display_full()), None)?;
-                handler.unpark();
+                view("Message".to_string(), message.display_full())
+            }
+            KeyCode::Char('x') => {
+                let id = match self.selected_reply() {
+                    Some(m) if m.expanded() || m.overflows(self.width.get()) => m.id.clone(),
+                    _ => return Ok(EventResult::Ignored),
+                };
+
+                let expanded = !self.expanded.remove(&id);
+
+                if expanded {
+                    self.expanded.insert(id.clone());
+                }
+
+                if let Some(m) = self.messages.iter_mut().find(|m| m.id == id) {
+                    m.set_expanded(expanded);
+                }
+
+                // the message changed size, so keep the cursor on it
+                self.select_event(&id);
+                Ok(consumed!())
+            }
+            KeyCode::Char('F') => {
+                if let Err(e) = set_setting("full_width", !full_width()) {
+                    error!("could not save full width: {}", e);
+                }
+
+                Ok(consumed!())
+            }
+            KeyCode::Char('Z') => {
+                if let Err(e) = set_setting("zen_mode", !zen_mode()) {
+                    error!("could not save zen mode: {}", e);
+                }
+
+                Ok(consumed!())
+            }
+            KeyCode::Char('C') => {
+                let Some(call) = self.calls.first() else {
+                    return Ok(EventResult::Ignored);
+                };
 
-                App::get_sender().send(Event::Redraw)?;
+                open::that(&call.url)?;
                 Ok(consumed!())
             }
-            KeyCode::Char('V') => {
-                handler.park();
-                get_text(Some(&self.display_full()), None)?;
-                handler.unpark();
+            KeyCode::Char('V') => view(self.room.display_name(), self.display_full()),
+            KeyCode::Char('I') => {
+                let info = RoomInfo::new(&self.room(), self.room.display_name());
+                Ok(Consumed(Box::new(|app| {
+                    app.push_popup(Popup::RoomInfo(info))
+                })))
+            }
+            KeyCode::Char('E') => {
+                let export = Export::new(self.room(), &self.room.display_name());
+                Ok(Consumed(Box::new(|app| {
+                    app.push_popup(Popup::Export(export))
+                })))
+            }
+            KeyCode::Char('w') => {
+                let receipts = match self.selected_reply() {
+                    Some(m) if !m.receipts.is_empty() => m.receipts.clone(),
+                    _ => return Ok(EventResult::Ignored),
+                };
 
-                App::get_sender().send(Event::Redraw)?;
+                let seen = Seen::new(receipts);
+                Ok(Consumed(Box::new(|app| app.push_popup(Popup::Seen(seen)))))
+            }
+            KeyCode::Char('S') => {
+                self.matrix
+                    .fetch_room_state(self.room(), self.room.display_name());
+                Ok(consumed!())
+            }
+            KeyCode::Char('K') => {
+                self.matrix
+                    .fetch_knocks(self.room(), self.room.display_name());
+                Ok(consumed!())
+            }
+            KeyCode::Char('m') => {
+                self.load_gap();
                 Ok(consumed!())
             }
             KeyCode::Char('r') => {
-                self.react = Some(React::new(
+                let message = match self.selected_reply() {
+                    Some(m) => m,
+                    None => return Ok(EventResult::Ignored),
+                };
+
+                let react = React::new(
+                    self.room(),
+                    message.id.clone(),
                     self.selected_reactions()
                         .into_iter()
                         .map(|r| r.body)
                         .collect(),
-                    self.my_selected_reactions()
-                        .into_iter()
-                        .map(|r| r.body)
-                        .collect(),
-                ));
-                Ok(consumed!())
+                    self.my_selected_reactions(),
+                    emotes::for_room(self.room.room_id()),
+                );
+
+                Ok(Consumed(Box::new(|app| {
+                    app.push_popup(Popup::React(react))
+                })))
             }
             KeyCode::Char('u') => {
-                let paths = get_file_paths()?;
-
-                App::get_sender().send(Event::Redraw)?;
-
-                if paths.is_empty() {
-                    return Ok(EventResult::Ignored);
-                }
-
-                self.matrix.send_attachements(self.room(), paths);
+                let parked = spawn_file_picker(
+                    handler,
+                    PickTarget::Upload(self.room(), self.room.display_name()),
+                );
 
-                Ok(consumed!())
+                Ok(Consumed(Box::new(move |app| app.editing |= parked)))
             }
             _ => Ok(EventResult::Ignored),
         }
@@ -340,6 +581,89 @@ impl Chat {
 
     pub fn blur_event(&mut self) {
         self.focus = false;
+        self.flush_receipt();
+    }
+
+    /// The terminal changed size, so every message has to be wrapped again,
+    /// and the selection, which counts lines, would land somewhere else.
+    pub fn resize_event(&mut self) {
+        // a drag sends a lot of these, but only the first knows the old width
+        if self.anchor.get_mut().is_none() {
+            self.anchor.set(self.selected_anchor());
+        }
+
+        for message in self.messages.iter_mut() {
+            message.touch();
+        }
+    }
+
+    // the selected message, and the line of it that's selected
+    fn selected_anchor(&self) -> Option<(OwnedEventId, usize)> {
+        let state = self.list_state.take();
+        let selected = state.selected();
+        self.list_state.set(state);
+
+        let selected = selected?;
+        let mut counter = 0;
+
+        for message in &self.messages {
+            let height = message.height(self.width.get());
+
+            if counter + height > selected {
+                return Some((message.id.clone(), selected - counter));
+            }
+
+            counter += height;
+        }
+
+        None
+    }
+
+    // select the anchored message again, with the list scrolled however it
+    // needs to be to show it
+    fn restore_anchor(&self, id: &OwnedEventId, line: usize) {
+        let mut counter = 0;
+
+        for message in &self.messages {
+            let height = message.height(self.width.get());
+
+            if &message.id == id {
+                // it may have fewer lines now, and the top one can be a spacer
+                let last = height.saturating_sub(1 + message.spaced() as usize);
+
+                let mut state = ListState::default();
+                state.select(Some(counter + line.min(last)));
+                self.list_state.set(state);
+                return;
+            }
+
+            counter += height;
+        }
+    }
+
+    pub fn connection_event(&mut self, state: ConnectionState) {
+        self.connection = state;
+    }
+
+    /// Lay the messages out again, with whatever the config says now.
+    pub fn settings_changed(&mut self) {
+        self.bridges = Bridges::from_settings();
+        self.rebuild_messages();
+    }
+
+    pub fn tick_event(&mut self) {
+        if let Some(id) = self.receipt_timer.poll() {
+            self.matrix.read_to(self.room(), id);
+        }
+
+        self.try_fetch_previous();
+    }
+
+    /// Send off any receipt that's been held back.
+    pub fn flush_receipt(&mut self) -> Option<JoinHandle<()>> {
+        self.receipt_timer
+            .flush()
+            .map(|id| self.matrix.read_to(self.room(), id))
     }
 
     pub fn timeline_event(&mut self, event: AnyTimelineEvent) {
@@ -347,9 +671,22 @@ impl Chat {
             return;
         }
 
+        // a call was started or ended
+        if WIDGET_EVENTS.contains(&event.event_type().to_string().as_str()) {
+            self.matrix.load_calls(self.room());
+        }
+
+        // someone changed their name or avatar, so pick up the new one
+        if let AnyTimelineEvent::State(AnyStateEvent::RoomMember(member)) = &event {
+            if member.membership() == &MembershipState::Join {
+                self.matrix
+                    .fetch_room_member(self.room(), member.state_key().to_owned());
+            }
+        }
+
         self.check_event_sender(&event);
         self.events.insert(OrderedEvent::new(event));
-        self.messages = make_message_list(&self.events, &self.members, &self.receipts);
+        self.rebuild_messages();
         self.pretty_members = OnceCell::new();
         self.set_fully_read();
     }
@@ -370,10 +707,10 @@ impl Chat {
             return;
         }
 
-        let suffix = if typing.len() > 1 {
-            " are typing."
+        let key = if typing.len() > 1 {
+            "are_typing"
         } else {
-            " is typing."
+            "is_typing"
         };
 
         let total = typing.len();
@@ -383,17 +720,13 @@ impl Chat {
             .map(|m| m.display_name().unwrap_or(m.user_id().as_str()))
             .map(|n| n.to_string());
 
-        self.typing = Some(format!(
-            "{}{}",
-            pretty_list(limit_list(iter, 3, total, None)),
-            suffix
-        ));
+        self.typing = Some(tr(key, &pretty_list(limit_list(iter, 3, total, None))));
     }
 
     pub fn receipt_event(&mut self, room: &Room, content: &ReceiptEventContent) {
         if room.room_id() == self.room.room_id() {
             self.receipts.apply_event(content);
-            self.messages = make_message_list(&self.events, &self.members, &self.receipts);
+            self.rebuild_messages();
             self.pretty_members = OnceCell::new();
             let me = self.matrix.me();
 
@@ -410,11 +743,109 @@ impl Chat {
         }
     }
 
-    pub fn batch_event(&mut self, batch: Batch) {
+    pub fn trust_event(&mut self, room: Room, id: OwnedEventId, trust: Trust) {
+        if room.room_id() != self.room.room_id() {
+            return;
+        }
+
+        let loaded = self.messages.iter().any(|m| m.id == id);
+        self.trust.insert(id, trust);
+
+        if loaded {
+            self.rebuild_messages();
+        }
+    }
+
+    pub fn gap_event(&mut self, room: Room, token: String, before: MilliSecondsSinceUnixEpoch) {
+        if room.room_id() != self.room.room_id() {
+            return;
+        }
+
+        // only a gap if there's history on the other side of it
+        if !self.events.iter().any(|e| e.origin_server_ts() < before) {
+            return;
+        }
+
+        info!("timeline gap before {:?}", before);
+
+        self.gaps.push(Gap {
+            token,
+            before,
+            loading: false,
+        });
+
+        self.gaps.sort_by(|a, b| b.before.cmp(&a.before));
+        self.rebuild_messages();
+    }
+
+    // the next gap back from the selection, or else the newest one
+    fn load_gap(&mut self) {
+        let selected = self.selected_reply().map(|m| m.sent);
+
+        let index = selected
+            .and_then(|sent| self.gaps.iter().position(|g| g.before <= sent))
+            .or_else(|| (!self.gaps.is_empty()).then_some(0));
+
+        let Some(gap) = index.and_then(|i| self.gaps.get_mut(i)) else {
+            return;
+        };
+
+        if gap.loading {
+            return;
+        }
+
+        gap.loading = true;
+        self.matrix.fetch_gap(self.room.inner(), gap.token.clone());
+        self.rebuild_messages();
+    }
+
+    // Fill in a gap, until we run into something we already have.
+    fn fill_gap(&mut self, token: String, batch: Batch) {
+        let Some(index) = self.gaps.iter().position(|g| g.token == token) else {
+            return;
+        };
+
+        let overlaps = batch
+            .events
+            .iter()
+            .any(|e| self.events.iter().any(|o| o.event_id() == e.event_id()));
+
+        let oldest = batch.events.iter().map(|e| e.origin_server_ts()).min();
+
+        for event in batch.events {
+            self.check_event_sender(&event);
+            self.events.insert(OrderedEvent::new(event));
+        }
+
+        match (batch.cursor, oldest) {
+            (Some(cursor), Some(oldest)) if !overlaps => {
+                let gap = &mut self.gaps[index];
+                gap.token = cursor;
+                gap.before = oldest;
+                gap.loading = false;
+            }
+            _ => {
+                self.gaps.remove(index);
+            }
+        }
+
+        self.rebuild_messages();
+        self.pretty_members = OnceCell::new();
+    }
+
+    pub fn batch_event(&mut self, mut batch: Batch) {
         if batch.room.room_id() != self.room.room_id() {
             return;
         }
 
+        self.trust.extend(std::mem::take(&mut batch.trust));
+
+        if let Some(token) = batch.gap.take() {
+            self.fill_gap(token, batch);
+            return;
+        }
+
+        self.reconcile_cached(&batch);
         self.next_cursor = batch.cursor;
         let previous_count = self.messages.len();
 
@@ -425,7 +856,7 @@ impl Chat {
 
         let reset = self.messages.is_empty();
 
-        self.messages = make_message_list(&self.events, &self.members, &self.receipts);
+        self.rebuild_messages();
         self.pretty_members = OnceCell::new();
         self.fetching.set(false);
         self.set_fully_read();
@@ -436,6 +867,8 @@ impl Chat {
             self.list_state.set(state);
         }
 
+        self.try_jump();
+
         if self.messages.len() > previous_count {
             self.try_fetch_previous();
         } else {
@@ -458,6 +891,15 @@ impl Chat {
             return;
         }
 
+        // if the full list is still loading, wait to see if they're in it
+        if !self.members_loaded {
+            if !self.stragglers.contains(user_id) {
+                self.stragglers.push(user_id.clone());
+            }
+
+            return;
+        }
+
         // otherwise, record them as in flight and fetch
         self.in_flight.push(user_id.clone());
         self.matrix.fetch_room_member(self.room(), user_id.clone());
@@ -481,7 +923,11 @@ impl Chat {
         }
 
         if let Some(id) = read_to.clone() {
-            self.matrix.read_to(self.room(), id);
+            // busy rooms would otherwise send a receipt for every event
+            if let Some(id) = self.receipt_timer.record(id) {
+                self.matrix.read_to(self.room(), id);
+            }
+
             self.read_to = read_to;
         }
     }
@@ -547,15 +993,75 @@ impl Chat {
         })
     }
 
+    // how far back the selection is, and how far back we've loaded
+    fn position(&self) -> Option<String> {
+        let selected = self.selected_reply()?;
+        let index = self.messages.iter().position(|m| m.id == selected.id)?;
+        let oldest = self.messages.last()?;
+
+        let mut position = format!(
+            "{} of {} loaded · back to {}",
+            index + 1,
+            self.messages.len(),
+            oldest.sent_date()
+        );
+
+        if self.fetching.get() {
+            position.push_str(" · loading…");
+        } else if self.next_cursor.is_none() {
+            position.push_str(" · that's all");
+        }
+
+        Some(position)
+    }
+
+    // custom emoji reactions can be named now
+    pub fn emotes_loaded_event(&mut self, room: Room) {
+        if self.room.room_id() == room.room_id() {
+            self.rebuild_messages();
+        }
+    }
+
+    pub fn calls_event(&mut self, room: Room, calls: Vec<CallWidget>) {
+        if self.room.room_id() == room.room_id() {
+            self.calls = calls;
+        }
+    }
+
+    pub fn room_members_event(&mut self, room: Room, members: Vec<RoomMember>) {
+        if self.room.room_id() != room.room_id() {
+            return;
+        }
+
+        for member in members {
+            if !self.members.iter().any(|m| m.user_id() == member.user_id()) {
+                self.members.push(member);
+            }
+        }
+
+        self.members_loaded = true;
+        self.pretty_members = OnceCell::new();
+        self.rebuild_messages();
+
+        info!("loaded {} room members", self.members.len());
+
+        // anyone we still don't know about (they've likely left) gets
+        // fetched on their own
+        for id in std::mem::take(&mut self.stragglers) {
+            self.check_sender(&id);
+        }
+    }
+
     pub fn room_member_event(&mut self, room: Room, member: RoomMember) {
         if self.room.room_id() != room.room_id() {
             return;
         }
 
         self.in_flight.retain(|id| id != member.user_id());
+        self.members.retain(|m| m.user_id() != member.user_id());
         self.members.push(member);
         self.pretty_members = OnceCell::new();
-        self.messages = make_message_list(&self.events, &self.members, &self.receipts);
+        self.rebuild_messages();
     }
 
     fn try_fetch_previous(&self) {
@@ -564,10 +1070,17 @@ impl Chat {
         }
 
         let state = self.list_state.take();
-        let buffer = self.total_list_items.get() - state.selected().unwrap_or_default();
+        let buffer = self
+            .total_list_items
+            .get()
+            .saturating_sub(state.selected().unwrap_or_default());
         self.list_state.set(state);
 
-        if buffer < 100 {
+        // keep a few screens of history ahead of the cursor
+        let window = (prefetch_screens() * self.list_height.get()).max(100);
+
+        // or keep going until we find the event we're looking for
+        if buffer < window || self.jump_to.is_some() {
             self.matrix
                 .fetch_messages(self.room(), self.next_cursor.clone());
             self.fetching.set(true);
@@ -575,6 +1088,39 @@ impl Chat {
         }
     }
 
+    /// Select the given event, as soon as it's loaded.
+    pub fn jump_to(&mut self, id: OwnedEventId) {
+        self.jump_to = Some(id);
+        self.try_jump();
+        self.try_fetch_previous();
+    }
+
+    fn try_jump(&mut self) {
+        if let Some(id) = &self.jump_to {
+            if self.select_event(id) {
+                self.jump_to = None;
+            }
+        }
+    }
+
+    // put the cursor on the first line of the given message
+    fn select_event(&self, id: &OwnedEventId) -> bool {
+        let mut counter = 0;
+
+        for message in &self.messages {
+            if &message.id == id {
+                let mut state = self.list_state.take();
+                state.select(Some(counter));
+                self.list_state.set(state);
+                return true;
+            }
+
+            counter += message.height(self.width.get());
+        }
+
+        false
+    }
+
     fn next(&self) {
         let mut state = self.list_state.take();
 
@@ -619,7 +1165,7 @@ impl Chat {
         self.list_state.set(state);
     }
 
-    // the message (or reply) currently selected by the UI
+    // the message currently selected by the UI
     fn selected_reply(&self) -> Option<&Message> {
         if self.messages.is_empty() {
             return None;
@@ -632,39 +1178,28 @@ impl Chat {
         // count message heights until we overrun the counter
         let mut counter = 0;
 
-        for m in &self.messages {
-            let flattened = m.flatten();
-
-            for (index, message) in flattened.iter().rev().enumerate() {
-                counter += message.height(self.width.get(), index < flattened.len() - 1);
+        for message in &self.messages {
+            counter += message.height(self.width.get());
 
-                if counter > selected {
-                    return Some(message);
-                }
+            if counter > selected {
+                return Some(message);
             }
         }
 
-        // otherwise, return the last reply on the last message
-        if let Some(last) = self.messages.last() {
-            return last.flatten().last().copied();
-        }
-
-        None
+        // otherwise, return the last message
+        self.messages.last()
     }
 
     // is the given selection in the middle of two messages?
     fn invalid_selection(&self, selected: usize) -> bool {
         let mut counter = 0;
 
-        for m in &self.messages {
-            let flattened = m.flatten();
-
-            for (index, message) in flattened.iter().rev().enumerate() {
-                counter += message.height(self.width.get(), index < flattened.len() - 1);
+        for message in &self.messages {
+            counter += message.height(self.width.get());
 
-                if counter > selected {
-                    return counter == selected + 1;
-                }
+            // the spacer above a message, if it has one
+            if counter > selected {
+                return message.spaced() && counter == selected + 1;
             }
         }
 
@@ -679,40 +1214,20 @@ impl Chat {
         }
     }
 
-    // the reactions belonging to the current user on the selected message
-    fn my_selected_reactions(&self) -> Vec<Reaction> {
+    // the reactions belonging to the current user on the selected message,
+    // with the events that would need to be redacted to take them back
+    fn my_selected_reactions(&self) -> HashMap<String, OwnedEventId> {
         let me = self.matrix.me();
-        let mut ret = self.selected_reactions();
 
-        ret.retain(|r| {
-            for e in &r.events {
-                if e.sender.id == me {
-                    return true;
-                }
-            }
-            false
-        });
-
-        ret
-    }
-
-    // the exact reaction event on the selected message
-    fn my_selected_reaction_event(&self, body: String) -> Option<ReactionEvent> {
-        let me = self.matrix.me();
-
-        for reaction in self.selected_reactions() {
-            if reaction.body != body {
-                break;
-            }
-
-            for event in reaction.events {
-                if event.sender.id == me {
-                    return Some(event);
-                }
-            }
-        }
-
-        None
+        self.selected_reactions()
+            .into_iter()
+            .filter_map(|r| {
+                r.events
+                    .into_iter()
+                    .find(|e| e.sender.id == me)
+                    .map(|e| (r.body, e.id))
+            })
+            .collect()
     }
 }
 
@@ -770,48 +1285,89 @@ pub struct ChatWidget<'a> {
     pub chat: &'a Chat,
 }
 
-impl Widget for ChatWidget<'_> {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        if area.width < 12 {
-            return;
+impl ChatWidget<'_> {
+    fn header_text(&self) -> String {
+        let mut header_text = self.chat.room.display_name();
+
+        if self.chat.muted() {
+            header_text.push_str(" (muted)")
         }
 
-        buf.set_style(area, Style::default().bg(Color::Black));
+        if low_bandwidth() {
+            header_text.push_str(" (low-bandwidth)")
+        }
 
-        let area = Layout::default()
-            .direction(Direction::Horizontal)
-            .horizontal_margin(get_margin(area.width, 80))
-            .constraints([Constraint::Percentage(100)].as_ref())
-            .split(area)[0];
+        if presentation_mode() {
+            header_text.push_str(" (presenting)")
+        }
 
-        let splits = Layout::default()
-            .direction(Direction::Vertical)
-            .vertical_margin(1)
-            .constraints([Constraint::Length(3), Constraint::Percentage(100)].as_ref())
-            .split(area);
+        if !self.chat.calls.is_empty() {
+            header_text.push_str(" 📞 Call in progress")
+        }
 
-        let mut header_text = self.chat.room.name.to_string();
+        if self.chat.connection == ConnectionState::Offline {
+            match self.chat.matrix.queued() {
+                0 => header_text.push_str(" (offline)"),
+                n => header_text.push_str(&format!(" (offline, {} queued)", n)),
+            }
+        }
 
-        if self.chat.muted() {
-            header_text.push_str(" (muted)")
+        header_text
+    }
+
+    fn accent(&self) -> Option<Color> {
+        if let Some(color) = room_accent(self.chat.room.room_id()) {
+            Some(color)
+        } else if self.chat.room.server_notices {
+            Some(Color::LightRed)
+        } else {
+            None
         }
+    }
 
-        // render the header
-        let header = Block::default()
-            .title(truncate(header_text, (splits[0].width - 8).into()))
+    // the room name, who's in it, and who's typing
+    fn render_header(&self, area: Rect, buf: &mut Buffer) {
+        let mut header = Block::default()
+            .title(truncate(
+                self.header_text(),
+                area.width.saturating_sub(8).into(),
+            ))
             .title_alignment(Alignment::Center)
             .style(Style::default().bg(Color::Black))
             .borders(Borders::ALL)
             .border_type(BorderType::Rounded);
 
-        header.render(splits[0], buf);
+        if let Some(color) = self.accent() {
+            header = header.border_style(Style::default().fg(color));
+        }
+
+        header.render(area, buf);
+
+        // tucked into the bottom border, on the right
+        if show_position() {
+            if let Some(position) = self.chat.position() {
+                let area = Rect::new(
+                    area.x + 2,
+                    area.bottom().saturating_sub(1),
+                    area.width.saturating_sub(4),
+                    1,
+                );
+
+                let position: String = position.chars().take(area.width.into()).collect();
+
+                Paragraph::new(position)
+                    .style(Style::default().fg(Color::DarkGray))
+                    .alignment(Alignment::Right)
+                    .render(area, buf);
+            }
+        }
 
         let p_area = Layout::default()
             .direction(Direction::Vertical)
             .horizontal_margin(2)
             .vertical_margin(1)
             .constraints([Constraint::Percentage(100)].as_ref())
-            .split(splits[0])[0];
+            .split(area)[0];
 
         let (p_content, p_color) = if self.chat.typing.is_some() {
             (self.chat.typing.as_ref().unwrap().as_str(), Color::Yellow)
@@ -822,33 +1378,133 @@ impl Widget for ChatWidget<'_> {
         Paragraph::new(p_content)
             .style(Style::default().fg(p_color))
             .render(p_area, buf);
+    }
+
+    // no room for a border, or the member list, so just the name on one line,
+    // unless somebody's typing
+    fn render_compact_header(&self, area: Rect, buf: &mut Buffer) {
+        let (text, style) = match &self.chat.typing {
+            Some(typing) => (typing.clone(), Style::default().fg(Color::Yellow)),
+            None => (
+                self.header_text(),
+                Style::default()
+                    .fg(self.accent().unwrap_or(Color::White))
+                    .add_modifier(Modifier::BOLD),
+            ),
+        };
+
+        Paragraph::new(truncate(text, area.width.into()))
+            .style(style)
+            .alignment(Alignment::Center)
+            .render(area, buf);
+    }
+}
+
+impl Widget for ChatWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        buf.set_style(area, Style::default().bg(Color::Black));
+
+        let narrow = is_narrow(area.width);
+
+        let width = if full_width() || narrow {
+            area.width
+        } else {
+            chat_width()
+        };
+
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .horizontal_margin(get_margin(area.width, width))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        // every row goes to messages in zen mode
+        let zen = zen_mode();
+
+        let header_height = match (zen, narrow) {
+            (true, _) => 0,
+            (false, true) => 1,
+            (false, false) => 3,
+        };
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .vertical_margin(if zen || narrow { 0 } else { 1 })
+            .constraints(
+                [
+                    Constraint::Length(header_height),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        if !zen && narrow {
+            self.render_compact_header(splits[0], buf);
+        } else if !zen {
+            self.render_header(splits[0], buf);
+        }
+
+        // chat messages, with a column kept for the scrollbar
+        let list_area = Rect {
+            width: splits[1].width.saturating_sub(1),
+            ..splits[1]
+        };
+
+        let width = list_area.width.saturating_sub(2).max(1);
 
-        // chat messages
         let items: Vec<ListItem> = self
             .chat
             .messages
             .iter()
-            .flat_map(|m| m.to_list_items((area.width - 2) as usize))
+            .flat_map(|m| m.to_list_items(width as usize))
             .collect();
 
         // make sure we save our last render width and total items
-        self.chat.width.set((area.width - 2).into());
-        self.chat.total_list_items.set(items.len());
+        let total = items.len();
+        self.chat.width.set(width.into());
+        self.chat.total_list_items.set(total);
+        self.chat.list_height.set(list_area.height.into());
+
+        if let Some((id, line)) = self.chat.anchor.take() {
+            self.chat.restore_anchor(&id, line);
+        }
 
         let mut list_state = self.chat.list_state.take();
+        let selected = list_state.selected().unwrap_or_default();
 
         let list = List::new(items)
             .highlight_symbol("> ")
             .start_corner(Corner::BottomLeft);
 
-        StatefulWidget::render(list, splits[1], buf, &mut list_state);
+        StatefulWidget::render(list, list_area, buf, &mut list_state);
+
+        // the list starts at the bottom, but the bar counts from the top
+        Scrollbar::new(
+            total,
+            list_area.height.into(),
+            total.saturating_sub(selected + 1),
+        )
+        .render(
+            Rect::new(list_area.right(), list_area.y, 1, list_area.height),
+            buf,
+        );
+
         self.chat.list_state.set(list_state);
+    }
+}
 
-        // reaction window
-        if let Some(react) = self.chat.react.as_ref() {
-            react.widget().render(area, buf)
-        }
+// Read some text in the pager, or the editor if that's what you'd rather.
+fn view(title: String, text: String) -> anyhow::Result<EventResult> {
+    if external_pager() {
+        App::get_sender().send(Event::View(text))?;
+        return Ok(consumed!());
     }
+
+    let pager = Pager::new(title, text);
+    Ok(Consumed(Box::new(|app| {
+        app.push_popup(Popup::Pager(pager))
+    })))
 }
 
 fn make_message_list(
@@ -862,14 +1518,10 @@ fn make_message_list(
     // split everything into either a starting message, or something that
     // modifies an existing message
     for event in timeline.iter() {
-        if let Some(message) = Message::try_from(event, false) {
+        if let Some(message) = Message::try_from(event, true) {
             messages.push(message);
-        } else if Message::apply_timeline_event(&mut messages, event, 0) == MergeResult::Missed {
-            // the event needed to be merge, but couldn't for some reason;
-            // force it into place, if possible
-            if let Some(message) = Message::try_from(event, true) {
-                messages.push(message);
-            }
+        } else {
+            Message::apply_timeline_event(&mut messages, event);
         }
     }
 
@@ -890,9 +1542,3 @@ fn make_message_list(
 
     messages
 }
-
-const REPLY_TEMPLATE: &str = "<!--
-  Replying to:
-
-{}
--->";
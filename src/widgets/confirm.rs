@@ -1,30 +1,30 @@
 use crossterm::event::{KeyCode, KeyEvent};
 
-use matrix_sdk::room::Room;
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
 use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget};
-use ruma::OwnedEventId;
 
+use crate::app::App;
 use crate::widgets::button::Button;
 use crate::widgets::{focus_next, Focusable};
 use crate::{close, consumed};
 
-use super::{get_margin, EventResult};
+use super::{get_margin, get_padding, EventResult};
 
-#[derive(Clone)]
-pub enum ConfirmBehavior {
-    Verification,
-    DeleteMessage(Room, OwnedEventId),
-}
+/// What to do once a choice is made. The popup is already closed by then.
+pub type OnChoice = Box<dyn FnOnce(&mut App)>;
 
 pub struct Confirm {
     title: String,
     message: String,
     yes: Button,
     no: Button,
-    behavior: ConfirmBehavior,
+    on_yes: Option<OnChoice>,
+    on_no: Option<OnChoice>,
+
+    // what it's about, so it can be taken down if that goes away
+    key: Option<String>,
 }
 
 impl Confirm {
@@ -33,17 +33,35 @@ impl Confirm {
         message: String,
         yes: String,
         no: String,
-        behavior: ConfirmBehavior,
+        on_yes: impl FnOnce(&mut App) + 'static,
     ) -> Self {
         Self {
             title,
             message,
             yes: Button::new(yes, true),
             no: Button::new(no, false),
-            behavior,
+            on_yes: Some(Box::new(on_yes)),
+            on_no: None,
+            key: None,
         }
     }
 
+    /// Do something on "no" as well, rather than just closing.
+    pub fn on_no(mut self, on_no: impl FnOnce(&mut App) + 'static) -> Self {
+        self.on_no = Some(Box::new(on_no));
+        self
+    }
+
+    /// Name what's being confirmed, for finding the popup again.
+    pub fn with_key(mut self, key: String) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
     pub fn widget(&self) -> ConfirmWidget {
         ConfirmWidget { confirm: self }
     }
@@ -73,30 +91,20 @@ impl Confirm {
         vec![Box::new(&mut self.yes), Box::new(&mut self.no)]
     }
 
-    fn make_result(&self) -> EventResult {
-        let focused = self.yes.focused();
-
-        match self.behavior.clone() {
-            ConfirmBehavior::Verification if focused => EventResult::Consumed(Box::new(|app| {
-                if let Some(s) = app.sas.clone() {
-                    app.matrix.confirm_verification(s);
-                    app.close_popup();
-                }
-            })),
-            ConfirmBehavior::Verification => EventResult::Consumed(Box::new(|app| {
-                if let Some(s) = app.sas.clone() {
-                    app.matrix.mismatched_verification(s);
-                    app.close_popup();
-                }
-            })),
-            ConfirmBehavior::DeleteMessage(room, id) if focused => {
-                EventResult::Consumed(Box::new(|app| {
-                    app.matrix.redact_event(room, id);
-                    app.close_popup();
-                }))
+    fn make_result(&mut self) -> EventResult {
+        let choice = if self.yes.focused() {
+            self.on_yes.take()
+        } else {
+            self.on_no.take()
+        };
+
+        EventResult::Consumed(Box::new(|app| {
+            app.close_popup();
+
+            if let Some(choice) = choice {
+                choice(app);
             }
-            ConfirmBehavior::DeleteMessage(_, _) => close!(),
-        }
+        }))
     }
 }
 
@@ -125,7 +133,7 @@ impl Widget for ConfirmWidget<'_> {
 
         let splits = Layout::default()
             .direction(Direction::Vertical)
-            .horizontal_margin(4)
+            .horizontal_margin(get_padding(area.width, 4))
             .vertical_margin(1)
             .constraints(
                 [
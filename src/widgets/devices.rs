@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::encryption::identities::Device;
+use matrix_sdk::room::Room;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+use ruma::{OwnedDeviceId, OwnedUserId};
+
+use crate::matrix::matrix::Outgoing;
+use crate::matrix::username::Username;
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// A device in the room that nobody has vouched for.
+#[derive(Clone, Debug)]
+pub struct UnverifiedDevice {
+    pub user_id: OwnedUserId,
+    pub device_id: OwnedDeviceId,
+    pub name: Option<String>,
+    // showed up since we last looked
+    pub new: bool,
+}
+
+impl UnverifiedDevice {
+    pub fn from_device(device: &Device, new: bool) -> Self {
+        UnverifiedDevice {
+            user_id: device.user_id().to_owned(),
+            device_id: device.device_id().to_owned(),
+            name: device.display_name().map(String::from),
+            new,
+        }
+    }
+}
+
+/// Hold a message back until the user has seen who else will be able to read
+/// it, and maybe blocked a few of them.
+pub struct Devices {
+    room: Room,
+    outgoing: Outgoing,
+    devices: Vec<UnverifiedDevice>,
+    blocked: HashSet<usize>,
+    selected: usize,
+}
+
+impl Devices {
+    pub fn new(room: Room, outgoing: Outgoing, devices: Vec<UnverifiedDevice>) -> Self {
+        Self {
+            room,
+            outgoing,
+            devices,
+            blocked: HashSet::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn widget(&self) -> DevicesWidget {
+        DevicesWidget { devices: self }
+    }
+
+    fn toggle(&mut self) {
+        if !self.blocked.remove(&self.selected) {
+            self.blocked.insert(self.selected);
+        }
+    }
+
+    fn to_block(&self) -> Vec<UnverifiedDevice> {
+        self.devices
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.blocked.contains(i))
+            .map(|(_, d)| d.clone())
+            .collect()
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        match input.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.devices.len().saturating_sub(1));
+                consumed!()
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                consumed!()
+            }
+            KeyCode::Char(' ') | KeyCode::Char('b') => {
+                self.toggle();
+                consumed!()
+            }
+            KeyCode::Enter => {
+                let room = self.room.clone();
+                let outgoing = self.outgoing.clone();
+                let devices = self.devices.clone();
+                let blocked = self.to_block();
+
+                EventResult::Consumed(Box::new(move |app| {
+                    app.close_popup();
+                    app.matrix
+                        .send_past_warning(room, outgoing, devices, blocked);
+                }))
+            }
+            KeyCode::Esc | KeyCode::Char('q') => close!(),
+            _ => consumed!(),
+        }
+    }
+}
+
+pub struct DevicesWidget<'a> {
+    devices: &'a Devices,
+}
+
+impl DevicesWidget<'_> {
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = vec![
+            Line::from(Span::styled(
+                "Nobody has verified these devices, and they'll be able to read what you send.",
+                Style::default().fg(Color::DarkGray),
+            )),
+            Line::default(),
+        ];
+
+        for (i, device) in self.devices.devices.iter().enumerate() {
+            let user = Username {
+                id: device.user_id.clone(),
+                display_name: None,
+            };
+
+            let blocked = self.devices.blocked.contains(&i);
+
+            let mut style = Style::default().fg(user.color());
+
+            if i == self.devices.selected {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            let mut spans = vec![
+                if blocked {
+                    Span::styled("[✗] ", Style::default().fg(Color::Red))
+                } else {
+                    Span::raw("[ ] ")
+                },
+                Span::styled(user.id.to_string(), style),
+                Span::raw(" "),
+                Span::styled(
+                    match &device.name {
+                        Some(name) => format!("{} ({})", name, device.device_id),
+                        None => device.device_id.to_string(),
+                    },
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ];
+
+            if device.new {
+                spans.push(Span::styled(" new", Style::default().fg(Color::Yellow)));
+            }
+
+            lines.push(Line::from(spans));
+        }
+
+        lines
+    }
+}
+
+impl Widget for DevicesWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines = self.lines();
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 80))
+            .vertical_margin(get_margin(area.height, lines.len() as u16 + 5))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title("Unverified Devices")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints(
+                [
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .render(splits[0], buf);
+
+        Paragraph::new(Span::styled(
+            "b block · Enter send · Esc cancel",
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[2], buf);
+    }
+}
@@ -1,44 +1,143 @@
-use crossterm::event::KeyEvent;
+use crossterm::event::{KeyCode, KeyEvent};
+use log::error;
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget};
+use std::cell::Cell;
 
-use crate::close;
+use crate::spawn::copy_to_clipboard;
 use crate::widgets::button::Button;
+use crate::{close, consumed};
 
-use super::{get_margin, EventResult};
+use super::{get_margin, get_padding, EventResult};
 
 pub struct Error {
     heading: String,
     message: String,
+
+    // the whole story, for bug reports
+    details: Option<String>,
+    show_details: bool,
+
+    // lines scrolled off the top, and how many there are to scroll
+    offset: usize,
+    max_offset: Cell<usize>,
+
+    copied: bool,
     button: Button,
 }
 
 impl Error {
     pub fn new(message: String) -> Self {
-        Self {
-            heading: "Error".to_string(),
-            message,
-            button: Button::new("OK".to_string(), true),
-        }
+        Self::with_heading("Error".to_string(), message)
     }
 
     pub fn with_heading(heading: String, message: String) -> Self {
         Self {
             heading,
             message,
+            details: None,
+            show_details: false,
+            offset: 0,
+            max_offset: Cell::new(0),
+            copied: false,
             button: Button::new("OK".to_string(), true),
         }
     }
 
+    pub fn from_anyhow(err: &anyhow::Error) -> Self {
+        Self::new(err.to_string()).details(details(err))
+    }
+
+    pub fn details(mut self, details: Option<String>) -> Self {
+        self.details = details;
+        self
+    }
+
     pub fn widget(&self) -> ErrorWidget {
         ErrorWidget { error: self }
     }
 
-    pub fn key_event(&mut self, _: &KeyEvent) -> EventResult {
-        // no matter what, close
-        close!()
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        match input.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.offset = (self.offset + 1).min(self.max_offset.get());
+            }
+            KeyCode::Char('k') | KeyCode::Up => self.offset = self.offset.saturating_sub(1),
+            KeyCode::Char('d') if self.details.is_some() => {
+                self.show_details = !self.show_details;
+                self.offset = 0;
+            }
+            KeyCode::Char('y') => match copy_to_clipboard(&self.report()) {
+                Ok(_) => self.copied = true,
+                Err(e) => error!("could not copy error: {}", e),
+            },
+            KeyCode::Enter | KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char(' ') => {
+                return close!()
+            }
+            _ => {}
+        }
+
+        consumed!()
+    }
+
+    // what's on the screen right now
+    fn text(&self) -> String {
+        match &self.details {
+            Some(details) if self.show_details => format!("{}\n\n{}", self.message, details),
+            _ => self.message.clone(),
+        }
+    }
+
+    // everything, whether it's showing or not
+    fn report(&self) -> String {
+        match &self.details {
+            Some(details) => format!("{}: {}\n\n{}", self.heading, self.message, details),
+            None => format!("{}: {}", self.heading, self.message),
+        }
+    }
+
+    fn hint(&self) -> String {
+        let mut hints = vec![];
+
+        if self.max_offset.get() > 0 {
+            hints.push("j/k scroll");
+        }
+
+        match (&self.details, self.show_details) {
+            (Some(_), false) => hints.push("d details"),
+            (Some(_), true) => hints.push("d hide details"),
+            _ => {}
+        }
+
+        hints.push(if self.copied { "copied!" } else { "y copy" });
+
+        hints.join(" · ")
+    }
+}
+
+/// The causes of an error, and what the SDK had to say about it, for when
+/// the message alone doesn't explain much.
+pub fn details(err: &anyhow::Error) -> Option<String> {
+    let mut lines: Vec<String> = err
+        .chain()
+        .skip(1)
+        .map(|e| format!("Caused by: {}", e))
+        .collect();
+
+    let root = err.root_cause();
+    let debug = format!("{:?}", root);
+
+    if debug.trim_matches('"') != root.to_string() {
+        lines.push(debug);
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n\n"))
     }
 }
 
@@ -48,9 +147,32 @@ pub struct ErrorWidget<'a> {
 
 impl Widget for ErrorWidget<'_> {
     fn render(self, area: Rect, buf: &mut Buffer) {
+        let width = if self.error.show_details { 80 } else { 60 };
+        let width = width.min(area.width);
+        let padding = get_padding(width, 4);
+
+        let lines: Vec<String> = self
+            .error
+            .text()
+            .lines()
+            .flat_map(|l| {
+                if l.is_empty() {
+                    vec!["".to_string()]
+                } else {
+                    textwrap::wrap(l, width.saturating_sub(padding * 2 + 2).max(1) as usize)
+                        .into_iter()
+                        .map(|l| l.to_string())
+                        .collect()
+                }
+            })
+            .collect();
+
+        // grow with the message, up to most of the screen
+        let height = (lines.len() as u16 + 7).min(area.height.saturating_sub(4).max(8));
+
         let area = Layout::default()
-            .horizontal_margin(get_margin(area.width, 60))
-            .vertical_margin(get_margin(area.height, 8))
+            .horizontal_margin(get_margin(area.width, width))
+            .vertical_margin(get_margin(area.height, height))
             .constraints([Constraint::Percentage(100)].as_ref())
             .split(area)[0];
 
@@ -58,12 +180,13 @@ impl Widget for ErrorWidget<'_> {
 
         let splits = Layout::default()
             .direction(Direction::Vertical)
-            .horizontal_margin(4)
+            .horizontal_margin(padding)
             .vertical_margin(1)
             .constraints(
                 [
                     Constraint::Length(1),
-                    Constraint::Length(2),
+                    Constraint::Min(1),
+                    Constraint::Length(1),
                     Constraint::Length(3),
                 ]
                 .as_ref(),
@@ -79,15 +202,61 @@ impl Widget for ErrorWidget<'_> {
 
         block.render(area, buf);
 
-        Paragraph::new(self.error.message.clone()).render(splits[1], buf);
+        let visible = splits[1].height as usize;
+        let max_offset = lines.len().saturating_sub(visible);
+        self.error.max_offset.set(max_offset);
+
+        let lines: Vec<Line> = lines
+            .into_iter()
+            .skip(self.error.offset.min(max_offset))
+            .take(visible)
+            .map(Line::from)
+            .collect();
+
+        Paragraph::new(Text::from(lines)).render(splits[1], buf);
+
+        Paragraph::new(Span::styled(
+            self.error.hint(),
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[2], buf);
 
         // pop the OK button in the middle
         let area = Layout::default()
             .direction(Direction::Horizontal)
             .horizontal_margin(get_margin(splits[1].width, 20))
             .constraints([Constraint::Percentage(100)].as_ref())
-            .split(splits[2])[0];
+            .split(splits[3])[0];
 
         self.error.button.widget().render(area, buf);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn test_details() {
+        assert_eq!(details(&anyhow!("plain")), None);
+
+        let err = Err::<(), _>(anyhow!("inner")).context("outer").unwrap_err();
+
+        assert_eq!(err.to_string(), "outer");
+        assert_eq!(details(&err), Some("Caused by: inner".to_string()));
+    }
+
+    // a phone's SSH client, or a tiling WM's corner, shouldn't take us down
+    #[test]
+    fn test_narrow() {
+        let error = Error::new("Something went wrong, and this is what.".to_string());
+
+        for width in [0, 1, 8, 20, 39] {
+            let area = Rect::new(0, 0, width, 12);
+            error.widget().render(area, &mut Buffer::empty(area));
+        }
+    }
+}
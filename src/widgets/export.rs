@@ -0,0 +1,228 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::room::Room;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, BorderType, Borders, Widget};
+
+use crate::app::Popup;
+use crate::matrix::export::{ExportFormat, ExportOptions};
+use crate::widgets::button::Button;
+use crate::widgets::error::Error;
+use crate::widgets::textinput::{paste_into, TextInput};
+use crate::widgets::EventResult::{Consumed, Ignored};
+use crate::widgets::{focus_next, focus_prev, get_margin, get_padding, EventResult, Focusable};
+use crate::{close, consumed};
+
+pub struct Export {
+    room: Room,
+    format: TextInput,
+    from: TextInput,
+    to: TextInput,
+    path: TextInput,
+    submit: Button,
+}
+
+impl Export {
+    pub fn new(room: Room, room_name: &str) -> Self {
+        let slug: String = room_name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+
+        Self {
+            room,
+            format: TextInput::new("Format (text, json, html)".to_string(), true, false)
+                .with_value("text"),
+            from: TextInput::new("From (YYYY-MM-DD, optional)".to_string(), false, false),
+            to: TextInput::new("To (YYYY-MM-DD, optional)".to_string(), false, false),
+            path: TextInput::new("File".to_string(), false, false)
+                .with_value(&format!("~/{}", slug.to_lowercase())),
+            submit: Button::new("Export".to_string(), false),
+        }
+    }
+
+    fn focus_order(&mut self) -> Vec<Box<dyn Focusable + '_>> {
+        vec![
+            Box::new(&mut self.format),
+            Box::new(&mut self.from),
+            Box::new(&mut self.to),
+            Box::new(&mut self.path),
+            Box::new(&mut self.submit),
+        ]
+    }
+
+    pub fn widget(&self) -> ExportWidget {
+        ExportWidget { export: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        paste_into(
+            [
+                &mut self.format,
+                &mut self.from,
+                &mut self.to,
+                &mut self.path,
+            ],
+            text,
+        )
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if input.code == KeyCode::Esc {
+            return close!();
+        }
+
+        for text in [
+            &mut self.format,
+            &mut self.from,
+            &mut self.to,
+            &mut self.path,
+        ] {
+            if let Consumed(_) = text.key_event(input) {
+                return consumed!();
+            }
+        }
+
+        if let Consumed(_) = self.submit.key_event(input) {
+            let room = self.room.clone();
+
+            return match self.options() {
+                Ok(options) => Consumed(Box::new(move |app| {
+                    app.close_popup();
+                    app.matrix.export_room(room, options);
+                })),
+                Err(err) => Consumed(Box::new(move |app| {
+                    app.push_popup(Popup::Error(Error::from_anyhow(&err)));
+                })),
+            };
+        }
+
+        match input.code {
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Down => focus_next(self.focus_order()),
+            KeyCode::BackTab | KeyCode::Up => focus_prev(self.focus_order()),
+            _ => Ignored,
+        }
+    }
+
+    fn options(&self) -> anyhow::Result<ExportOptions> {
+        let format = ExportFormat::from_str(&self.format.value)?;
+
+        Ok(ExportOptions {
+            format,
+            from: parse_date(&self.from.value)?,
+            to: parse_date(&self.to.value)?,
+            path: expand_path(&self.path.value, format)?,
+        })
+    }
+}
+
+fn parse_date(value: &str) -> anyhow::Result<Option<NaiveDate>> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let date = NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .with_context(|| format!("Could not parse date: {}", value))?;
+
+    Ok(Some(date))
+}
+
+// Handle a leading ~, and tack on an extension if there isn't one.
+fn expand_path(value: &str, format: ExportFormat) -> anyhow::Result<PathBuf> {
+    let value = value.trim();
+
+    let mut path = match value.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().context("no home directory")?.join(rest),
+        None => PathBuf::from(value),
+    };
+
+    if path.extension().is_none() {
+        path.set_extension(format.extension());
+    }
+
+    Ok(path)
+}
+
+pub struct ExportWidget<'a> {
+    export: &'a Export,
+}
+
+impl Widget for ExportWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 60))
+            .vertical_margin(get_margin(area.height, 22))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(get_padding(area.width, 4))
+            .vertical_margin(2)
+            .constraints(
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        let block = Block::default()
+            .title("Export History")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        block.render(area, buf);
+        self.export.format.widget().render(splits[0], buf);
+        self.export.from.widget().render(splits[1], buf);
+        self.export.to.widget().render(splits[2], buf);
+        self.export.path.widget().render(splits[3], buf);
+
+        // pop the submit button on the right side
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(splits[5])[1];
+
+        self.export.submit.widget().render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_path() {
+        let path = expand_path("/tmp/room", ExportFormat::Html).unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/room.html"));
+
+        let path = expand_path("/tmp/room.log", ExportFormat::Text).unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/room.log"));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date(" ").unwrap(), None);
+        assert_eq!(
+            parse_date("2023-05-01").unwrap(),
+            NaiveDate::from_ymd_opt(2023, 5, 1)
+        );
+        assert!(parse_date("May 1st").is_err());
+    }
+}
@@ -1,40 +1,108 @@
-use crate::close;
-use crossterm::event::KeyEvent;
+use crate::keybindings::{matching, Binding, Section};
+use crate::{close, consumed};
+use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
-use ratatui::widgets::{Block, BorderType, Borders, Row, Table, Widget};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Row, Table, Widget};
 
-use crate::widgets::get_margin;
+use crate::widgets::{get_margin, get_padding};
 
 use super::EventResult;
 
-pub struct Help;
+#[derive(Default)]
+pub struct Help {
+    // only show bindings that match
+    filter: String,
+    typing: bool,
+
+    // how many rows are scrolled off the top
+    offset: usize,
+}
 
 impl Help {
     pub fn widget(&self) -> HelpWidget {
-        HelpWidget
+        HelpWidget { help: self }
     }
 
-    pub fn key_event(&mut self, _: &KeyEvent) -> EventResult {
-        // no matter what, close
-        close!()
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if self.typing {
+            match input.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Enter => self.typing = false,
+                KeyCode::Esc => {
+                    self.typing = false;
+                    self.filter.clear();
+                }
+                _ => {}
+            }
+
+            self.offset = 0;
+            return consumed!();
+        }
+
+        let last = self.rows().len().saturating_sub(1);
+
+        match input.code {
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.offset = 0;
+            }
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('?') => return close!(),
+            KeyCode::Char('/') => self.typing = true,
+            KeyCode::Char('j') | KeyCode::Down => self.offset = (self.offset + 1).min(last),
+            KeyCode::Char('k') | KeyCode::Up => self.offset = self.offset.saturating_sub(1),
+            KeyCode::Char('g') | KeyCode::Home => self.offset = 0,
+            KeyCode::Char('G') | KeyCode::End => self.offset = last,
+            _ => {}
+        }
+
+        consumed!()
     }
-}
 
-impl Default for Help {
-    fn default() -> Self {
-        Self
+    // everything that matches the filter, under a heading for its section
+    fn rows(&self) -> Vec<HelpRow> {
+        let bindings = matching(&self.filter);
+        let mut rows = vec![];
+
+        for section in Section::ALL {
+            let mut found = bindings.iter().filter(|b| b.section == section).peekable();
+
+            if found.peek().is_none() {
+                continue;
+            }
+
+            if !rows.is_empty() {
+                rows.push(HelpRow::Blank);
+            }
+
+            rows.push(HelpRow::Section(section));
+            rows.extend(found.map(|b| HelpRow::Binding(b)));
+        }
+
+        rows
     }
 }
 
-pub struct HelpWidget;
+enum HelpRow {
+    Section(Section),
+    Binding(&'static Binding),
+    Blank,
+}
+
+pub struct HelpWidget<'a> {
+    help: &'a Help,
+}
 
-impl Widget for HelpWidget {
+impl Widget for HelpWidget<'_> {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let area = Layout::default()
             .direction(Direction::Horizontal)
-            .vertical_margin(get_margin(area.height, 20))
+            .vertical_margin(get_margin(area.height, 30))
             .horizontal_margin(get_margin(area.width, 70))
             .constraints([Constraint::Percentage(100)].as_ref())
             .split(area)[0];
@@ -54,47 +122,53 @@ impl Widget for HelpWidget {
         let splits = Layout::default()
             .direction(Direction::Vertical)
             .vertical_margin(2)
-            .horizontal_margin(2)
-            .constraints([Constraint::Percentage(100)].as_ref())
+            .horizontal_margin(get_padding(area.width, 3))
+            .constraints(
+                [
+                    Constraint::Length(2),
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
             .split(area);
 
-        let area = Layout::default()
-            .horizontal_margin(1)
-            .constraints([Constraint::Percentage(100)].as_ref())
-            .split(splits[0])[0];
-
-        Table::new(vec![
-            Row::new(vec!["Space", "Show the room switcher"]),
-            Row::new(vec!["j*", "Select one line down."]),
-            Row::new(vec!["k*", "Select one line up."]),
-            Row::new(vec!["i", "Create a new message using the external editor."]),
-            Row::new(vec![
-                "Enter",
-                "Open the selected message (images, videos, urls, etc).",
-            ]),
-            Row::new(vec!["s", "Save the selected message (images and videos)."]),
-            Row::new(vec![
-                "c",
-                "Edit the selected message in the external editor.",
-            ]),
-            Row::new(vec!["r", "React to the selected message."]),
-            Row::new(vec!["R", "Reply to the selected message."]),
-            Row::new(vec![
-                "v",
-                "View the selected message in the external editor.",
-            ]),
-            Row::new(vec!["V", "View the current room in the external editor."]),
-            Row::new(vec!["u", "Upload a file."]),
-            Row::new(vec!["?", "Show this helper."]),
-            Row::new(vec!["", "* arrow keys are fine too."]),
-        ])
-        .header(
-            Row::new(vec!["Key", "Description"])
-                .style(Style::default().fg(Color::Green))
-                .bottom_margin(1),
-        )
-        .widths(&[Constraint::Length(6), Constraint::Percentage(90)])
-        .column_spacing(1)
-        .render(area, buf)
+        let filter = if self.help.typing {
+            Span::raw(format!("/{}█", self.help.filter))
+        } else if !self.help.filter.is_empty() {
+            Span::raw(format!("/{}", self.help.filter))
+        } else {
+            Span::styled("/ to filter", Style::default().fg(Color::DarkGray))
+        };
+
+        Paragraph::new(filter).render(splits[0], buf);
+
+        // keep the last page full, rather than scrolling into nothing
+        let rows = self.help.rows();
+        let height = splits[1].height as usize;
+        let offset = self.help.offset.min(rows.len().saturating_sub(height));
+
+        let rows: Vec<Row> = rows
+            .into_iter()
+            .skip(offset)
+            .map(|r| match r {
+                HelpRow::Section(s) => {
+                    Row::new(vec!["", s.title()]).style(Style::default().fg(Color::Green))
+                }
+                HelpRow::Binding(b) => Row::new(vec![b.keys, b.description]),
+                HelpRow::Blank => Row::new(vec![""]),
+            })
+            .collect();
+
+        Table::new(rows)
+            .widths(&[Constraint::Length(6), Constraint::Percentage(90)])
+            .column_spacing(1)
+            .render(splits[1], buf);
+
+        Paragraph::new(Span::styled(
+            "* arrow keys are fine too",
+            Style::default().fg(Color::DarkGray),
+        ))
+        .render(splits[2], buf);
     }
 }
@@ -0,0 +1,128 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+use ruma::OwnedRoomOrAliasId;
+
+use crate::widgets::button::Button;
+use crate::widgets::textinput::TextInput;
+use crate::widgets::EventResult::{Consumed, Ignored};
+use crate::widgets::{focus_next, focus_prev, get_margin, get_padding, EventResult, Focusable};
+use crate::{close, consumed};
+
+/// Ask to be let into a room that needs an invite, with a note for whoever
+/// answers.
+pub struct Knock {
+    room: OwnedRoomOrAliasId,
+    message: TextInput,
+    submit: Button,
+}
+
+impl Knock {
+    pub fn new(room: OwnedRoomOrAliasId) -> Self {
+        Self {
+            room,
+            message: TextInput::new("Message (optional)".to_string(), true, false),
+            submit: Button::new("Knock".to_string(), false),
+        }
+    }
+
+    fn focus_order(&mut self) -> Vec<Box<dyn Focusable + '_>> {
+        vec![Box::new(&mut self.message), Box::new(&mut self.submit)]
+    }
+
+    pub fn widget(&self) -> KnockWidget {
+        KnockWidget { knock: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        self.message.paste(text)
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if input.code == KeyCode::Esc {
+            return close!();
+        }
+
+        if let Consumed(_) = self.message.key_event(input) {
+            return consumed!();
+        }
+
+        if let Consumed(_) = self.submit.key_event(input) {
+            let room = self.room.clone();
+            let message = self.message.value().trim().to_string();
+            let reason = (!message.is_empty()).then_some(message);
+
+            return Consumed(Box::new(move |app| {
+                app.close_popup();
+                app.matrix.knock(room, reason);
+            }));
+        }
+
+        match input.code {
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Down => focus_next(self.focus_order()),
+            KeyCode::BackTab | KeyCode::Up => focus_prev(self.focus_order()),
+            _ => Ignored,
+        }
+    }
+}
+
+pub struct KnockWidget<'a> {
+    knock: &'a Knock,
+}
+
+impl Widget for KnockWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 60))
+            .vertical_margin(get_margin(area.height, 13))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(get_padding(area.width, 4))
+            .vertical_margin(1)
+            .constraints(
+                [
+                    Constraint::Length(2),
+                    Constraint::Length(1),
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        let block = Block::default()
+            .title(format!("Knock on {}", self.knock.room))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        block.render(area, buf);
+
+        Paragraph::new(Span::styled(
+            "You need an invite to join. Knock, and someone inside can let you in.",
+            Style::default().fg(Color::DarkGray),
+        ))
+        .wrap(Wrap { trim: true })
+        .render(splits[0], buf);
+
+        self.knock.message.widget().render(splits[2], buf);
+
+        // pop the submit button on the right side
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(splits[4])[1];
+
+        self.knock.submit.widget().render(area, buf);
+    }
+}
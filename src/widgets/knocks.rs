@@ -0,0 +1,178 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::room::{Room, RoomMember};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+use ruma::OwnedUserId;
+
+use crate::matrix::username::Username;
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// Someone asking to be let in.
+#[derive(Clone, Debug)]
+pub struct Knocker {
+    pub user_id: OwnedUserId,
+    pub name: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl Knocker {
+    pub fn from_member(member: &RoomMember) -> Self {
+        Knocker {
+            user_id: member.user_id().to_owned(),
+            name: member.display_name().map(String::from),
+            reason: member
+                .event()
+                .original_content()
+                .and_then(|c| c.reason.clone()),
+        }
+    }
+}
+
+/// Everyone knocking on a room, to let in or turn away.
+pub struct Knocks {
+    room: Room,
+    room_name: String,
+    knockers: Vec<Knocker>,
+    selected: usize,
+}
+
+impl Knocks {
+    pub fn new(room: Room, room_name: String, knockers: Vec<Knocker>) -> Self {
+        Self {
+            room,
+            room_name,
+            knockers,
+            selected: 0,
+        }
+    }
+
+    pub fn widget(&self) -> KnocksWidget {
+        KnocksWidget { knocks: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        match input.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.knockers.len().saturating_sub(1));
+                consumed!()
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                consumed!()
+            }
+            KeyCode::Char('a') | KeyCode::Char('d') => {
+                if self.knockers.is_empty() {
+                    return consumed!();
+                }
+
+                let accept = input.code == KeyCode::Char('a');
+                let knocker = self.knockers.remove(self.selected);
+                let room = self.room.clone();
+
+                self.selected = self.selected.min(self.knockers.len().saturating_sub(1));
+
+                EventResult::Consumed(Box::new(move |app| {
+                    app.matrix.answer_knock(room, knocker.user_id, accept)
+                }))
+            }
+            KeyCode::Esc | KeyCode::Char('q') => close!(),
+            _ => consumed!(),
+        }
+    }
+}
+
+pub struct KnocksWidget<'a> {
+    knocks: &'a Knocks,
+}
+
+impl KnocksWidget<'_> {
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = vec![];
+
+        for (i, knocker) in self.knocks.knockers.iter().enumerate() {
+            let user = Username {
+                id: knocker.user_id.clone(),
+                display_name: knocker.name.clone(),
+            };
+
+            let mut style = Style::default().fg(user.color());
+
+            if i == self.knocks.selected {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            lines.push(Line::from(vec![
+                Span::styled(user.to_string(), style),
+                Span::raw(" "),
+                Span::styled(user.id.to_string(), Style::default().fg(Color::DarkGray)),
+            ]));
+
+            if let Some(reason) = &knocker.reason {
+                lines.push(Line::from(format!("  “{}”", reason)));
+            }
+        }
+
+        if lines.is_empty() {
+            lines.push(Line::from(Span::styled(
+                "Nobody's knocking.",
+                Style::default().fg(Color::DarkGray),
+            )));
+        }
+
+        lines
+    }
+}
+
+impl Widget for KnocksWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines = self.lines();
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 70))
+            .vertical_margin(get_margin(area.height, lines.len() as u16 + 5))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title(format!("Knocking on {}", self.knocks.room_name))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints(
+                [
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .render(splits[0], buf);
+
+        Paragraph::new(Span::styled(
+            "a let in · d turn away · q close",
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[2], buf);
+    }
+}
@@ -0,0 +1,101 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget};
+
+use crate::logging::{get_log_path, tail};
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// How much of the log we bother to read.
+const MAX_LINES: usize = 500;
+
+pub struct Logs {
+    lines: Vec<String>,
+    // how far up from the bottom we've scrolled
+    offset: usize,
+}
+
+impl Logs {
+    pub fn new() -> Self {
+        Self {
+            lines: tail(MAX_LINES),
+            offset: 0,
+        }
+    }
+
+    pub fn widget(&self) -> LogsWidget {
+        LogsWidget { logs: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        match input.code {
+            KeyCode::Esc | KeyCode::Char('q') => close!(),
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.offset = (self.offset + 1).min(self.lines.len().saturating_sub(1));
+                consumed!()
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.offset = self.offset.saturating_sub(1);
+                consumed!()
+            }
+            KeyCode::Char('G') | KeyCode::End => {
+                self.offset = 0;
+                consumed!()
+            }
+            KeyCode::Char('r') => {
+                self.lines = tail(MAX_LINES);
+                self.offset = 0;
+                consumed!()
+            }
+            _ => consumed!(),
+        }
+    }
+}
+
+impl Default for Logs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct LogsWidget<'a> {
+    logs: &'a Logs,
+}
+
+impl Widget for LogsWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 120))
+            .vertical_margin(get_margin(area.height, 40))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title(format!("Logs ({})", get_log_path().display()))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let height = inner.height as usize;
+        let end = self.logs.lines.len().saturating_sub(self.logs.offset);
+        let start = end.saturating_sub(height);
+
+        let lines: Vec<Line> = self.logs.lines[start..end]
+            .iter()
+            .map(|l| Line::from(l.as_str()))
+            .collect();
+
+        Paragraph::new(Text::from(lines)).render(inner, buf);
+    }
+}
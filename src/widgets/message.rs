@@ -1,26 +1,29 @@
 use crate::widgets::message::MessageType::File;
 use chrono::TimeZone;
 use human_bytes::human_bytes;
-use std::cell::Cell;
+use std::cell::{Ref, RefCell};
 use std::collections::BinaryHeap;
 use std::time::{Duration, SystemTime};
 
+use crate::locale::{self, tr};
+use crate::matrix::bridges::strip_name_prefix;
+use crate::matrix::emotes;
 use crate::matrix::matrix::{pad_emoji, AfterDownload, Matrix};
 use crate::matrix::username::Username;
+use crate::settings::text_receipts;
 use crate::spawn::view_text;
 use crate::{limit_list, pretty_list};
 use chrono::offset::Local;
+use matrix_sdk::deserialized_responses::{VerificationLevel, VerificationState};
 use matrix_sdk::room::RoomMember;
 use once_cell::unsync::OnceCell;
-use ratatui::style::{Color, Style};
+use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
 use ratatui::widgets::ListItem;
 use ruma::events::relation::{InReplyTo, Replacement};
-use ruma::events::room::message::MessageType::{self, Image, Text, Video};
-use ruma::events::room::message::{
-    FileMessageEventContent, ImageMessageEventContent, Relation, TextMessageEventContent,
-    VideoMessageEventContent,
-};
+use ruma::events::room::message::sanitize::remove_plain_reply_fallback;
+use ruma::events::room::message::MessageType::{self, Image, ServerNotice, Text, Video};
+use ruma::events::room::message::{Relation, TextMessageEventContent};
 use ruma::events::room::redaction::{OriginalRoomRedactionEvent, RoomRedactionEvent};
 use ruma::events::AnyMessageLikeEvent::Reaction as Rctn;
 use ruma::events::AnyMessageLikeEvent::RoomMessage;
@@ -30,7 +33,7 @@ use ruma::events::AnyTimelineEvent::MessageLike;
 use ruma::events::MessageLikeEvent;
 use ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId};
 
-use super::receipts::Receipt;
+use super::receipts::{ReadReceipt, Receipt};
 
 // A Message is a line in the chat window; what a user would generally
 // consider a "message". It has reactions, edits, and is generally in a state
@@ -44,78 +47,176 @@ pub struct Message {
     pub history: Vec<MessageType>,
     pub sender: Username,
     pub reactions: Vec<Reaction>,
-    pub replies: Vec<Message>,
-    pub receipts: Vec<Username>,
+    pub quote: Option<Quote>,
+    pub receipts: Vec<ReadReceipt>,
 
-    last_height: Cell<LastHeight>,
-}
+    // calls out the whole room, whether or not the sender is allowed to
+    pub room_mention: bool,
+
+    // the room mention was allowed, so it should stand out
+    highlight: bool,
+
+    // the network the sender is bridged from, if any
+    bridge: Option<String>,
+
+    // how much we trust the device that sent it, if it was encrypted
+    trust: Option<Trust>,
+
+    // follows right on from the same sender, so the header is left off
+    grouped: bool,
 
-#[derive(PartialEq, Eq)]
-pub enum MergeResult {
-    Consumed,
-    Missed,
-    Ignored,
+    // no blank line between this and the message before
+    compact: bool,
+
+    // history is missing from just before this message, and if it's loading
+    gap: Option<bool>,
+
+    // where long messages are cut off, unless they've been expanded
+    max_lines: usize,
+    expanded: bool,
+
+    // bumped whenever anything that changes the layout changes
+    revision: usize,
+    layout: RefCell<Option<Layout>>,
+
+    // "5 minutes ago", and the minute of the clock it was worked out in
+    elapsed: RefCell<Option<(u64, String)>>,
 }
 
-// We need to calculate the message hight a lot, but it rarely changes;
-// keep it around.
-#[derive(Copy, Clone, Default)]
-struct LastHeight {
-    width: usize,
-    height: usize,
+/// A snippet of the message a reply points at.
+#[derive(Clone)]
+pub struct Quote {
+    // missing if we couldn't find the original
+    pub sender: Option<Username>,
+    pub body: String,
 }
 
-impl Message {
-    pub fn sort_order(&self) -> &MilliSecondsSinceUnixEpoch {
-        if self.replies.is_empty() {
-            &self.sent
-        } else {
-            &self.replies.last().unwrap().sent
+impl Quote {
+    pub fn new(sender: OwnedUserId, body: &str) -> Self {
+        Quote {
+            sender: Some(Username::new(sender)),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn unavailable() -> Self {
+        Quote {
+            sender: None,
+            body: "original message unavailable".to_string(),
         }
     }
 
+    // a single line, no matter how long the original was
+    fn line(&self, width: usize) -> String {
+        let body = self.body.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let line = match &self.sender {
+            Some(sender) => format!("↪ {}: {}", sender, body),
+            None => format!("↪ {}", body),
+        };
+
+        if line.chars().count() <= width {
+            return line;
+        }
+
+        let mut line: String = line.chars().take(width.saturating_sub(1)).collect();
+        line.push('…');
+        line
+    }
+}
+
+// We need to calculate the message height (and wrap the body) a lot, but it
+// rarely changes; keep it around until the width or content does. The text
+// it was wrapped from lets the body outlive the message, when the list is
+// rebuilt. The rest depends on more than the text, so it's worked out again.
+struct Layout {
+    width: usize,
+    revision: usize,
+    text: String,
+    body: Vec<String>,
+    height: Option<usize>,
+    // and the minute of the clock they were drawn in, for the time ago
+    lines: Option<(u64, Vec<ListItem<'static>>)>,
+}
+
+/// Any more lines than this, and the message is truncated, unless the config
+/// says otherwise.
+const MAX_LINES: usize = 10;
+
+/// Same for reactions.
+const MAX_REACTIONS: usize = 5;
+
+/// And the initials in a row of read receipts.
+const MAX_RECEIPTS: usize = 8;
+
+/// How close together messages from one sender need to be to share a header.
+const GROUP_WINDOW: u64 = 5 * 60 * 1000;
+
+impl Message {
     fn display_body(body: &MessageType) -> String {
         match body {
             Text(TextMessageEventContent { body, .. }) => body.to_string(),
-            Image(ImageMessageEventContent { body, info, .. }) => {
-                if let Some(info) = info {
-                    if let Some(size) = info.size {
-                        format!("Image: {} ({})", body, human_bytes(size))
-                    } else {
-                        body.to_string()
-                    }
-                } else {
-                    body.to_string()
-                }
+            // with a caption (MSC2530), the body is the caption, and the file
+            // name has its own field
+            Image(content) => {
+                let line = match content.info.as_ref().and_then(|i| i.size) {
+                    Some(size) => format!("Image: {} ({})", content.filename(), human_bytes(size)),
+                    None => content.filename().to_string(),
+                };
+
+                with_caption(line, content.caption())
             }
-            Video(VideoMessageEventContent { body, info, .. }) => {
-                if let Some(info) = info {
-                    if let Some(size) = info.size {
-                        format!("Video: {} ({})", body, human_bytes(size))
-                    } else {
-                        "no size".to_string()
-                    }
-                } else {
-                    "no info".to_string()
-                }
+            Video(content) => {
+                let line = match &content.info {
+                    Some(info) => match info.size {
+                        Some(size) => {
+                            format!("Video: {} ({})", content.filename(), human_bytes(size))
+                        }
+                        None => "no size".to_string(),
+                    },
+                    None => "no info".to_string(),
+                };
+
+                with_caption(line, content.caption())
             }
-            File(FileMessageEventContent { body, info, .. }) => {
-                if let Some(info) = info {
-                    if let Some(size) = info.size {
-                        format!("File: {} ({})", body, human_bytes(size))
-                    } else {
-                        body.to_string()
-                    }
-                } else {
-                    body.to_string()
-                }
+            File(content) => {
+                let line = match content.info.as_ref().and_then(|i| i.size) {
+                    Some(size) => format!("File: {} ({})", content.filename(), human_bytes(size)),
+                    None => content.filename().to_string(),
+                };
+
+                with_caption(line, content.caption())
             }
+            ServerNotice(content) => match &content.admin_contact {
+                Some(contact) => format!("{}\n\nContact: {}", content.body, contact),
+                None => content.body.to_string(),
+            },
             _ => "unknown".to_string(),
         }
     }
 
     pub fn display(&self) -> String {
-        Message::display_body(&self.body).trim().to_string()
+        let body = Message::display_body(&self.body);
+
+        // the quoted context is shown on its own
+        let body = if self.in_reply_to.is_some() {
+            remove_plain_reply_fallback(&body)
+        } else {
+            &body
+        };
+
+        // and the sender is already in the header
+        if self.bridge.is_some() {
+            return strip_name_prefix(body.trim(), self.sender.as_str()).to_string();
+        }
+
+        body.trim().to_string()
+    }
+
+    /// When it was sent, down to the minute.
+    pub fn sent_date(&self) -> String {
+        let date = Local.timestamp_opt(self.sent.as_secs().into(), 0).unwrap();
+        date.format("%Y-%m-%d %I:%M %p").to_string()
     }
 
     pub fn display_full(&self) -> String {
@@ -161,27 +262,47 @@ impl Message {
         ret
     }
 
+    /// How long ago this was sent. It's only worked out again once the
+    /// clock ticks over to a new minute, so redraws stay cheap.
     pub fn pretty_elapsed(&self) -> String {
-        let formatter = timeago::Formatter::new();
-
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
+        let minute = locale::clock_minute();
+
+        if let Some((m, text)) = &*self.elapsed.borrow() {
+            if *m == minute {
+                return text.clone();
+            }
+        }
+
         let then: u64 = self.sent.as_secs().into();
-        formatter.convert(Duration::from_secs(now - then))
+        let text = locale::pretty_elapsed(Duration::from_secs(now.saturating_sub(then)));
+
+        self.elapsed.replace(Some((minute, text.clone())));
+        text
     }
 
     pub fn style(&self) -> Style {
         match &self.body {
+            Text(_) if self.highlight => Style::default().fg(Color::Yellow),
             Text(_) => Style::default(),
+            ServerNotice(_) => Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
             _ => Style::default().fg(Color::Blue),
         }
     }
 
-    pub fn open(&self, matrix: Matrix) {
+    /// Open the message in whatever makes sense. Images can be previewed at
+    /// a smaller size first.
+    pub fn open(&self, matrix: Matrix, preview: bool) {
         match &self.body {
+            Image(_) if preview => {
+                matrix.download_content(self.body.clone(), AfterDownload::Preview)
+            }
             Image(_) => matrix.download_content(self.body.clone(), AfterDownload::View),
             Video(_) => matrix.download_content(self.body.clone(), AfterDownload::View),
             File(_) => matrix.download_content(self.body.clone(), AfterDownload::Save),
@@ -202,6 +323,12 @@ impl Message {
     pub fn edit(&mut self, new_body: MessageType) {
         let old = std::mem::replace(&mut self.body, new_body);
         self.history.push(old);
+        self.touch();
+    }
+
+    /// Mark the layout as stale.
+    pub fn touch(&mut self) {
+        self.revision += 1;
     }
 
     // can we make a brand-new message, just from this event?
@@ -210,7 +337,7 @@ impl Message {
             let c = c.clone();
 
             let body = match c.content.msgtype {
-                Text(_) | Image(_) | Video(_) | File(_) => c.content.msgtype,
+                Text(_) | Image(_) | Video(_) | File(_) | ServerNotice(_) => c.content.msgtype,
                 _ => return None,
             };
 
@@ -233,7 +360,9 @@ impl Message {
                 None
             };
 
-            return Some(Message {
+            let explicit = c.content.mentions.as_ref().map_or(false, |m| m.room);
+
+            let mut message = Message {
                 id: c.event_id,
                 in_reply_to,
                 room_id: c.room_id,
@@ -242,10 +371,25 @@ impl Message {
                 history: vec![],
                 sender: Username::new(c.sender),
                 reactions: Vec::new(),
-                replies: Vec::new(),
+                quote: None,
                 receipts: Vec::new(),
-                last_height: Cell::new(LastHeight::default()),
-            });
+                room_mention: false,
+                highlight: false,
+                bridge: None,
+                trust: None,
+                grouped: false,
+                compact: false,
+                gap: None,
+                max_lines: MAX_LINES,
+                expanded: false,
+                revision: 0,
+                layout: RefCell::new(None),
+                elapsed: RefCell::new(None),
+            };
+
+            message.room_mention = explicit || mentions_room(&message.display());
+
+            return Some(message);
         }
 
         None
@@ -253,67 +397,21 @@ impl Message {
 
     // if not, we should send the event here, to possibly act on existing
     // events
-    pub fn apply_timeline_event(
-        messages: &mut Vec<Message>,
-        event: &AnyTimelineEvent,
-        depth: usize,
-    ) -> MergeResult {
-        let mut reply_result = MergeResult::Ignored;
-
-        // replacements and replies
+    pub fn apply_timeline_event(messages: &mut Vec<Message>, event: &AnyTimelineEvent) {
+        // replacements
         if let MessageLike(RoomMessage(MessageLikeEvent::Original(c))) = event {
-            let event_content = c.clone().content;
-
             if let Some(Relation::Replacement(Replacement {
                 event_id: id,
                 new_content: content,
                 ..
-            })) = event_content.relates_to.clone()
+            })) = c.content.relates_to.clone()
             {
                 for message in messages.iter_mut() {
                     if message.id == id {
                         message.edit(content.msgtype);
-                        return MergeResult::Consumed;
-                    }
-                }
-            }
-
-            if let Some(Relation::Reply {
-                in_reply_to: InReplyTo { event_id: id, .. },
-            }) = event_content.relates_to
-            {
-                let mut found_index = None;
-                let mut sibling = None;
-
-                for (i, message) in messages.iter_mut().enumerate() {
-                    if message.id == id {
-                        if let Some(reply) = Message::try_from(event, true) {
-                            if depth > 3 {
-                                sibling = Some(reply)
-                            } else {
-                                message.replies.push(reply);
-                                found_index = Some(i);
-                            }
-                            break;
-                        }
+                        return;
                     }
                 }
-
-                // we found a message with a new reply, so move it to the end
-                if let Some(i) = found_index {
-                    let found = messages.remove(i);
-                    messages.push(found);
-                    return MergeResult::Consumed;
-                }
-
-                // we found a new reply, but can't nest any deeper
-                if let Some(message) = sibling {
-                    messages.push(message);
-                    return MergeResult::Consumed;
-                }
-
-                // we found a reply, but can't put it here
-                reply_result = MergeResult::Missed;
             }
         }
 
@@ -335,7 +433,9 @@ impl Message {
                         list_view: OnceCell::new(),
                     });
 
-                    return MergeResult::Consumed;
+                    message.touch();
+
+                    return;
                 }
             }
         }
@@ -354,51 +454,40 @@ impl Message {
                 }
 
                 // making sure to get rid of reactions that have no events
+                let before = message.reactions.len();
                 message.reactions.retain(|r| !r.events.is_empty());
+
+                if message.reactions.len() != before {
+                    message.touch();
+                }
             }
 
             // then look at the messages
             messages.retain(|m| &m.id != id);
         }
-
-        // and finally, continue down the tree, propogating a "missed" result
-        for message in messages.iter_mut() {
-            if !message.replies.is_empty() {
-                let result = Message::apply_timeline_event(&mut message.replies, event, depth + 1);
-
-                if result != MergeResult::Missed {
-                    reply_result = result;
-                }
-            }
-        }
-
-        reply_result
     }
 
     /// Given a binary heap (priority queue) of Receipts, run through the
     /// the messages, popping off receipts and attaching them. This way we
-    /// only show a single receipt per user (per reply chain), on the latest
-    /// message they have read.
+    /// only show a single receipt per user, on the latest message they have
+    /// read. Readers are kept in the order they got there.
     pub fn apply_receipts(messages: &mut [Message], heap: &mut BinaryHeap<Receipt>) {
-        // hold on to a fresh copy of the heap to clone into each reply chain
-        let og_heap = heap.clone();
-
         for message in messages.iter_mut().rev() {
-            if !message.replies.is_empty() {
-                Message::apply_receipts(&mut message.replies, &mut og_heap.clone());
-            }
+            let before = message.receipts.len();
 
             while let Some(candidate) = heap.peek() {
                 if candidate.timestamp > &message.sent {
-                    message
-                        .receipts
-                        .push(Username::new(candidate.user_id.clone()));
-
+                    message.receipts.push(ReadReceipt::new(candidate));
                     heap.pop();
                 } else {
                     break;
                 }
             }
+
+            if message.receipts.len() != before {
+                message.receipts.sort_by_key(|r| r.timestamp);
+                message.touch();
+            }
         }
     }
 
@@ -413,157 +502,471 @@ impl Message {
                 }
             }
 
-            for username in self.receipts.iter_mut() {
-                username.update(member);
+            for receipt in self.receipts.iter_mut() {
+                receipt.user.update(member);
+            }
+
+            if let Some(Quote {
+                sender: Some(sender),
+                ..
+            }) = &mut self.quote
+            {
+                sender.update(member);
             }
         }
 
-        for reply in self.replies.iter_mut() {
-            reply.update_senders(members);
+        // any of those names may have changed
+        self.touch();
+    }
+
+    /// Only room mentions from someone with the power to make them stand out.
+    pub fn set_highlight(&mut self, highlight: bool) {
+        if self.highlight != highlight {
+            self.highlight = highlight;
+            self.touch();
         }
     }
 
-    // try our best to remove the fomatting that Matrix adds to the top of
-    // message reply bodies
-    fn remove_reply_header(body: &str) -> &str {
-        let mut marker = 0;
+    /// Call out missing history just before this message.
+    pub fn set_gap(&mut self, gap: Option<bool>) {
+        if self.gap != gap {
+            self.gap = gap;
+            self.touch();
+        }
+    }
 
-        for line in body.split('\n') {
-            if line.trim().is_empty() || line.starts_with("> ") {
-                marker += line.len() + 1;
-            } else {
-                break;
+    /// Tag the sender with the network they're bridged from.
+    pub fn set_bridge(&mut self, bridge: Option<&str>) {
+        if self.bridge.as_deref() != bridge {
+            self.bridge = bridge.map(String::from);
+            self.touch();
+        }
+    }
+
+    /// Show a shield for the device this came from.
+    pub fn set_trust(&mut self, trust: Option<Trust>) {
+        if self.trust != trust {
+            self.trust = trust;
+            self.touch();
+        }
+    }
+
+    /// Leave the sender and time off of messages that follow on from the one
+    /// before. Messages are newest first, as in the chat.
+    pub fn group(messages: &mut [Message]) {
+        for i in 0..messages.len() {
+            let grouped = match messages.get(i + 1) {
+                Some(previous) => messages[i].gap.is_none() && messages[i].follows(previous),
+                None => false,
+            };
+
+            if messages[i].grouped != grouped {
+                messages[i].grouped = grouped;
+                messages[i].touch();
             }
         }
+    }
 
-        &body[std::cmp::min(marker, body.len())..body.len()]
+    // same sender, not long after, and nothing the header needs to call out
+    fn follows(&self, previous: &Message) -> bool {
+        let now: u64 = self.sent.get().into();
+        let then: u64 = previous.sent.get().into();
+
+        self.sender.id == previous.sender.id
+            && now.saturating_sub(then) <= GROUP_WINDOW
+            && self.in_reply_to.is_none()
+            && self.history.is_empty()
+            && !self.highlight
+            && self.trust == previous.trust
     }
 
-    pub fn height(&self, width: usize, reply: bool) -> usize {
-        let last = self.last_height.get();
+    pub fn grouped(&self) -> bool {
+        self.grouped
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        if self.compact != compact {
+            self.compact = compact;
+            self.touch();
+        }
+    }
+
+    /// Is there a blank line above this message?
+    pub fn spaced(&self) -> bool {
+        !self.grouped && !self.compact
+    }
+
+    // the spacer and the sender line
+    fn header_height(&self) -> usize {
+        match (self.grouped, self.compact) {
+            (true, _) => 0,
+            (false, true) => 1,
+            (false, false) => 2,
+        }
+    }
+
+    /// Cut the body off after this many lines.
+    pub fn set_max_lines(&mut self, max_lines: usize) {
+        let max_lines = max_lines.max(1);
 
-        if last.width == width {
-            return last.height;
+        if self.max_lines != max_lines {
+            self.max_lines = max_lines;
+            self.touch();
+        }
+    }
+
+    /// Show the whole body, and every reaction, right in the chat.
+    pub fn set_expanded(&mut self, expanded: bool) {
+        if self.expanded != expanded {
+            self.expanded = expanded;
+            self.touch();
+        }
+    }
+
+    pub fn expanded(&self) -> bool {
+        self.expanded
+    }
+
+    fn line_limit(&self) -> usize {
+        if self.expanded {
+            usize::MAX
+        } else {
+            self.max_lines
         }
+    }
 
-        let mut height = if reply {
-            textwrap::wrap(Message::remove_reply_header(&self.display()), width).len()
+    fn reaction_limit(&self) -> usize {
+        if self.expanded {
+            usize::MAX
         } else {
-            textwrap::wrap(&self.display(), width).len()
+            MAX_REACTIONS
+        }
+    }
+
+    /// Fill in what this message is replying to.
+    pub fn set_quote(&mut self, quote: Quote) {
+        self.quote = Some(quote);
+        self.touch();
+    }
+
+    // Bring the layout up to date. The body is only wrapped again if the
+    // width or the text has changed; the rest goes whenever anything does.
+    fn refresh_layout(&self, width: usize) {
+        let mut layout = self.layout.borrow_mut();
+
+        if let Some(l) = layout.as_ref() {
+            if l.width == width && l.revision == self.revision {
+                return;
+            }
+        }
+
+        let text = self.display();
+
+        if let Some(l) = layout
+            .as_mut()
+            .filter(|l| l.width == width && l.text == text)
+        {
+            l.revision = self.revision;
+            l.height = None;
+            l.lines = None;
+            return;
+        }
+
+        let body = textwrap::wrap(&text, width)
+            .into_iter()
+            .map(|l| l.trim().to_string())
+            .collect();
+
+        *layout = Some(Layout {
+            width,
+            revision: self.revision,
+            text,
+            body,
+            height: None,
+            lines: None,
+        });
+    }
+
+    // the wrapped body, cached until the width or content changes
+    fn body_lines(&self, width: usize) -> Ref<Vec<String>> {
+        self.refresh_layout(width);
+        Ref::map(self.layout.borrow(), |l| &l.as_ref().unwrap().body)
+    }
+
+    /// Take the wrapped body from the message this one replaces, as long as
+    /// it still says the same thing, so rebuilding the list doesn't mean
+    /// wrapping everything again.
+    pub fn keep_layout(&self, old: &Message) {
+        let Some(layout) = old.layout.borrow_mut().take() else {
+            return;
         };
 
-        height += 2;
+        if layout.revision == old.revision && layout.text == self.display() {
+            *self.layout.borrow_mut() = Some(Layout {
+                revision: self.revision,
+                height: None,
+                lines: None,
+                ..layout
+            });
+        }
+    }
+
+    /// Is there more to this message than fits?
+    pub fn overflows(&self, width: usize) -> bool {
+        self.body_lines(width).len() > self.line_limit()
+            || self.reactions.len() > self.reaction_limit()
+    }
 
-        if !self.receipts.is_empty() {
-            height += 1;
+    // this needs to match up exactly with to_list_items
+    pub fn height(&self, width: usize) -> usize {
+        self.refresh_layout(width);
+
+        if let Some(height) = self.layout.borrow().as_ref().and_then(|l| l.height) {
+            return height;
+        }
+
+        let height = self.count_lines(width);
+
+        if let Some(layout) = self.layout.borrow_mut().as_mut() {
+            layout.height = Some(height);
         }
 
-        height += self.reactions.len();
-        self.last_height.set(LastHeight { width, height });
         height
     }
 
-    // Indent 2 chars.
-    fn indent(lines: &mut [Vec<Span>], first: bool) {
-        let first_pipe = if first { "╷" } else { "│" };
+    fn count_lines(&self, width: usize) -> usize {
+        let mut height = self.body_lines(width).len().min(self.line_limit()) + self.header_height();
 
-        for (index, line) in lines.iter_mut().enumerate() {
-            let pipe = if index == 0 { first_pipe } else { "│ " };
+        if self.in_reply_to.is_some() {
+            height += 1;
+        }
 
-            line.insert(0, Span::styled(pipe, Style::default().fg(Color::Magenta)));
+        if self.gap.is_some() {
+            height += 1;
         }
-    }
 
-    pub fn flatten(&self) -> Vec<&Message> {
-        let mut messages = vec![self];
+        if self.overflows(width) {
+            height += 1;
+        }
 
-        for r in &self.replies {
-            messages.append(&mut r.flatten());
+        if !self.receipts.is_empty() {
+            height += 1;
         }
 
-        messages
+        height + self.reactions.len().min(self.reaction_limit())
     }
 
-    pub fn to_list_items(&self, width: usize) -> Vec<ListItem> {
-        let items: Vec<ratatui::text::Text> = self
-            .to_list_items_internal(&self.display(), width)
+    pub fn to_list_items(&self, width: usize) -> Vec<ListItem<'static>> {
+        let minute = locale::clock_minute();
+        self.refresh_layout(width);
+
+        if let Some((m, items)) = self.layout.borrow().as_ref().and_then(|l| l.lines.as_ref()) {
+            if *m == minute {
+                return items.clone();
+            }
+        }
+
+        let items: Vec<ListItem<'static>> = self
+            .to_lines(width)
             .into_iter()
-            .map(|spans| ratatui::text::Text::from(Line::from(spans)))
+            .rev()
+            .map(|spans| ListItem::new(ratatui::text::Text::from(Line::from(spans))))
             .collect();
 
-        items.into_iter().rev().map(ListItem::new).collect()
+        if let Some(layout) = self.layout.borrow_mut().as_mut() {
+            layout.lines = Some((minute, items.clone()));
+        }
+
+        items
     }
 
-    fn to_list_items_internal(&self, body: &str, width: usize) -> Vec<Vec<Span>> {
+    fn to_lines(&self, width: usize) -> Vec<Vec<Span<'static>>> {
         let mut lines = vec![];
 
+        if let Some(loading) = self.gap {
+            let marker = if loading {
+                "⋯ loading missing messages ⋯"
+            } else {
+                "⋯ messages missing here, press m to load them ⋯"
+            };
+
+            lines.push(vec![Span::styled(
+                marker,
+                Style::default().fg(Color::Yellow),
+            )]);
+        }
+
         // start with some negative space
-        lines.push(vec![Span::from(" ")]);
+        if self.spaced() {
+            lines.push(vec![Span::from(" ")]);
+        }
 
-        // author
-        let mut spans = vec![
-            Span::styled(self.sender.as_str(), Style::default().fg(Color::Green)),
-            Span::from(" "),
-            Span::styled(self.pretty_elapsed(), Style::default().fg(Color::DarkGray)),
-        ];
+        if !self.grouped {
+            // author
+            let mut spans = vec![
+                Span::styled(
+                    self.sender.as_str().to_string(),
+                    Style::default().fg(Color::Green),
+                ),
+                Span::from(" "),
+            ];
 
-        if !self.history.is_empty() {
-            spans.push(Span::styled(" (edited)", Style::default().fg(Color::Red)))
+            if let Some(trust) = self.trust {
+                spans.push(Span::styled("⛨ ", Style::default().fg(trust.color())));
+            }
+
+            if let Some(bridge) = &self.bridge {
+                spans.push(Span::styled(
+                    format!("[{}] ", bridge),
+                    Style::default().fg(Color::Cyan),
+                ));
+            }
+
+            spans.push(Span::styled(
+                self.pretty_elapsed(),
+                Style::default().fg(Color::DarkGray),
+            ));
+
+            if !self.history.is_empty() {
+                spans.push(Span::styled(" (edited)", Style::default().fg(Color::Red)))
+            }
+
+            if self.highlight {
+                spans.push(Span::styled(" @room", Style::default().fg(Color::Yellow)))
+            }
+
+            lines.push(spans);
         }
 
-        lines.push(spans);
+        // what we're replying to, if anything
+        if self.in_reply_to.is_some() {
+            let line = match &self.quote {
+                Some(quote) => quote.line(width),
+                None => "↪ …".to_string(),
+            };
 
-        // the actual message
-        let wrapped = textwrap::wrap(body, width);
-        let message_overlap = wrapped.len() > 10;
+            lines.push(vec![Span::styled(
+                line,
+                Style::default().fg(Color::Magenta),
+            )]);
+        }
 
-        for l in wrapped.into_iter().take(10) {
-            lines.push(vec![Span::styled(l.trim().to_string(), self.style())]);
+        // the actual message
+        for l in self.body_lines(width).iter().take(self.line_limit()) {
+            lines.push(vec![Span::styled(l.clone(), self.style())]);
         }
 
         // overflow warning
-        if message_overlap || self.reactions.len() > 5 {
+        if self.overflows(width) {
             lines.push(vec![Span::styled(
-                "* overflow: type \"v\" to view entire message",
+                "* overflow: type \"x\" to expand, or \"v\" to view entire message",
                 Style::default().fg(Color::Red),
             )])
         }
 
         // receipts
         if !self.receipts.is_empty() {
-            let iter = self
-                .receipts
-                .iter()
-                .map(Username::to_string)
-                .map(|n| n.split_whitespace().next().unwrap().to_string());
-
-            lines.push(vec![Span::styled(
-                format!(
-                    "Seen by {}.",
-                    pretty_list(limit_list(iter, 4, self.receipts.len(), None))
-                ),
-                Style::default().fg(Color::DarkGray),
-            )])
+            if text_receipts() {
+                let iter = self
+                    .receipts
+                    .iter()
+                    .map(|r| r.user.to_string())
+                    .map(|n| n.split_whitespace().next().unwrap().to_string());
+
+                lines.push(vec![Span::styled(
+                    tr(
+                        "seen_by",
+                        &pretty_list(limit_list(iter, 4, self.receipts.len(), None)),
+                    ),
+                    Style::default().fg(Color::DarkGray),
+                )])
+            } else {
+                lines.push(receipt_row(&self.receipts, width));
+            }
         }
 
         // reactions
-        for r in self.reactions.iter().take(5) {
+        for r in self.reactions.iter().take(self.reaction_limit()) {
             lines.push(vec![Span::styled(
-                r.list_view(),
+                r.list_view().to_string(),
                 Style::default().fg(Color::DarkGray),
             )])
         }
 
-        // replies
-        for (i, r) in self.replies.iter().enumerate() {
-            let reply = r.display();
-            let body = Message::remove_reply_header(&reply);
-            let mut reply_lines = r.to_list_items_internal(body, width - 2);
-            Message::indent(&mut reply_lines, i == 0);
-            lines.append(&mut reply_lines);
+        lines
+    }
+}
+
+// The caption goes on its own line, under what the file is.
+fn with_caption(line: String, caption: Option<&str>) -> String {
+    match caption {
+        Some(caption) => format!("{}\n{}", line, caption),
+        None => line,
+    }
+}
+
+// A colored initial for each reader, pushed over to the right.
+fn receipt_row(receipts: &[ReadReceipt], width: usize) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span> = receipts
+        .iter()
+        .take(MAX_RECEIPTS)
+        .map(|r| {
+            Span::styled(
+                format!(" {}", r.user.initial()),
+                Style::default()
+                    .fg(r.user.color())
+                    .add_modifier(Modifier::BOLD),
+            )
+        })
+        .collect();
+
+    if receipts.len() > MAX_RECEIPTS {
+        spans.push(Span::styled(
+            format!(" +{}", receipts.len() - MAX_RECEIPTS),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+
+    let used: usize = spans.iter().map(|s| s.width()).sum();
+    spans.insert(0, Span::from(" ".repeat(width.saturating_sub(used))));
+
+    spans
+}
+
+/// Does the text call out everyone in the room?
+pub fn mentions_room(body: &str) -> bool {
+    body.split(|c: char| c.is_whitespace() || ",.:;!?()\"'".contains(c))
+        .any(|word| word == "@room")
+}
+
+/// How far to trust an encrypted message, going by the device that sent it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trust {
+    // from a device we've verified
+    Verified,
+    // from someone we haven't verified
+    Unverified,
+    // from a device nobody has signed, or one we can't find at all
+    Unknown,
+}
+
+impl Trust {
+    pub fn from_state(state: &VerificationState) -> Self {
+        match state {
+            VerificationState::Verified => Trust::Verified,
+            VerificationState::Unverified(VerificationLevel::UnverifiedIdentity) => {
+                Trust::Unverified
+            }
+            VerificationState::Unverified(_) => Trust::Unknown,
         }
+    }
 
-        lines
+    fn color(&self) -> Color {
+        match self {
+            Trust::Verified => Color::Green,
+            Trust::Unverified => Color::DarkGray,
+            Trust::Unknown => Color::Red,
+        }
     }
 }
 
@@ -600,6 +1003,14 @@ impl Reaction {
     }
 
     pub fn display(&self) -> String {
+        // we can't draw the image, but we can say what it's called
+        if self.body.starts_with("mxc://") {
+            return match emotes::shortcode(&self.body) {
+                Some(shortcode) => format!(":{}:", shortcode),
+                None => "(custom emoji)".to_string(),
+            };
+        }
+
         if let Some(emoji) = emojis::get(&self.body) {
             if let Some(shortcode) = emoji.shortcode() {
                 format!("{} ({})", pad_emoji(&self.body), shortcode)
@@ -648,23 +1059,299 @@ impl ReactionEvent {
 
 #[cfg(test)]
 mod tests {
-    use crate::widgets::message::Message;
+    use super::*;
+    use matrix_sdk::ruma::exports::serde_json;
+    use ruma::UInt;
+
+    fn text_event(id: &str, ts: u64) -> AnyTimelineEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "m.room.message",
+            "event_id": id,
+            "room_id": "!room:example.org",
+            "sender": "@alice:example.org",
+            "origin_server_ts": ts,
+            "content": { "msgtype": "m.text", "body": "hi" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_server_notice() {
+        let event: AnyTimelineEvent = serde_json::from_value(serde_json::json!({
+            "type": "m.room.message",
+            "event_id": "$1",
+            "room_id": "!notices:example.org",
+            "sender": "@notices:example.org",
+            "origin_server_ts": 10,
+            "content": {
+                "msgtype": "m.server_notice",
+                "body": "You're over your monthly active user limit.",
+                "server_notice_type": "m.server_notice.usage_limit_reached",
+                "admin_contact": "mailto:admin@example.org",
+                "limit_type": "monthly_active_user",
+            },
+        }))
+        .unwrap();
+
+        let message = Message::try_from(&event, true).unwrap();
+
+        assert_eq!(
+            message.display(),
+            "You're over your monthly active user limit.\n\nContact: mailto:admin@example.org"
+        );
+        assert_eq!(message.style().fg, Some(Color::LightRed));
+    }
 
     #[test]
-    fn remove_matrix_headers() {
-        let msg = Message::remove_reply_header("> this is a header\n\nAnd this is a message.");
-        assert_eq!(msg, "And this is a message.");
+    fn test_caption() {
+        let image = |body: &str, filename: Option<&str>| -> AnyTimelineEvent {
+            serde_json::from_value(serde_json::json!({
+                "type": "m.room.message",
+                "event_id": "$1",
+                "room_id": "!room:example.org",
+                "sender": "@alice:example.org",
+                "origin_server_ts": 10,
+                "content": {
+                    "msgtype": "m.image",
+                    "body": body,
+                    "filename": filename,
+                    "url": "mxc://example.org/abc",
+                    "info": { "size": 2048 },
+                },
+            }))
+            .unwrap()
+        };
 
-        let msg = Message::remove_reply_header("> this is a header\n\n");
-        assert_eq!(msg, "");
+        let plain = Message::try_from(&image("cat.jpg", None), true).unwrap();
+        assert_eq!(plain.display(), "Image: cat.jpg (2 KiB)");
 
-        let msg = Message::remove_reply_header("> this is a header\n");
-        assert_eq!(msg, "");
+        // the same name twice isn't a caption
+        let same = Message::try_from(&image("cat.jpg", Some("cat.jpg")), true).unwrap();
+        assert_eq!(same.display(), "Image: cat.jpg (2 KiB)");
+
+        let captioned = Message::try_from(&image("My cat.", Some("cat.jpg")), true).unwrap();
+        assert_eq!(captioned.display(), "Image: cat.jpg (2 KiB)\nMy cat.");
+    }
+
+    #[test]
+    fn test_apply_receipts() {
+        let mut messages: Vec<Message> = [("$1", 10), ("$2", 20)]
+            .iter()
+            .filter_map(|(id, ts)| Message::try_from(&text_event(id, *ts), true))
+            .collect();
 
-        let msg = Message::remove_reply_header("> this is a header");
-        assert_eq!(msg, "");
+        let bob = OwnedUserId::try_from("@bob:example.org").unwrap();
+        let carol = OwnedUserId::try_from("@carol:example.org").unwrap();
+        let dave = OwnedUserId::try_from("@dave:example.org").unwrap();
+        let ts = |t: u32| MilliSecondsSinceUnixEpoch(UInt::from(t));
+        let (t12, t15, t25) = (ts(12), ts(15), ts(25));
 
-        let msg = Message::remove_reply_header("message");
-        assert_eq!(msg, "message");
+        let mut heap = BinaryHeap::from(vec![
+            Receipt {
+                timestamp: &t15,
+                user_id: &bob,
+            },
+            Receipt {
+                timestamp: &t12,
+                user_id: &carol,
+            },
+            Receipt {
+                timestamp: &t25,
+                user_id: &dave,
+            },
+        ]);
+
+        Message::apply_receipts(&mut messages, &mut heap);
+
+        // carol got there first
+        let readers: Vec<&str> = messages[0]
+            .receipts
+            .iter()
+            .map(|r| r.user.id.as_str())
+            .collect();
+
+        assert_eq!(readers, vec!["@carol:example.org", "@bob:example.org"]);
+        assert_eq!(messages[1].receipts.len(), 1);
+        assert_eq!(messages[1].receipts[0].timestamp, t25);
+    }
+
+    #[test]
+    fn test_group() {
+        let mut messages: Vec<Message> = [("$1", 0), ("$2", 60_000), ("$3", 1_200_000)]
+            .iter()
+            .filter_map(|(id, ts)| Message::try_from(&text_event(id, *ts), true))
+            .rev()
+            .collect();
+
+        let heights: Vec<usize> = messages.iter().map(|m| m.height(80)).collect();
+        Message::group(&mut messages);
+
+        // only the one straight after gets folded in
+        let grouped: Vec<bool> = messages.iter().map(|m| m.grouped()).collect();
+        assert_eq!(grouped, vec![false, true, false]);
+        assert_eq!(messages[1].height(80), heights[1] - 2);
+        assert_eq!(messages[1].to_list_items(80).len(), messages[1].height(80));
+    }
+
+    #[test]
+    fn test_trust() {
+        use matrix_sdk::deserialized_responses::DeviceLinkProblem;
+
+        let trust = |state| Trust::from_state(&state);
+
+        assert_eq!(trust(VerificationState::Verified), Trust::Verified);
+        assert_eq!(
+            trust(VerificationState::Unverified(
+                VerificationLevel::UnverifiedIdentity
+            )),
+            Trust::Unverified
+        );
+        assert_eq!(
+            trust(VerificationState::Unverified(
+                VerificationLevel::UnsignedDevice
+            )),
+            Trust::Unknown
+        );
+        assert_eq!(
+            trust(VerificationState::Unverified(VerificationLevel::None(
+                DeviceLinkProblem::MissingDevice
+            ))),
+            Trust::Unknown
+        );
+
+        // a different device means a new header
+        let mut messages: Vec<Message> = [("$1", 0), ("$2", 60_000)]
+            .iter()
+            .filter_map(|(id, ts)| Message::try_from(&text_event(id, *ts), true))
+            .rev()
+            .collect();
+
+        messages[0].set_trust(Some(Trust::Unknown));
+        messages[1].set_trust(Some(Trust::Verified));
+        Message::group(&mut messages);
+        assert!(!messages[0].grouped());
+    }
+
+    #[test]
+    fn test_gap() {
+        let mut messages: Vec<Message> = [("$1", 0), ("$2", 60_000)]
+            .iter()
+            .filter_map(|(id, ts)| Message::try_from(&text_event(id, *ts), true))
+            .rev()
+            .collect();
+
+        let height = messages[0].height(80);
+        messages[0].set_gap(Some(false));
+        Message::group(&mut messages);
+
+        // the marker keeps the header, so it's clear where the gap is
+        assert!(!messages[0].grouped());
+        assert_eq!(messages[0].height(80), height + 1);
+        assert_eq!(messages[0].to_list_items(80).len(), messages[0].height(80));
+    }
+
+    #[test]
+    fn test_expand() {
+        let event: AnyTimelineEvent = serde_json::from_value(serde_json::json!({
+            "type": "m.room.message",
+            "event_id": "$long",
+            "room_id": "!room:example.org",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 0,
+            "content": { "msgtype": "m.text", "body": "line\n".repeat(20) },
+        }))
+        .unwrap();
+
+        let mut message = Message::try_from(&event, true).unwrap();
+        message.set_max_lines(4);
+
+        // header, four lines, and the overflow warning
+        assert!(message.overflows(80));
+        assert_eq!(message.height(80), 7);
+
+        message.set_expanded(true);
+        assert!(!message.overflows(80));
+        assert_eq!(message.height(80), 22);
+    }
+
+    #[test]
+    fn test_layout_cache() {
+        let mut message = Message::try_from(&text_event("$a", 0), true).unwrap();
+        assert_eq!(message.height(80), 3);
+        assert_eq!(message.to_list_items(80).len(), 3);
+
+        // the spacer goes, and the cache has to notice
+        message.set_compact(true);
+        assert_eq!(message.height(80), 2);
+        assert_eq!(message.to_list_items(80).len(), 2);
+    }
+
+    #[test]
+    fn test_keep_layout() {
+        let old = Message::try_from(&text_event("$a", 0), true).unwrap();
+        old.height(80);
+
+        let message = Message::try_from(&text_event("$a", 0), true).unwrap();
+        message.keep_layout(&old);
+        assert!(message.layout.borrow().is_some());
+
+        // anything that changed since it was wrapped means starting over
+        let mut old = Message::try_from(&text_event("$a", 0), true).unwrap();
+        old.height(80);
+        old.edit(Text(TextMessageEventContent::plain("bye")));
+
+        let message = Message::try_from(&text_event("$a", 0), true).unwrap();
+        message.keep_layout(&old);
+        assert!(message.layout.borrow().is_none());
+    }
+
+    #[test]
+    fn test_receipt_row() {
+        let receipts: Vec<ReadReceipt> = (0..10)
+            .map(|i| ReadReceipt {
+                user: Username::new(
+                    OwnedUserId::try_from(format!("@user{}:example.org", i)).unwrap(),
+                ),
+                timestamp: MilliSecondsSinceUnixEpoch(UInt::from(i as u32)),
+            })
+            .collect();
+
+        let row = receipt_row(&receipts[..2], 20);
+        let text: String = row.iter().map(|s| s.content.to_string()).collect();
+        assert_eq!(text, "                 U U");
+
+        let row = receipt_row(&receipts, 30);
+        let text: String = row.iter().map(|s| s.content.to_string()).collect();
+        assert_eq!(text.chars().count(), 30);
+        assert!(text.ends_with(" U +2"));
+    }
+
+    #[test]
+    fn test_mentions_room() {
+        assert!(mentions_room("@room"));
+        assert!(mentions_room("hey @room, lunch is here!"));
+        assert!(mentions_room("lunch (@room)"));
+        assert!(!mentions_room("@roommates"));
+        assert!(!mentions_room("me@room.org"));
+        assert!(!mentions_room("the room"));
+    }
+
+    #[test]
+    fn test_quote_line() {
+        let quote = Quote::new(
+            OwnedUserId::try_from("@alice:example.org").unwrap(),
+            "hello\nthere, how are you doing?",
+        );
+
+        assert_eq!(
+            quote.line(80),
+            "↪ @alice:example.org: hello there, how are you doing?"
+        );
+        assert_eq!(quote.line(26), "↪ @alice:example.org: hel…");
+
+        assert_eq!(
+            Quote::unavailable().line(80),
+            "↪ original message unavailable"
+        );
     }
 }
@@ -1,11 +1,28 @@
 use crate::app::App;
 use crate::widgets::EventResult::Ignored;
+use std::cell::Cell;
 
+pub mod access;
+pub mod away;
+pub mod devices;
 pub mod error;
+pub mod export;
+pub mod help;
+pub mod knock;
+pub mod knocks;
+pub mod logs;
+pub mod pager;
+pub mod pickfile;
+pub mod profile;
 pub mod progress;
+pub mod register;
+pub mod rename;
+pub mod roominfo;
 pub mod rooms;
+pub mod seen;
 pub mod signin;
-pub mod help;
+pub mod upload;
+pub mod verify;
 
 pub mod button;
 pub mod chat;
@@ -13,6 +30,8 @@ pub mod confirm;
 pub mod message;
 pub mod react;
 pub mod receipts;
+pub mod scrollbar;
+pub mod state;
 pub mod textinput;
 
 #[macro_export]
@@ -77,6 +96,21 @@ fn focus_prev<'a>(mut elements: Vec<Box<dyn Focusable + 'a>>) -> EventResult {
     focus_next(elements)
 }
 
+thread_local! {
+    // where the terminal's own cursor should go, if anywhere
+    static CURSOR: Cell<Option<(u16, u16)>> = const { Cell::new(None) };
+}
+
+/// Widgets only get a buffer, so a focused input leaves its cursor here for
+/// the frame to pick up once everything is drawn.
+pub fn set_cursor(x: u16, y: u16) {
+    CURSOR.with(|c| c.set(Some((x, y))));
+}
+
+pub fn take_cursor() -> Option<(u16, u16)> {
+    CURSOR.with(|c| c.take())
+}
+
 fn get_margin(available: u16, requested: u16) -> u16 {
     if requested >= available {
         0
@@ -84,3 +118,20 @@ fn get_margin(available: u16, requested: u16) -> u16 {
         (available - requested) / 2
     }
 }
+
+/// Below this many columns, there's no room to spare for headers, margins,
+/// or padding, like in a phone's SSH client or the corner of a tiling WM.
+pub const NARROW: u16 = 40;
+
+pub fn is_narrow(width: u16) -> bool {
+    width < NARROW
+}
+
+// Padding inside a popup, down to a single column when things are tight.
+fn get_padding(available: u16, requested: u16) -> u16 {
+    if is_narrow(available) {
+        requested.min(1)
+    } else {
+        requested
+    }
+}
@@ -0,0 +1,338 @@
+use std::cell::{Cell, RefCell};
+
+use crossterm::event::{KeyCode, KeyEvent};
+use log::error;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget};
+
+use crate::app::App;
+use crate::event::Event;
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// A scrollable, searchable look at some text, without leaving the app.
+pub struct Pager {
+    title: String,
+    text: String,
+    top: usize,
+
+    // the text wrapped to the width we last drew at, and how much fit
+    lines: RefCell<Vec<String>>,
+    width: Cell<usize>,
+    height: Cell<usize>,
+
+    // the first half of gg
+    pending_g: bool,
+
+    // what's being typed after a /, the last thing searched for, and the
+    // line it was last found on
+    typing: Option<String>,
+    query: String,
+    found: Cell<Option<usize>>,
+    status: String,
+}
+
+impl Pager {
+    pub fn new(title: String, text: String) -> Self {
+        Self {
+            title,
+            text,
+            top: 0,
+            lines: RefCell::new(vec![]),
+            width: Cell::new(0),
+            height: Cell::new(0),
+            pending_g: false,
+            typing: None,
+            query: String::new(),
+            found: Cell::new(None),
+            status: String::new(),
+        }
+    }
+
+    pub fn widget(&self) -> PagerWidget {
+        PagerWidget { pager: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if let Some(typing) = &mut self.typing {
+            match input.code {
+                KeyCode::Char(c) => typing.push(c),
+                KeyCode::Backspace => {
+                    typing.pop();
+                }
+                KeyCode::Enter => {
+                    self.query = self.typing.take().unwrap_or_default();
+                    self.found.set(None);
+                    self.search(true);
+                }
+                KeyCode::Esc => self.typing = None,
+                _ => {}
+            }
+
+            return consumed!();
+        }
+
+        let pending_g = std::mem::take(&mut self.pending_g);
+        let page = self.height.get().max(1);
+
+        match input.code {
+            KeyCode::Esc | KeyCode::Char('q') => return close!(),
+            KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => self.scroll_to(self.top + 1),
+            KeyCode::Char('k') | KeyCode::Up => self.scroll_to(self.top.saturating_sub(1)),
+            KeyCode::Char(' ') | KeyCode::PageDown => self.scroll_to(self.top + page),
+            KeyCode::Char('b') | KeyCode::PageUp => self.scroll_to(self.top.saturating_sub(page)),
+            KeyCode::Char('g') if pending_g => self.scroll_to(0),
+            KeyCode::Char('g') => self.pending_g = true,
+            KeyCode::Home => self.scroll_to(0),
+            KeyCode::Char('G') | KeyCode::End => self.scroll_to(usize::MAX),
+            KeyCode::Char('/') => self.typing = Some(String::new()),
+            KeyCode::Char('n') => self.search(true),
+            KeyCode::Char('N') => self.search(false),
+            KeyCode::Char('e') => {
+                let text = self.text.clone();
+
+                return EventResult::Consumed(Box::new(|app| {
+                    app.close_popup();
+
+                    if let Err(e) = App::get_sender().send(Event::View(text)) {
+                        error!("could not open the editor: {}", e);
+                    }
+                }));
+            }
+            _ => {}
+        }
+
+        consumed!()
+    }
+
+    fn scroll_to(&mut self, top: usize) {
+        let max = self.lines.borrow().len().saturating_sub(self.height.get());
+
+        self.top = top.min(max);
+    }
+
+    // Look from the line after the last match (or before, going back), if
+    // it's still on screen, or from the top of the screen if not. The top
+    // can't be used for both, since it stops short of a match on the last page.
+    fn search(&mut self, forward: bool) {
+        if self.query.is_empty() {
+            return;
+        }
+
+        let len = self.lines.borrow().len();
+        let visible = self.top..self.top + self.height.get();
+
+        let from = match self.found.get() {
+            Some(i) if visible.contains(&i) && forward => (i + 1) % len.max(1),
+            Some(i) if visible.contains(&i) => (i + len - 1) % len.max(1),
+            _ => self.top,
+        };
+
+        let found = find(&self.lines.borrow(), &self.query, from, forward);
+        self.found.set(found);
+
+        match found {
+            Some(i) => {
+                self.status = String::new();
+                self.scroll_to(i);
+            }
+            None => self.status = format!("Not found: {}", self.query),
+        }
+    }
+
+    fn wrap(&self, width: usize) {
+        if width == self.width.get() {
+            return;
+        }
+
+        let lines = self
+            .text
+            .lines()
+            .flat_map(|line| {
+                if line.is_empty() {
+                    vec![String::new()]
+                } else {
+                    textwrap::wrap(line, width)
+                        .into_iter()
+                        .map(|l| l.to_string())
+                        .collect()
+                }
+            })
+            .collect();
+
+        // the old line numbers mean nothing now
+        self.width.set(width);
+        self.lines.replace(lines);
+        self.found.set(None);
+    }
+}
+
+// The next line with the query in it, going around the end if we have to.
+fn find(lines: &[String], query: &str, from: usize, forward: bool) -> Option<usize> {
+    let query = query.to_lowercase();
+    let len = lines.len();
+
+    if len == 0 {
+        return None;
+    }
+
+    let from = from.min(len - 1);
+
+    (0..len)
+        .map(|i| {
+            if forward {
+                (from + i) % len
+            } else {
+                (from + len - i) % len
+            }
+        })
+        .find(|i| lines[*i].to_lowercase().contains(&query))
+}
+
+fn highlight<'a>(line: &'a str, query: &str) -> Line<'a> {
+    let lowered = line.to_lowercase();
+
+    // lowercasing can change byte lengths, and then the indices are no good
+    if query.is_empty() || lowered.len() != line.len() {
+        return Line::from(line);
+    }
+
+    let query = query.to_lowercase();
+    let mut spans = vec![];
+    let mut last = 0;
+
+    for (start, _) in lowered.match_indices(&query) {
+        spans.push(Span::raw(&line[last..start]));
+        spans.push(Span::styled(
+            &line[start..start + query.len()],
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ));
+        last = start + query.len();
+    }
+
+    spans.push(Span::raw(&line[last..]));
+
+    Line::from(spans)
+}
+
+pub struct PagerWidget<'a> {
+    pager: &'a Pager,
+}
+
+impl Widget for PagerWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 100))
+            .vertical_margin(get_margin(area.height, 50))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title(self.pager.title.clone())
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .split(inner);
+
+        self.pager.wrap(splits[0].width as usize);
+        self.pager.height.set(splits[0].height as usize);
+
+        let lines = self.pager.lines.borrow();
+        let start = self.pager.top.min(lines.len());
+        let end = (start + splits[0].height as usize).min(lines.len());
+
+        let text: Vec<Line> = lines[start..end]
+            .iter()
+            .map(|l| highlight(l, &self.pager.query))
+            .collect();
+
+        Paragraph::new(Text::from(text)).render(splits[0], buf);
+
+        let footer = match &self.pager.typing {
+            Some(typing) => Span::raw(format!("/{}█", typing)),
+            None if !self.pager.status.is_empty() => Span::styled(
+                self.pager.status.clone(),
+                Style::default().fg(Color::Yellow),
+            ),
+            None => Span::styled(
+                format!(
+                    "{}/{}  / to search, e to open in your editor, q to close",
+                    end,
+                    lines.len()
+                ),
+                Style::default().fg(Color::DarkGray),
+            ),
+        };
+
+        Paragraph::new(footer).render(splits[1], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let lines: Vec<String> = ["one", "Two", "three", "two again"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(find(&lines, "two", 0, true), Some(1));
+        assert_eq!(find(&lines, "two", 2, true), Some(3));
+        assert_eq!(find(&lines, "two", 3, true), Some(3));
+        assert_eq!(find(&lines, "one", 1, true), Some(0));
+        assert_eq!(find(&lines, "two", 2, false), Some(1));
+        assert_eq!(find(&lines, "four", 0, true), None);
+    }
+
+    #[test]
+    fn test_search_last_page() {
+        let text = (0..10)
+            .map(|i| format!("line {}", if i >= 7 { "match" } else { "plain" }))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut pager = Pager::new("Test".to_string(), text);
+        pager.wrap(80);
+        pager.height.set(5);
+        pager.query = "match".to_string();
+
+        // the first match is already on the last page, so the top stays put
+        pager.search(true);
+        assert_eq!((pager.found.get(), pager.top), (Some(7), 5));
+
+        pager.search(true);
+        assert_eq!(pager.found.get(), Some(8));
+
+        pager.search(true);
+        assert_eq!(pager.found.get(), Some(9));
+
+        // and around to the start
+        pager.search(true);
+        assert_eq!(pager.found.get(), Some(7));
+
+        pager.search(false);
+        assert_eq!(pager.found.get(), Some(9));
+    }
+}
@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::room::Room;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+
+use crate::app::Popup;
+use crate::widgets::profile::confirm_avatar;
+use crate::widgets::textinput::TextInput;
+use crate::widgets::upload::Upload;
+use crate::widgets::EventResult::Consumed;
+use crate::widgets::{get_margin, get_padding, EventResult};
+use crate::{close, consumed};
+
+/// What the picked file is for.
+pub enum PickFor {
+    /// Uploading to a room, by name.
+    Upload(Room, String),
+    /// My new avatar.
+    Avatar,
+}
+
+/// Type out the path to a file, for when there's no file dialog to be had.
+/// Tab completes against the filesystem.
+pub struct PickFile {
+    target: PickFor,
+    path: TextInput,
+    hint: String,
+}
+
+impl PickFile {
+    pub fn new(room: Room, room_name: String) -> Self {
+        PickFile::with_target(PickFor::Upload(room, room_name))
+    }
+
+    pub fn avatar() -> Self {
+        PickFile::with_target(PickFor::Avatar)
+    }
+
+    fn with_target(target: PickFor) -> Self {
+        Self {
+            target,
+            path: TextInput::new("Path".to_string(), true, false).with_value("~/"),
+            hint: String::new(),
+        }
+    }
+
+    pub fn widget(&self) -> PickFileWidget {
+        PickFileWidget { pick: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        self.path.paste(text)
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        match input.code {
+            KeyCode::Esc => close!(),
+            KeyCode::Tab => {
+                let (completed, candidates) = complete(&self.path.value());
+
+                self.path = TextInput::new("Path".to_string(), true, false).with_value(&completed);
+                self.hint = candidates.join("  ");
+
+                consumed!()
+            }
+            KeyCode::Enter => {
+                let path = expand(&self.path.value());
+
+                if !path.is_file() {
+                    self.hint = "Not a file.".to_string();
+                    return consumed!();
+                }
+
+                match &self.target {
+                    PickFor::Upload(room, room_name) => {
+                        let upload = Upload::new(room.clone(), room_name.clone(), vec![path]);
+
+                        Consumed(Box::new(|app| app.replace_popup(Popup::Upload(upload))))
+                    }
+                    PickFor::Avatar => {
+                        Consumed(Box::new(|app| app.replace_popup(confirm_avatar(path))))
+                    }
+                }
+            }
+            _ => self.path.key_event(input),
+        }
+    }
+}
+
+fn expand(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+// Complete as far as everything that matches agrees, and return what matched
+// if that's more than one thing. Directories get a trailing slash.
+fn complete(input: &str) -> (String, Vec<String>) {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    };
+
+    let lookup = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand(dir)
+    };
+
+    let Ok(entries) = fs::read_dir(lookup) else {
+        return (input.to_string(), vec![]);
+    };
+
+    let mut matches: Vec<String> = entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+
+            // hidden files only if asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            if e.path().is_dir() {
+                Some(format!("{}/", name))
+            } else {
+                Some(name)
+            }
+        })
+        .collect();
+
+    matches.sort();
+
+    let Some(first) = matches.first() else {
+        return (input.to_string(), vec![]);
+    };
+
+    let common = matches.iter().fold(first.clone(), |common, m| {
+        common
+            .chars()
+            .zip(m.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect()
+    });
+
+    let completed = format!("{}{}", dir, common);
+
+    if matches.len() == 1 {
+        (completed, vec![])
+    } else {
+        (completed, matches)
+    }
+}
+
+pub struct PickFileWidget<'a> {
+    pick: &'a PickFile,
+}
+
+impl Widget for PickFileWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 80))
+            .vertical_margin(get_margin(area.height, 10))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let title = match &self.pick.target {
+            PickFor::Upload(_, room_name) => format!("Upload to {}", room_name),
+            PickFor::Avatar => "New Avatar".to_string(),
+        };
+
+        let block = Block::default()
+            .title(title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(get_padding(area.width, 2))
+            .vertical_margin(1)
+            .constraints([Constraint::Length(3), Constraint::Min(1)].as_ref())
+            .split(area);
+
+        self.pick.path.widget().render(splits[0], buf);
+
+        let hint = if self.pick.hint.is_empty() {
+            "Tab to complete, Enter to pick, Esc to cancel"
+        } else {
+            &self.pick.hint
+        };
+
+        Paragraph::new(Span::styled(hint, Style::default().fg(Color::DarkGray)))
+            .wrap(Wrap { trim: true })
+            .render(splits[1], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("alpha.txt"), "").unwrap();
+        fs::write(dir.path().join("beta.txt"), "").unwrap();
+        fs::write(dir.path().join(".hidden"), "").unwrap();
+        fs::create_dir(dir.path().join("alpine")).unwrap();
+
+        let root = format!("{}/", dir.path().display());
+
+        let (completed, candidates) = complete(&format!("{}al", root));
+        assert_eq!(completed, format!("{}alp", root));
+        assert_eq!(candidates, vec!["alpha.txt", "alpine/"]);
+
+        let (completed, candidates) = complete(&format!("{}b", root));
+        assert_eq!(completed, format!("{}beta.txt", root));
+        assert!(candidates.is_empty());
+
+        let (completed, _) = complete(&format!("{}alpi", root));
+        assert_eq!(completed, format!("{}alpine/", root));
+
+        let (_, candidates) = complete(&root);
+        assert_eq!(candidates.len(), 3);
+    }
+}
@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Cell, Paragraph, Row, Table, Widget};
+
+use crate::app::Popup;
+use crate::event::EventHandler;
+use crate::spawn::{spawn_file_picker, PickTarget};
+use crate::widgets::confirm::Confirm;
+use crate::widgets::get_margin;
+use crate::widgets::rename::{Rename, Renaming};
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// Who I am, and where.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    display_name: Option<String>,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Profile {
+    pub fn new(
+        display_name: Option<String>,
+        user_id: String,
+        homeserver: String,
+        device_id: Option<String>,
+        avatar: Option<String>,
+    ) -> Self {
+        let fields = vec![
+            ("User ID", user_id),
+            ("Homeserver", homeserver),
+            ("Device ID", device_id.unwrap_or_else(|| "none".to_string())),
+            ("Avatar", avatar.unwrap_or_else(|| "none".to_string())),
+        ];
+
+        Self {
+            display_name,
+            fields,
+        }
+    }
+
+    pub fn widget(&self) -> ProfileWidget {
+        ProfileWidget { profile: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent, handler: &EventHandler) -> EventResult {
+        match input.code {
+            KeyCode::Char('n') => {
+                let current = self.display_name.clone().unwrap_or_default();
+
+                EventResult::Consumed(Box::new(move |app| {
+                    app.replace_popup(Popup::Rename(Rename::new(Renaming::DisplayName, &current)));
+                }))
+            }
+            KeyCode::Char('a') => {
+                let parked = spawn_file_picker(handler, PickTarget::Avatar);
+
+                EventResult::Consumed(Box::new(move |app| app.editing |= parked))
+            }
+            KeyCode::Enter | KeyCode::Esc | KeyCode::Char('q') => close!(),
+            _ => consumed!(),
+        }
+    }
+}
+
+/// Make sure, before everyone sees it.
+pub fn confirm_avatar(path: PathBuf) -> Popup {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    Popup::Confirm(Confirm::new(
+        "Change Avatar".to_string(),
+        format!("Everyone, in every room, will see {} as your avatar.", name),
+        "Change".to_string(),
+        "Cancel".to_string(),
+        move |app| app.matrix.set_avatar(path),
+    ))
+}
+
+pub struct ProfileWidget<'a> {
+    profile: &'a Profile,
+}
+
+impl Widget for ProfileWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = self.profile.fields.len() as u16 + 7;
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 70))
+            .vertical_margin(get_margin(area.height, height))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title("Me")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints(
+                [
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        let name = match &self.profile.display_name {
+            Some(name) => {
+                Span::styled(name.as_str(), Style::default().add_modifier(Modifier::BOLD))
+            }
+            None => Span::styled("no display name", Style::default().fg(Color::DarkGray)),
+        };
+
+        Paragraph::new(name)
+            .alignment(Alignment::Center)
+            .render(splits[0], buf);
+
+        let rows = self.profile.fields.iter().map(|(label, value)| {
+            Row::new(vec![
+                Cell::from(Span::styled(*label, Style::default().fg(Color::DarkGray))),
+                Cell::from(value.as_str()),
+            ])
+        });
+
+        Table::new(rows)
+            .widths(&[Constraint::Length(11), Constraint::Percentage(100)])
+            .column_spacing(1)
+            .render(splits[2], buf);
+
+        Paragraph::new(Span::styled(
+            "n change name · a new avatar · q close",
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[4], buf);
+    }
+}
@@ -1,41 +1,138 @@
 use crate::matrix::matrix::center_emoji;
-use crate::settings::get_settings;
+use crate::settings::{get_settings, quick_reactions};
 use crossterm::event::{KeyCode, KeyEvent};
-use std::cell::Cell;
+use emojis::Emoji;
+use matrix_sdk::room::Room;
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
-use ratatui::text::Text;
-use ratatui::widgets::{Block, BorderType, Borders, List, ListItem, ListState, StatefulWidget, Widget};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{
+    Block, BorderType, Borders, List, ListItem, ListState, Paragraph, StatefulWidget, Widget,
+};
+use ruma::OwnedEventId;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::iter;
 
-use crate::widgets::get_margin;
+use crate::fuzzy::fuzzy_match;
+use crate::matrix::emotes::{self, Emote};
+use crate::usage::{ReactionUsage, MAX_RECENT};
+use crate::widgets::EventResult::{Consumed, Ignored};
+use crate::widgets::{get_margin, get_padding, EventResult};
+use crate::{close, consumed};
 
-pub enum ReactResult {
-    SelectReaction(String),
-    RemoveReaction(String),
-    Exit,
-    Consumed,
-    Ignored,
-}
+/// How many search results to show.
+const MAX_RESULTS: usize = 40;
 
 pub struct React {
+    // the message we're reacting to
+    room: Room,
+    event_id: OwnedEventId,
+
+    favorites: Vec<Reaction>,
     reactions: Vec<Reaction>,
-    existing: Vec<String>,
+
+    // reactions already on the message, which always come first
+    additions: Vec<String>,
+
+    // our own reactions already on the message, by their events
+    existing: HashMap<String, OwnedEventId>,
+
+    // custom emoji from image packs, offered in search
+    emotes: Vec<Emote>,
+
+    // keys that pick a reaction without moving the cursor to it
+    quick: HashMap<char, String>,
     list_state: Cell<ListState>,
+
+    // what's been typed after a /
+    query: Option<String>,
 }
 
+#[derive(Clone)]
 struct Reaction {
     emoji: String,
     description: String,
 }
 
+impl Reaction {
+    fn new(emoji: String) -> Self {
+        let description = if emoji.starts_with("mxc://") {
+            match emotes::shortcode(&emoji) {
+                Some(shortcode) => format!(" :{}:", shortcode),
+                None => " (custom emoji)".to_string(),
+            }
+        } else if let Some(e) = emojis::get(&emoji) {
+            format!(
+                "{} {}",
+                center_emoji(&emoji),
+                e.shortcode().unwrap_or(e.name())
+            )
+        } else {
+            center_emoji(&emoji)
+        };
+
+        Reaction { emoji, description }
+    }
+}
+
+impl From<&Emote> for Reaction {
+    fn from(emote: &Emote) -> Self {
+        Reaction {
+            emoji: emote.url.to_string(),
+            description: format!(" :{}: ({})", emote.shortcode, emote.pack),
+        }
+    }
+}
+
+// The best match against any of the emoji's names.
+fn score(query: &str, emoji: &Emoji) -> Option<i64> {
+    emoji
+        .shortcodes()
+        .chain(iter::once(emoji.name()))
+        .filter_map(|name| fuzzy_match(query, name))
+        .map(|m| m.score)
+        .max()
+}
+
 impl React {
-    pub fn new(additions: Vec<String>, existing: Vec<String>) -> Self {
+    pub fn new(
+        room: Room,
+        event_id: OwnedEventId,
+        additions: Vec<String>,
+        existing: HashMap<String, OwnedEventId>,
+        emotes: Vec<Emote>,
+    ) -> Self {
+        let (favorites, quick) = React::favorites(&additions);
+
+        let mut list_state = ListState::default();
+        list_state.select(Some(0_usize));
+        let list_state = Cell::new(list_state);
+
+        React {
+            room,
+            event_id,
+            reactions: favorites.clone(),
+            favorites,
+            additions,
+            existing,
+            emotes,
+            quick,
+            list_state,
+            query: None,
+        }
+    }
+
+    // what's offered before searching, from the config and what we've used,
+    // and the keys that pick them
+    fn favorites(additions: &[String]) -> (Vec<Reaction>, HashMap<char, String>) {
         let mut reactions: Vec<String> = get_settings().get("reactions").unwrap_or_default();
 
         // get rid of any dupes
         reactions.retain(|r| {
-            for ex in &additions {
+            for ex in additions {
                 if ex == r {
                     return false;
                 }
@@ -44,72 +141,189 @@ impl React {
             true
         });
 
-        let reactions = additions
+        // single keys that send a reaction right away
+        let quick: HashMap<char, String> = quick_reactions()
             .into_iter()
-            .chain(reactions)
-            .map(|emoji| {
-                let description = if let Some(e) = emojis::get(&emoji) {
-                    format!(
-                        "{} {}",
-                        center_emoji(&emoji),
-                        e.shortcode().unwrap_or(e.name())
-                    )
-                } else {
-                    center_emoji(&emoji)
-                };
+            .filter_map(|(key, emoji)| {
+                let mut chars = key.chars();
 
-                Reaction { emoji, description }
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some((c, emoji)),
+                    _ => None,
+                }
             })
             .collect();
 
-        let mut list_state = ListState::default();
-        list_state.select(Some(0_usize));
-        let list_state = Cell::new(list_state);
+        let mut quick_emojis: Vec<String> = quick.values().cloned().collect();
+        quick_emojis.sort();
+        quick_emojis.dedup();
+        quick_emojis.retain(|r| !additions.contains(r) && !reactions.contains(r));
 
-        React {
-            reactions,
-            existing,
-            list_state,
-        }
+        // then whatever we use the most, after the ones pinned in the config
+        let recent: Vec<String> = ReactionUsage::load()
+            .ranked()
+            .into_iter()
+            .filter(|r| {
+                !additions.contains(r) && !reactions.contains(r) && !quick_emojis.contains(r)
+            })
+            .take(MAX_RECENT)
+            .collect();
+
+        let favorites: Vec<Reaction> = additions
+            .iter()
+            .cloned()
+            .chain(reactions)
+            .chain(quick_emojis)
+            .chain(recent)
+            .map(Reaction::new)
+            .collect();
+
+        (favorites, quick)
+    }
+
+    /// Pick up new favorites and quick keys from the config.
+    pub fn settings_changed(&mut self) {
+        let (favorites, quick) = React::favorites(&self.additions);
+        self.favorites = favorites;
+        self.quick = quick;
+        self.search();
+    }
+
+    // Favorites that match come first, then custom emoji, then everything
+    // else, best first.
+    fn search(&mut self) {
+        let query = self.query.clone().unwrap_or_default();
+
+        self.reactions = if query.is_empty() {
+            self.favorites.clone()
+        } else {
+            let favorites: Vec<Reaction> = self
+                .favorites
+                .iter()
+                .filter(|r| {
+                    emojis::get(&r.emoji)
+                        .and_then(|e| score(&query, e))
+                        .is_some()
+                })
+                .cloned()
+                .collect();
+
+            let mut emotes: Vec<(i64, &Emote)> = self
+                .emotes
+                .iter()
+                .filter(|e| !favorites.iter().any(|f| f.emoji == e.url.as_str()))
+                .filter_map(|e| fuzzy_match(&query, &e.shortcode).map(|m| (m.score, e)))
+                .collect();
+
+            emotes.sort_by_key(|(s, _)| Reverse(*s));
+
+            let mut found: Vec<(i64, &Emoji)> = emojis::iter()
+                .filter(|e| !favorites.iter().any(|f| f.emoji == e.as_str()))
+                .filter_map(|e| score(&query, e).map(|s| (s, e)))
+                .collect();
+
+            found.sort_by_key(|(s, _)| Reverse(*s));
+
+            favorites
+                .into_iter()
+                .chain(emotes.into_iter().map(|(_, e)| Reaction::from(e)))
+                .chain(found.into_iter().map(|(_, e)| Reaction::new(e.to_string())))
+                .take(MAX_RESULTS)
+                .collect()
+        };
+
+        let mut state = self.list_state.take();
+        state.select(Some(0));
+        self.list_state.set(state);
     }
 
     pub fn widget(&self) -> ReactWidget {
         ReactWidget { parent: self }
     }
 
-    pub fn key_event(&mut self, input: &KeyEvent) -> ReactResult {
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if let Some(query) = &mut self.query {
+            match input.code {
+                KeyCode::Char(c) => query.push(c),
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Esc => self.query = None,
+                KeyCode::Up => self.previous(),
+                KeyCode::Down => self.next(),
+                KeyCode::Enter => return self.select(),
+                _ => return Ignored,
+            }
+
+            if !matches!(input.code, KeyCode::Up | KeyCode::Down) {
+                self.search();
+            }
+
+            return consumed!();
+        }
+
         match input.code {
+            KeyCode::Char('/') => {
+                self.query = Some(String::new());
+                consumed!()
+            }
             KeyCode::Char('k') | KeyCode::Up => {
                 self.previous();
-                ReactResult::Consumed
+                consumed!()
             }
             KeyCode::Char('j') | KeyCode::Down => {
                 self.next();
-                ReactResult::Consumed
+                consumed!()
             }
-            KeyCode::Esc => ReactResult::Exit,
-            KeyCode::Enter => {
-                if let Some(reaction) = self.selected_reaction() {
-                    if self.existing.contains(&reaction) {
-                        ReactResult::RemoveReaction(reaction)
-                    } else {
-                        ReactResult::SelectReaction(reaction)
-                    }
-                } else {
-                    ReactResult::Exit
-                }
-            }
-            _ => ReactResult::Ignored,
+            KeyCode::Esc => close!(),
+            KeyCode::Enter => self.select(),
+            KeyCode::Char(c) if self.quick.contains_key(&c) => self.choose(self.quick[&c].clone()),
+            _ => Ignored,
+        }
+    }
+
+    fn select(&self) -> EventResult {
+        match self.selected_reaction() {
+            Some(reaction) => self.choose(reaction),
+            None => close!(),
         }
     }
 
+    // picking one we've already reacted with takes it back
+    fn choose(&self, reaction: String) -> EventResult {
+        let room = self.room.clone();
+
+        if let Some(event_id) = self.existing.get(&reaction).cloned() {
+            return Consumed(Box::new(move |app| {
+                app.close_popup();
+                app.matrix.redact_event(room, event_id);
+            }));
+        }
+
+        let event_id = self.event_id.clone();
+
+        Consumed(Box::new(move |app| {
+            app.close_popup();
+            ReactionUsage::record(&reaction);
+            app.matrix.send_reaction(room, event_id, reaction);
+        }))
+    }
+
+    fn quick_key(&self, emoji: &str) -> Option<char> {
+        self.quick
+            .iter()
+            .filter(|(_, e)| e.as_str() == emoji)
+            .map(|(k, _)| *k)
+            .min()
+    }
+
     fn next(&mut self) {
         let mut state = self.list_state.take();
 
         let i = match state.selected() {
             Some(i) => {
-                if i >= self.reactions.len() - 1 {
-                    self.reactions.len() - 1
+                if i + 1 >= self.reactions.len() {
+                    self.reactions.len().saturating_sub(1)
                 } else {
                     i + 1
                 }
@@ -162,7 +376,7 @@ impl Widget for ReactWidget<'_> {
             .direction(Direction::Horizontal)
             .vertical_margin(get_margin(
                 area.height,
-                (self.parent.reactions.len() + 4) as u16,
+                (self.parent.reactions.len() + 6) as u16,
             ))
             .horizontal_margin(get_margin(area.width, 40))
             .constraints([Constraint::Percentage(100)].as_ref())
@@ -172,10 +386,8 @@ impl Widget for ReactWidget<'_> {
 
         let title = 'title: {
             if let Some(selected) = self.parent.selected_reaction() {
-                for ex in &self.parent.existing {
-                    if ex == &selected {
-                        break 'title "Remove Reaction";
-                    }
+                if self.parent.existing.contains_key(&selected) {
+                    break 'title "Remove Reaction";
                 }
             }
 
@@ -191,18 +403,39 @@ impl Widget for ReactWidget<'_> {
 
         block.render(area, buf);
 
-        let area = Layout::default()
-            .direction(Direction::Horizontal)
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
             .vertical_margin(2)
-            .horizontal_margin(2)
-            .constraints([Constraint::Percentage(100)].as_ref())
-            .split(area)[0];
+            .horizontal_margin(get_padding(area.width, 2))
+            .constraints([Constraint::Length(2), Constraint::Min(1)].as_ref())
+            .split(area);
+
+        let search = match &self.parent.query {
+            Some(query) => Span::raw(format!("/{}█", query)),
+            None if self.parent.emotes.is_empty() => {
+                Span::styled("/ to search", Style::default().fg(Color::DarkGray))
+            }
+            None => Span::styled(
+                format!("/ to search ({} custom)", self.parent.emotes.len()),
+                Style::default().fg(Color::DarkGray),
+            ),
+        };
+
+        Paragraph::new(search).render(splits[0], buf);
+
+        let area = splits[1];
 
         let items: Vec<ListItem> = self
             .parent
             .reactions
             .iter()
-            .map(|r| ListItem::new(Text::from(r.description.clone())))
+            .map(|r| match self.parent.quick_key(&r.emoji) {
+                Some(key) => ListItem::new(Line::from(vec![
+                    Span::raw(r.description.clone()),
+                    Span::styled(format!(" [{}]", key), Style::default().fg(Color::DarkGray)),
+                ])),
+                None => ListItem::new(Text::from(r.description.clone())),
+            })
             .collect();
 
         let mut list_state = self.parent.list_state.take();
@@ -211,3 +444,18 @@ impl Widget for ReactWidget<'_> {
         self.parent.list_state.set(list_state)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        let thumbs = emojis::get("👍").unwrap();
+
+        // by shortcode, or by name
+        assert!(score("+1", thumbs).is_some());
+        assert!(score("thumbs up", thumbs).is_some());
+        assert!(score("heart", thumbs).is_none());
+    }
+}
@@ -3,6 +3,8 @@ use std::collections::{btree_map::Entry, BTreeMap, BinaryHeap};
 
 use ruma::events::receipt::{ReceiptEventContent, ReceiptType};
 
+use crate::matrix::username::Username;
+
 /// A place to put and update read receipts.
 pub struct Receipts {
     markers: BTreeMap<OwnedUserId, MilliSecondsSinceUnixEpoch>,
@@ -61,6 +63,52 @@ impl Receipts {
         };
     }
 
+    /// Merge one receipt event into another, keeping only the latest
+    /// receipt for each user so the result stays the size of the room.
+    pub fn merge_content(into: &mut ReceiptEventContent, from: ReceiptEventContent) {
+        for (event_id, receipts) in from.0 {
+            let existing = into.entry(event_id).or_default();
+
+            for (receipt_type, users) in receipts {
+                existing.entry(receipt_type).or_default().extend(users);
+            }
+        }
+
+        // find the latest timestamp for every user and type
+        let mut latest: BTreeMap<(ReceiptType, OwnedUserId), MilliSecondsSinceUnixEpoch> =
+            BTreeMap::new();
+
+        for receipts in into.values() {
+            for (receipt_type, users) in receipts {
+                for (user_id, receipt) in users {
+                    if let Some(ts) = receipt.ts {
+                        let entry = latest
+                            .entry((receipt_type.clone(), user_id.clone()))
+                            .or_insert(ts);
+
+                        if ts > *entry {
+                            *entry = ts;
+                        }
+                    }
+                }
+            }
+        }
+
+        // then drop everything else
+        for receipts in into.values_mut() {
+            for (receipt_type, users) in receipts.iter_mut() {
+                users.retain(|user_id, receipt| match receipt.ts {
+                    Some(ts) => latest.get(&(receipt_type.clone(), user_id.clone())) == Some(&ts),
+                    None => true,
+                });
+            }
+
+            receipts.retain(|_, users| !users.is_empty());
+        }
+
+        into.retain(|_, receipts| !receipts.is_empty());
+    }
+
     pub fn get_senders(event: &ReceiptEventContent) -> Vec<&OwnedUserId> {
         let mut ids = vec![];
 
@@ -81,3 +129,56 @@ pub struct Receipt<'a> {
     pub timestamp: &'a MilliSecondsSinceUnixEpoch,
     pub user_id: &'a OwnedUserId,
 }
+
+/// A receipt that has found its message, and when it was read.
+#[derive(Clone)]
+pub struct ReadReceipt {
+    pub user: Username,
+    pub timestamp: MilliSecondsSinceUnixEpoch,
+}
+
+impl ReadReceipt {
+    pub fn new(receipt: &Receipt) -> Self {
+        ReadReceipt {
+            user: Username::new(receipt.user_id.clone()),
+            timestamp: *receipt.timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::ruma::exports::serde_json;
+    use ruma::events::receipt::{ReceiptEventContent, ReceiptType};
+    use ruma::{event_id, user_id};
+
+    use crate::widgets::receipts::Receipts;
+
+    #[test]
+    fn it_merges_receipts() -> anyhow::Result<()> {
+        let mut first: ReceiptEventContent = serde_json::from_str(
+            r#"{ "$1:a.com": { "m.read": { "@phil:a.com": { "ts": 1 } } } }"#,
+        )?;
+
+        let second: ReceiptEventContent = serde_json::from_str(
+            r#"{
+                "$1:a.com": { "m.read": { "@bob:a.com": { "ts": 2 } } },
+                "$2:a.com": { "m.read": { "@phil:a.com": { "ts": 3 } } }
+            }"#,
+        )?;
+
+        Receipts::merge_content(&mut first, second);
+
+        assert_eq!(first.len(), 2);
+
+        // phil has moved on
+        let read = &first[event_id!("$1:a.com")][&ReceiptType::Read];
+        assert!(!read.contains_key(user_id!("@phil:a.com")));
+        assert!(read.contains_key(user_id!("@bob:a.com")));
+
+        let read = &first[event_id!("$2:a.com")][&ReceiptType::Read];
+        assert!(read.contains_key(user_id!("@phil:a.com")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+
+use crate::app::Popup;
+use crate::matrix::register::RegistrationStep;
+use crate::widgets::button::Button;
+use crate::widgets::error::Error;
+use crate::widgets::textinput::{paste_into, TextInput};
+use crate::widgets::EventResult::{Consumed, Ignored};
+use crate::widgets::{focus_next, focus_prev, get_margin, get_padding, EventResult, Focusable};
+use crate::{close, consumed};
+
+/// The form to sign up for a new account.
+pub struct Register {
+    id: TextInput,
+    password: TextInput,
+    confirm: TextInput,
+    email: TextInput,
+    token: TextInput,
+    submit: Button,
+}
+
+impl Register {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: TextInput::new("Matrix ID (@you:example.org)".to_string(), true, false)
+                .with_value(id),
+            password: TextInput::new("Password".to_string(), false, true),
+            confirm: TextInput::new("Password Again".to_string(), false, true),
+            email: TextInput::new("Email (if required)".to_string(), false, false),
+            token: TextInput::new("Registration Token (if required)".to_string(), false, false),
+            submit: Button::new("Register".to_string(), false),
+        }
+    }
+
+    fn focus_order(&mut self) -> Vec<Box<dyn Focusable + '_>> {
+        vec![
+            Box::new(&mut self.id),
+            Box::new(&mut self.password),
+            Box::new(&mut self.confirm),
+            Box::new(&mut self.email),
+            Box::new(&mut self.token),
+            Box::new(&mut self.submit),
+        ]
+    }
+
+    pub fn widget(&self) -> RegisterWidget {
+        RegisterWidget { register: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        paste_into(
+            [
+                &mut self.id,
+                &mut self.password,
+                &mut self.confirm,
+                &mut self.email,
+                &mut self.token,
+            ],
+            text,
+        )
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        // back to where we came from
+        if input.code == KeyCode::Esc {
+            return close!();
+        }
+
+        for text in [
+            &mut self.id,
+            &mut self.password,
+            &mut self.confirm,
+            &mut self.email,
+            &mut self.token,
+        ] {
+            if let Consumed(_) = text.key_event(input) {
+                return consumed!();
+            }
+        }
+
+        if let Consumed(_) = self.submit.key_event(input) {
+            if self.password.value() != self.confirm.value() {
+                return Consumed(Box::new(|app| {
+                    app.push_popup(Popup::Error(Error::new(
+                        "The passwords don't match.".to_string(),
+                    )));
+                }));
+            }
+
+            let id = self.id.value();
+            let password = self.password.value();
+            let email = optional(self.email.value());
+            let token = optional(self.token.value());
+
+            return Consumed(Box::new(move |app| {
+                app.close_popup();
+                app.matrix.register(&id, &password, email, token);
+            }));
+        }
+
+        match input.code {
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Down => focus_next(self.focus_order()),
+            KeyCode::BackTab | KeyCode::Up => focus_prev(self.focus_order()),
+            _ => Ignored,
+        }
+    }
+}
+
+fn optional(value: String) -> Option<String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+pub struct RegisterWidget<'a> {
+    register: &'a Register,
+}
+
+impl Widget for RegisterWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 60))
+            .vertical_margin(get_margin(area.height, 25))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(get_padding(area.width, 4))
+            .vertical_margin(2)
+            .constraints(
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        let block = Block::default()
+            .title("Register")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        block.render(area, buf);
+        self.register.id.widget().render(splits[0], buf);
+        self.register.password.widget().render(splits[1], buf);
+        self.register.confirm.widget().render(splits[2], buf);
+        self.register.email.widget().render(splits[3], buf);
+        self.register.token.widget().render(splits[4], buf);
+
+        // pop the submit button on the right side
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(splits[6])[1];
+
+        self.register.submit.widget().render(area, buf);
+    }
+}
+
+/// Waits on the user to do something outside of matui, then carries on with
+/// the registration.
+pub struct RegisterStep {
+    step: RegistrationStep,
+    error: Option<String>,
+    code: TextInput,
+    submit: Button,
+}
+
+impl RegisterStep {
+    pub fn new(step: RegistrationStep, error: Option<String>) -> Self {
+        let email = matches!(step, RegistrationStep::Email(_));
+
+        Self {
+            step,
+            error,
+            code: TextInput::new("Code (if you got one)".to_string(), email, false),
+            submit: Button::new("Continue".to_string(), !email),
+        }
+    }
+
+    fn focus_order(&mut self) -> Vec<Box<dyn Focusable + '_>> {
+        match self.step {
+            RegistrationStep::Email(_) => {
+                vec![Box::new(&mut self.code), Box::new(&mut self.submit)]
+            }
+            RegistrationStep::Browser(_) => vec![Box::new(&mut self.submit)],
+        }
+    }
+
+    pub fn widget(&self) -> RegisterStepWidget {
+        RegisterStepWidget { step: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        self.code.paste(text)
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if input.code == KeyCode::Esc {
+            return Consumed(Box::new(|app| {
+                app.close_popup();
+                app.matrix.cancel_registration();
+            }));
+        }
+
+        if let Consumed(_) = self.code.key_event(input) {
+            return consumed!();
+        }
+
+        if let Consumed(_) = self.submit.key_event(input) {
+            let code = self.code.value();
+
+            return Consumed(Box::new(move |app| {
+                app.close_popup();
+                app.matrix.continue_registration(&code);
+            }));
+        }
+
+        match input.code {
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Down => focus_next(self.focus_order()),
+            KeyCode::BackTab | KeyCode::Up => focus_prev(self.focus_order()),
+            _ => Ignored,
+        }
+    }
+
+    fn instructions(&self) -> String {
+        match &self.step {
+            RegistrationStep::Email(address) => format!(
+                "We sent an email to {}. Click the link inside, or enter the code \
+                 if there is one, then continue.",
+                address
+            ),
+            RegistrationStep::Browser(url) => format!(
+                "Your homeserver needs you to finish up in a browser. If one didn't \
+                 open, go to {} and then continue here.",
+                url
+            ),
+        }
+    }
+}
+
+pub struct RegisterStepWidget<'a> {
+    step: &'a RegisterStep,
+}
+
+impl Widget for RegisterStepWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 60))
+            .vertical_margin(get_margin(area.height, 18))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(get_padding(area.width, 4))
+            .vertical_margin(2)
+            .constraints(
+                [
+                    Constraint::Length(6),
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        let block = Block::default()
+            .title("Register")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        block.render(area, buf);
+
+        let mut lines = vec![];
+
+        if let Some(error) = &self.step.error {
+            lines.push(Line::from(Span::styled(
+                error.clone(),
+                Style::default().fg(Color::LightRed),
+            )));
+        }
+
+        lines.push(Line::from(self.step.instructions()));
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: true })
+            .render(splits[0], buf);
+
+        if let RegistrationStep::Email(_) = self.step.step {
+            self.step.code.widget().render(splits[1], buf);
+        }
+
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(splits[3])[1];
+
+        self.step.submit.widget().render(area, buf);
+    }
+}
@@ -0,0 +1,160 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, BorderType, Borders, Widget};
+
+use crate::app::Popup;
+use crate::widgets::button::Button;
+use crate::widgets::confirm::Confirm;
+use crate::widgets::textinput::TextInput;
+use crate::widgets::EventResult::{Consumed, Ignored};
+use crate::widgets::{focus_next, focus_prev, get_margin, get_padding, EventResult, Focusable};
+use crate::{close, consumed};
+
+/// What's getting a new name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renaming {
+    /// This session, in everyone's device lists.
+    Device,
+    /// Me, everywhere I've joined.
+    DisplayName,
+}
+
+impl Renaming {
+    fn title(&self) -> &'static str {
+        match self {
+            Renaming::Device => "Rename Device",
+            Renaming::DisplayName => "Change Display Name",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Renaming::Device => "Device Name",
+            Renaming::DisplayName => "Display Name",
+        }
+    }
+}
+
+/// Change what this session, or I, am called.
+pub struct Rename {
+    renaming: Renaming,
+    name: TextInput,
+    submit: Button,
+}
+
+impl Rename {
+    pub fn new(renaming: Renaming, current: &str) -> Self {
+        Self {
+            renaming,
+            name: TextInput::new(renaming.label().to_string(), true, false).with_value(current),
+            submit: Button::new("Rename".to_string(), false),
+        }
+    }
+
+    fn focus_order(&mut self) -> Vec<Box<dyn Focusable + '_>> {
+        vec![Box::new(&mut self.name), Box::new(&mut self.submit)]
+    }
+
+    pub fn widget(&self) -> RenameWidget {
+        RenameWidget { rename: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        self.name.paste(text)
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if input.code == KeyCode::Esc {
+            return close!();
+        }
+
+        if let Consumed(_) = self.name.key_event(input) {
+            return consumed!();
+        }
+
+        if let Consumed(_) = self.submit.key_event(input) {
+            let name = self.name.value().trim().to_string();
+
+            if name.is_empty() {
+                return consumed!();
+            }
+
+            let renaming = self.renaming;
+
+            return Consumed(Box::new(move |app| {
+                app.close_popup();
+
+                match renaming {
+                    Renaming::Device => app.matrix.rename_device(&name),
+                    Renaming::DisplayName => {
+                        let confirm = Confirm::new(
+                            "Change Display Name".to_string(),
+                            format!("Everyone, in every room, will see you as \"{}\".", name),
+                            "Change".to_string(),
+                            "Cancel".to_string(),
+                            move |app| app.matrix.set_display_name(&name),
+                        );
+
+                        app.push_popup(Popup::Confirm(confirm));
+                    }
+                }
+            }));
+        }
+
+        match input.code {
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Down => focus_next(self.focus_order()),
+            KeyCode::BackTab | KeyCode::Up => focus_prev(self.focus_order()),
+            _ => Ignored,
+        }
+    }
+}
+
+pub struct RenameWidget<'a> {
+    rename: &'a Rename,
+}
+
+impl Widget for RenameWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 60))
+            .vertical_margin(get_margin(area.height, 10))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(get_padding(area.width, 4))
+            .vertical_margin(1)
+            .constraints(
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                    Constraint::Percentage(100),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        let block = Block::default()
+            .title(self.rename.renaming.title())
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        block.render(area, buf);
+        self.rename.name.widget().render(splits[0], buf);
+
+        // pop the submit button on the right side
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(splits[2])[1];
+
+        self.rename.submit.widget().render(area, buf);
+    }
+}
@@ -0,0 +1,220 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use log::error;
+use matrix_sdk::room::Room;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Cell, Paragraph, Row, Table, Widget};
+use ruma::events::room::guest_access::GuestAccess;
+use ruma::events::room::history_visibility::HistoryVisibility;
+use ruma::events::room::join_rules::JoinRule;
+use ruma::OwnedRoomAliasId;
+
+use crate::app::Popup;
+use crate::spawn::copy_to_clipboard;
+use crate::widgets::access::RoomAccess;
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// The addresses and settings of a room, with a quick way to share it.
+pub struct RoomInfo {
+    room: Room,
+    name: String,
+    fields: Vec<(&'static str, String)>,
+
+    // the best address we have, and a link to it
+    address: String,
+    link: String,
+
+    copied: Option<&'static str>,
+}
+
+impl RoomInfo {
+    pub fn new(room: &Room, name: String) -> Self {
+        let alias = room.canonical_alias();
+
+        let address = match &alias {
+            Some(alias) => alias.to_string(),
+            None => room.room_id().to_string(),
+        };
+
+        let link = match &alias {
+            Some(alias) => alias.matrix_to_uri().to_string(),
+            None => room.room_id().matrix_to_uri().to_string(),
+        };
+
+        let alt_aliases: Vec<String> = room.alt_aliases().iter().map(|a| a.to_string()).collect();
+
+        let version = room
+            .create_content()
+            .map(|c| c.room_version.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let encryption = room
+            .encryption_settings()
+            .map(|e| e.algorithm.to_string())
+            .unwrap_or_else(|| "none".to_string());
+
+        let fields = vec![
+            ("Alias", none_if_empty(address_of(alias))),
+            ("Also Known As", none_if_empty(alt_aliases.join(", "))),
+            ("Room ID", room.room_id().to_string()),
+            ("Version", version),
+            ("Encryption", encryption),
+            ("Join Rule", join_rule(&room.join_rule())),
+            ("History", history(&room.history_visibility())),
+            ("Guest Access", guests(&room.guest_access())),
+        ];
+
+        Self {
+            room: room.clone(),
+            name,
+            fields,
+            address,
+            link,
+            copied: None,
+        }
+    }
+
+    pub fn widget(&self) -> RoomInfoWidget {
+        RoomInfoWidget { info: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        let (what, text) = match input.code {
+            KeyCode::Char('a') => ("address", &self.address),
+            KeyCode::Char('l') => ("link", &self.link),
+            KeyCode::Char('e') => {
+                let room = self.room.clone();
+                let name = self.name.clone();
+
+                return EventResult::Consumed(Box::new(move |app| {
+                    let spaces = app.matrix.spaces();
+                    app.replace_popup(Popup::RoomAccess(RoomAccess::new(room, name, spaces)));
+                }));
+            }
+            KeyCode::Enter | KeyCode::Esc | KeyCode::Char('q') => return close!(),
+            _ => return consumed!(),
+        };
+
+        match copy_to_clipboard(text) {
+            Ok(_) => self.copied = Some(what),
+            Err(e) => error!("could not copy {}: {}", what, e),
+        }
+
+        consumed!()
+    }
+
+    fn hint(&self) -> String {
+        match self.copied {
+            Some(what) => format!("{} copied!", what),
+            None => "a copy address · l copy link · e edit access · q close".to_string(),
+        }
+    }
+}
+
+fn address_of(alias: Option<OwnedRoomAliasId>) -> String {
+    alias.map(|a| a.to_string()).unwrap_or_default()
+}
+
+fn none_if_empty(s: String) -> String {
+    if s.is_empty() {
+        "none".to_string()
+    } else {
+        s
+    }
+}
+
+// Who can get in, in a few words.
+fn join_rule(rule: &JoinRule) -> String {
+    match rule {
+        JoinRule::Public => "public, anyone can join".to_string(),
+        JoinRule::Invite => "invite only".to_string(),
+        JoinRule::Knock => "invite, or knock to ask".to_string(),
+        JoinRule::Private => "private".to_string(),
+        JoinRule::Restricted(_) => "members of certain spaces".to_string(),
+        JoinRule::KnockRestricted(_) => "members of certain spaces, or knock".to_string(),
+        other => other.as_str().to_string(),
+    }
+}
+
+fn history(visibility: &HistoryVisibility) -> String {
+    match visibility {
+        HistoryVisibility::Shared => "members, even before they joined".to_string(),
+        HistoryVisibility::Invited => "members, since they were invited".to_string(),
+        HistoryVisibility::Joined => "members, since they joined".to_string(),
+        HistoryVisibility::WorldReadable => "anyone".to_string(),
+        other => other.as_str().to_string(),
+    }
+}
+
+fn guests(access: &GuestAccess) -> String {
+    match access {
+        GuestAccess::CanJoin => "can join".to_string(),
+        GuestAccess::Forbidden => "forbidden".to_string(),
+        other => other.as_str().to_string(),
+    }
+}
+
+pub struct RoomInfoWidget<'a> {
+    info: &'a RoomInfo,
+}
+
+impl Widget for RoomInfoWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = self.info.fields.len() as u16 + 5;
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 80))
+            .vertical_margin(get_margin(area.height, height))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title(&*self.info.name)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints(
+                [
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        let rows = self.info.fields.iter().map(|(label, value)| {
+            Row::new(vec![
+                Cell::from(Span::styled(*label, Style::default().fg(Color::DarkGray))),
+                Cell::from(value.as_str()),
+            ])
+        });
+
+        Table::new(rows)
+            .widths(&[Constraint::Length(14), Constraint::Percentage(100)])
+            .column_spacing(1)
+            .render(splits[0], buf);
+
+        Paragraph::new(Span::styled(
+            self.info.hint(),
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[2], buf);
+    }
+}
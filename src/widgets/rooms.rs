@@ -1,57 +1,207 @@
+use crate::fuzzy::fuzzy_match;
+use crate::locale::{short_duration, tr};
 use crate::matrix::matrix::Matrix;
-use crate::matrix::roomcache::DecoratedRoom;
-use crate::{close, consumed};
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::matrix::roomcache::{DecoratedRoom, ACTIVITY_HOURS};
+use crate::matrix::username::Username;
+use crate::settings::{
+    archived_rooms, presentation_mode, room_accent, room_sort, set_setting, unread_rooms_only,
+};
+use crate::{close, consumed, limit_list, pretty_list};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::error;
 use matrix_sdk::room::Room;
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
-use ratatui::style::{Color, Style};
+use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{
     Block, BorderType, Borders, List, ListItem, ListState, StatefulWidget, Widget,
 };
+use ruma::presence::PresenceState;
+use ruma::MilliSecondsSinceUnixEpoch;
 use std::cell::Cell;
+use std::time::Duration;
+use toml_edit::Array;
+use unicode_width::UnicodeWidthChar;
 
-use crate::widgets::get_margin;
+use crate::app::Popup;
+use crate::widgets::error::Error;
 use crate::widgets::textinput::TextInput;
 use crate::widgets::EventResult::Consumed;
+use crate::widgets::{get_margin, get_padding};
 
 use super::EventResult;
 
+/// The orders the room switcher can be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomSort {
+    Unread,
+    Recent,
+    Alphabetical,
+}
+
+impl RoomSort {
+    fn from_settings() -> Self {
+        match room_sort().as_deref() {
+            Some("recent") => RoomSort::Recent,
+            Some("alphabetical") => RoomSort::Alphabetical,
+            _ => RoomSort::Unread,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            RoomSort::Unread => "unread",
+            RoomSort::Recent => "recent",
+            RoomSort::Alphabetical => "alphabetical",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            RoomSort::Unread => "unread first",
+            RoomSort::Recent => "recent activity",
+            RoomSort::Alphabetical => "alphabetical",
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            RoomSort::Unread => RoomSort::Recent,
+            RoomSort::Recent => RoomSort::Alphabetical,
+            RoomSort::Alphabetical => RoomSort::Unread,
+        }
+    }
+
+    fn sort(&self, rooms: &mut [DecoratedRoom]) {
+        match self {
+            RoomSort::Unread => sort_rooms(rooms),
+            RoomSort::Recent => rooms.sort_by(|a, b| b.last_ts.cmp(&a.last_ts)),
+            RoomSort::Alphabetical => rooms.sort_by_cached_key(|r| r.display_name().to_lowercase()),
+        }
+    }
+}
+
 pub struct Rooms {
     pub textinput: TextInput,
     pub room: Vec<DecoratedRoom>,
     pub list_state: Cell<ListState>,
+    sort: RoomSort,
+    unread_only: bool,
+    current: Option<Room>,
+    // IDs of the rooms hidden from the list, unless we're showing just them
+    archived: Vec<String>,
+    show_archived: bool,
+    // what's showing, worked out again only when something changes
+    matches: Vec<RoomMatch>,
 }
 
 impl Rooms {
     pub fn new(matrix: Matrix, current: Option<Room>) -> Self {
-        let mut rooms = matrix.fetch_rooms();
-        sort_rooms(&mut rooms);
-
-        // if the current room is at the top, put it at the bottom
-        if let Some(current) = current {
-            if rooms.len() > 1 && rooms.first().unwrap().inner.room_id() == current.room_id() {
-                let first = rooms.remove(0);
-                rooms.push(first);
-            }
-        }
-
         let mut ret = Self {
             textinput: TextInput::new("Search".to_string(), true, false),
-            room: rooms,
+            room: matrix.fetch_rooms(),
             list_state: Cell::new(ListState::default()),
+            sort: RoomSort::from_settings(),
+            unread_only: unread_rooms_only(),
+            current,
+            archived: archived_rooms(),
+            show_archived: false,
+            matches: vec![],
         };
 
+        ret.sort();
         ret.reset();
         ret
     }
 
+    fn sort(&mut self) {
+        self.sort.sort(&mut self.room);
+
+        // if the current room is at the top, put it at the bottom
+        if let Some(current) = &self.current {
+            if self.sort != RoomSort::Alphabetical
+                && self.room.len() > 1
+                && self.room[0].room_id() == current.room_id()
+            {
+                let first = self.room.remove(0);
+                self.room.push(first);
+            }
+        }
+
+        // word from the homeserver admins always comes first
+        self.room.sort_by_key(|r| !r.server_notices);
+    }
+
+    /// Pick up the sort, the unread filter and the archive from the config.
+    pub fn settings_changed(&mut self) {
+        self.sort = RoomSort::from_settings();
+        self.unread_only = unread_rooms_only();
+        self.archived = archived_rooms();
+        self.sort();
+        self.reset();
+    }
+
     pub fn widget(&self) -> RoomsWidget {
         RoomsWidget { rooms: self }
     }
 
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        let result = self.textinput.paste(text);
+
+        if let Consumed(_) = &result {
+            self.reset();
+        }
+
+        result
+    }
+
     pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if input.modifiers == KeyModifiers::CONTROL {
+            match input.code {
+                KeyCode::Char('s') => {
+                    self.sort = self.sort.next();
+                    self.sort();
+                    self.reset();
+
+                    if let Err(e) = set_setting("room_sort", self.sort.name()) {
+                        error!("could not save room sort: {}", e);
+                    }
+
+                    return consumed!();
+                }
+                KeyCode::Char('u') => {
+                    self.unread_only = !self.unread_only;
+                    self.reset();
+
+                    if let Err(e) = set_setting("unread_rooms_only", self.unread_only) {
+                        error!("could not save unread filter: {}", e);
+                    }
+
+                    return consumed!();
+                }
+                KeyCode::Char('a') => {
+                    if self.selected_room().is_some_and(|r| r.server_notices) {
+                        return Consumed(Box::new(|app| {
+                            app.push_popup(Popup::Error(Error::with_heading(
+                                "Server Notices".to_string(),
+                                "Notices from your homeserver can't be archived.".to_string(),
+                            )))
+                        }));
+                    }
+
+                    self.toggle_archived();
+                    return consumed!();
+                }
+                KeyCode::Char('x') => {
+                    self.show_archived = !self.show_archived;
+                    self.reset();
+                    return consumed!();
+                }
+                _ => {}
+            }
+        }
+
         match input.code {
             KeyCode::Esc => close!(),
             KeyCode::Down => {
@@ -84,7 +234,25 @@ impl Rooms {
         }
     }
 
+    // a room has been updated in the background; swap it in place so the
+    // list doesn't jump around under the cursor
+    pub fn room_cache_event(&mut self, room: DecoratedRoom) {
+        let Some(r) = self.room.iter_mut().find(|r| r.room_id() == room.room_id()) else {
+            return;
+        };
+
+        *r = room;
+
+        // with only unread rooms showing, one read elsewhere drops out of the
+        // list, maybe from under the cursor
+        self.filter();
+    }
+
     fn next(&mut self) {
+        if self.filtered_rooms().is_empty() {
+            return;
+        }
+
         let mut state = self.list_state.take();
 
         let i = match state.selected() {
@@ -103,6 +271,10 @@ impl Rooms {
     }
 
     fn previous(&mut self) {
+        if self.filtered_rooms().is_empty() {
+            return;
+        }
+
         let mut state = self.list_state.take();
 
         let i = match state.selected() {
@@ -121,18 +293,107 @@ impl Rooms {
     }
 
     fn reset(&mut self) {
+        self.filter();
+
         let mut state = self.list_state.take();
         state.select(Some(0));
         self.list_state.set(state);
     }
 
-    fn filtered_rooms(&self) -> Vec<&DecoratedRoom> {
-        let pattern = self.textinput.value.to_lowercase();
+    // Work out what's showing, the best matches first, or everything in
+    // order if there's no search, and keep the cursor on the list.
+    fn filter(&mut self) {
+        let pattern = self.textinput.value.trim();
 
-        self.room
+        let mut matches: Vec<RoomMatch> = self
+            .room
             .iter()
-            .filter(|j| j.name.to_string().to_lowercase().contains(pattern.as_str()))
-            .collect()
+            .enumerate()
+            .filter(|(_, r)| self.is_archived(r) == self.show_archived)
+            .filter(|(_, r)| !self.unread_only || r.unread_count() > 0)
+            .filter_map(|(i, r)| RoomMatch::new(pattern, i, r))
+            .collect();
+
+        // stable, so ties keep the sort order
+        matches.sort_by(|a, b| b.score.cmp(&a.score));
+
+        // a search is best match first, otherwise people go above rooms
+        if pattern.is_empty() {
+            matches.sort_by_key(|m| !self.room[m.index].is_direct());
+        }
+
+        self.matches = matches;
+
+        let mut state = self.list_state.take();
+
+        if let Some(i) = state.selected() {
+            state.select(Some(i.min(self.matches.len().saturating_sub(1))));
+        }
+
+        self.list_state.set(state);
+    }
+
+    fn filtered_rooms(&self) -> &[RoomMatch] {
+        &self.matches
+    }
+
+    // How many people there are at the top of the list, if it's split into
+    // people and rooms.
+    fn people(&self) -> Option<usize> {
+        if !self.textinput.value.trim().is_empty() {
+            return None;
+        }
+
+        match self
+            .matches
+            .iter()
+            .take_while(|m| self.room[m.index].is_direct())
+            .count()
+        {
+            0 => None,
+            people => Some(people),
+        }
+    }
+
+    fn is_archived(&self, room: &DecoratedRoom) -> bool {
+        self.archived.iter().any(|id| id == room.room_id().as_str())
+    }
+
+    // move the selected room in or out of the archive
+    fn toggle_archived(&mut self) {
+        let Some(room) = self.selected_room() else {
+            return;
+        };
+
+        let id = room.room_id().to_string();
+
+        if self.is_archived(&room) {
+            self.archived.retain(|a| a != &id);
+        } else {
+            self.archived.push(id);
+        }
+
+        if let Err(e) = set_setting("archived", Array::from_iter(self.archived.iter())) {
+            error!("could not save archived rooms: {}", e);
+        }
+
+        // the room is gone from this list, so don't fall off the end
+        self.filter();
+    }
+
+    fn title(&self) -> String {
+        let mut title = format!("Rooms ({}", self.sort.description());
+
+        if self.unread_only {
+            title.push_str(", unread only");
+        }
+
+        if self.show_archived {
+            title.push_str(", archived");
+        }
+
+        title.push(')');
+        title
     }
 
     fn selected_room(&self) -> Option<DecoratedRoom> {
@@ -142,10 +403,91 @@ impl Rooms {
             return None;
         }
 
-        match self.list_state.take().selected() {
-            Some(i) => Some(filtered_rooms[i].clone()),
-            None => Some(filtered_rooms[0].clone()),
+        // put the state back, or the selection is lost for whatever's next
+        let state = self.list_state.take();
+        let i = state.selected().unwrap_or_default();
+        self.list_state.set(state);
+
+        filtered_rooms
+            .get(i)
+            .or(filtered_rooms.last())
+            .map(|m| self.room[m.index].clone())
+    }
+}
+
+/// Matching an alias is nearly as good as matching the name.
+const ALIAS_PENALTY: i64 = 4;
+
+/// A topic has a lot of letters to pick from, so it has to be a run, and
+/// still counts for less.
+const TOPIC_PENALTY: i64 = 16;
+
+// What turned up a room in a search.
+enum Found {
+    Name,
+    Alias(String),
+    Topic(String),
+}
+
+struct RoomMatch {
+    // where the room is in the full list
+    index: usize,
+    score: i64,
+    found: Found,
+    // the characters that matched, in whatever was found
+    indices: Vec<usize>,
+}
+
+impl RoomMatch {
+    fn new(pattern: &str, index: usize, room: &DecoratedRoom) -> Option<Self> {
+        let mut best = fuzzy_match(pattern, &room.display_name()).map(|m| RoomMatch {
+            index,
+            score: m.score,
+            found: Found::Name,
+            indices: m.indices,
+        });
+
+        if pattern.is_empty() {
+            return best;
         }
+
+        let mut consider = |candidate: RoomMatch| {
+            if best.as_ref().map_or(true, |b| candidate.score > b.score) {
+                best = Some(candidate);
+            }
+        };
+
+        let aliases = room
+            .inner
+            .canonical_alias()
+            .into_iter()
+            .chain(room.inner.alt_aliases());
+
+        for alias in aliases {
+            if let Some(m) = fuzzy_match(pattern, alias.as_str()) {
+                consider(RoomMatch {
+                    index,
+                    score: m.score - ALIAS_PENALTY,
+                    found: Found::Alias(alias.to_string()),
+                    indices: m.indices,
+                });
+            }
+        }
+
+        if let Some(topic) = room.inner.topic() {
+            if let Some(m) = fuzzy_match(pattern, &topic) {
+                if m.indices.windows(2).all(|w| w[1] == w[0] + 1) {
+                    consider(RoomMatch {
+                        index,
+                        score: m.score - TOPIC_PENALTY,
+                        found: Found::Topic(topic),
+                        indices: m.indices,
+                    });
+                }
+            }
+        }
+
+        best
     }
 }
 
@@ -166,7 +508,7 @@ impl Widget for RoomsWidget<'_> {
 
         // Render the main block
         let block = Block::default()
-            .title("Rooms")
+            .title(self.rooms.title())
             .title_alignment(Alignment::Center)
             .style(Style::default().bg(Color::Black))
             .borders(Borders::ALL)
@@ -177,55 +519,241 @@ impl Widget for RoomsWidget<'_> {
         let splits = Layout::default()
             .direction(Direction::Vertical)
             .vertical_margin(2)
-            .horizontal_margin(2)
+            .horizontal_margin(get_padding(area.width, 2))
             .constraints([Constraint::Length(3), Constraint::Percentage(100)].as_ref())
             .split(area);
 
         self.rooms.textinput.widget().render(splits[0], buf);
 
-        let items: Vec<ListItem> = self
-            .rooms
-            .filtered_rooms()
-            .into_iter()
-            .map(make_list_item)
-            .collect();
-
         let area = Layout::default()
             .horizontal_margin(1)
             .constraints([Constraint::Percentage(100)].as_ref())
             .split(splits[1])[0];
 
-        let mut list_state = self.rooms.list_state.take();
+        // leave room for the highlight symbol
+        let width = area.width.saturating_sub(2) as usize;
+        let now = MilliSecondsSinceUnixEpoch::now();
+
+        let people = self.rooms.people();
+        let mut items = vec![];
+
+        for (i, m) in self.rooms.filtered_rooms().iter().enumerate() {
+            match people {
+                Some(_) if i == 0 => items.push(heading("People", false)),
+                Some(p) if i == p => items.push(heading("Rooms", true)),
+                _ => {}
+            }
+
+            items.push(make_list_item(&self.rooms.room[m.index], m, width, now));
+        }
+
+        // the selection is a room, but the list counts the headings too
+        let state = self.rooms.list_state.take();
+        let selected = state.selected();
+
+        let mut list_state = ListState::default()
+            .with_offset(state.offset())
+            .with_selected(Some(list_row(selected.unwrap_or_default(), people)));
+
+        // and the first heading should come back into view with the first room
+        if selected.unwrap_or_default() == 0 {
+            *list_state.offset_mut() = 0;
+        }
+
         let list = List::new(items).highlight_symbol("> ");
         StatefulWidget::render(list, area, buf, &mut list_state);
-        self.rooms.list_state.set(list_state)
+
+        self.rooms.list_state.set(
+            ListState::default()
+                .with_offset(list_state.offset())
+                .with_selected(selected),
+        )
+    }
+}
+
+// Where a room ends up in the list, once the headings are in.
+fn list_row(index: usize, people: Option<usize>) -> usize {
+    match people {
+        None => index,
+        Some(people) if index < people => index + 1,
+        Some(_) => index + 2,
     }
 }
 
-fn make_list_item(room: &DecoratedRoom) -> ListItem {
-    let name = room.name.to_string();
+fn heading(title: &'static str, gap: bool) -> ListItem<'static> {
+    let line = Line::from(Span::styled(
+        title,
+        Style::default()
+            .fg(Color::DarkGray)
+            .add_modifier(Modifier::BOLD),
+    ));
+
+    if gap {
+        ListItem::new(vec![Line::from(""), line])
+    } else {
+        ListItem::new(line)
+    }
+}
+
+// A dot for whether someone's around.
+fn presence(state: Option<&PresenceState>) -> Span<'static> {
+    let color = match state {
+        Some(PresenceState::Online) => Color::Green,
+        Some(PresenceState::Unavailable) => Color::Yellow,
+        _ => Color::DarkGray,
+    };
+
+    Span::styled("● ", Style::default().fg(color))
+}
+
+fn make_list_item<'a>(
+    room: &'a DecoratedRoom,
+    m: &'a RoomMatch,
+    width: usize,
+    now: MilliSecondsSinceUnixEpoch,
+) -> ListItem<'a> {
     let unread = room.unread_count();
     let highlights = room.highlight_count();
+    let matched = Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::BOLD);
+
+    let name = match room_accent(room.room_id()) {
+        Some(color) => Style::default().fg(color),
+        None if room.server_notices => Style::default()
+            .fg(Color::LightRed)
+            .add_modifier(Modifier::BOLD),
+        None => match &room.peer {
+            Some(peer) => Style::default().fg(Username::new(peer.id.clone()).color()),
+            None => Style::default(),
+        },
+    };
 
-    let mut spans = vec![Span::from(name)];
+    let mut spans = match &m.found {
+        Found::Name => highlight(&room.display_name(), &m.indices, name, matched),
+        _ => vec![Span::styled(room.display_name(), name)],
+    };
 
-    if unread > 0 {
-        spans.push(Span::styled(
-            format!(" ({})", unread),
+    if room.server_notices {
+        spans.insert(0, Span::styled("⚠ ", name));
+    }
+
+    if let Some(peer) = &room.peer {
+        spans.insert(0, presence(peer.presence.as_ref()));
+    }
+
+    if let Found::Alias(alias) = &m.found {
+        spans.push(Span::from(" "));
+        spans.extend(highlight(
+            alias,
+            &m.indices,
             Style::default().fg(Color::DarkGray),
+            matched,
+        ));
+    }
+
+    // then the columns on the right: when, and how much is waiting
+    let elapsed = room
+        .last_ts
+        .map(|ts| short_elapsed(ts, now))
+        .unwrap_or_default();
+
+    let mut badges = vec![];
+
+    if unread > 0 {
+        badges.push(Span::styled(
+            format!(" {} ", unread),
+            Style::default().fg(Color::Black).bg(Color::DarkGray),
         ));
     }
 
     if highlights > 0 {
-        spans.push(Span::styled(
-            format!(" ({})", highlights),
-            Style::default().fg(Color::Green),
+        badges.push(Span::from(" "));
+        badges.push(Span::styled(
+            format!(" {} ", highlights),
+            Style::default().fg(Color::Black).bg(Color::Green),
+        ));
+    }
+
+    let badges_width: usize = badges.iter().map(|s| s.width()).sum();
+
+    let mut right = vec![];
+
+    // only once there's room to spare for it
+    if width >= SPARKLINE_MIN_WIDTH {
+        right.push(Span::styled(
+            sparkline(&room.activity.counts(now)),
+            Style::default().fg(Color::Cyan),
         ));
+        right.push(Span::from(" "));
     }
 
+    right.push(Span::styled(
+        format!("{:>width$}", elapsed, width = TIME_COLUMN),
+        Style::default().fg(Color::DarkGray),
+    ));
+
+    right.push(Span::from(
+        " ".repeat(BADGE_COLUMN.saturating_sub(badges_width) + 1),
+    ));
+    right.extend(badges);
+
+    let right_width: usize = right.iter().map(|s| s.width()).sum();
+    let mut spans = truncate(spans, width.saturating_sub(right_width + 1));
+    let used: usize = spans.iter().map(|s| s.width()).sum();
+
+    spans.push(Span::from(
+        " ".repeat(width.saturating_sub(used + right_width)),
+    ));
+    spans.extend(right);
+
     let mut lines = Text::from(Line::from(spans));
 
-    let spans = if room.last_sender.is_none() || room.last_message.is_none() {
+    let spans = if let Found::Topic(topic) = &m.found {
+        // start a little before the match, so it's (probably) on screen
+        let skip = m.indices[0].saturating_sub(TOPIC_CONTEXT);
+        let snippet: String = topic.chars().skip(skip).collect();
+        let indices: Vec<usize> = m.indices.iter().map(|i| i - skip).collect();
+
+        let mut spans = vec![];
+
+        if skip > 0 {
+            spans.push(Span::styled("…", Style::default().fg(Color::DarkGray)));
+        }
+
+        spans.extend(highlight(
+            &snippet,
+            &indices,
+            Style::default().fg(Color::DarkGray),
+            matched,
+        ));
+
+        spans
+    } else if !room.typing.is_empty() {
+        let key = if room.typing.len() > 1 {
+            "room_are_typing"
+        } else {
+            "room_is_typing"
+        };
+
+        let names = limit_list(room.typing.iter().cloned(), 2, room.typing.len(), None);
+
+        vec![Span::styled(
+            tr(key, &pretty_list(names)),
+            Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+        )]
+    } else if presentation_mode() {
+        // nothing anyone watching shouldn't see
+        let preview = if unread > 0 {
+            format!("{} unread", unread)
+        } else {
+            blur(room.last_message.as_deref().unwrap_or_default())
+        };
+
+        vec![Span::styled(preview, Style::default().fg(Color::DarkGray))]
+    } else if room.last_sender.is_none() || room.last_message.is_none() {
         vec![Span::styled("", Style::default().fg(Color::DarkGray))]
     } else {
         vec![Span::styled(
@@ -243,7 +771,202 @@ fn make_list_item(room: &DecoratedRoom) -> ListItem {
     ListItem::new(lines)
 }
 
+// The shape of the text, without any of what it says.
+fn blur(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_whitespace() { c } else { '░' })
+        .collect()
+}
+
+/// How wide the last activity column is.
+const TIME_COLUMN: usize = 4;
+
+/// And the unread and mention badges, which are right aligned.
+const BADGE_COLUMN: usize = 9;
+
+/// How wide a row has to be before it gets an activity sparkline.
+const SPARKLINE_MIN_WIDTH: usize = 50;
+
+// Messages per hour as braille, two hours to a character, each a column of
+// up to four dots. The scale is fixed, so a busy room looks busy next to a
+// quiet one.
+fn sparkline(counts: &[u32; ACTIVITY_HOURS]) -> String {
+    // dots from the bottom up, for the left and right columns
+    const LEFT: [u32; 4] = [0x40, 0x04, 0x02, 0x01];
+    const RIGHT: [u32; 4] = [0x80, 0x20, 0x10, 0x08];
+
+    let level = |count: u32| match count {
+        0 => 0,
+        1..=2 => 1,
+        3..=9 => 2,
+        10..=29 => 3,
+        _ => 4,
+    };
+
+    counts
+        .chunks(2)
+        .map(|pair| {
+            let dots: u32 = LEFT[..level(pair[0])].iter().sum::<u32>()
+                + RIGHT[..level(pair[1])].iter().sum::<u32>();
+
+            char::from_u32(0x2800 + dots).unwrap_or(' ')
+        })
+        .collect()
+}
+
+fn short_elapsed(then: MilliSecondsSinceUnixEpoch, now: MilliSecondsSinceUnixEpoch) -> String {
+    let secs = u64::from(now.as_secs()).saturating_sub(then.as_secs().into());
+    short_duration(Duration::from_secs(secs))
+}
+
+// Cut the spans down to fit the width, with an ellipsis if anything's lost.
+fn truncate(spans: Vec<Span<'static>>, width: usize) -> Vec<Span<'static>> {
+    let total: usize = spans.iter().map(|s| s.width()).sum();
+
+    if total <= width {
+        return spans;
+    }
+
+    let mut left = width.saturating_sub(1);
+    let mut ret = vec![];
+
+    for span in spans {
+        let mut content = String::new();
+
+        for c in span.content.chars() {
+            let w = c.width().unwrap_or(0);
+
+            if w > left {
+                left = 0;
+                break;
+            }
+
+            left -= w;
+            content.push(c);
+        }
+
+        let done = left == 0;
+        ret.push(Span::styled(content, span.style));
+
+        if done {
+            break;
+        }
+    }
+
+    if width > 0 {
+        ret.push(Span::from("…"));
+    }
+
+    ret
+}
+
+/// How much of a topic to show before the part that matched.
+const TOPIC_CONTEXT: usize = 10;
+
+// Split the text into spans, with the characters at the indices picked out.
+fn highlight(text: &str, indices: &[usize], style: Style, matched: Style) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    let mut run = String::new();
+    let mut in_match = false;
+
+    for (i, c) in text.chars().enumerate() {
+        let is_match = indices.contains(&i);
+
+        if is_match != in_match && !run.is_empty() {
+            let style = if in_match { matched } else { style };
+            spans.push(Span::styled(std::mem::take(&mut run), style));
+        }
+
+        in_match = is_match;
+        run.push(c);
+    }
+
+    if !run.is_empty() {
+        spans.push(Span::styled(run, if in_match { matched } else { style }));
+    }
+
+    spans
+}
+
 pub fn sort_rooms(rooms: &mut [DecoratedRoom]) {
     rooms.sort_by_key(|r| (r.unread_count(), r.last_ts));
     rooms.reverse()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_elapsed() {
+        let at = |secs: u32| {
+            MilliSecondsSinceUnixEpoch::from_system_time(
+                std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs.into()),
+            )
+            .unwrap()
+        };
+
+        let now = at(1_000_000);
+
+        assert_eq!(short_elapsed(at(999_990), now), "now");
+        assert_eq!(short_elapsed(at(1_000_000 - 300), now), "5m");
+        assert_eq!(short_elapsed(at(1_000_000 - 7200), now), "2h");
+        assert_eq!(short_elapsed(at(1_000_000 - 3 * 86400), now), "3d");
+        assert_eq!(short_elapsed(at(0), now), "1w");
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0; ACTIVITY_HOURS]), "⠀⠀⠀⠀");
+        assert_eq!(sparkline(&[1, 0, 0, 3, 10, 30, 0, 1]), "⡀⢠⣾⢀");
+    }
+
+    #[test]
+    fn test_blur() {
+        assert_eq!(blur("see you at 5"), "░░░ ░░░ ░░ ░");
+        assert_eq!(blur(""), "");
+    }
+
+    #[test]
+    fn test_truncate() {
+        let spans = vec![Span::from("Matrix "), Span::from("Rust")];
+
+        let text = |spans: Vec<Span>| {
+            spans
+                .iter()
+                .map(|s| s.content.to_string())
+                .collect::<String>()
+        };
+
+        assert_eq!(text(truncate(spans.clone(), 20)), "Matrix Rust");
+        assert_eq!(text(truncate(spans.clone(), 9)), "Matrix R…");
+        assert_eq!(text(truncate(spans, 4)), "Mat…");
+    }
+
+    #[test]
+    fn test_highlight() {
+        let plain = Style::default();
+        let matched = Style::default().fg(Color::Yellow);
+
+        let spans = highlight("#rust:matrix.org", &[1, 2, 3, 4], plain, matched);
+        let parts: Vec<(&str, bool)> = spans
+            .iter()
+            .map(|s| (s.content.as_ref(), s.style == matched))
+            .collect();
+
+        assert_eq!(
+            parts,
+            vec![("#", false), ("rust", true), (":matrix.org", false)]
+        );
+
+        assert_eq!(highlight("Rust", &[], plain, matched).len(), 1);
+    }
+
+    #[test]
+    fn test_list_row() {
+        assert_eq!(list_row(3, None), 3);
+        assert_eq!(list_row(0, Some(2)), 1);
+        assert_eq!(list_row(1, Some(2)), 2);
+        assert_eq!(list_row(2, Some(2)), 4);
+    }
+}
@@ -0,0 +1,76 @@
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Style};
+use ratatui::widgets::Widget;
+
+/// A thin bar down the side of a list, showing where in it we are. Nothing
+/// is drawn if everything fits.
+pub struct Scrollbar {
+    // how many rows there are, how many fit, and which one we're on, from
+    // the top
+    total: usize,
+    visible: usize,
+    position: usize,
+}
+
+impl Scrollbar {
+    pub fn new(total: usize, visible: usize, position: usize) -> Self {
+        Self {
+            total,
+            visible,
+            position,
+        }
+    }
+
+    // where the thumb starts, and how long it is, in a bar of this height
+    fn thumb(&self, height: usize) -> Option<(usize, usize)> {
+        if self.total <= self.visible || height == 0 {
+            return None;
+        }
+
+        let length = (height * self.visible / self.total).clamp(1, height);
+        let travel = height - length;
+        let start = travel * self.position.min(self.total - 1) / (self.total - 1);
+
+        Some((start, length))
+    }
+}
+
+impl Widget for Scrollbar {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let Some((start, length)) = self.thumb(area.height.into()) else {
+            return;
+        };
+
+        for row in 0..area.height {
+            let (symbol, color) = if (start..start + length).contains(&row.into()) {
+                ("┃", Color::Gray)
+            } else {
+                ("│", Color::DarkGray)
+            };
+
+            buf.get_mut(area.x, area.y + row)
+                .set_symbol(symbol)
+                .set_style(Style::default().fg(color));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumb() {
+        // it all fits
+        assert_eq!(Scrollbar::new(10, 20, 0).thumb(20), None);
+
+        // a quarter of it fits
+        assert_eq!(Scrollbar::new(80, 20, 0).thumb(20), Some((0, 5)));
+        assert_eq!(Scrollbar::new(80, 20, 79).thumb(20), Some((15, 5)));
+        assert_eq!(Scrollbar::new(80, 20, 40).thumb(20), Some((7, 5)));
+
+        // loads of history still gets a thumb
+        assert_eq!(Scrollbar::new(10_000, 20, 9_999).thumb(20), Some((19, 1)));
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::{Local, TimeZone};
+use crossterm::event::KeyEvent;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Cell, Row, Table, Widget};
+use ruma::MilliSecondsSinceUnixEpoch;
+
+use crate::close;
+use crate::widgets::get_margin;
+use crate::widgets::receipts::ReadReceipt;
+
+use super::EventResult;
+
+/// Everyone who has read up to a message, and when.
+pub struct Seen {
+    receipts: Vec<ReadReceipt>,
+}
+
+impl Seen {
+    pub fn new(receipts: Vec<ReadReceipt>) -> Self {
+        Self { receipts }
+    }
+
+    pub fn widget(&self) -> SeenWidget {
+        SeenWidget { seen: self }
+    }
+
+    pub fn key_event(&mut self, _: &KeyEvent) -> EventResult {
+        close!()
+    }
+}
+
+// Just the time for today, otherwise the day as well.
+fn read_at(timestamp: MilliSecondsSinceUnixEpoch) -> String {
+    let Some(date) = Local.timestamp_millis_opt(timestamp.get().into()).single() else {
+        return String::new();
+    };
+
+    if date.date_naive() == Local::now().date_naive() {
+        date.format("read at %H:%M").to_string()
+    } else {
+        date.format("read %b %-d at %H:%M").to_string()
+    }
+}
+
+pub struct SeenWidget<'a> {
+    seen: &'a Seen,
+}
+
+impl Widget for SeenWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = self.seen.receipts.len() as u16 + 2;
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 80))
+            .vertical_margin(get_margin(area.height, height))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title("Seen By")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let rows = self.seen.receipts.iter().map(|receipt| {
+            let user = &receipt.user;
+
+            Row::new(vec![
+                Cell::from(Span::styled(
+                    user.initial().to_string(),
+                    Style::default()
+                        .fg(user.color())
+                        .add_modifier(Modifier::BOLD),
+                )),
+                Cell::from(user.to_string()),
+                Cell::from(Span::styled(
+                    user.id.to_string(),
+                    Style::default().fg(Color::DarkGray),
+                )),
+                Cell::from(Span::styled(
+                    read_at(receipt.timestamp),
+                    Style::default().fg(Color::DarkGray),
+                )),
+            ])
+        });
+
+        Table::new(rows)
+            .widths(&[
+                Constraint::Length(2),
+                Constraint::Percentage(35),
+                Constraint::Percentage(40),
+                Constraint::Percentage(25),
+            ])
+            .column_spacing(1)
+            .render(inner, buf);
+    }
+}
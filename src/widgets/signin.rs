@@ -1,42 +1,98 @@
 use crossterm::event::{KeyCode, KeyEvent};
+use log::error;
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
-use ratatui::widgets::{Block, BorderType, Borders, Widget};
+use ratatui::text::Span;
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+use ruma::UserId;
+use std::fs;
+use std::path::PathBuf;
 
+use crate::app::Popup;
 use crate::consumed;
 use crate::widgets::button::Button;
-use crate::widgets::textinput::TextInput;
+use crate::widgets::register::Register;
+use crate::widgets::textinput::{paste_into, TextInput};
 use crate::widgets::EventResult::{Consumed, Ignored};
-use crate::widgets::{focus_next, focus_prev, get_margin, EventResult, Focusable};
+use crate::widgets::{focus_next, focus_prev, get_margin, get_padding, EventResult, Focusable};
 
 pub struct Signin {
     pub id: TextInput,
     pub password: TextInput,
     submit: Button,
+    register: Button,
+
+    // why the last attempt didn't work out
+    error: Option<String>,
+
+    // why we're asking again, if we've been signed out
+    notice: Option<&'static str>,
+
+    // waiting to hear back from the homeserver
+    pending: bool,
 }
 
 impl Default for Signin {
     fn default() -> Self {
-        let id = TextInput::new("Matrix ID".to_string(), true, false);
-        let password = TextInput::new("Password".to_string(), false, true);
+        // whoever signed in last time probably will again
+        let last = last_user();
+
+        let id = TextInput::new("Matrix ID".to_string(), last.is_none(), false)
+            .with_value(last.as_deref().unwrap_or_default());
+        let password = TextInput::new("Password".to_string(), last.is_some(), true);
 
         let submit = Button::new("Submit".to_string(), false);
+        let register = Button::new("Register".to_string(), false);
 
         Self {
             id,
             password,
             submit,
+            register,
+            error: None,
+            notice: None,
+            pending: false,
         }
     }
 }
 
 impl Signin {
+    /// The homeserver forgot our token, so sign back in as the same person,
+    /// on the same device, keeping the encryption keys.
+    pub fn expired(user_id: &str, soft_logout: bool) -> Self {
+        let notice = if soft_logout {
+            "Your session expired. Sign in again to pick up where you left off."
+        } else {
+            "You were signed out by the homeserver. Signing in again keeps your \
+             history, but you may have to verify this device again."
+        };
+
+        Self {
+            id: TextInput::new("Matrix ID".to_string(), false, false).with_value(user_id),
+            password: TextInput::new("Password".to_string(), true, true),
+            notice: Some(notice),
+            ..Self::default()
+        }
+    }
+
+    /// The homeserver said no, so let them try again.
+    pub fn login_failed(&mut self, error: String) {
+        self.pending = false;
+        self.error = Some(error);
+    }
+
+    // what's wrong with the Matrix ID, as it's being typed
+    fn id_problem(&self) -> Option<&'static str> {
+        id_problem(&self.id.value())
+    }
+
     fn focus_order(&mut self) -> Vec<Box<dyn Focusable + '_>> {
         vec![
             Box::new(&mut self.id),
             Box::new(&mut self.password),
             Box::new(&mut self.submit),
+            Box::new(&mut self.register),
         ]
     }
 
@@ -44,6 +100,10 @@ impl Signin {
         SigninWidget { signin: self }
     }
 
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        paste_into([&mut self.id, &mut self.password], text)
+    }
+
     pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
         if let Consumed(_) = self.id.key_event(input) {
             return consumed!();
@@ -54,12 +114,29 @@ impl Signin {
         }
 
         if let Consumed(_) = self.submit.key_event(input) {
+            if self.pending || self.id_problem().is_some() || self.password.value().is_empty() {
+                return consumed!();
+            }
+
             let id = self.id.value();
             let password = self.password.value();
 
+            save_last_user(&id);
+
+            // stick around until we know how it went
+            self.pending = true;
+            self.error = None;
+
             return EventResult::Consumed(Box::new(move |app| {
                 app.matrix.login(id.as_str(), password.as_str());
-                app.close_popup();
+            }));
+        }
+
+        if let Consumed(_) = self.register.key_event(input) {
+            let id = self.id.value();
+
+            return EventResult::Consumed(Box::new(move |app| {
+                app.push_popup(Popup::Register(Register::new(&id)));
             }));
         }
 
@@ -79,20 +156,20 @@ impl Widget for SigninWidget<'_> {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let area = Layout::default()
             .horizontal_margin(get_margin(area.width, 60))
-            .vertical_margin(get_margin(area.height, 18))
+            .vertical_margin(get_margin(area.height, 20))
             .constraints([Constraint::Percentage(100)].as_ref())
             .split(area)[0];
 
         let splits = Layout::default()
             .direction(Direction::Vertical)
-            .horizontal_margin(8)
+            .horizontal_margin(get_padding(area.width, 8))
             .vertical_margin(3)
             .constraints(
                 [
                     Constraint::Length(3),
                     Constraint::Length(1),
                     Constraint::Length(3),
-                    Constraint::Length(1),
+                    Constraint::Length(3),
                     Constraint::Percentage(100),
                 ]
                 .as_ref(),
@@ -108,14 +185,105 @@ impl Widget for SigninWidget<'_> {
 
         block.render(area, buf);
         self.signin.id.widget().render(splits[0], buf);
+
+        // check the ID as it's typed, but don't nag before there's anything
+        if !self.signin.id.value().is_empty() {
+            if let Some(problem) = self.signin.id_problem() {
+                Paragraph::new(Span::styled(problem, Style::default().fg(Color::DarkGray)))
+                    .render(splits[1], buf);
+            }
+        }
+
         self.signin.password.widget().render(splits[2], buf);
 
-        // pop the submit button on the right side
-        let area = Layout::default()
+        let status = if self.signin.pending {
+            Some(Span::styled(
+                "Signing in...",
+                Style::default().fg(Color::DarkGray),
+            ))
+        } else {
+            self.signin
+                .error
+                .as_ref()
+                .map(|e| Span::styled(e.as_str(), Style::default().fg(Color::Red)))
+                .or_else(|| {
+                    self.signin
+                        .notice
+                        .map(|n| Span::styled(n, Style::default().fg(Color::Yellow)))
+                })
+        };
+
+        if let Some(status) = status {
+            Paragraph::new(status)
+                .wrap(Wrap { trim: true })
+                .render(splits[3], buf);
+        }
+
+        // submit on the right, register for new folks on the left
+        let buttons = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-            .split(splits[4])[1];
+            .split(splits[4]);
+
+        self.signin.register.widget().render(buttons[0], buf);
+        self.signin.submit.widget().render(buttons[1], buf);
+    }
+}
+
+fn last_user_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("matui").join("last_user"))
+}
+
+fn last_user() -> Option<String> {
+    let id = fs::read_to_string(last_user_path()?).ok()?;
+    let id = id.trim();
+
+    if id.is_empty() {
+        None
+    } else {
+        Some(id.to_string())
+    }
+}
+
+// just the ID, never the password
+fn save_last_user(id: &str) {
+    let Some(path) = last_user_path() else {
+        return;
+    };
+
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, id));
+
+    if let Err(e) = result {
+        error!("could not save the last user: {}", e);
+    }
+}
+
+fn id_problem(id: &str) -> Option<&'static str> {
+    if !id.starts_with('@') {
+        return Some("Starts with @, like @alice:example.com");
+    }
+
+    match id.split_once(':') {
+        None => Some("Needs a server, like @alice:example.com"),
+        Some((_, "")) => Some("Needs a server after the :"),
+        _ if UserId::parse(id).is_err() => Some("That's not a valid Matrix ID"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        self.signin.submit.widget().render(area, buf);
+    #[test]
+    fn test_id_problem() {
+        assert!(id_problem("alice").is_some());
+        assert!(id_problem("@alice").is_some());
+        assert!(id_problem("@alice:").is_some());
+        assert!(id_problem("@alice:example.com").is_none());
+        assert!(id_problem("@alice:example.com:8448").is_none());
     }
 }
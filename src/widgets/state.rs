@@ -0,0 +1,195 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::ruma::exports::serde_json::{self, Value};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{
+    Block, BorderType, Borders, List, ListItem, ListState, Paragraph, StatefulWidget, Widget,
+};
+use ruma::events::AnyStateEvent;
+use ruma::serde::Raw;
+
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// A single piece of room state, ready to show.
+#[derive(Clone, Debug)]
+pub struct StateEntry {
+    pub event_type: String,
+    pub state_key: String,
+    pub json: String,
+}
+
+impl StateEntry {
+    pub fn from_raw(raw: &Raw<AnyStateEvent>) -> Option<Self> {
+        let value: Value = serde_json::from_str(raw.json().get()).ok()?;
+
+        Some(StateEntry {
+            event_type: value.get("type")?.as_str()?.to_string(),
+            state_key: value.get("state_key")?.as_str()?.to_string(),
+            json: serde_json::to_string_pretty(&value).ok()?,
+        })
+    }
+}
+
+/// Every state event in a room, with the full JSON of one at a time.
+pub struct State {
+    room_name: String,
+    entries: Vec<StateEntry>,
+    selected: usize,
+    // the scroll offset of the JSON, when we're showing it
+    detail: Option<u16>,
+}
+
+impl State {
+    pub fn new(room_name: String, mut entries: Vec<StateEntry>) -> Self {
+        entries.sort_by(|a, b| (&a.event_type, &a.state_key).cmp(&(&b.event_type, &b.state_key)));
+
+        Self {
+            room_name,
+            entries,
+            selected: 0,
+            detail: None,
+        }
+    }
+
+    pub fn widget(&self) -> StateWidget {
+        StateWidget { state: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if let Some(offset) = self.detail {
+            self.detail = match input.code {
+                KeyCode::Esc | KeyCode::Char('q') => None,
+                KeyCode::Char('j') | KeyCode::Down => Some(offset.saturating_add(1)),
+                KeyCode::Char('k') | KeyCode::Up => Some(offset.saturating_sub(1)),
+                _ => Some(offset),
+            };
+
+            return consumed!();
+        }
+
+        match input.code {
+            KeyCode::Esc | KeyCode::Char('q') => close!(),
+            KeyCode::Char('j') | KeyCode::Down => {
+                if self.selected + 1 < self.entries.len() {
+                    self.selected += 1;
+                }
+                consumed!()
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                consumed!()
+            }
+            KeyCode::Enter => {
+                if !self.entries.is_empty() {
+                    self.detail = Some(0);
+                }
+                consumed!()
+            }
+            _ => consumed!(),
+        }
+    }
+}
+
+pub struct StateWidget<'a> {
+    state: &'a State,
+}
+
+impl Widget for StateWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = Layout::default()
+            .direction(Direction::Horizontal)
+            .vertical_margin(2)
+            .horizontal_margin(get_margin(area.width, 100))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let title = match self.state.detail {
+            Some(_) => {
+                let entry = &self.state.entries[self.state.selected];
+                format!("{} ({})", entry.event_type, entry.state_key)
+            }
+            None => format!("State of {}", self.state.room_name),
+        };
+
+        let block = Block::default()
+            .title(title)
+            .title_alignment(Alignment::Center)
+            .style(Style::default().bg(Color::Black))
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded);
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let inner = Layout::default()
+            .horizontal_margin(1)
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(inner)[0];
+
+        if let Some(offset) = self.state.detail {
+            let json = self.state.entries[self.state.selected].json.as_str();
+
+            Paragraph::new(json).scroll((offset, 0)).render(inner, buf);
+
+            return;
+        }
+
+        let items: Vec<ListItem> = self
+            .state
+            .entries
+            .iter()
+            .map(|e| {
+                ListItem::new(Line::from(vec![
+                    Span::styled(e.event_type.clone(), Style::default().fg(Color::Green)),
+                    Span::raw(" "),
+                    Span::raw(e.state_key.clone()),
+                ]))
+            })
+            .collect();
+
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.state.selected));
+
+        let list =
+            List::new(items).highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        StatefulWidget::render(list, inner, buf, &mut list_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_raw() {
+        let raw: Raw<AnyStateEvent> = Raw::from_json_string(
+            r#"{
+                "type": "m.room.encryption",
+                "state_key": "",
+                "sender": "@a:b.c",
+                "event_id": "$abc",
+                "origin_server_ts": 0,
+                "room_id": "!a:b.c",
+                "content": { "algorithm": "m.megolm.v1.aes-sha2" }
+            }"#
+            .to_string(),
+        )
+        .unwrap();
+
+        let entry = StateEntry::from_raw(&raw).unwrap();
+
+        assert_eq!(entry.event_type, "m.room.encryption");
+        assert_eq!(entry.state_key, "");
+        assert!(entry
+            .json
+            .contains("\"algorithm\": \"m.megolm.v1.aes-sha2\""));
+    }
+}
@@ -1,20 +1,26 @@
 use crate::consumed;
-use crate::widgets::EventResult::Ignored;
-use crate::widgets::{EventResult, Focusable};
+use crate::widgets::EventResult::{Consumed, Ignored};
+use crate::widgets::{set_cursor, EventResult, Focusable};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Constraint, Layout, Rect};
 use ratatui::style::{Color, Style};
 use ratatui::widgets::{Block, Borders, Paragraph, Widget};
 use std::cell::Cell;
+use unicode_width::UnicodeWidthStr;
 
 pub struct TextInput {
     title: String,
     pub value: String,
     pub focused: bool,
     password: bool,
+
+    // in chars, not bytes
     cursor: usize,
 
+    // a password that's being shown, for now
+    revealed: bool,
+
     // state that needs to be modified by the widget and the struct
     left: Cell<usize>,
 }
@@ -30,6 +36,7 @@ impl Focusable for &mut TextInput {
 
     fn defocus(&mut self) {
         self.focused = false;
+        self.revealed = false;
     }
 }
 
@@ -41,10 +48,17 @@ impl TextInput {
             focused,
             password,
             cursor: 0,
+            revealed: false,
             left: Cell::new(0),
         }
     }
 
+    pub fn with_value(mut self, value: &str) -> TextInput {
+        self.value = value.to_string();
+        self.cursor = self.value.chars().count();
+        self
+    }
+
     pub fn widget(&self) -> TextInputWidget {
         TextInputWidget { textinput: self }
     }
@@ -54,15 +68,25 @@ impl TextInput {
             return Ignored;
         }
 
-        if input.modifiers != KeyModifiers::SHIFT && input.modifiers != KeyModifiers::NONE {
-            return Ignored;
+        if self.password
+            && input.modifiers == KeyModifiers::CONTROL
+            && input.code == KeyCode::Char('r')
+        {
+            self.revealed = !self.revealed;
+            return consumed!();
         }
 
         match input.code {
-            KeyCode::Char(c) => {
+            // AltGr comes through as Ctrl+Alt, and it's how some layouts type
+            KeyCode::Char(c) if is_typing(input.modifiers) => {
                 self.append_char(c);
                 consumed!()
             }
+            _ if input.modifiers != KeyModifiers::SHIFT
+                && input.modifiers != KeyModifiers::NONE =>
+            {
+                Ignored
+            }
             KeyCode::Backspace => {
                 self.backspace();
                 consumed!()
@@ -78,22 +102,48 @@ impl TextInput {
             _ => Ignored,
         }
     }
+
+    /// Insert pasted (or composed) text at the cursor, as one line.
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        if !self.focused {
+            return Ignored;
+        }
+
+        for c in text.chars() {
+            match c {
+                '\n' | '\t' => self.append_char(' '),
+                c if c.is_control() => {}
+                c => self.append_char(c),
+            }
+        }
+
+        consumed!()
+    }
+
     pub fn value(&self) -> String {
         self.value.clone()
     }
 
-    fn append_char(&mut self, ch: char) {
-        if self.cursor == self.value.len() {
-            self.value.push(ch);
-        } else {
-            self.value.insert(self.cursor, ch);
-        }
+    fn len(&self) -> usize {
+        self.value.chars().count()
+    }
+
+    // where the char at the given index starts
+    fn byte_index(&self, index: usize) -> usize {
+        self.value
+            .char_indices()
+            .nth(index)
+            .map(|(i, _)| i)
+            .unwrap_or(self.value.len())
+    }
 
+    fn append_char(&mut self, ch: char) {
+        self.value.insert(self.byte_index(self.cursor), ch);
         self.cursor += 1;
     }
 
     fn move_right(&mut self) {
-        if self.cursor < self.value.len() {
+        if self.cursor < self.len() {
             self.cursor += 1;
         }
     }
@@ -103,7 +153,7 @@ impl TextInput {
             return;
         }
 
-        self.value.replace_range(self.cursor - 1..self.cursor, "");
+        self.value.remove(self.byte_index(self.cursor - 1));
         self.cursor -= 1;
 
         let left = self.left.get();
@@ -126,22 +176,29 @@ impl TextInput {
     }
 
     fn display_value(&self) -> String {
-        let mut value = if self.password {
-            "*".repeat(self.value.len())
+        if self.password && !self.revealed {
+            "*".repeat(self.len())
         } else {
             self.value.clone()
-        };
-
-        if self.focused {
-            if self.cursor >= self.value.len() {
-                value.push('█');
-            } else {
-                value.replace_range(self.cursor..self.cursor + 1, "█");
-            }
         }
+    }
+}
 
-        value
+/// Paste into whichever of the inputs has focus.
+pub fn paste_into<const N: usize>(inputs: [&mut TextInput; N], text: &str) -> EventResult {
+    for input in inputs {
+        if let Consumed(f) = input.paste(text) {
+            return Consumed(f);
+        }
     }
+
+    Ignored
+}
+
+fn is_typing(modifiers: KeyModifiers) -> bool {
+    modifiers == KeyModifiers::NONE
+        || modifiers == KeyModifiers::SHIFT
+        || modifiers.contains(KeyModifiers::CONTROL | KeyModifiers::ALT)
 }
 
 pub struct TextInputWidget<'a> {
@@ -156,8 +213,15 @@ impl TextInputWidget<'_> {
     fn adjust_window(&self, size: usize) {
         let left = self.textinput.left.get();
 
+        let len = self.textinput.len();
+
+        // squeezed down to nothing, so there's no window to move
+        if size == 0 {
+            return;
+        }
+
         // we fit entirely
-        if self.textinput.value.len() <= size {
+        if len <= size {
             self.set_left(0);
             return;
         }
@@ -169,8 +233,8 @@ impl TextInputWidget<'_> {
         }
 
         // scroll right
-        if left >= self.textinput.value.len() - size {
-            self.set_left(self.textinput.value.len() - size + 1);
+        if left >= len - size {
+            self.set_left(len - size + 1);
         }
     }
 
@@ -178,11 +242,7 @@ impl TextInputWidget<'_> {
         let left = self.textinput.left.get();
         let value = self.textinput.display_value();
 
-        if left == 0 {
-            return value;
-        }
-
-        value[left..].to_string()
+        value.chars().skip(left).collect()
     }
 }
 
@@ -194,8 +254,20 @@ impl Widget for TextInputWidget<'_> {
             Color::DarkGray
         };
 
+        let title = if self.textinput.password && self.textinput.focused {
+            let action = if self.textinput.revealed {
+                "hide"
+            } else {
+                "show"
+            };
+
+            format!("{} (Ctrl+R to {})", self.textinput.title, action)
+        } else {
+            self.textinput.title.clone()
+        };
+
         Block::default()
-            .title(self.textinput.title.as_str())
+            .title(title)
             .borders(Borders::ALL)
             .style(Style::default().fg(color))
             .render(area, buf);
@@ -211,6 +283,22 @@ impl Widget for TextInputWidget<'_> {
         Paragraph::new(self.adjusted_value())
             .style(Style::default().fg(color))
             .render(area, buf);
+
+        // the terminal draws the real cursor, so IMEs know where we are
+        if self.textinput.focused {
+            let before: String = self
+                .adjusted_value()
+                .chars()
+                .take(
+                    self.textinput
+                        .cursor
+                        .saturating_sub(self.textinput.left.get()),
+                )
+                .collect();
+
+            let x = (before.width() as u16).min(area.width.saturating_sub(1));
+            set_cursor(area.x + x, area.y);
+        }
     }
 }
 
@@ -221,6 +309,7 @@ mod tests {
     use ratatui::layout::Rect;
     use ratatui::widgets::Widget;
 
+    use crate::widgets::take_cursor;
     use crate::widgets::textinput::TextInput;
 
     #[test]
@@ -260,7 +349,8 @@ mod tests {
         // do an initial render
         input.widget().render(area, &mut buf);
 
-        assert_eq!(get_line(&buf, 1), "│█       │");
+        assert_eq!(get_line(&buf, 1), "│        │");
+        assert_eq!(take_cursor(), Some((1, 1)));
 
         // type out a long string
         for c in "Hello, world, this is me typing some things.".chars() {
@@ -268,7 +358,8 @@ mod tests {
         }
 
         input.widget().render(area, &mut buf);
-        assert_eq!(get_line(&buf, 1), "│things.█│");
+        assert_eq!(get_line(&buf, 1), "│things. │");
+        assert_eq!(take_cursor(), Some((8, 1)));
 
         // arrow backwards a bit
         for _ in 0..3 {
@@ -277,20 +368,67 @@ mod tests {
 
         let mut buf = Buffer::empty(area);
         input.widget().render(area, &mut buf);
-        assert_eq!(get_line(&buf, 1), "│thin█s. │");
+        assert_eq!(get_line(&buf, 1), "│things. │");
+        assert_eq!(take_cursor(), Some((5, 1)));
 
         // delete
         input.key_event(&KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
 
         input.widget().render(area, &mut buf);
-        assert_eq!(get_line(&buf, 1), "│ thi█s. │");
+        assert_eq!(get_line(&buf, 1), "│ thigs. │");
+        assert_eq!(take_cursor(), Some((5, 1)));
 
         // resize larger
         let area = Rect::new(0, 0, 20, 3);
         let mut buf = Buffer::empty(area);
 
         input.widget().render(area, &mut buf);
-        assert_eq!(get_line(&buf, 1), "│yping some thi█s. │");
+        assert_eq!(get_line(&buf, 1), "│yping some thigs. │");
+        assert_eq!(take_cursor(), Some((15, 1)));
+    }
+
+    #[test]
+    fn it_reveals_passwords() {
+        let area = Rect::new(0, 0, 10, 3);
+        let mut input = TextInput::new("Test".to_string(), true, true);
+
+        for c in "hunter2".chars() {
+            input.key_event(&KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+
+        let mut buf = Buffer::empty(area);
+        input.widget().render(area, &mut buf);
+        assert_eq!(get_line(&buf, 1), "│******* │");
+
+        input.key_event(&KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL));
+
+        let mut buf = Buffer::empty(area);
+        input.widget().render(area, &mut buf);
+        assert_eq!(get_line(&buf, 1), "│hunter2 │");
+        assert_eq!(input.value(), "hunter2");
+    }
+
+    #[test]
+    fn it_accepts_composed_input() {
+        let mut input = TextInput::new("Test".to_string(), true, false);
+
+        // a Japanese IME hands over whole words at once
+        input.paste("日本");
+
+        for c in "é語".chars() {
+            input.key_event(&KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+
+        input.key_event(&KeyEvent::new(KeyCode::Left, KeyModifiers::NONE));
+        input.key_event(&KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(input.value(), "日本語");
+
+        let area = Rect::new(0, 0, 10, 3);
+        let mut buf = Buffer::empty(area);
+        input.widget().render(area, &mut buf);
+
+        // wide characters take up two cells each
+        assert_eq!(take_cursor(), Some((5, 1)));
     }
 
     fn get_line(buf: &Buffer, line: usize) -> String {
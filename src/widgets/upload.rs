@@ -0,0 +1,274 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::room::Room;
+use mime::Mime;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Span;
+use ratatui::widgets::{
+    Block, BorderType, Borders, Cell, Paragraph, Row, StatefulWidget, Table, TableState, Widget,
+};
+
+use crate::matrix::mime::mime_from_file;
+use crate::widgets::get_margin;
+use crate::widgets::textinput::TextInput;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+// plenty to sniff the type from
+const HEADER_SIZE: u64 = 1024;
+
+/// The files picked for upload, for one last look (and a chance to drop the
+/// wrong ones, or add a caption) before they're sent.
+pub struct Upload {
+    room: Room,
+    room_name: String,
+    files: Vec<PendingFile>,
+    selected: usize,
+    caption: TextInput,
+}
+
+struct PendingFile {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    mime: Mime,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl PendingFile {
+    fn new(path: PathBuf) -> Self {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let size = path.metadata().map(|m| m.len()).unwrap_or_default();
+
+        let mut header = vec![];
+
+        if let Ok(file) = File::open(&path) {
+            let _ = file.take(HEADER_SIZE).read_to_end(&mut header);
+        }
+
+        let mime = mime_from_file(&path, &header);
+
+        // only reads as much of the file as it needs to
+        let dimensions = if mime.type_() == mime::IMAGE {
+            image::image_dimensions(&path).ok()
+        } else {
+            None
+        };
+
+        Self {
+            path,
+            name,
+            size,
+            mime,
+            dimensions,
+        }
+    }
+}
+
+impl Upload {
+    pub fn new(room: Room, room_name: String, paths: Vec<PathBuf>) -> Self {
+        Self {
+            room,
+            room_name,
+            files: paths.into_iter().map(PendingFile::new).collect(),
+            selected: 0,
+            caption: TextInput::new("Caption".to_string(), false, false),
+        }
+    }
+
+    pub fn widget(&self) -> UploadWidget {
+        UploadWidget { upload: self }
+    }
+
+    pub fn paste(&mut self, text: &str) -> EventResult {
+        self.caption.paste(text)
+    }
+
+    fn send(&self) -> EventResult {
+        let room = self.room.clone();
+        let paths: Vec<PathBuf> = self.files.iter().map(|f| f.path.clone()).collect();
+
+        let caption = Some(self.caption.value().trim().to_string()).filter(|c| !c.is_empty());
+
+        EventResult::Consumed(Box::new(move |app| {
+            // close first, so we don't take the progress popup with us
+            app.close_popup();
+            app.matrix.send_attachements(room, paths, caption);
+        }))
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if self.caption.focused {
+            return match input.code {
+                KeyCode::Enter => self.send(),
+                KeyCode::Esc | KeyCode::Tab => {
+                    self.caption.focused = false;
+                    consumed!()
+                }
+                _ => match self.caption.key_event(input) {
+                    EventResult::Ignored => consumed!(),
+                    result => result,
+                },
+            };
+        }
+
+        match input.code {
+            KeyCode::Tab | KeyCode::Char('c') => {
+                self.caption.focused = true;
+                consumed!()
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                if self.selected + 1 < self.files.len() {
+                    self.selected += 1;
+                }
+
+                consumed!()
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                consumed!()
+            }
+            KeyCode::Char('d') | KeyCode::Delete | KeyCode::Backspace => {
+                if !self.files.is_empty() {
+                    self.files.remove(self.selected);
+                }
+
+                if self.files.is_empty() {
+                    return close!();
+                }
+
+                self.selected = self.selected.min(self.files.len() - 1);
+                consumed!()
+            }
+            KeyCode::Enter => self.send(),
+            KeyCode::Esc | KeyCode::Char('q') => close!(),
+            _ => EventResult::Ignored,
+        }
+    }
+}
+
+// sizes the way people read them: 1.4 MB, not 1468006 bytes
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub struct UploadWidget<'a> {
+    upload: &'a Upload,
+}
+
+impl Widget for UploadWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = self.upload.files.len() as u16 + 6;
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 80))
+            .vertical_margin(get_margin(area.height, height))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title(format!("Upload to {}", self.upload.room_name))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Min(1),
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        let dim = Style::default().fg(Color::DarkGray);
+
+        let rows = self.upload.files.iter().map(|file| {
+            let dimensions = file
+                .dimensions
+                .map(|(w, h)| format!("{}×{}", w, h))
+                .unwrap_or_default();
+
+            Row::new(vec![
+                Cell::from(file.name.clone()),
+                Cell::from(Span::styled(human_size(file.size), dim)),
+                Cell::from(Span::styled(file.mime.essence_str().to_string(), dim)),
+                Cell::from(Span::styled(dimensions, dim)),
+            ])
+        });
+
+        let mut state = TableState::default();
+        state.select(Some(self.upload.selected));
+
+        let table = Table::new(rows)
+            .widths(&[
+                Constraint::Percentage(40),
+                Constraint::Length(9),
+                Constraint::Percentage(30),
+                Constraint::Length(11),
+            ])
+            .column_spacing(1)
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+
+        StatefulWidget::render(table, splits[0], buf, &mut state);
+
+        self.upload.caption.widget().render(splits[1], buf);
+
+        let hint = if self.upload.caption.focused {
+            "Enter to send, Esc to go back to the files"
+        } else {
+            "Enter to send, c for a caption, d to remove, Esc to cancel"
+        };
+
+        Paragraph::new(Span::styled(hint, dim))
+            .alignment(Alignment::Center)
+            .render(splits[2], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MB");
+    }
+}
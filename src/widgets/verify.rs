@@ -0,0 +1,328 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use matrix_sdk::encryption::verification::{Emoji, SasVerification, VerificationRequest};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, BorderType, Borders, Paragraph, Widget, Wrap};
+
+use crate::matrix::matrix::format_emojis;
+use crate::widgets::get_margin;
+use crate::{close, consumed};
+
+use super::EventResult;
+
+/// How far along a verification is.
+#[derive(Clone, Debug)]
+pub enum Step {
+    // we asked, and the other side hasn't answered yet
+    Waiting,
+    // they asked
+    Requested,
+    Accepted,
+    Exchanging,
+    Compare([Emoji; 7]),
+    // we said they match, and the other side has to say so too
+    Confirmed,
+    Done,
+    Cancelled(String),
+}
+
+impl Step {
+    // where this lands in the list of stages
+    fn stage(&self) -> usize {
+        match self {
+            Step::Waiting | Step::Requested => 0,
+            Step::Accepted => 1,
+            Step::Exchanging => 2,
+            Step::Compare(_) | Step::Confirmed => 3,
+            Step::Done => 5,
+            Step::Cancelled(_) => 6,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self, Step::Done | Step::Cancelled(_))
+    }
+}
+
+/// Walk through verifying another session, from the request all the way to
+/// comparing emoji, so nobody's left wondering what the other device is
+/// waiting on.
+pub struct Verify {
+    who: String,
+    step: Step,
+    // the last stage we got to, to show where a cancelled one stopped
+    reached: usize,
+    request: Option<VerificationRequest>,
+    sas: Option<SasVerification>,
+}
+
+impl Verify {
+    pub fn from_request(request: VerificationRequest) -> Self {
+        let who = if request.is_self_verification() {
+            "your other session".to_string()
+        } else {
+            request.other_user_id().to_string()
+        };
+
+        let step = if request.we_started() {
+            Step::Waiting
+        } else {
+            Step::Requested
+        };
+
+        Self {
+            who,
+            reached: step.stage(),
+            step,
+            request: Some(request),
+            sas: None,
+        }
+    }
+
+    pub fn from_sas(sas: SasVerification) -> Self {
+        let mut verify = Self {
+            who: String::new(),
+            step: Step::Exchanging,
+            reached: Step::Exchanging.stage(),
+            request: None,
+            sas: None,
+        };
+
+        verify.set_sas(sas);
+        verify
+    }
+
+    /// The request turned into emoji verification.
+    pub fn set_sas(&mut self, sas: SasVerification) {
+        self.who = if sas.is_self_verification() {
+            format!("your other session ({})", sas.other_device().device_id())
+        } else {
+            sas.other_user_id().to_string()
+        };
+
+        self.sas = Some(sas);
+        self.advance(Step::Exchanging);
+    }
+
+    /// Move on to the given step, unless we're already past it. The request
+    /// and the emoji flow report separately, so they can arrive out of order.
+    pub fn advance(&mut self, step: Step) {
+        if self.step.finished() {
+            return;
+        }
+
+        if step.stage() < self.step.stage() {
+            return;
+        }
+
+        if !matches!(step, Step::Cancelled(_)) {
+            self.reached = step.stage();
+        }
+
+        self.step = step;
+    }
+
+    pub fn widget(&self) -> VerifyWidget {
+        VerifyWidget { verify: self }
+    }
+
+    pub fn key_event(&mut self, input: &KeyEvent) -> EventResult {
+        if self.step.finished() {
+            return match input.code {
+                KeyCode::Enter | KeyCode::Esc | KeyCode::Char('q') => close!(),
+                _ => consumed!(),
+            };
+        }
+
+        match (input.code, &self.step, self.sas.clone()) {
+            (KeyCode::Char('y'), Step::Compare(_), Some(sas)) => {
+                self.advance(Step::Confirmed);
+                EventResult::Consumed(Box::new(move |app| app.matrix.confirm_verification(sas)))
+            }
+            (KeyCode::Char('n'), Step::Compare(_), Some(sas)) => {
+                self.advance(Step::Cancelled("The emoji didn't match.".to_string()));
+                EventResult::Consumed(Box::new(move |app| app.matrix.mismatched_verification(sas)))
+            }
+            (KeyCode::Esc | KeyCode::Char('c'), _, sas) => {
+                let request = self.request.clone();
+                self.advance(Step::Cancelled("You cancelled.".to_string()));
+
+                EventResult::Consumed(Box::new(move |app| {
+                    app.matrix.cancel_verification(request, sas)
+                }))
+            }
+            _ => consumed!(),
+        }
+    }
+}
+
+pub struct VerifyWidget<'a> {
+    verify: &'a Verify,
+}
+
+impl VerifyWidget<'_> {
+    fn lines(&self) -> Vec<Line> {
+        let step = &self.verify.step;
+        let reached = self.verify.reached;
+
+        let first = if matches!(step, Step::Waiting) {
+            "Request sent"
+        } else {
+            "Request received"
+        };
+
+        let stages = [
+            first,
+            "Accepted",
+            "Exchanging keys",
+            "Comparing emoji",
+            "Verified",
+        ];
+
+        let mut lines = vec![
+            Line::from(Span::styled(
+                format!("Verifying with {}", self.verify.who),
+                Style::default().fg(Color::DarkGray),
+            )),
+            Line::default(),
+        ];
+
+        for (i, stage) in stages.iter().enumerate() {
+            let line = if i < reached {
+                Line::from(vec![
+                    Span::styled(" ✓ ", Style::default().fg(Color::Green)),
+                    Span::raw(*stage),
+                ])
+            } else if i == reached && matches!(step, Step::Cancelled(_)) {
+                Line::from(Span::styled(
+                    format!(" ✗ {}", stage),
+                    Style::default().fg(Color::Red),
+                ))
+            } else if i == reached {
+                Line::from(Span::styled(
+                    format!(" ▸ {}", stage),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ))
+            } else {
+                Line::from(Span::styled(
+                    format!("   {}", stage),
+                    Style::default().fg(Color::DarkGray),
+                ))
+            };
+
+            lines.push(line);
+        }
+
+        lines.push(Line::default());
+
+        match step {
+            Step::Waiting => lines.push(Line::from("Accept the request on your other session.")),
+            Step::Compare(emojis) => {
+                lines.push(Line::from(format_emojis(emojis.clone())));
+                lines.push(Line::default());
+                lines.push(Line::from("Do these match what the other side sees?"));
+            }
+            Step::Confirmed => lines.push(Line::from("Waiting for the other side to confirm.")),
+            Step::Done => lines.push(Line::from(Span::styled(
+                "All done. You're verified.",
+                Style::default().fg(Color::Green),
+            ))),
+            Step::Cancelled(reason) => lines.push(Line::from(Span::styled(
+                format!("Cancelled: {}", reason),
+                Style::default().fg(Color::Red),
+            ))),
+            _ => lines.push(Line::from("Waiting for the other side.")),
+        }
+
+        lines
+    }
+
+    fn hint(&self) -> &'static str {
+        match self.verify.step {
+            Step::Compare(_) => "y they match · n they don't · Esc cancel",
+            Step::Done | Step::Cancelled(_) => "Enter close",
+            _ => "Esc cancel",
+        }
+    }
+}
+
+impl Widget for VerifyWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines = self.lines();
+
+        let area = Layout::default()
+            .horizontal_margin(get_margin(area.width, 60))
+            .vertical_margin(get_margin(area.height, lines.len() as u16 + 5))
+            .constraints([Constraint::Percentage(100)].as_ref())
+            .split(area)[0];
+
+        buf.merge(&Buffer::empty(area));
+
+        let block = Block::default()
+            .title("Verify")
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black));
+
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let splits = Layout::default()
+            .direction(Direction::Vertical)
+            .horizontal_margin(1)
+            .constraints(
+                [
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .render(splits[0], buf);
+
+        Paragraph::new(Span::styled(
+            self.hint(),
+            Style::default().fg(Color::DarkGray),
+        ))
+        .alignment(Alignment::Center)
+        .render(splits[2], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let mut verify = Verify {
+            who: "your other session".to_string(),
+            step: Step::Requested,
+            reached: 0,
+            request: None,
+            sas: None,
+        };
+
+        verify.advance(Step::Exchanging);
+        assert!(matches!(verify.step, Step::Exchanging));
+
+        // the request catching up can't take us backwards
+        verify.advance(Step::Accepted);
+        assert!(matches!(verify.step, Step::Exchanging));
+
+        verify.advance(Step::Cancelled("timed out".to_string()));
+        assert!(verify.step.finished());
+        assert_eq!(verify.reached, Step::Exchanging.stage());
+
+        // and nothing comes after the end
+        verify.advance(Step::Done);
+        assert!(matches!(verify.step, Step::Cancelled(_)));
+    }
+}